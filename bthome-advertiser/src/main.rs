@@ -0,0 +1,143 @@
+//! Advertises measurements of the local machine as BTHome data, turning it into a BTHome sensor.
+
+use std::{error::Error, path::PathBuf, time::Duration};
+
+use clap::Parser;
+use serde::Deserialize;
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+mod measurement;
+
+use measurement::{Measurement, MeasurementConfig, Source};
+
+/// Advertise BTHome data, e.g. the CPU temperature, read from fixed values, files or commands.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Path to a TOML configuration file with the measurements to advertise
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Bluetooth adapter to advertise on (default: the system's default adapter)
+    #[arg(short, long, value_name = "NAME")]
+    adapter: Option<String>,
+
+    /// Local name included in the advertisement
+    #[arg(long)]
+    name: Option<String>,
+
+    /// How often the values are read again, in seconds
+    #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+    interval: u64,
+
+    /// Advertise a fixed value, e.g. battery=100, can be given multiple times
+    #[arg(long, value_name = "NAME=VALUE")]
+    value: Vec<String>,
+
+    /// Advertise the contents of a file, e.g. a sensor in /sys, can be given multiple times
+    #[arg(long, value_name = "NAME=PATH")]
+    file: Vec<String>,
+
+    /// Advertise the output of a shell command, can be given multiple times
+    #[arg(long, value_name = "NAME=COMMAND")]
+    command: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    measurement: Vec<MeasurementConfig>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with_writer(std::io::stderr)
+        .init();
+    let args = Args::parse();
+
+    let mut measurements = Vec::new();
+    if let Some(path) = &args.config {
+        let config: Config = toml::from_str(&std::fs::read_to_string(path)?)?;
+        for measurement in config.measurement {
+            measurements.push(Measurement::try_from(measurement)?);
+        }
+    }
+    for spec in &args.value {
+        measurements.push(Measurement::parse(spec, Source::Fixed)?);
+    }
+    for spec in &args.file {
+        measurements.push(Measurement::parse(spec, |path| Source::File(path.into()))?);
+    }
+    for spec in &args.command {
+        measurements.push(Measurement::parse(spec, Source::Command)?);
+    }
+    if measurements.is_empty() {
+        return Err("Nothing to advertise, give measurements with --value, --file, --command or --config".into());
+    }
+
+    advertise(&args, &measurements).await
+}
+
+#[cfg(target_os = "linux")]
+async fn advertise(args: &Args, measurements: &[Measurement]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    use std::collections::BTreeMap;
+
+    let session = bluer::Session::new().await?;
+    let adapter = match &args.adapter {
+        Some(name) => session.adapter(name)?,
+        None => session.default_adapter().await?,
+    };
+    adapter.set_powered(true).await?;
+    info!(adapter = adapter.name(), "Advertising BTHome data");
+
+    let mut interval = tokio::time::interval(Duration::from_secs(args.interval.max(1)));
+    let mut packet_id: u8 = 0;
+    let mut advertised: Option<Vec<u8>> = None;
+    let mut handle = None;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            result = tokio::signal::ctrl_c() => {
+                result?;
+                info!("Stopping");
+                return Ok(());
+            }
+        }
+        let mut values = Vec::new();
+        for measurement in measurements {
+            match measurement.read().await {
+                Ok(value) => values.push((measurement.object, value)),
+                Err(err) => warn!(object = measurement.object, error = %err, "Error reading measurement"),
+            }
+        }
+        // The packet id only changes with the data, so that receivers can drop repeated packets
+        let mut data = measurement::encode(packet_id, &values)?;
+        if advertised.as_ref() == Some(&data) {
+            continue;
+        }
+        if advertised.is_some() {
+            packet_id = packet_id.wrapping_add(1);
+            data = measurement::encode(packet_id, &values)?;
+        }
+        info!(data = ?data, "Updating advertisement");
+        // The data of a registered advertisement can't be changed, so it is replaced
+        drop(handle.take());
+        let advertisement = bluer::adv::Advertisement {
+            advertisement_type: bluer::adv::Type::Broadcast,
+            service_data: BTreeMap::from([(bluer::Uuid::from_u128(bthome::BTHOME_UUID), data.clone())]),
+            local_name: args.name.clone(),
+            ..Default::default()
+        };
+        handle = Some(adapter.advertise(advertisement).await?);
+        advertised = Some(data);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn advertise(_args: &Args, _measurements: &[Measurement]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Err("Advertising is only supported on Linux with bluez".into())
+}
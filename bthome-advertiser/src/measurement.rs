@@ -0,0 +1,212 @@
+//! The values to advertise and where they come from: fixed values, files like the temperature
+//! sensors in `/sys` or the output of shell commands.
+
+use std::path::PathBuf;
+
+use bthome::{encode_service_data, Object, ObjectId, ObjectValue, ServiceData};
+use serde::Deserialize;
+
+/// Parses an object name like `temperature` or a raw object id like `0x45`.
+pub fn parse_object(name: &str) -> Result<u8, String> {
+    let id = match name.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).map_err(|_| format!("invalid object id {:?}", name))?,
+        None => ObjectId::from_name(name)
+            .map(|object| object as u8)
+            .ok_or_else(|| format!("unknown object {:?}, use one of {} or an id like 0x45", name, names()))?,
+    };
+    ObjectId::try_from(id).map_err(|_| format!("unknown object id {:?}", name))?;
+    Ok(id)
+}
+
+fn names() -> String {
+    ObjectId::names().collect::<Vec<_>>().join(", ")
+}
+
+/// Where the value of a measurement is read from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Fixed(String),
+    File(PathBuf),
+    Command(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    pub object: u8,
+    pub source: Source,
+    /// Factor the value read is multiplied with, e.g. 0.001 for millidegrees
+    pub scale: Option<f64>,
+}
+
+impl Measurement {
+    /// Parses `NAME=VALUE` as given on the command line, `make_source` turns the value into the
+    /// source of the measurement.
+    pub fn parse(spec: &str, make_source: fn(String) -> Source) -> Result<Measurement, String> {
+        let (name, value) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=VALUE, got {:?}", spec))?;
+        Ok(Measurement {
+            object: parse_object(name.trim())?,
+            source: make_source(value.to_string()),
+            scale: None,
+        })
+    }
+
+    /// Reads the current value.
+    pub async fn read(&self) -> Result<ObjectValue, String> {
+        let text = match &self.source {
+            Source::Fixed(value) => value.clone(),
+            Source::File(path) => tokio::fs::read_to_string(path)
+                .await
+                .map_err(|err| format!("error reading {}: {}", path.display(), err))?,
+            Source::Command(command) => {
+                let output = tokio::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .output()
+                    .await
+                    .map_err(|err| format!("error running {:?}: {}", command, err))?;
+                if !output.status.success() {
+                    return Err(format!("{:?} failed with {}", command, output.status));
+                }
+                String::from_utf8_lossy(&output.stdout).into_owned()
+            }
+        };
+        parse_value(&text, self.scale)
+    }
+}
+
+/// Parses a value, booleans are given as true/false, on/off or open/closed.
+pub fn parse_value(text: &str, scale: Option<f64>) -> Result<ObjectValue, String> {
+    let text = text.trim();
+    match text.to_ascii_lowercase().as_str() {
+        "true" | "on" | "open" => return Ok(ObjectValue::Bool(true)),
+        "false" | "off" | "closed" => return Ok(ObjectValue::Bool(false)),
+        _ => {}
+    }
+    if let Some(scale) = scale {
+        let value: f64 = text.parse().map_err(|_| format!("{:?} is not a number", text))?;
+        return Ok(ObjectValue::Float(value * scale));
+    }
+    if let Ok(value) = text.parse::<i64>() {
+        return Ok(ObjectValue::Int(value));
+    }
+    text.parse::<f64>()
+        .map(ObjectValue::Float)
+        .map_err(|_| format!("{:?} is neither a number nor a boolean", text))
+}
+
+/// A measurement in the configuration file:
+///
+/// ```toml
+/// [[measurement]]
+/// object = "temperature"
+/// file = "/sys/class/thermal/thermal_zone0/temp"
+/// scale = 0.001
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MeasurementConfig {
+    object: String,
+    value: Option<toml::Value>,
+    file: Option<PathBuf>,
+    command: Option<String>,
+    scale: Option<f64>,
+}
+
+impl TryFrom<MeasurementConfig> for Measurement {
+    type Error = String;
+
+    fn try_from(config: MeasurementConfig) -> Result<Self, Self::Error> {
+        let source = match (config.value, config.file, config.command) {
+            (Some(toml::Value::String(value)), None, None) => Source::Fixed(value),
+            (Some(value), None, None) => Source::Fixed(value.to_string()),
+            (None, Some(path), None) => Source::File(path),
+            (None, None, Some(command)) => Source::Command(command),
+            _ => {
+                return Err(format!(
+                    "measurement {:?} needs exactly one of value, file or command",
+                    config.object
+                ))
+            }
+        };
+        Ok(Measurement {
+            object: parse_object(&config.object)?,
+            source,
+            scale: config.scale,
+        })
+    }
+}
+
+/// Encodes the values as BTHome service data, the objects are ordered by id as required by the
+/// specification.
+pub fn encode(packet_id: u8, values: &[(u8, ObjectValue)]) -> Result<Vec<u8>, String> {
+    let mut values: Vec<&(u8, ObjectValue)> = values.iter().collect();
+    values.sort_by_key(|(object, _)| *object);
+    let mut objects = vec![Object::new(ObjectId::PacketId, ObjectValue::Int(packet_id as i64))];
+    for (object, value) in values {
+        let object_id = ObjectId::try_from(*object).map_err(|err| format!("{:?}", err))?;
+        let value = match value {
+            ObjectValue::Float(value) => ObjectValue::Float(*value),
+            ObjectValue::Int(value) => ObjectValue::Int(*value),
+            ObjectValue::UInt(value) => ObjectValue::UInt(*value),
+            ObjectValue::Bool(value) => ObjectValue::Bool(*value),
+            _ => return Err(format!("unsupported value {:?}", value)),
+        };
+        objects.push(Object::new(object_id, value));
+    }
+    let service_data = ServiceData {
+        encrypted: false,
+        trigger_based: false,
+        version: 2,
+        objects,
+    };
+    encode_service_data(&service_data).map_err(|err| format!("error encoding {:?}: {:?}", service_data, err))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_measurements() {
+        assert_eq!(
+            Measurement::parse("temperature=21.5", Source::Fixed),
+            Ok(Measurement {
+                object: 0x02,
+                source: Source::Fixed("21.5".to_string()),
+                scale: None,
+            })
+        );
+        assert_eq!(Measurement::parse("0x45=echo 21.5", Source::Command).unwrap().object, 0x45);
+        assert!(Measurement::parse("temperature", Source::Fixed).is_err());
+        assert!(Measurement::parse("loudness=3", Source::Fixed).is_err());
+        assert!(Measurement::parse("0xEE=3", Source::Fixed).is_err());
+    }
+
+    #[test]
+    fn parse_values() {
+        assert_eq!(parse_value("21.5\n", None), Ok(ObjectValue::Float(21.5)));
+        assert_eq!(parse_value("97", None), Ok(ObjectValue::Int(97)));
+        assert_eq!(parse_value("48312\n", Some(0.001)), Ok(ObjectValue::Float(48.312)));
+        assert_eq!(parse_value("Open", None), Ok(ObjectValue::Bool(true)));
+        assert!(parse_value("warm", None).is_err());
+    }
+
+    #[test]
+    fn encode_ordered_by_object_id() {
+        let values = [(0x03, ObjectValue::Float(50.55)), (0x02, ObjectValue::Float(25.0))];
+        assert_eq!(
+            encode(7, &values),
+            Ok(vec![0x40, 0x00, 0x07, 0x02, 0xC4, 0x09, 0x03, 0xBF, 0x13])
+        );
+    }
+
+    #[test]
+    fn measurement_from_config() {
+        let config: MeasurementConfig = toml::from_str("object = \"battery\"\nvalue = 100").unwrap();
+        assert_eq!(Measurement::try_from(config).unwrap().source, Source::Fixed("100".to_string()));
+        let config: MeasurementConfig = toml::from_str("object = \"battery\"\nvalue = 100\ncommand = \"true\"").unwrap();
+        assert!(Measurement::try_from(config).is_err());
+    }
+}
@@ -0,0 +1,356 @@
+//! The checks a payload goes through. Errors are violations of the BTHome specification that
+//! receivers reject or misinterpret, warnings are deviations from its recommendations.
+
+use std::collections::HashMap;
+
+use bthome::{
+    encode_service_data, hex, parse_service_data_with, DeviceInfo, Object, ObjectId, ObjectValue, ParseOptions,
+    ServiceData,
+};
+
+/// Service data that fits into a legacy advertisement next to the flags and the header of the
+/// service data, 31 - 3 - 4 bytes.
+const MAX_LEGACY_PAYLOAD: usize = 24;
+/// Encrypted payloads have at least one byte of ciphertext, the counter and the MIC.
+const MIN_ENCRYPTED_PAYLOAD: usize = 1 + 1 + 4 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Check {
+    /// Version and reserved bits of the device information byte
+    Header,
+    /// Length of encrypted payloads
+    Encryption,
+    /// The counter of encrypted payloads increases
+    Counter,
+    /// The objects parse
+    Parse,
+    /// The objects have the sizes of their ids, without trailing bytes
+    Size,
+    /// The objects are sorted by id
+    Order,
+    /// Encoding the parsed objects gives the payload again
+    RoundTrip,
+    /// The payload fits into a legacy advertisement
+    Length,
+    /// Packet ids are not reused and events come with one
+    PacketId,
+}
+
+pub const CHECKS: [Check; 9] = [
+    Check::Header,
+    Check::Encryption,
+    Check::Counter,
+    Check::Parse,
+    Check::Size,
+    Check::Order,
+    Check::RoundTrip,
+    Check::Length,
+    Check::PacketId,
+];
+
+impl Check {
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::Header => "header",
+            Check::Encryption => "encryption",
+            Check::Counter => "counter",
+            Check::Parse => "parse",
+            Check::Size => "size",
+            Check::Order => "order",
+            Check::RoundTrip => "round_trip",
+            Check::Length => "length",
+            Check::PacketId => "packet_id",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub check: Check,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    fn error(check: Check, message: String) -> Finding {
+        Finding {
+            check,
+            severity: Severity::Error,
+            message,
+        }
+    }
+
+    fn warning(check: Check, message: String) -> Finding {
+        Finding {
+            check,
+            severity: Severity::Warning,
+            message,
+        }
+    }
+}
+
+/// Checks payloads, remembering what is needed for the checks across packets of a device.
+#[derive(Default)]
+pub struct Checker {
+    /// The last encrypted payload and its counter by address
+    counters: HashMap<String, (Vec<u8>, u32)>,
+    /// The packet id and payload of the last packet with a packet id by address
+    packets: HashMap<String, (u8, Vec<u8>)>,
+}
+
+impl Checker {
+    /// The checks that were run on the payload and what they found.
+    pub fn check(&mut self, address: Option<&str>, payload: &[u8]) -> (Vec<Check>, Vec<Finding>) {
+        let mut run = vec![Check::Header, Check::Length];
+        let mut findings = Vec::new();
+        let Some(&header) = payload.first() else {
+            findings.push(Finding::error(Check::Header, "empty payload".to_string()));
+            return (run, findings);
+        };
+        let info = DeviceInfo::from(header);
+        if info.version != 2 {
+            findings.push(Finding::error(Check::Header, format!("version {}, expected 2", info.version)));
+        }
+        if info.reserved != 0 {
+            findings.push(Finding::error(
+                Check::Header,
+                format!("reserved bits set in device information 0x{:02x}", header),
+            ));
+        }
+        if payload.len() > MAX_LEGACY_PAYLOAD {
+            findings.push(Finding::warning(
+                Check::Length,
+                format!(
+                    "{} bytes don't fit a legacy advertisement with flags, at most {} do",
+                    payload.len(),
+                    MAX_LEGACY_PAYLOAD
+                ),
+            ));
+        }
+
+        if info.encrypted {
+            run.push(Check::Encryption);
+            findings.extend(self.check_encrypted(address, payload, &mut run));
+            return (run, findings);
+        }
+
+        run.push(Check::Parse);
+        let service_data = match parse(payload) {
+            Ok(service_data) => service_data,
+            Err(err) => {
+                findings.push(Finding::error(Check::Parse, err.to_string()));
+                return (run, findings);
+            }
+        };
+        run.extend([Check::Size, Check::Order, Check::RoundTrip, Check::PacketId]);
+        // Objects that don't encode are reported by the round trip
+        let layout = layout(payload).unwrap_or_default();
+        let sizes_match = match check_sizes(payload, &layout) {
+            Some(finding) if !layout.is_empty() || service_data.objects.is_empty() => {
+                findings.push(finding);
+                false
+            }
+            _ => true,
+        };
+        findings.extend(check_order(&layout));
+        // Trailing bytes would be reported twice
+        if sizes_match {
+            findings.extend(check_round_trip(payload, &service_data, &layout));
+        }
+        findings.extend(self.check_packet_id(address, payload, &service_data.objects));
+        (run, findings)
+    }
+
+    fn check_encrypted(&mut self, address: Option<&str>, payload: &[u8], run: &mut Vec<Check>) -> Vec<Finding> {
+        if payload.len() < MIN_ENCRYPTED_PAYLOAD {
+            let message = format!(
+                "{} bytes are too short for ciphertext, counter and MIC, at least {} are needed",
+                payload.len(),
+                MIN_ENCRYPTED_PAYLOAD
+            );
+            return vec![Finding::error(Check::Encryption, message)];
+        }
+        let Some(address) = address else {
+            return Vec::new();
+        };
+        run.push(Check::Counter);
+        let counter = payload.len() - 8;
+        let counter = u32::from_le_bytes(payload[counter..counter + 4].try_into().expect("4 bytes"));
+        let previous = self.counters.insert(address.to_string(), (payload.to_vec(), counter));
+        match previous {
+            // Scanners report the same advertisement several times
+            Some((previous, _)) if previous == payload => Vec::new(),
+            Some((_, previous)) if counter <= previous => vec![Finding::error(
+                Check::Counter,
+                format!("counter {} does not increase, the previous packet had {}", counter, previous),
+            )],
+            _ => Vec::new(),
+        }
+    }
+
+    fn check_packet_id(&mut self, address: Option<&str>, payload: &[u8], objects: &[Object]) -> Vec<Finding> {
+        let packet_id = objects.iter().find_map(|object| match (&object.object_id, &object.value) {
+            (ObjectId::PacketId, ObjectValue::Int(id)) => Some(*id as u8),
+            _ => None,
+        });
+        let has_events = objects
+            .iter()
+            .any(|object| matches!(object.object_id, ObjectId::Button | ObjectId::Dimmer));
+        let Some(packet_id) = packet_id else {
+            if has_events {
+                let message = "events without a packet id can't be told apart from repeated advertisements";
+                return vec![Finding::warning(Check::PacketId, message.to_string())];
+            }
+            return Vec::new();
+        };
+        let Some(address) = address else {
+            return Vec::new();
+        };
+        // Receivers drop a packet with the packet id of the previous one as a repetition
+        match self.packets.insert(address.to_string(), (packet_id, payload.to_vec())) {
+            Some((previous_id, previous)) if previous_id == packet_id && previous != payload => vec![Finding::error(
+                Check::PacketId,
+                format!("packet id {} is the same as of the previous packet {}", packet_id, hex::encode(&previous)),
+            )],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Walks the payload with the encoded size of every parsed object, the parser stops silently at
+/// an object that is cut short.
+fn check_sizes(payload: &[u8], layout: &[(u8, usize)]) -> Option<Finding> {
+    let offset = 1 + layout.iter().map(|(_, size)| size).sum::<usize>();
+    let rest = payload.get(offset..).filter(|rest| !rest.is_empty())?;
+    let message = match ObjectId::try_from(rest[0]) {
+        Ok(id) => format!("object {:?} (0x{:02x}) is cut short after {} bytes", id, rest[0], rest.len() - 1),
+        Err(_) => format!("{} bytes after the last object", rest.len()),
+    };
+    Some(Finding::error(Check::Size, message))
+}
+
+/// The specification asks for objects sorted by id, repeated ids are fine.
+fn check_order(layout: &[(u8, usize)]) -> Option<Finding> {
+    let pair = layout.windows(2).find(|pair| pair[0].0 > pair[1].0)?;
+    Some(Finding::warning(
+        Check::Order,
+        format!("object 0x{:02x} follows 0x{:02x}, objects should be sorted by id", pair[1].0, pair[0].0),
+    ))
+}
+
+fn check_round_trip(payload: &[u8], service_data: &ServiceData, layout: &[(u8, usize)]) -> Option<Finding> {
+    // The encoder sorts the objects by id, objects out of order are reported on their own
+    let mut objects = Vec::new();
+    let mut offset = 1;
+    for (_, size) in layout {
+        objects.extend(payload.get(offset..offset + size));
+        offset += size;
+    }
+    objects.sort_by_key(|object| object[0]);
+    let sorted = if objects.len() == layout.len() { objects.concat() } else { payload[1..].to_vec() };
+    match encode_service_data(service_data) {
+        // The header is checked on its own
+        Ok(encoded) if encoded[1..] == sorted[..] => None,
+        // E.g. binary sensors with values other than 0 and 1, or values the factor can't represent
+        Ok(encoded) => Some(Finding::warning(
+            Check::RoundTrip,
+            format!("encodes back as {}", hex::encode(&encoded)),
+        )),
+        Err(err) => Some(Finding::warning(Check::RoundTrip, format!("does not encode back: {:?}", err))),
+    }
+}
+
+/// Parses the payload even with a wrong version, which is reported as a header finding.
+fn parse(payload: &[u8]) -> Result<ServiceData, bthome::Error> {
+    let options = ParseOptions { lenient_version: true, ..Default::default() };
+    parse_service_data_with(payload, &options).map(|(service_data, _)| service_data)
+}
+
+/// The id and encoded size of every object of a payload that parses, if all objects encode.
+fn layout(payload: &[u8]) -> Option<Vec<(u8, usize)>> {
+    let service_data = parse(payload).ok()?;
+    service_data
+        .objects
+        .into_iter()
+        .map(|object| {
+            let single = ServiceData {
+                encrypted: false,
+                trigger_based: false,
+                version: 2,
+                objects: vec![object],
+            };
+            // The header, the id and the value
+            let bytes = encode_service_data(&single).ok()?;
+            Some((bytes[1], bytes.len() - 1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn findings(checker: &mut Checker, address: Option<&str>, payload: &[u8]) -> Vec<(Check, Severity)> {
+        let (_, findings) = checker.check(address, payload);
+        findings.into_iter().map(|finding| (finding.check, finding.severity)).collect()
+    }
+
+    #[test]
+    fn single_payloads() {
+        let mut checker = Checker::default();
+        let mut check = |payload: &[u8]| findings(&mut checker, None, payload);
+        assert_eq!(check(&[0x40, 0x01, 0x61, 0x02, 0xC4, 0x09]), vec![]);
+        assert_eq!(check(&[]), vec![(Check::Header, Severity::Error)]);
+        assert_eq!(check(&[0x20, 0x01, 0x61]), vec![(Check::Header, Severity::Error)]);
+        assert_eq!(check(&[0x42, 0x01, 0x61]), vec![(Check::Header, Severity::Error)]);
+        // Temperature cut short
+        assert_eq!(check(&[0x40, 0x01, 0x61, 0x02, 0xC4]), vec![(Check::Size, Severity::Error)]);
+        assert_eq!(check(&[0x40, 0x02, 0xC4, 0x09, 0x01, 0x61]), vec![(Check::Order, Severity::Warning)]);
+        assert_eq!(check(&[0x40, 0xFE, 0x01]), vec![(Check::Parse, Severity::Error)]);
+        assert_eq!(check(&[0x41, 0x01, 0x02]), vec![(Check::Encryption, Severity::Error)]);
+        assert_eq!(check(&[0x44, 0x3A, 0x01]), vec![(Check::PacketId, Severity::Warning)]);
+
+        let mut long = vec![0x40];
+        for _ in 0..12 {
+            long.extend([0x01, 0x61]);
+        }
+        assert_eq!(check(&long), vec![(Check::Length, Severity::Warning)]);
+    }
+
+    #[test]
+    fn across_packets() {
+        let mut checker = Checker::default();
+        let address = Some("A4:C1:38:12:34:56");
+        let mut check = |payload: &[u8]| findings(&mut checker, address, payload);
+        assert_eq!(check(&[0x44, 0x00, 0x01, 0x3A, 0x01]), vec![]);
+        assert_eq!(check(&[0x44, 0x00, 0x01, 0x3A, 0x01]), vec![]);
+        assert_eq!(check(&[0x44, 0x00, 0x01, 0x3A, 0x02]), vec![(Check::PacketId, Severity::Error)]);
+        assert_eq!(check(&[0x44, 0x00, 0x02, 0x3A, 0x02]), vec![]);
+
+        let encrypted = |counter: u32| {
+            let mut payload = vec![0x41, 0xA4, 0x72, 0x66];
+            payload.extend(counter.to_le_bytes());
+            payload.extend([0x56, 0x8E, 0x98, 0x17]);
+            payload
+        };
+        assert_eq!(check(&encrypted(5)), vec![]);
+        assert_eq!(check(&encrypted(5)), vec![]);
+        assert_eq!(check(&encrypted(6)), vec![]);
+        assert_eq!(check(&encrypted(4)), vec![(Check::Counter, Severity::Error)]);
+    }
+}
@@ -0,0 +1,79 @@
+//! The payloads to check, read line by line. Accepted are bare hex payloads, an address followed
+//! by the payload as written for `bthome-sniffer --stdin`, and JSON objects with `address` and
+//! `payload` or `data`, as output by `bthome-recorder replay` and the forwarding of the sniffer.
+
+use bthome::hex;
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// Where the payload came from, e.g. `capture.txt:12`
+    pub source: String,
+    /// Upper case and colon separated, payloads without one are checked without the checks
+    /// across packets of a device
+    pub address: Option<String>,
+    pub payload: Vec<u8>,
+}
+
+/// The address, if the line has one, and the payload.
+pub type Line = (Option<String>, Vec<u8>);
+
+/// Parses a line, blank lines and `#` comments are skipped.
+pub fn parse_line(line: &str) -> Option<Result<Line, String>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    if line.starts_with('{') {
+        return Some(parse_json(line));
+    }
+    let result = match line.split_once(char::is_whitespace) {
+        Some((address, payload)) if parse_address(address).is_some() => {
+            hex::decode(payload).map(|payload| (parse_address(address), payload))
+        }
+        _ => hex::decode(line).map(|payload| (None, payload)),
+    };
+    Some(result.ok_or_else(|| format!("invalid hex {:?}", line)))
+}
+
+fn parse_json(line: &str) -> Result<Line, String> {
+    let value: Value = serde_json::from_str(line).map_err(|err| format!("invalid JSON: {}", err))?;
+    let address = value["address"].as_str().and_then(parse_address);
+    let payload = value["payload"]
+        .as_str()
+        .or(value["data"].as_str())
+        .ok_or("no payload or data in JSON object")?;
+    let payload = hex::decode(payload).ok_or_else(|| format!("invalid hex {:?}", payload))?;
+    Ok((address, payload))
+}
+
+/// A MAC address like `a4:c1:38:12:34:56` in upper case.
+pub fn parse_address(text: &str) -> Option<String> {
+    let parts: Vec<&str> = text.split(':').collect();
+    let valid = parts.len() == 6 && parts.iter().all(|part| part.len() == 2 && part.chars().all(|c| c.is_ascii_hexdigit()));
+    valid.then(|| text.to_ascii_uppercase())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_lines() {
+        let address = Some("A4:C1:38:12:34:56".to_string());
+        assert_eq!(parse_line("40 02 c4 09"), Some(Ok((None, vec![0x40, 0x02, 0xC4, 0x09]))));
+        assert_eq!(parse_line("40:02:c4:09"), Some(Ok((None, vec![0x40, 0x02, 0xC4, 0x09]))));
+        assert_eq!(parse_line("a4:c1:38:12:34:56 4002c409"), Some(Ok((address.clone(), vec![0x40, 0x02, 0xC4, 0x09]))));
+        assert_eq!(
+            parse_line(r#"{"time": 1, "address": "A4:C1:38:12:34:56", "payload": "4002c409"}"#),
+            Some(Ok((address.clone(), vec![0x40, 0x02, 0xC4, 0x09])))
+        );
+        assert_eq!(
+            parse_line(r#"{"address": "A4:C1:38:12:34:56", "data": "400161"}"#),
+            Some(Ok((address, vec![0x40, 0x01, 0x61])))
+        );
+        assert_eq!(parse_line("  # firmware 1.2"), None);
+        assert!(parse_line("40 0").unwrap().is_err());
+        assert!(parse_line(r#"{"address": "A4:C1:38:12:34:56"}"#).unwrap().is_err());
+    }
+}
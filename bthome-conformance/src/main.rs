@@ -0,0 +1,185 @@
+//! Runs payloads from files or a live capture through checks against the BTHome specification
+//! and reports what fails, for firmware developers validating their implementation.
+
+use std::{
+    error::Error,
+    io::BufRead,
+    path::PathBuf,
+    process::ExitCode,
+    time::Duration,
+};
+
+use clap::Parser;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+mod check;
+mod input;
+mod report;
+
+use check::{Checker, Severity};
+use input::Sample;
+use report::{Format, Report};
+
+/// Check BTHome payloads for conformance with the specification.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Files with one payload per line, as hex, `ADDRESS HEX` or JSON with address and payload,
+    /// - for stdin, which is read if neither files nor --scan are given
+    paths: Vec<PathBuf>,
+
+    /// Check the advertisements received during this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    scan: Option<u64>,
+
+    /// Bluetooth adapter to scan with, e.g. hci1, instead of the default one
+    #[arg(long, requires = "scan")]
+    adapter: Option<String>,
+
+    /// Only check the advertisements of this device when scanning
+    #[arg(long, requires = "scan")]
+    address: Option<String>,
+
+    #[arg(long, value_enum, default_value_t)]
+    format: Format,
+
+    /// Fail on warnings too
+    #[arg(long)]
+    strict: bool,
+}
+
+pub struct Advertisement {
+    pub address: [u8; 6],
+    pub service_data: Vec<u8>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<ExitCode, Box<dyn Error + Send + Sync>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with_writer(std::io::stderr)
+        .init();
+    let args = Args::parse();
+
+    let mut checker = Checker::default();
+    let mut report = Report::default();
+    let mut paths = args.paths.clone();
+    if paths.is_empty() && args.scan.is_none() {
+        paths.push(PathBuf::from("-"));
+    }
+    for path in &paths {
+        let (name, lines): (String, Box<dyn BufRead>) = if path.as_os_str() == "-" {
+            ("stdin".to_string(), Box::new(std::io::stdin().lock()))
+        } else {
+            let file = std::fs::File::open(path).map_err(|err| format!("Error reading {}: {}", path.display(), err))?;
+            (path.display().to_string(), Box::new(std::io::BufReader::new(file)))
+        };
+        for (number, line) in lines.lines().enumerate() {
+            let line = line.map_err(|err| format!("Error reading {}: {}", name, err))?;
+            let source = format!("{}:{}", name, number + 1);
+            match input::parse_line(&line) {
+                Some(Ok((address, payload))) => {
+                    let sample = Sample {
+                        source,
+                        address,
+                        payload,
+                    };
+                    check(&mut checker, &mut report, &sample);
+                }
+                Some(Err(err)) => report.unreadable(source, err),
+                None => {}
+            }
+        }
+    }
+    if let Some(seconds) = args.scan {
+        let address = match &args.address {
+            Some(text) => Some(input::parse_address(text).ok_or_else(|| format!("Invalid address {}", text))?),
+            None => None,
+        };
+        for advertisement in capture(args.adapter.clone(), Duration::from_secs(seconds)).await? {
+            let sample = Sample {
+                source: "scan".to_string(),
+                address: Some(address_text(&advertisement.address)),
+                payload: advertisement.service_data,
+            };
+            if address.is_none() || sample.address == address {
+                check(&mut checker, &mut report, &sample);
+            }
+        }
+    }
+
+    println!("{}", report.render(args.format));
+    let failed = report.count(Severity::Error) > 0 || (args.strict && report.count(Severity::Warning) > 0);
+    Ok(if failed { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
+
+fn check(checker: &mut Checker, report: &mut Report, sample: &Sample) {
+    let (run, findings) = checker.check(sample.address.as_deref(), &sample.payload);
+    report.add(sample, &run, findings);
+}
+
+/// The advertisements received within `duration`, or until stopped.
+async fn capture(adapter: Option<String>, duration: Duration) -> Result<Vec<Advertisement>, Box<dyn Error + Send + Sync>> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let scanner = scan(adapter, tx);
+    tokio::pin!(scanner);
+    let deadline = tokio::time::sleep(duration);
+    tokio::pin!(deadline);
+    info!(seconds = duration.as_secs(), "Capturing advertisements");
+    let mut advertisements = Vec::new();
+    loop {
+        tokio::select! {
+            Some(advertisement) = rx.recv() => advertisements.push(advertisement),
+            result = &mut scanner => {
+                return match result {
+                    Ok(()) => Err("Scanning stopped unexpectedly".into()),
+                    Err(err) => Err(format!("Error scanning: {}", err).into()),
+                };
+            }
+            _ = &mut deadline => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    info!(received = advertisements.len(), "Stopped capturing");
+    Ok(advertisements)
+}
+
+fn address_text(address: &[u8; 6]) -> String {
+    address.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":")
+}
+
+/// Receives BTHome advertisements until the receiver is gone. Scanning is restarted when it stops,
+/// e.g. because bluetoothd restarted, so only failing to find the adapter is an error.
+#[cfg(target_os = "linux")]
+async fn scan(
+    adapter: Option<String>,
+    tx: tokio::sync::mpsc::UnboundedSender<Advertisement>,
+) -> Result<(), bluer::Error> {
+    use bthome_listener::Listener;
+    use futures::StreamExt;
+
+    let listener = match adapter {
+        Some(name) => Listener::named_adapter(&name).await?,
+        None => Listener::default_adapter().await?,
+    };
+    let mut advertisements = listener.advertisements();
+    while let Some(advertisement) = advertisements.next().await {
+        let advertisement = Advertisement {
+            address: advertisement.mac.0,
+            service_data: advertisement.service_data,
+        };
+        if tx.send(advertisement).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn scan(
+    _adapter: Option<String>,
+    _tx: tokio::sync::mpsc::UnboundedSender<Advertisement>,
+) -> Result<(), &'static str> {
+    Err("scanning is only supported on Linux with bluez")
+}
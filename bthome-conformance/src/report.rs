@@ -0,0 +1,175 @@
+//! The structured result of a run, as text for reading or JSON for CI pipelines.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use bthome::hex;
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use crate::{
+    check::{Check, Finding, Severity, CHECKS},
+    input::Sample,
+};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// The findings and a summary per check
+    #[default]
+    Text,
+    /// One JSON document with the summary, the checks and all findings
+    Json,
+}
+
+#[derive(Default)]
+pub struct Report {
+    payloads: u64,
+    /// Payloads with at least one error
+    failed: u64,
+    /// How often each check ran and failed
+    checks: BTreeMap<Check, (u64, u64)>,
+    findings: Vec<(Sample, Finding)>,
+    /// Lines that are not payloads, with the reason
+    unreadable: Vec<(String, String)>,
+}
+
+impl Report {
+    pub fn add(&mut self, sample: &Sample, run: &[Check], findings: Vec<Finding>) {
+        self.payloads += 1;
+        self.failed += findings.iter().any(|finding| finding.severity == Severity::Error) as u64;
+        for check in run {
+            let failed = findings.iter().any(|finding| finding.check == *check);
+            let (runs, failures) = self.checks.entry(*check).or_default();
+            *runs += 1;
+            *failures += failed as u64;
+        }
+        self.findings
+            .extend(findings.into_iter().map(|finding| (sample.clone(), finding)));
+    }
+
+    pub fn unreadable(&mut self, source: String, error: String) {
+        self.unreadable.push((source, error));
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings.iter().filter(|(_, finding)| finding.severity == severity).count()
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.to_text(),
+            Format::Json => serde_json::to_string_pretty(&self.to_json()).unwrap_or_default(),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let checks: serde_json::Map<String, Value> = CHECKS
+            .iter()
+            .map(|check| {
+                let (run, failed) = self.checks.get(check).copied().unwrap_or_default();
+                (check.name().to_string(), json!({"run": run, "failed": failed}))
+            })
+            .collect();
+        let findings: Vec<Value> = self
+            .findings
+            .iter()
+            .map(|(sample, finding)| {
+                json!({
+                    "source": sample.source,
+                    "address": sample.address,
+                    "payload": hex::encode(&sample.payload),
+                    "check": finding.check.name(),
+                    "severity": finding.severity.name(),
+                    "message": finding.message,
+                })
+            })
+            .collect();
+        let unreadable: Vec<Value> = self
+            .unreadable
+            .iter()
+            .map(|(source, error)| json!({"source": source, "error": error}))
+            .collect();
+        json!({
+            "summary": {
+                "payloads": self.payloads,
+                "passed": self.payloads - self.failed,
+                "failed": self.failed,
+                "errors": self.count(Severity::Error),
+                "warnings": self.count(Severity::Warning),
+                "unreadable": self.unreadable.len(),
+            },
+            "checks": checks,
+            "findings": findings,
+            "unreadable": unreadable,
+        })
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (sample, finding) in &self.findings {
+            let address = sample.address.as_deref().map(|address| address.to_string() + " ").unwrap_or_default();
+            let _ = writeln!(
+                text,
+                "{} {}{}: {} [{}] {}",
+                sample.source,
+                address,
+                hex::encode(&sample.payload),
+                finding.severity.name(),
+                finding.check.name(),
+                finding.message
+            );
+        }
+        for (source, error) in &self.unreadable {
+            let _ = writeln!(text, "{}: unreadable: {}", source, error);
+        }
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        for check in CHECKS {
+            let (run, failed) = self.checks.get(&check).copied().unwrap_or_default();
+            let _ = writeln!(text, "{:<12} {:>8} run {:>8} failed", check.name(), run, failed);
+        }
+        let _ = write!(
+            text,
+            "\n{} payloads, {} passed, {} failed, {} warnings",
+            self.payloads,
+            self.payloads - self.failed,
+            self.failed,
+            self.count(Severity::Warning)
+        );
+        text
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::check::Checker;
+
+    #[test]
+    fn report() {
+        let mut checker = Checker::default();
+        let mut report = Report::default();
+        for (line, payload) in [vec![0x40, 0x01, 0x61], vec![0x40, 0x01, 0x61, 0x02, 0xC4]].into_iter().enumerate() {
+            let sample = Sample {
+                source: format!("corpus.txt:{}", line + 1),
+                address: None,
+                payload,
+            };
+            let (run, findings) = checker.check(None, &sample.payload);
+            report.add(&sample, &run, findings);
+        }
+        report.unreadable("corpus.txt:3".to_string(), "invalid hex".to_string());
+
+        let json = report.to_json();
+        assert_eq!(json["summary"]["payloads"], 2);
+        assert_eq!(json["summary"]["failed"], 1);
+        assert_eq!(json["summary"]["unreadable"], 1);
+        assert_eq!(json["checks"]["size"], json!({"run": 2, "failed": 1}));
+        assert_eq!(json["checks"]["counter"], json!({"run": 0, "failed": 0}));
+        assert_eq!(json["findings"][0]["source"], "corpus.txt:2");
+        assert_eq!(json["findings"][0]["check"], "size");
+        assert!(report
+            .to_text()
+            .starts_with("corpus.txt:2 40016102c4: error [size] object Temperature4 (0x02) is cut short after 1 bytes\n"));
+    }
+}
@@ -0,0 +1,39 @@
+//! Parses arbitrary payloads with every parser, which must fail with an error instead of
+//! panicking, and checks that what parses encodes again, unless a value is outside of the range
+//! the object can hold, e.g. a battery of 200 %.
+
+#![no_main]
+
+use bthome_core::{
+    parse_encrypted_service_data, parse_service_data, parse_service_data_borrowed, parse_service_data_v1,
+    parse_service_data_with, Error, Object, ObjectValue, OnUnknown, ParseOptions, ServiceDataIter,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(service_data) = parse_service_data(data) {
+        match service_data.to_bytes() {
+            Ok(_) => {}
+            Err(Error::ValueOutOfRange { .. }) => assert!(!service_data.objects.iter().all(is_valid)),
+            Err(err) => panic!("{:?} doesn't encode: {}", service_data, err),
+        }
+    }
+    let options = ParseOptions { on_unknown: OnUnknown::Raw, strict_order: true, lenient_version: true };
+    let _ = parse_service_data_with(data, &options);
+    let _ = parse_service_data_borrowed(data);
+    let _ = parse_service_data_v1(data);
+    let _ = parse_encrypted_service_data(data, &[0; 16], &[0; 6]);
+    if let Ok(objects) = ServiceDataIter::new(data) {
+        objects.for_each(drop);
+    }
+});
+
+fn is_valid(object: &Object) -> bool {
+    let number = match object.value {
+        ObjectValue::Float(value) => value,
+        ObjectValue::Int(value) => value as f64,
+        ObjectValue::UInt(value) => value as f64,
+        _ => return true,
+    };
+    object.object_id.valid_range().is_none_or(|range| range.contains(&number))
+}
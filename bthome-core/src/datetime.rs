@@ -0,0 +1,48 @@
+//! Timestamp objects as [`chrono`] date times, with the `chrono` feature. The value stays the
+//! seconds since the Unix epoch, so that matching on [`ObjectValue`] doesn't depend on features.
+
+use chrono::{DateTime, Utc};
+
+use crate::{Error, Object, ObjectId, ObjectValue};
+
+impl Object {
+    /// A timestamp object for `time`, which is sent in whole seconds.
+    pub fn from_date_time(time: DateTime<Utc>) -> Result<Object, Error> {
+        let seconds = u64::try_from(time.timestamp()).map_err(|_| Error::ValueOutOfRange {
+            object: ObjectId::Timestamp,
+            value: time.timestamp() as f64,
+        })?;
+        Ok(Object::new(ObjectId::Timestamp, ObjectValue::UInt(seconds)))
+    }
+
+    /// The time of a timestamp object, `None` for other objects.
+    pub fn date_time(&self) -> Option<DateTime<Utc>> {
+        let seconds = match (self.object_id, &self.value) {
+            (ObjectId::Timestamp, ObjectValue::UInt(seconds)) => i64::try_from(*seconds).ok()?,
+            (ObjectId::Timestamp, ObjectValue::Int(seconds)) => *seconds,
+            _ => return None,
+        };
+        DateTime::from_timestamp(seconds, 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse_service_data;
+
+    #[test]
+    fn timestamps() {
+        let service_data = parse_service_data(&[0x40, 0x50, 0x00, 0xF1, 0x53, 0x65, 0x00, 0x00]).unwrap();
+        let time = service_data.objects[0].date_time().unwrap();
+        assert_eq!(time, DateTime::from_timestamp(1_700_000_000, 0).unwrap());
+        assert_eq!(Object::from_date_time(time), Ok(service_data.objects[0].clone()));
+
+        let before_epoch = DateTime::from_timestamp(-1, 0).unwrap();
+        assert_eq!(
+            Object::from_date_time(before_epoch),
+            Err(Error::ValueOutOfRange { object: ObjectId::Timestamp, value: before_epoch.timestamp() as f64 })
+        );
+        assert_eq!(Object::new(ObjectId::CountU32, ObjectValue::Int(0)).date_time(), None);
+    }
+}
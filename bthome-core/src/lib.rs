@@ -0,0 +1,2525 @@
+//! Parsing and encoding of BTHome service data without the standard library, so that it can be
+//! used in firmware. Host applications use the `bthome` crate, which adds std conveniences.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use core::ops::RangeInclusive;
+
+#[cfg(feature = "chrono")]
+mod datetime;
+mod measurement;
+#[cfg(feature = "serde")]
+mod serialization;
+
+pub use measurement::*;
+
+pub const BTHOME_UUID16: u16 = 0xFCD2;
+pub const BTHOME_UUID: u128 = 0x0000FCD2_0000_1000_8000_00805F9B34FB;
+/// The UUID of unencrypted BTHome v1 service data
+pub const BTHOME_V1_UUID16: u16 = 0x181C;
+/// The UUID of encrypted BTHome v1 service data
+pub const BTHOME_V1_ENCRYPTED_UUID16: u16 = 0x181E;
+/// The most objects parsed from one payload. Service data in an advertisement has at most 251
+/// bytes after the UUID and device information, and every object takes at least two.
+pub const MAX_OBJECTS: usize = 126;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The data ended in the middle of an object
+    UnexpectedEnd,
+    InvalidTextEncoding,
+    Encrypted,
+    InvalidObjectId(u8),
+    InvalidButtonEvent(u8),
+    InvalidDimmerEvent(u8),
+    /// The value has the wrong type for the object, e.g. text for a temperature
+    InvalidValue,
+    /// The value can't be represented by the object or is no valid measurement, like a humidity
+    /// above 100 %, see [`ObjectId::valid_range`]. The value of text and raw data is their length.
+    ValueOutOfRange { object: ObjectId, value: f64 },
+    /// The data is not encrypted, but decryption was asked for
+    NotEncrypted,
+    /// The MIC does not match, the key or address is wrong or the data was altered
+    DecryptionFailed,
+    /// A BTHome v1 object is longer or shorter than the object with its id
+    InvalidLength,
+    /// The service data UUID is none of BTHome
+    UnknownUuid(u16),
+    /// The object with this id follows one with a higher id, rejected with
+    /// [`ParseOptions::strict_order`]
+    OutOfOrder(u8),
+    /// The device information announces another version than 2, accepted with
+    /// [`ParseOptions::lenient_version`]
+    UnsupportedVersion(u8),
+    /// The data has more than [`MAX_OBJECTS`] objects, more than fit into an advertisement
+    TooManyObjects,
+    /// The buffer given to [`encode_service_data_into`] or the length given to
+    /// [`ServiceData::split_into_advertisements`] can't hold the encoded objects
+    BufferTooSmall,
+    /// Parsing an object failed, with where it starts in the service data, its index and the
+    /// objects before it, to locate where a firmware deviates from the specification
+    Object {
+        offset: usize,
+        index: usize,
+        parsed: Vec<Object>,
+        error: Box<Error>,
+    },
+}
+
+impl Error {
+    /// The error without the context of [`Error::Object`], to match on what went wrong.
+    pub fn kind(&self) -> &Error {
+        match self {
+            Error::Object { error, .. } => error.kind(),
+            error => error,
+        }
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::UnexpectedEnd => write!(f, "the data ended in the middle of an object"),
+            Error::InvalidTextEncoding => write!(f, "text is not valid UTF-8"),
+            Error::Encrypted => write!(f, "the data is encrypted"),
+            Error::InvalidObjectId(id) => write!(f, "unknown object id {:#04x}", id),
+            Error::InvalidButtonEvent(event) => write!(f, "unknown button event {:#04x}", event),
+            Error::InvalidDimmerEvent(event) => write!(f, "unknown dimmer event {:#04x}", event),
+            Error::InvalidValue => write!(f, "the value has the wrong type for the object"),
+            Error::ValueOutOfRange { object, value } => write!(f, "{} is out of range for {}", value, object.name()),
+            Error::NotEncrypted => write!(f, "the data is not encrypted"),
+            Error::DecryptionFailed => write!(f, "decryption failed, the key or address is wrong or the data was altered"),
+            Error::InvalidLength => write!(f, "the length does not match the object"),
+            Error::UnknownUuid(uuid) => write!(f, "unknown service data UUID {:#06x}", uuid),
+            Error::OutOfOrder(id) => write!(f, "object id {:#04x} follows a higher one", id),
+            Error::UnsupportedVersion(version) => write!(f, "unsupported BTHome version {}", version),
+            Error::TooManyObjects => write!(f, "more than {} objects", MAX_OBJECTS),
+            Error::BufferTooSmall => write!(f, "the buffer is too small for the encoded objects"),
+            Error::Object { offset, index, error, .. } => write!(f, "{} in object {} at byte {}", error, index, offset),
+        }
+    }
+}
+
+/// Formatted like with `Display`, the derive can't handle the nested error of [`Error::Object`].
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}", defmt::Display2Format(self))
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::Object { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[repr(C)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ButtonEvent {
+    None = 0x00,
+    Press = 0x01,
+    DoublePress = 0x02,
+    TriplePress = 0x03,
+    LongPress = 0x04,
+    LongDoublePress = 0x05,
+    LongTriplePress = 0x06,
+    HoldPress = 0x80,
+    /// Sent by Shelly BLU buttons while a button is held, outside of the specification
+    Hold = 0xFE,
+}
+
+impl TryFrom<u8> for ButtonEvent {
+    type Error = Error;
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            x if x == ButtonEvent::None as u8 => Ok(ButtonEvent::None),
+            x if x == ButtonEvent::Press as u8 => Ok(ButtonEvent::Press),
+            x if x == ButtonEvent::DoublePress as u8 => Ok(ButtonEvent::DoublePress),
+            x if x == ButtonEvent::TriplePress as u8 => Ok(ButtonEvent::TriplePress),
+            x if x == ButtonEvent::LongPress as u8 => Ok(ButtonEvent::LongPress),
+            x if x == ButtonEvent::LongDoublePress as u8 => Ok(ButtonEvent::LongDoublePress),
+            x if x == ButtonEvent::LongTriplePress as u8 => Ok(ButtonEvent::LongTriplePress),
+            x if x == ButtonEvent::HoldPress as u8 => Ok(ButtonEvent::HoldPress),
+            x if x == ButtonEvent::Hold as u8 => Ok(ButtonEvent::Hold),
+            _ => Err(Error::InvalidButtonEvent(v)),
+        }
+    }
+}
+
+/// The name of the specification, e.g. `double_press`.
+impl core::fmt::Display for ButtonEvent {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            ButtonEvent::None => "none",
+            ButtonEvent::Press => "press",
+            ButtonEvent::DoublePress => "double_press",
+            ButtonEvent::TriplePress => "triple_press",
+            ButtonEvent::LongPress => "long_press",
+            ButtonEvent::LongDoublePress => "long_double_press",
+            ButtonEvent::LongTriplePress => "long_triple_press",
+            ButtonEvent::HoldPress => "hold_press",
+            ButtonEvent::Hold => "hold",
+        })
+    }
+}
+
+#[repr(C)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DimmerEvent {
+    None = 0x00,
+    RotateLeft = 0x01,
+    RotateRight = 0x02,
+}
+
+impl TryFrom<u8> for DimmerEvent {
+    type Error = Error;
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            x if x == DimmerEvent::None as u8 => Ok(DimmerEvent::None),
+            x if x == DimmerEvent::RotateLeft as u8 => Ok(DimmerEvent::RotateLeft),
+            x if x == DimmerEvent::RotateRight as u8 => Ok(DimmerEvent::RotateRight),
+            _ => Err(Error::InvalidDimmerEvent(v)),
+        }
+    }
+}
+
+/// The name of the specification, e.g. `rotate_left`.
+impl core::fmt::Display for DimmerEvent {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            DimmerEvent::None => "none",
+            DimmerEvent::RotateLeft => "rotate_left",
+            DimmerEvent::RotateRight => "rotate_right",
+        })
+    }
+}
+
+impl DimmerEvent {
+    /// The steps of the event as one signed number, negative when rotating left.
+    pub fn rotation(self, steps: u8) -> i16 {
+        match self {
+            DimmerEvent::None => 0,
+            DimmerEvent::RotateLeft => -i16::from(steps),
+            DimmerEvent::RotateRight => i16::from(steps),
+        }
+    }
+
+    /// The event and steps of a signed rotation, the inverse of [`DimmerEvent::rotation`]. Fails
+    /// with [`Error::ValueOutOfRange`] for more than 255 steps.
+    pub fn from_rotation(rotation: i16) -> Result<(DimmerEvent, u8), Error> {
+        let steps = u8::try_from(rotation.unsigned_abs()).map_err(|_| Error::ValueOutOfRange {
+            object: ObjectId::Dimmer,
+            value: rotation.into(),
+        })?;
+        let event = match rotation {
+            0 => DimmerEvent::None,
+            ..0 => DimmerEvent::RotateLeft,
+            _ => DimmerEvent::RotateRight,
+        };
+        Ok((event, steps))
+    }
+}
+
+/// The firmware version of a device, written like `4.2.1.0`. The short form of the object has no
+/// build number.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+    pub build: Option<u8>,
+}
+
+impl core::fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(build) = self.build {
+            write!(f, ".{}", build)?;
+        }
+        Ok(())
+    }
+}
+
+impl core::str::FromStr for FirmwareVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = [None; 4];
+        for (index, part) in s.split('.').enumerate() {
+            let slot = parts.get_mut(index).ok_or(Error::InvalidValue)?;
+            *slot = Some(part.parse::<u8>().map_err(|_| Error::InvalidValue)?);
+        }
+        match parts {
+            [Some(major), Some(minor), Some(patch), build] => Ok(FirmwareVersion { major, minor, patch, build }),
+            _ => Err(Error::InvalidValue),
+        }
+    }
+}
+
+/// The kind of device, from the registry of device type ids of the BTHome specification.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DeviceType {
+    ShellyBluButton1,
+    ShellyBluDoorWindow,
+    ShellyBluHt,
+    ShellyBluMotion,
+    ShellyBluWallSwitch4,
+    ShellyBluRcButton4,
+    /// An id not in the registry yet
+    Unknown(u16),
+}
+
+/// The registered device types with their ids and product names.
+const DEVICE_TYPES: &[(u16, DeviceType, &str)] = &[
+    (0x0001, DeviceType::ShellyBluButton1, "Shelly BLU Button1"),
+    (0x0002, DeviceType::ShellyBluDoorWindow, "Shelly BLU Door/Window"),
+    (0x0003, DeviceType::ShellyBluHt, "Shelly BLU H&T"),
+    (0x0005, DeviceType::ShellyBluMotion, "Shelly BLU Motion"),
+    (0x0006, DeviceType::ShellyBluWallSwitch4, "Shelly BLU Wall Switch 4"),
+    (0x0007, DeviceType::ShellyBluRcButton4, "Shelly BLU RC Button 4"),
+];
+
+impl DeviceType {
+    /// The id sent in the device type id object.
+    pub fn id(self) -> u16 {
+        match self {
+            DeviceType::Unknown(id) => id,
+            known => DEVICE_TYPES.iter().find(|(_, device_type, _)| *device_type == known).map_or(0, |(id, ..)| *id),
+        }
+    }
+
+    /// The product name, e.g. `Shelly BLU Button1`, `None` for unknown ids.
+    pub fn name(self) -> Option<&'static str> {
+        DEVICE_TYPES.iter().find(|(_, device_type, _)| *device_type == self).map(|(.., name)| *name)
+    }
+}
+
+impl From<u16> for DeviceType {
+    fn from(id: u16) -> Self {
+        DEVICE_TYPES
+            .iter()
+            .find(|(known, ..)| *known == id)
+            .map_or(DeviceType::Unknown(id), |(_, device_type, _)| *device_type)
+    }
+}
+
+impl core::fmt::Display for DeviceType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "unknown device type {:#06x}", self.id()),
+        }
+    }
+}
+
+/// The value of the integers read by the codecs, those read as `u64`, uint48 and uint64, are
+/// unsigned as they don't fit into `Int`.
+trait IntValue {
+    fn value(self) -> ObjectValueRef<'static>;
+}
+
+macro_rules! int_values {
+    ($($type:ty),*) => {
+        $(impl IntValue for $type {
+            fn value(self) -> ObjectValueRef<'static> {
+                ObjectValueRef::Int(self.into())
+            }
+        })*
+    };
+}
+
+int_values!(u8, i8, u16, i16, u32, i32);
+
+impl IntValue for u64 {
+    fn value(self) -> ObjectValueRef<'static> {
+        ObjectValueRef::UInt(self)
+    }
+}
+
+macro_rules! value_codecs {
+    ($(($bttype:ident, $rtype:ident, $rsize:literal$(, $btsize:literal)?),)*) => {
+
+        #[allow(dead_code)]
+        mod float_from {
+            use crate::{Reader, ObjectValueRef, Error};
+            $(pub(crate) fn $bttype(data: &mut Reader, factor: f64) -> Result<ObjectValueRef<'static>, Error> {
+                let mut bytes = [0u8; $rsize];
+                data.read_exact(&mut bytes$([..$btsize])?)?;
+                Ok(ObjectValueRef::Float(crate::scale($rtype::from_le_bytes(bytes) as f64, factor)))
+            })*
+        }
+        
+        #[allow(dead_code)]
+        mod int_from {
+            use crate::{Reader, ObjectValueRef, Error};
+            $(pub(crate) fn $bttype(data: &mut Reader) -> Result<ObjectValueRef<'static>, Error> {
+                let mut bytes = [0u8; $rsize];
+                data.read_exact(&mut bytes$([..$btsize])?)?;
+                Ok(crate::IntValue::value($rtype::from_le_bytes(bytes)))
+            })*
+        }
+
+        /// Writers mirroring the readers above, named like them so that the object table can
+        /// refer to both.
+        mod encode {
+            #[allow(dead_code)]
+            pub(crate) mod float_from {
+                use crate::{ObjectId, ObjectValueRef, Error, Output};
+                $(pub(crate) fn $bttype(
+                    object: ObjectId,
+                    value: &ObjectValueRef,
+                    out: &mut impl Output,
+                    factor: f64,
+                ) -> Result<(), Error> {
+                    let value = match value {
+                        ObjectValueRef::Float(value) => *value,
+                        ObjectValueRef::Int(value) => *value as f64,
+                        ObjectValueRef::UInt(value) => *value as f64,
+                        _ => return Err(Error::InvalidValue),
+                    };
+                    let raw = crate::round(value / factor);
+                    if !raw.is_finite() || raw < i64::MIN as f64 || raw > i64::MAX as f64 {
+                        return Err(Error::ValueOutOfRange { object, value });
+                    }
+                    crate::write_int(object, value, raw as i128, $rsize $(- $rsize + $btsize)?, $rtype::MIN != 0, out)
+                })*
+            }
+
+            #[allow(dead_code)]
+            pub(crate) mod int_from {
+                use crate::{ObjectId, ObjectValueRef, Error, Output};
+                $(pub(crate) fn $bttype(object: ObjectId, value: &ObjectValueRef, out: &mut impl Output) -> Result<(), Error> {
+                    let size = $rsize $(- $rsize + $btsize)?;
+                    match value {
+                        ObjectValueRef::Int(value) => {
+                            crate::write_int(object, *value as f64, *value as i128, size, $rtype::MIN != 0, out)
+                        }
+                        ObjectValueRef::UInt(value) => {
+                            crate::write_int(object, *value as f64, *value as i128, size, $rtype::MIN != 0, out)
+                        }
+                        _ => Err(Error::InvalidValue),
+                    }
+                })*
+            }
+
+            pub(crate) use crate::{
+                write_bool as read_bool, write_bytes as read_bytes, write_text as read_text,
+                write_button_event as read_button_event, write_dimmer_event as read_dimmer_event,
+                write_firmware_version as read_firmware_version, write_device_type as read_device_type,
+            };
+        }
+
+        /// The values the writers above can encode, `None` for values that aren't numbers.
+        mod range {
+            use core::ops::RangeInclusive;
+
+            #[allow(dead_code)]
+            pub(crate) mod float_from {
+                use core::ops::RangeInclusive;
+                $(pub(crate) fn $bttype(factor: f64) -> Option<RangeInclusive<f64>> {
+                    let (min, max) = crate::int_range($rsize $(- $rsize + $btsize)?, $rtype::MIN != 0);
+                    Some(crate::scale(min as f64, factor)..=crate::scale(max as f64, factor))
+                })*
+            }
+
+            #[allow(dead_code)]
+            pub(crate) mod int_from {
+                use core::ops::RangeInclusive;
+                $(pub(crate) fn $bttype() -> Option<RangeInclusive<f64>> {
+                    let (min, max) = crate::int_range($rsize $(- $rsize + $btsize)?, $rtype::MIN != 0);
+                    Some(min as f64..=max as f64)
+                })*
+            }
+
+            pub(crate) fn read_firmware_version(_len: usize) -> Option<RangeInclusive<f64>> {
+                None
+            }
+
+            pub(crate) use crate::no_range as read_bool;
+            pub(crate) use crate::no_range as read_bytes;
+            pub(crate) use crate::no_range as read_text;
+            pub(crate) use crate::no_range as read_button_event;
+            pub(crate) use crate::no_range as read_dimmer_event;
+            pub(crate) use crate::no_range as read_device_type;
+        }
+    };
+}
+
+value_codecs! {
+    (uint8, u8, 1),
+    (sint8, i8, 1),
+    (uint16, u16, 2),
+    (sint16, i16, 2),
+    (uint24, u32, 4, 3),
+    (sint24, i32, 4, 3),
+    (uint32, u32, 4),
+    (sint32, i32, 4),
+    (uint48, u64, 8, 6),
+    (uint64, u64, 8),
+}
+
+/// Reads from a slice, like `std::io::Cursor` but without the standard library.
+#[derive(Debug, Clone)]
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        if self.data.len() < buf.len() {
+            return Err(Error::UnexpectedEnd);
+        }
+        let (head, rest) = self.data.split_at(buf.len());
+        buf.copy_from_slice(head);
+        self.data = rest;
+        Ok(())
+    }
+
+    /// The next `len` bytes, borrowed from the data.
+    fn read_slice(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            return Err(Error::UnexpectedEnd);
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+}
+
+/// Reads 0 as false and 1 as true, other values are neither and rejected.
+fn read_bool(data: &mut Reader) -> Result<ObjectValueRef<'static>, Error> {
+    let mut bytes = [0u8; 1];
+    data.read_exact(&mut bytes)?;
+    match bytes[0] {
+        0 => Ok(ObjectValueRef::Bool(false)),
+        1 => Ok(ObjectValueRef::Bool(true)),
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+fn read_bytes<'a>(data: &mut Reader<'a>) -> Result<ObjectValueRef<'a>, Error> {
+    let mut size = [0u8; 1];
+    data.read_exact(&mut size)?;
+    Ok(ObjectValueRef::Raw(data.read_slice(size[0] as usize)?))
+}
+
+fn read_text<'a>(data: &mut Reader<'a>) -> Result<ObjectValueRef<'a>, Error> {
+    let mut size = [0u8; 1];
+    data.read_exact(&mut size)?;
+    let bytes = data.read_slice(size[0] as usize)?;
+    Ok(ObjectValueRef::Text(
+        core::str::from_utf8(bytes).map_err(|_| Error::InvalidTextEncoding)?,
+    ))
+}
+
+fn read_button_event(data: &mut Reader) -> Result<ObjectValueRef<'static>, Error> {
+    let mut bytes = [0u8; 1];
+    data.read_exact(&mut bytes)?;
+    Ok(ObjectValueRef::ButtonEvent(ButtonEvent::try_from(bytes[0])?))
+}
+
+fn read_dimmer_event(data: &mut Reader) -> Result<ObjectValueRef<'static>, Error> {
+    let mut bytes = [0u8; 2];
+    data.read_exact(&mut bytes)?;
+    Ok(ObjectValueRef::DimmerEvent(DimmerEvent::try_from(bytes[0])?, bytes[1]))
+}
+
+fn read_device_type(data: &mut Reader) -> Result<ObjectValueRef<'static>, Error> {
+    let mut bytes = [0u8; 2];
+    data.read_exact(&mut bytes)?;
+    Ok(ObjectValueRef::DeviceType(DeviceType::from(u16::from_le_bytes(bytes))))
+}
+
+/// Reads a firmware version of `len` bytes, 4 with a build number or 3 without, which are sent
+/// starting with the least significant part.
+fn read_firmware_version(data: &mut Reader, len: usize) -> Result<ObjectValueRef<'static>, Error> {
+    let bytes = data.read_slice(len)?;
+    let (build, bytes) = match bytes {
+        [build, rest @ ..] if len == 4 => (Some(*build), rest),
+        bytes => (None, bytes),
+    };
+    Ok(ObjectValueRef::FirmwareVersion(FirmwareVersion {
+        patch: bytes[0],
+        minor: bytes[1],
+        major: bytes[2],
+        build,
+    }))
+}
+
+/// The value of `raw` with the `factor` of its object. Dividing by the power of ten of a factor
+/// like 0.001 gives the float closest to the decimal value, e.g. 50.55 and not 50.550000000000004.
+fn scale(raw: f64, factor: f64) -> f64 {
+    let divisor = round(1.0 / factor);
+    if factor < 1.0 && divisor * factor == 1.0 {
+        raw / divisor
+    } else {
+        raw * factor
+    }
+}
+
+/// Rounds half away from zero like `f64::round`, which is not available without the standard
+/// library.
+fn round(value: f64) -> f64 {
+    // Beyond 2^52 every float is an integer
+    if !value.is_finite() || !(-4503599627370496.0..4503599627370496.0).contains(&value) {
+        return value;
+    }
+    let truncated = value as i64 as f64;
+    let fraction = value - truncated;
+    if fraction >= 0.5 {
+        truncated + 1.0
+    } else if fraction <= -0.5 {
+        truncated - 1.0
+    } else {
+        truncated
+    }
+}
+
+/// Where encoded objects are written to, a `Vec` or a fixed buffer to encode without allocating.
+trait Output {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), Error>;
+}
+
+impl Output for Vec<u8> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// A buffer filled from the start, see [`encode_service_data_into`].
+struct SliceOutput<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Output for SliceOutput<'_> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.len + bytes.len();
+        self.buf.get_mut(self.len..end).ok_or(Error::BufferTooSmall)?.copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// The smallest and largest integer of `size` bytes.
+fn int_range(size: usize, signed: bool) -> (i128, i128) {
+    let bits = 8 * size as u32;
+    if signed {
+        (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1)
+    } else {
+        (0, (1i128 << bits) - 1)
+    }
+}
+
+fn no_range() -> Option<core::ops::RangeInclusive<f64>> {
+    None
+}
+
+/// Writes the lowest `size` bytes of `raw`, which has to fit into them, `value` is the value of
+/// the object reported otherwise.
+fn write_int(object: ObjectId, value: f64, raw: i128, size: usize, signed: bool, out: &mut impl Output) -> Result<(), Error> {
+    let (min, max) = int_range(size, signed);
+    if !(min..=max).contains(&raw) {
+        return Err(Error::ValueOutOfRange { object, value });
+    }
+    out.put(&raw.to_le_bytes()[..size])
+}
+
+fn write_bool(_: ObjectId, value: &ObjectValueRef, out: &mut impl Output) -> Result<(), Error> {
+    match value {
+        ObjectValueRef::Bool(value) => out.put(&[*value as u8]),
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+fn write_length_prefixed(object: ObjectId, bytes: &[u8], out: &mut impl Output) -> Result<(), Error> {
+    let size = u8::try_from(bytes.len()).map_err(|_| Error::ValueOutOfRange { object, value: bytes.len() as f64 })?;
+    out.put(&[size])?;
+    out.put(bytes)
+}
+
+fn write_bytes(object: ObjectId, value: &ObjectValueRef, out: &mut impl Output) -> Result<(), Error> {
+    match value {
+        ObjectValueRef::Raw(bytes) => write_length_prefixed(object, bytes, out),
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+fn write_text(object: ObjectId, value: &ObjectValueRef, out: &mut impl Output) -> Result<(), Error> {
+    match value {
+        ObjectValueRef::Text(text) => write_length_prefixed(object, text.as_bytes(), out),
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+fn write_button_event(_: ObjectId, value: &ObjectValueRef, out: &mut impl Output) -> Result<(), Error> {
+    match value {
+        ObjectValueRef::ButtonEvent(event) => out.put(&[*event as u8]),
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+fn write_device_type(_: ObjectId, value: &ObjectValueRef, out: &mut impl Output) -> Result<(), Error> {
+    match value {
+        ObjectValueRef::DeviceType(device_type) => out.put(&device_type.id().to_le_bytes()),
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+fn write_firmware_version(object: ObjectId, value: &ObjectValueRef, out: &mut impl Output, len: usize) -> Result<(), Error> {
+    let ObjectValueRef::FirmwareVersion(version) = value else {
+        return Err(Error::InvalidValue);
+    };
+    match (len, version.build) {
+        (4, build) => out.put(&[build.unwrap_or(0)])?,
+        (_, None) => {}
+        // The short form has no room for the build number
+        (_, Some(build)) => return Err(Error::ValueOutOfRange { object, value: build.into() }),
+    }
+    out.put(&[version.patch, version.minor, version.major])
+}
+
+fn write_dimmer_event(_: ObjectId, value: &ObjectValueRef, out: &mut impl Output) -> Result<(), Error> {
+    match value {
+        ObjectValueRef::DimmerEvent(event, steps) => out.put(&[*event as u8, *steps]),
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+// Inspired by https://stackoverflow.com/questions/28028854/how-do-i-match-enum-values-with-an-integer
+macro_rules! bthome_objects {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident {
+        $($(#[$vmeta:meta])* $vname:ident($val:literal, $($conv:ident)::+$(, $args:literal)?),)*
+    }) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $($(#[$vmeta])* $vname = $val,)*
+        }
+
+        impl TryFrom<u8> for $name {
+            type Error = Error;
+
+            fn try_from(v: u8) -> Result<Self, Self::Error> {
+                match v {
+                    $(x if x == $name::$vname as u8 => Ok($name::$vname),)*
+                    _ => Err(Error::InvalidObjectId(v)),
+                }
+            }
+        }
+
+        fn value_from_raw<'a>(
+            object_id: $name,
+            data: &mut Reader<'a>,
+        ) -> Result<ObjectRef<'a>, Error> {
+            let value = match object_id {
+                $($name::$vname => $($conv)::+(data$(, $args)*)?,)*
+            };
+            Ok(ObjectRef {
+                object_id,
+                value,
+                instance: 0,
+            })
+        }
+
+        /// The values the encoding of `object_id` can hold, see [`ObjectId::valid_range`].
+        fn raw_range(object_id: $name) -> Option<core::ops::RangeInclusive<f64>> {
+            match object_id {
+                $($name::$vname => range::$($conv)::+($($args)*),)*
+            }
+        }
+
+        fn value_to_raw(object_id: $name, value: &ObjectValueRef, out: &mut impl Output) -> Result<(), Error> {
+            // The encoding itself is checked when writing, after rounding to the factor
+            if let (Some((min, max)), Some(number)) = (object_id.limits(), number(value)) {
+                if !(min..=max).contains(&number) {
+                    return Err(Error::ValueOutOfRange { object: object_id, value: number });
+                }
+            }
+            match object_id {
+                $($name::$vname => {
+                    out.put(&[$val])?;
+                    encode::$($conv)::+(object_id, value, out$(, $args)*)
+                })*
+            }
+        }
+    }
+}
+
+bthome_objects! {
+#[repr(u8)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ObjectId {
+    /* Sensor data */
+    /// Unit: m/s² type: uint16 factor: 0.001
+    Acceleration(0x51, float_from::uint16, 0.001),
+    /// Unit: % type: uint8
+    Battery(0x01, int_from::uint8),
+    /// Unit: ppm type: uint16
+    CO2(0x12, int_from::uint16),
+    /// Unit: µS/cm type: uint16
+    Conductivity(0x56, int_from::uint16),
+    /// type: uint8
+    CountU8(0x09, int_from::uint8),
+    /// type: uint16
+    CountU16(0x3D, int_from::uint16),
+    /// type: uint32
+    CountU32(0x3E, int_from::uint32),
+    /// type: sint8
+    CountI8(0x59, int_from::sint8),
+    /// type: sint16
+    CountI16(0x5A, int_from::sint16),
+    /// type: sint32
+    CountI32(0x5B, int_from::sint32),
+    /// Unit: A type: uint16 factor: 0.001
+    CurrentU16(0x43, float_from::uint16 , 0.001),
+    /// Unit: A type: sint16 factor: 0.001
+    CurrentI16(0x5D, float_from::sint16 , 0.001),
+    /// type: uint8
+    Channel(0x60, int_from::uint8),
+    /// Unit: °C type: sint16 factor: 0.01
+    Dewpoint(0x08, float_from::sint16 , 0.01),
+    /// Unit: mm type: uint16
+    DistanceMM(0x40, int_from::uint16),
+    /// Unit: m type: uint16 factor: 0.1
+    DistanceM(0x41, float_from::uint16 , 0.1),
+    /// Unit: ° type: uint16 factor: 0.01
+    Direction(0x5E, float_from::uint16 , 0.01),
+    /// Unit: s type: uint24 factor: 0.001
+    Duration(0x42, float_from::uint24 , 0.001),
+    /// Unit: kWh type: uint32 factor: 0.001
+    EnergyU32(0x4D, float_from::uint32 , 0.001),
+    /// Unit: kWh type: uint24 factor: 0.001
+    EngergyU24(0x0A, float_from::uint24 , 0.001),
+    /// Unit: m³ type: uint24 factor: 0.001
+    GasU24(0x4B, float_from::uint24 , 0.001),
+    /// Unit: m³ type: uint32 factor: 0.001
+    GasU32(0x4C, float_from::uint32 , 0.001),
+    /// Unit: °/s type: uint16 factor: 0.001
+    Gyroscope(0x52, float_from::uint16 , 0.001),
+    /// Unit: % type: uint16 factor: 0.01
+    HumidityU16(0x03, float_from::uint16 , 0.01),
+    /// Unit: % type: uint8
+    HumidityU8(0x2E, int_from::uint8),
+    /// Unit: lux type: uint24 factor: 0.01
+    Illuminance(0x05, float_from::uint24 , 0.01),
+    /// Unit: kg type: uint16 factor: 0.01
+    MassKg(0x06, float_from::uint16 , 0.01),
+    /// Unit: lb type: uint16 factor: 0.01
+    MassLb(0x07, float_from::uint16 , 0.01),
+    /// Unit: % type: uint16 factor: 0.01
+    MoistureSmall(0x14, float_from::uint16 , 0.01),
+    /// Unit: % type: uint8
+    MoistureLarge(0x2F, int_from::uint8),
+    /// Unit: µg/m³ type: uint16
+    PM2d5(0x0D, int_from::uint16),
+    /// Unit: µg/m³ type: uint16
+    PM10(0x0E, int_from::uint16),
+    /// Unit: W type: uint24 factor: 0.01
+    PowerSmall(0x0B, float_from::uint24 , 0.01),
+    /// Unit: W type: sint32 factor: 0.01
+    PowerLarge(0x5C, float_from::sint32 , 0.01),
+    /// Unit: mm type: uint16 factor: 0.1
+    Precipitation(0x5F, float_from::uint16 , 0.1),
+    /// Unit: hPa type: uint24 factor: 0.01
+    Pressure(0x04, float_from::uint24 , 0.01),
+    Raw(0x54, read_bytes),
+    /// Unit: ° type: sint16 factor: 0.1
+    Rotation(0x3F, float_from::sint16 , 0.1),
+    /// Unit: rpm type: uint16
+    RotationalSpeed(0x61, int_from::uint16),
+    /// Unit: m/s type: uint16 factor: 0.01
+    Speed(0x44, float_from::uint16, 0.01),
+    /// Unit: °C type: sint8
+    Temperature1(0x57, int_from::sint8),
+    /// Unit: °C type: sint8 factor: 0.35
+    Temperature2(0x58, float_from::sint8 , 0.35),
+    /// Unit: °C type: sint16 factor: 0.1
+    Temperature3(0x45, float_from::sint16 , 0.1),
+    /// Unit: °C type: sint16 factor: 0.01
+    Temperature4(0x02, float_from::sint16 , 0.01),
+    Text(0x53, read_text),
+    /// Unit: s type: uint48
+    Timestamp(0x50, int_from::uint48),
+    /// Unit: µg/m³ type: uint16
+    Tvoc(0x13, int_from::uint16),
+    /// Unit: V type: uint16 factor: 0.001
+    VoltageSmall(0x0C, float_from::uint16 , 0.001),
+    /// Unit: V type: uint16 factor: 0.1
+    VoltageLarge(0x4A, float_from::uint16 , 0.1),
+    /// Unit: L type: uint32 factor: 0.001
+    Volume1(0x4E, float_from::uint32 , 0.001),
+    /// Unit: L type: uint16 factor: 0.1
+    Volume2(0x47, float_from::uint16 , 0.1),
+    /// Unit: mL type: uint16
+    Volume3(0x48, int_from::uint16),
+    /// Unit: L type: uint32 factor: 0.001
+    VolumeStorage(0x55, float_from::uint32 , 0.001),
+    /// Unit: m³/h type: uint16 factor: 0.001
+    VolumeFlowRate(0x49, float_from::uint16 , 0.001),
+    /// type: uint8 factor: 0.1
+    UVIndex(0x46, float_from::uint8, 0.1),
+    /// Unit: L type: uint32 factor: 0.001
+    Water(0x4F, float_from::uint32 , 0.001),
+
+    /* Binary sensor data */
+    BatteryLow(0x15, read_bool),
+    BatteryCharging(0x16, read_bool),
+    CarbonMonoxideDetected(0x17, read_bool),
+    Cold(0x18, read_bool),
+    Connectivity(0x19, read_bool),
+    DoorOpen(0x1A, read_bool),
+    GarageDoorOpen(0x1B, read_bool),
+    GasDetected(0x1C, read_bool),
+    GenericBoolean(0x0F, read_bool),
+    Heat(0x1D, read_bool),
+    LightDetected(0x1E, read_bool),
+    LockUnlocked(0x1F, read_bool),
+    MoistureDetected(0x20, read_bool),
+    MotionDetected(0x21, read_bool),
+    MovementDetected(0x22, read_bool),
+    OccupancyDetected(0x23, read_bool),
+    IsOpen(0x11, read_bool),
+    PluggedIn(0x24, read_bool),
+    PowerOn(0x10, read_bool),
+    PresenceAtHome(0x25, read_bool),
+    ProblemDetected(0x26, read_bool),
+    IsRunning(0x27, read_bool),
+    IsSafe(0x28, read_bool),
+    SmokeDetected(0x29, read_bool),
+    SoundDetected(0x2A, read_bool),
+    TamperDetected(0x2B, read_bool),
+    VibrationDetected(0x2C, read_bool),
+    WindowOpen(0x2D, read_bool),
+
+    /* Events */
+    Button(0x3A, read_button_event),
+    Dimmer(0x3C, read_dimmer_event),
+
+    /* Device information */
+    /// type: uint16
+    DeviceTypeId(0xF0, read_device_type),
+    /// type: uint32, with build number
+    FirmwareVersionLarge(0xF1, read_firmware_version, 4),
+    /// type: uint24, without build number
+    FirmwareVersionSmall(0xF2, read_firmware_version, 3),
+
+    /* Misc data */
+    PacketId(0x00, int_from::uint8),
+}
+}
+
+/// Names of the objects as used by the BTHome specification, for objects sharing a name, like the
+/// temperatures with different precisions, the most precise one is used.
+const OBJECT_NAMES: &[(&str, u8)] = &[
+    ("packet_id", 0x00),
+    ("battery", 0x01),
+    ("temperature", 0x02),
+    ("humidity", 0x03),
+    ("pressure", 0x04),
+    ("illuminance", 0x05),
+    ("mass", 0x06),
+    ("dewpoint", 0x08),
+    ("energy", 0x0A),
+    ("power", 0x0B),
+    ("voltage", 0x0C),
+    ("pm2_5", 0x0D),
+    ("pm10", 0x0E),
+    ("generic_boolean", 0x0F),
+    ("power_on", 0x10),
+    ("opening", 0x11),
+    ("co2", 0x12),
+    ("tvoc", 0x13),
+    ("moisture", 0x14),
+    ("battery_low", 0x15),
+    ("battery_charging", 0x16),
+    ("carbon_monoxide", 0x17),
+    ("cold", 0x18),
+    ("connectivity", 0x19),
+    ("door", 0x1A),
+    ("garage_door", 0x1B),
+    ("gas_detected", 0x1C),
+    ("heat", 0x1D),
+    ("light", 0x1E),
+    ("lock", 0x1F),
+    ("moisture_detected", 0x20),
+    ("motion", 0x21),
+    ("moving", 0x22),
+    ("occupancy", 0x23),
+    ("plug", 0x24),
+    ("presence", 0x25),
+    ("problem", 0x26),
+    ("running", 0x27),
+    ("safety", 0x28),
+    ("smoke", 0x29),
+    ("sound", 0x2A),
+    ("tamper", 0x2B),
+    ("vibration", 0x2C),
+    ("window", 0x2D),
+    ("button", 0x3A),
+    ("dimmer", 0x3C),
+    ("count", 0x3E),
+    ("rotation", 0x3F),
+    ("distance", 0x40),
+    ("duration", 0x42),
+    ("current", 0x43),
+    ("speed", 0x44),
+    ("uv_index", 0x46),
+    ("volume_flow_rate", 0x49),
+    ("gas", 0x4C),
+    ("volume", 0x4E),
+    ("water", 0x4F),
+    ("timestamp", 0x50),
+    ("acceleration", 0x51),
+    ("gyroscope", 0x52),
+    ("text", 0x53),
+    ("raw", 0x54),
+    ("volume_storage", 0x55),
+    ("conductivity", 0x56),
+    ("direction", 0x5E),
+    ("precipitation", 0x5F),
+    ("channel", 0x60),
+    ("rotational_speed", 0x61),
+];
+
+/// Names of the objects missing from [`OBJECT_NAMES`], as they share their name with another
+/// object or are device information.
+const OBJECT_ALIASES: &[(&str, u8)] = &[
+    ("mass", 0x07),
+    ("count", 0x09),
+    ("humidity", 0x2E),
+    ("moisture", 0x2F),
+    ("count", 0x3D),
+    ("distance", 0x41),
+    ("temperature", 0x45),
+    ("volume", 0x47),
+    ("volume", 0x48),
+    ("voltage", 0x4A),
+    ("gas", 0x4B),
+    ("energy", 0x4D),
+    ("temperature", 0x57),
+    ("temperature", 0x58),
+    ("count", 0x59),
+    ("count", 0x5A),
+    ("count", 0x5B),
+    ("power", 0x5C),
+    ("current", 0x5D),
+    ("device_type_id", 0xF0),
+    ("firmware_version", 0xF1),
+    ("firmware_version", 0xF2),
+];
+
+impl ObjectId {
+    /// The name of the object in the BTHome specification, e.g. `temperature`.
+    pub fn name(self) -> &'static str {
+        OBJECT_NAMES
+            .iter()
+            .chain(OBJECT_ALIASES)
+            .find(|(_, id)| *id == self as u8)
+            .map_or("unknown", |(name, _)| *name)
+    }
+
+    /// The unit of the value, `None` for counts, binary sensors, events and the like.
+    pub fn unit(self) -> Option<&'static str> {
+        use ObjectId::*;
+
+        Some(match self {
+            Acceleration => "m/s²",
+            Battery | HumidityU16 | HumidityU8 | MoistureSmall | MoistureLarge => "%",
+            CO2 => "ppm",
+            Conductivity => "µS/cm",
+            CurrentU16 | CurrentI16 => "A",
+            Dewpoint | Temperature1 | Temperature2 | Temperature3 | Temperature4 => "°C",
+            DistanceMM => "mm",
+            DistanceM => "m",
+            Duration | Timestamp => "s",
+            EnergyU32 | EngergyU24 => "kWh",
+            GasU24 | GasU32 => "m³",
+            Gyroscope => "°/s",
+            Illuminance => "lx",
+            MassKg => "kg",
+            MassLb => "lb",
+            PM2d5 | PM10 | Tvoc => "µg/m³",
+            PowerSmall | PowerLarge => "W",
+            Precipitation => "mm",
+            Pressure => "hPa",
+            Rotation | Direction => "°",
+            RotationalSpeed => "rpm",
+            Speed => "m/s",
+            VoltageSmall | VoltageLarge => "V",
+            Volume1 | Volume2 | VolumeStorage | Water => "L",
+            Volume3 => "mL",
+            VolumeFlowRate => "m³/h",
+            _ => return None,
+        })
+    }
+
+    /// The values the object can hold, e.g. -327.68 to 327.67 for [`ObjectId::Temperature4`] and
+    /// 0 to 100 for a humidity, even though the encoding goes up to 655.35. `None` for objects that
+    /// aren't numbers. Encoding fails for other values, instead of wrapping around.
+    pub fn valid_range(self) -> Option<RangeInclusive<f64>> {
+        let range = raw_range(self)?;
+        Some(match self.limits() {
+            Some((min, max)) => range.start().max(min)..=range.end().min(max),
+            None => range,
+        })
+    }
+
+    /// The physical limits of the measurement, tighter than the encoding.
+    fn limits(self) -> Option<(f64, f64)> {
+        use ObjectId::*;
+
+        match self {
+            Battery | HumidityU16 | HumidityU8 | MoistureSmall | MoistureLarge => Some((0.0, 100.0)),
+            Direction => Some((0.0, 360.0)),
+            _ => None,
+        }
+    }
+
+    /// Looks up an object by its name in the BTHome specification, e.g. `temperature`.
+    pub fn from_name(name: &str) -> Option<ObjectId> {
+        let (_, id) = OBJECT_NAMES.iter().find(|(object, _)| *object == name)?;
+        ObjectId::try_from(*id).ok()
+    }
+
+    /// All names known to [`ObjectId::from_name`].
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        OBJECT_NAMES.iter().map(|(name, _)| *name)
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectValue {
+    Float(f64),
+    Int(i64),
+    /// Objects of 48 or 64 bit, like timestamps
+    UInt(u64),
+    Bool(bool),
+    Raw(Vec<u8>),
+    ButtonEvent(ButtonEvent),
+    DimmerEvent(DimmerEvent, u8),
+    Text(String),
+    FirmwareVersion(FirmwareVersion),
+    DeviceType(DeviceType),
+}
+
+/// A value borrowing text and raw bytes from the parsed data, see
+/// [`parse_service_data_borrowed`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectValueRef<'a> {
+    Float(f64),
+    Int(i64),
+    UInt(u64),
+    Bool(bool),
+    Raw(&'a [u8]),
+    ButtonEvent(ButtonEvent),
+    DimmerEvent(DimmerEvent, u8),
+    Text(&'a str),
+    FirmwareVersion(FirmwareVersion),
+    DeviceType(DeviceType),
+}
+
+impl<'a> From<&'a ObjectValue> for ObjectValueRef<'a> {
+    fn from(value: &'a ObjectValue) -> Self {
+        match value {
+            ObjectValue::Float(value) => ObjectValueRef::Float(*value),
+            ObjectValue::Int(value) => ObjectValueRef::Int(*value),
+            ObjectValue::UInt(value) => ObjectValueRef::UInt(*value),
+            ObjectValue::Bool(value) => ObjectValueRef::Bool(*value),
+            ObjectValue::Raw(bytes) => ObjectValueRef::Raw(bytes),
+            ObjectValue::ButtonEvent(event) => ObjectValueRef::ButtonEvent(*event),
+            ObjectValue::DimmerEvent(event, steps) => ObjectValueRef::DimmerEvent(*event, *steps),
+            ObjectValue::Text(text) => ObjectValueRef::Text(text),
+            ObjectValue::FirmwareVersion(version) => ObjectValueRef::FirmwareVersion(*version),
+            ObjectValue::DeviceType(device_type) => ObjectValueRef::DeviceType(*device_type),
+        }
+    }
+}
+
+impl From<ObjectValueRef<'_>> for ObjectValue {
+    fn from(value: ObjectValueRef<'_>) -> Self {
+        match value {
+            ObjectValueRef::Float(value) => ObjectValue::Float(value),
+            ObjectValueRef::Int(value) => ObjectValue::Int(value),
+            ObjectValueRef::UInt(value) => ObjectValue::UInt(value),
+            ObjectValueRef::Bool(value) => ObjectValue::Bool(value),
+            ObjectValueRef::Raw(bytes) => ObjectValue::Raw(bytes.to_vec()),
+            ObjectValueRef::ButtonEvent(event) => ObjectValue::ButtonEvent(event),
+            ObjectValueRef::DimmerEvent(event, steps) => ObjectValue::DimmerEvent(event, steps),
+            ObjectValueRef::Text(text) => ObjectValue::Text(String::from(text)),
+            ObjectValueRef::FirmwareVersion(version) => ObjectValue::FirmwareVersion(version),
+            ObjectValueRef::DeviceType(device_type) => ObjectValue::DeviceType(device_type),
+        }
+    }
+}
+
+/// The value without unit, e.g. `21.46`, `double_press` or `rotate_left by 3 steps`. Raw values are
+/// written as hex.
+impl core::fmt::Display for ObjectValueRef<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ObjectValueRef::Float(value) => write!(f, "{}", value),
+            ObjectValueRef::Int(value) => write!(f, "{}", value),
+            ObjectValueRef::UInt(value) => write!(f, "{}", value),
+            ObjectValueRef::Bool(value) => write!(f, "{}", value),
+            ObjectValueRef::Raw(bytes) => bytes.iter().try_for_each(|byte| write!(f, "{:02x}", byte)),
+            ObjectValueRef::ButtonEvent(event) => write!(f, "{}", event),
+            ObjectValueRef::DimmerEvent(event, steps) => write!(f, "{} by {} steps", event, steps),
+            ObjectValueRef::Text(text) => f.write_str(text),
+            ObjectValueRef::FirmwareVersion(version) => write!(f, "{}", version),
+            ObjectValueRef::DeviceType(device_type) => write!(f, "{}", device_type),
+        }
+    }
+}
+
+/// See [`ObjectValueRef`].
+impl core::fmt::Display for ObjectValue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        ObjectValueRef::from(self).fmt(f)
+    }
+}
+
+/// Serialized with `serde` as its name, id, unit and value, e.g.
+/// `{"name": "temperature", "id": 2, "unit": "°C", "value": 21.5}`.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    pub object_id: ObjectId,
+    pub value: ObjectValue,
+    /// How many objects with the same id come before it in the packet, e.g. 1 for the second of
+    /// two temperatures. They are displayed as `temperature` and `temperature_2`.
+    pub instance: u8,
+}
+
+impl Object {
+    /// An object that is the first one with its id.
+    pub fn new(object_id: ObjectId, value: ObjectValue) -> Object {
+        Object {
+            object_id,
+            value,
+            instance: 0,
+        }
+    }
+
+    /// The signed rotation of a dimmer event, see [`DimmerEvent::rotation`], `None` for other
+    /// objects.
+    pub fn rotation(&self) -> Option<i16> {
+        match self.value {
+            ObjectValue::DimmerEvent(event, steps) => Some(event.rotation(steps)),
+            _ => None,
+        }
+    }
+
+    fn read(data: &mut Reader) -> Result<Object, Error> {
+        ObjectRef::read(data).map(Object::from)
+    }
+
+    fn write(&self, out: &mut Vec<u8>) -> Result<(), Error> {
+        value_to_raw(self.object_id, &ObjectValueRef::from(&self.value), out)
+    }
+}
+
+/// The name, value and unit, e.g. `temperature: 21.46 °C`, and binary sensors with their state,
+/// e.g. `door: open`. Repeated objects are numbered from the second one on, e.g. `temperature_2`.
+impl core::fmt::Display for Object {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.object_id.name())?;
+        if self.instance > 0 {
+            write!(f, "_{}", u16::from(self.instance) + 1)?;
+        }
+        match self.binary_state() {
+            Some(state) => write!(f, ": {}", state)?,
+            None => write!(f, ": {}", self.value)?,
+        }
+        match (self.object_id.unit(), &self.value) {
+            (Some(unit), ObjectValue::Float(_) | ObjectValue::Int(_) | ObjectValue::UInt(_)) => write!(f, " {}", unit),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An object borrowing from the parsed data, serialized like [`Object`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObjectRef<'a> {
+    pub object_id: ObjectId,
+    pub value: ObjectValueRef<'a>,
+    /// See [`Object::instance`]
+    pub instance: u8,
+}
+
+impl<'a> ObjectRef<'a> {
+    /// An object that is the first one with its id, e.g. to encode it with
+    /// [`encode_service_data_into`].
+    pub fn new(object_id: ObjectId, value: ObjectValueRef<'a>) -> ObjectRef<'a> {
+        ObjectRef {
+            object_id,
+            value,
+            instance: 0,
+        }
+    }
+
+    /// An object for `value` of the measurement of `object_id`, e.g. a temperature of 21 °C as
+    /// [`ObjectId::Temperature1`] with [`Resolution::Best`]. Fails with [`Error::ValueOutOfRange`]
+    /// if no object can hold the value, instead of truncating it.
+    pub fn with_resolution(
+        object_id: ObjectId,
+        value: ObjectValueRef<'a>,
+        resolution: Resolution,
+    ) -> Result<ObjectRef<'a>, Error> {
+        let preferred = match resolution {
+            Resolution::Best => object_id,
+            Resolution::Exact(object_id) => object_id,
+        };
+        let others = (0..=u8::MAX).filter_map(|id| ObjectId::try_from(id).ok()).filter(|other| {
+            resolution == Resolution::Best
+                && *other != object_id
+                && other.name() == object_id.name()
+                && other.unit() == object_id.unit()
+        });
+        let mut best: Option<(f64, usize, ObjectRef<'a>)> = None;
+        let mut error = Error::InvalidValue;
+        // The given object comes first, so that it is kept when another one is just as good
+        for candidate in core::iter::once(preferred).chain(others) {
+            match encoded_deviation(candidate, value) {
+                Ok((deviation, size, object)) => {
+                    if best.as_ref().is_none_or(|(best_deviation, best_size, _)| {
+                        (deviation, size) < (*best_deviation, *best_size)
+                    }) {
+                        best = Some((deviation, size, object));
+                    }
+                }
+                // A value of the right type that doesn't fit is the more helpful error
+                Err(err) if !matches!(error, Error::ValueOutOfRange { .. }) => error = err,
+                Err(_) => {}
+            }
+        }
+        best.map(|(_, _, object)| object).ok_or(error)
+    }
+
+    fn read(data: &mut Reader<'a>) -> Result<ObjectRef<'a>, Error> {
+        let mut next_byte = [0u8];
+        data.read_exact(&mut next_byte)?;
+        let object_id = ObjectId::try_from(next_byte[0])?;
+        value_from_raw(object_id, data)
+    }
+}
+
+/// Which object a value is encoded with, where several objects like the four temperatures differ
+/// only in size and precision, see [`ObjectRef::with_resolution`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Resolution {
+    /// The smallest object with the same name and unit that holds the value exactly, or the one
+    /// coming closest if none does
+    #[default]
+    Best,
+    /// Only this object, the value is rounded to its precision
+    Exact(ObjectId),
+}
+
+/// How far `value` is off when encoded with `object_id`, with the encoded size and the object.
+/// Integral floats are encoded as integers into objects without a factor.
+fn encoded_deviation<'a>(object_id: ObjectId, value: ObjectValueRef<'a>) -> Result<(f64, usize, ObjectRef<'a>), Error> {
+    // The longest object is a text of 255 bytes
+    let mut buf = [0u8; 258];
+    let mut out = SliceOutput { buf: &mut buf, len: 0 };
+    let value = match (value_to_raw(object_id, &value, &mut out), value) {
+        (Err(Error::InvalidValue), ObjectValueRef::Float(float)) if round(float) == float => {
+            out.len = 0;
+            let int = ObjectValueRef::Int(float as i64);
+            value_to_raw(object_id, &int, &mut out)?;
+            int
+        }
+        (result, value) => result.map(|_| value)?,
+    };
+    let size = out.len;
+    let decoded = value_from_raw(object_id, &mut Reader::new(&buf[1..size]))?.value;
+    let deviation = match (number(&value), number(&decoded)) {
+        (Some(value), Some(decoded)) => (value - decoded).abs(),
+        _ if value == decoded => 0.0,
+        _ => f64::INFINITY,
+    };
+    Ok((deviation, size, ObjectRef::new(object_id, value)))
+}
+
+fn number(value: &ObjectValueRef) -> Option<f64> {
+    match value {
+        ObjectValueRef::Float(value) => Some(*value),
+        ObjectValueRef::Int(value) => Some(*value as f64),
+        ObjectValueRef::UInt(value) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+impl From<ObjectRef<'_>> for Object {
+    fn from(object: ObjectRef<'_>) -> Self {
+        Object {
+            object_id: object.object_id,
+            value: object.value.into(),
+            instance: object.instance,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceData {
+    pub encrypted: bool,
+    pub trigger_based: bool,
+    pub version: u8,
+    pub objects: Vec<Object>,
+}
+
+/// The device information byte at the start of the service data.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeviceInfo {
+    /// Bit 0
+    pub encrypted: bool,
+    /// Bit 2, the device only sends when something happens instead of regularly
+    pub trigger_based: bool,
+    /// Bits 5 to 7
+    pub version: u8,
+    /// Bits 1, 3 and 4, which the specification reserves and devices should leave 0
+    pub reserved: u8,
+}
+
+impl DeviceInfo {
+    pub const RESERVED_BITS: u8 = 0b0001_1010;
+}
+
+impl From<u8> for DeviceInfo {
+    fn from(byte: u8) -> Self {
+        DeviceInfo {
+            encrypted: byte & 0b0000_0001 != 0,
+            trigger_based: byte & 0b0000_0100 != 0,
+            version: byte >> 5,
+            reserved: byte & DeviceInfo::RESERVED_BITS,
+        }
+    }
+}
+
+impl From<DeviceInfo> for u8 {
+    /// Versions above 7 don't fit and are cut off.
+    fn from(info: DeviceInfo) -> Self {
+        (info.version & 0b111) << 5
+            | (info.trigger_based as u8) << 2
+            | info.encrypted as u8
+            | info.reserved & DeviceInfo::RESERVED_BITS
+    }
+}
+
+/// Reads the device information, rejecting versions other than 2 unless `lenient_version`.
+fn read_device_info(cursor: &mut Reader, lenient_version: bool) -> Result<DeviceInfo, Error> {
+    let mut head = [0u8];
+    cursor.read_exact(&mut head)?;
+    let info = DeviceInfo::from(head[0]);
+    if info.version != 2 && !lenient_version {
+        return Err(Error::UnsupportedVersion(info.version));
+    }
+    Ok(info)
+}
+
+pub fn parse_service_data(data: &[u8]) -> Result<ServiceData, Error> {
+    parse_service_data_with(data, &ParseOptions::default()).map(|(service_data, _)| service_data)
+}
+
+/// What the parser does with objects it doesn't know.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnUnknown {
+    /// Skip objects whose length is known from the specification
+    Skip,
+    /// Fail with [`Error::InvalidObjectId`]
+    #[default]
+    Error,
+    /// Collect objects whose length is known from the specification as [`UnknownObject`]
+    Raw,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    pub on_unknown: OnUnknown,
+    /// Fail with [`Error::OutOfOrder`] if the objects are not sorted by id, as the specification
+    /// asks for and Home Assistant expects
+    pub strict_order: bool,
+    /// Parse versions other than 2 like version 2 instead of failing with
+    /// [`Error::UnsupportedVersion`]
+    pub lenient_version: bool,
+}
+
+/// An object the crate doesn't decode yet, collected with [`OnUnknown::Raw`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownObject {
+    pub id: u8,
+    /// The number of known objects before it
+    pub index: usize,
+    pub data: Vec<u8>,
+}
+
+/// Lengths of the objects of the specification that aren't decoded yet, so they can be skipped.
+/// Objects missing here can't be skipped, as it's unknown where the next object starts.
+const UNKNOWN_OBJECT_LENGTHS: &[(u8, usize)] = &[
+    // speed, sint32
+    (0x62, 4),
+    // acceleration, sint32
+    (0x63, 4),
+];
+
+/// Parses service data like [`parse_service_data`], and also returns the unknown objects
+/// collected with [`OnUnknown::Raw`].
+pub fn parse_service_data_with(data: &[u8], options: &ParseOptions) -> Result<(ServiceData, Vec<UnknownObject>), Error> {
+    let mut cursor = Reader::new(data);
+    let info = read_device_info(&mut cursor, options.lenient_version)?;
+    let mut service_data = ServiceData {
+        encrypted: info.encrypted,
+        trigger_based: info.trigger_based,
+        version: info.version,
+        objects: Vec::new(),
+    };
+    if service_data.encrypted {
+        return Err(Error::Encrypted);
+    }
+    let mut unknown = Vec::new();
+    service_data.objects = read_objects(&data[1..], options, &mut unknown)?;
+    Ok((service_data, unknown))
+}
+
+/// Service data whose text and raw objects borrow from the parsed data.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceDataRef<'a> {
+    pub encrypted: bool,
+    pub trigger_based: bool,
+    pub version: u8,
+    pub objects: Vec<ObjectRef<'a>>,
+}
+
+/// Parses service data like [`parse_service_data`], but without copying text and raw objects,
+/// for receivers decoding many advertisements.
+pub fn parse_service_data_borrowed(data: &[u8]) -> Result<ServiceDataRef<'_>, Error> {
+    let mut objects = ServiceDataIter::new(data)?;
+    let mut service_data = ServiceDataRef {
+        encrypted: objects.encrypted,
+        trigger_based: objects.trigger_based,
+        version: objects.version,
+        objects: Vec::new(),
+    };
+    loop {
+        let offset = objects.offset();
+        match objects.next() {
+            Some(Ok(object)) => service_data.objects.push(object),
+            Some(Err(error)) => {
+                return Err(Error::Object {
+                    offset,
+                    index: service_data.objects.len(),
+                    parsed: service_data.objects.into_iter().map(Object::from).collect(),
+                    error: Box::new(error),
+                })
+            }
+            None => return Ok(service_data),
+        }
+    }
+}
+
+/// Walks service data object by object, without allocating, so that consumers interested in
+/// a single object can stop early.
+///
+/// Like [`parse_service_data`] it ends at a truncated final object. After an error no more
+/// objects are yielded, as the size of the erroneous object and thus the start of the next one
+/// is unknown.
+#[derive(Debug, Clone)]
+pub struct ServiceDataIter<'a> {
+    pub encrypted: bool,
+    pub trigger_based: bool,
+    pub version: u8,
+    cursor: Reader<'a>,
+    len: usize,
+    done: bool,
+    instances: Instances,
+}
+
+impl<'a> ServiceDataIter<'a> {
+    /// Reads the header, encrypted service data is rejected like by [`parse_service_data`].
+    pub fn new(data: &'a [u8]) -> Result<Self, Error> {
+        let mut cursor = Reader::new(data);
+        let info = read_device_info(&mut cursor, false)?;
+        if info.encrypted {
+            return Err(Error::Encrypted);
+        }
+        Ok(ServiceDataIter {
+            encrypted: false,
+            trigger_based: info.trigger_based,
+            version: info.version,
+            cursor,
+            len: data.len(),
+            done: false,
+            instances: Instances::default(),
+        })
+    }
+
+    /// Where the next object starts in the service data, to locate errors.
+    pub fn offset(&self) -> usize {
+        self.len - self.cursor.data.len()
+    }
+}
+
+impl<'a> Iterator for ServiceDataIter<'a> {
+    type Item = Result<ObjectRef<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match ObjectRef::read(&mut self.cursor) {
+            Ok(mut object) => {
+                object.instance = self.instances.next(object.object_id);
+                Some(Ok(object))
+            }
+            Err(Error::UnexpectedEnd) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl core::iter::FusedIterator for ServiceDataIter<'_> {}
+
+impl From<ServiceDataRef<'_>> for ServiceData {
+    fn from(service_data: ServiceDataRef<'_>) -> Self {
+        ServiceData {
+            encrypted: service_data.encrypted,
+            trigger_based: service_data.trigger_based,
+            version: service_data.version,
+            objects: service_data.objects.into_iter().map(Object::from).collect(),
+        }
+    }
+}
+
+/// Parses service data of BTHome v1, into the same representation as v2 with version 1.
+///
+/// v1 has no device info byte, instead every object starts with a byte with its format and
+/// length. The objects have the same ids as in v2, only objects with the length of the object of
+/// v2 are accepted. Encrypted v1 service data is not supported.
+pub fn parse_service_data_v1(data: &[u8]) -> Result<ServiceData, Error> {
+    let mut cursor = Reader::new(data);
+    let mut objects = Vec::new();
+    let mut instances = Instances::default();
+    let mut control = [0u8];
+    while cursor.read_exact(&mut control).is_ok() {
+        let offset = data.len() - cursor.data.len() - 1;
+        // Like in v2, a truncated final object ends the data
+        let Ok(object) = cursor.read_slice((control[0] & 0b00011111) as usize) else {
+            break;
+        };
+        let mut reader = Reader::new(object);
+        match Object::read(&mut reader) {
+            Ok(_) if objects.len() == MAX_OBJECTS => return Err(Error::TooManyObjects),
+            Ok(mut object) if reader.data.is_empty() => {
+                object.instance = instances.next(object.object_id);
+                objects.push(object)
+            }
+            result => {
+                let error = match result {
+                    Err(Error::UnexpectedEnd) | Ok(_) => Error::InvalidLength,
+                    Err(e) => e,
+                };
+                return Err(Error::Object {
+                    offset,
+                    index: objects.len(),
+                    parsed: objects,
+                    error: Box::new(error),
+                });
+            }
+        }
+    }
+    Ok(ServiceData {
+        encrypted: false,
+        trigger_based: false,
+        version: 1,
+        objects,
+    })
+}
+
+/// Parses service data of either BTHome version, told apart by the 16 bit UUID it was
+/// advertised with.
+pub fn parse_service_data_by_uuid(uuid16: u16, data: &[u8]) -> Result<ServiceData, Error> {
+    match uuid16 {
+        BTHOME_UUID16 => parse_service_data(data),
+        BTHOME_V1_UUID16 => parse_service_data_v1(data),
+        BTHOME_V1_ENCRYPTED_UUID16 => Err(Error::Encrypted),
+        uuid => Err(Error::UnknownUuid(uuid)),
+    }
+}
+
+/// AD type of service data with a 16 bit UUID.
+const AD_TYPE_SERVICE_DATA_UUID16: u8 = 0x16;
+/// AD type of service data with a 128 bit UUID.
+const AD_TYPE_SERVICE_DATA_UUID128: u8 = 0x21;
+
+/// Finds the BTHome service data in a raw advertising payload, the AD structures as found in HCI
+/// advertising reports or scan responses, and returns it without the UUID.
+///
+/// Returns `None` if there is no BTHome service data or a structure before it is truncated, a
+/// truncated structure after it doesn't matter.
+pub fn extract_bthome_from_adv(mut adv: &[u8]) -> Option<&[u8]> {
+    while let [len, rest @ ..] = adv {
+        let len = *len as usize;
+        // A length of zero ends the significant part, the rest is padding
+        if len == 0 || rest.len() < len {
+            return None;
+        }
+        let (ad_type, data) = (rest[0], &rest[1..len]);
+        let service_data = match ad_type {
+            AD_TYPE_SERVICE_DATA_UUID16 => data.strip_prefix(&BTHOME_UUID16.to_le_bytes()),
+            AD_TYPE_SERVICE_DATA_UUID128 => data.strip_prefix(&BTHOME_UUID.to_le_bytes()),
+            _ => None,
+        };
+        if service_data.is_some() {
+            return service_data;
+        }
+        adv = &rest[len..];
+    }
+    None
+}
+
+/// Reads the objects following the device info byte, in encrypted service data after decryption.
+fn read_objects(data: &[u8], options: &ParseOptions, unknown: &mut Vec<UnknownObject>) -> Result<Vec<Object>, Error> {
+    let mut cursor = Reader::new(data);
+    let mut objects = Vec::new();
+    let mut instances = Instances::default();
+    loop {
+        let offset = 1 + data.len() - cursor.data.len();
+        let skippable = cursor
+            .data
+            .first()
+            .filter(|id| options.on_unknown != OnUnknown::Error && ObjectId::try_from(**id).is_err())
+            .and_then(|id| UNKNOWN_OBJECT_LENGTHS.iter().find(|(known, _)| known == id));
+        if let Some(&(id, len)) = skippable {
+            let object = match cursor.read_slice(1 + len) {
+                Ok(object) => object,
+                Err(_) => break,
+            };
+            if options.on_unknown == OnUnknown::Raw {
+                unknown.push(UnknownObject {
+                    id,
+                    index: objects.len(),
+                    data: object[1..].to_vec(),
+                });
+            }
+            continue;
+        }
+        let obj = match Object::read(&mut cursor) {
+            Ok(o) if options.strict_order && objects.last().is_some_and(|last: &Object| last.object_id > o.object_id) => {
+                return Err(Error::Object {
+                    offset,
+                    index: objects.len(),
+                    parsed: objects,
+                    error: Box::new(Error::OutOfOrder(o.object_id as u8)),
+                })
+            }
+            Ok(_) if objects.len() == MAX_OBJECTS => return Err(Error::TooManyObjects),
+            Ok(mut o) => {
+                o.instance = instances.next(o.object_id);
+                o
+            }
+            Err(Error::UnexpectedEnd) => break,
+            Err(e) => {
+                return Err(Error::Object {
+                    offset,
+                    index: objects.len(),
+                    parsed: objects,
+                    error: Box::new(e),
+                })
+            }
+        };
+        objects.push(obj);
+    }
+    Ok(objects)
+}
+
+/// Number of objects read so far by id, for their instance.
+#[derive(Debug, Clone)]
+struct Instances([u8; 256]);
+
+impl Default for Instances {
+    fn default() -> Self {
+        Instances([0; 256])
+    }
+}
+
+impl Instances {
+    /// The instance of the next object with `object_id`.
+    fn next(&mut self, object_id: ObjectId) -> u8 {
+        let count = &mut self.0[object_id as usize];
+        let instance = *count;
+        *count = count.saturating_add(1);
+        instance
+    }
+}
+
+/// AES-128-CCM with the 4 byte MIC and 13 byte nonce of BTHome.
+type Cipher = ccm::Ccm<aes::Aes128, ccm::consts::U4, ccm::consts::U13>;
+
+/// The nonce of an encrypted advertisement: the MAC address of the device as written, e.g.
+/// `54:48:E6:8F:80:A5`, the UUID, the device information byte and the counter.
+fn nonce(mac: &[u8; 6], device_info: u8, counter: &[u8; 4]) -> [u8; 13] {
+    let mut nonce = [0u8; 13];
+    nonce[..6].copy_from_slice(mac);
+    nonce[6..8].copy_from_slice(&BTHOME_UUID16.to_le_bytes());
+    nonce[8] = device_info;
+    nonce[9..].copy_from_slice(counter);
+    nonce
+}
+
+/// Decrypts and parses encrypted service data, returns it with the counter of the
+/// advertisement. Receivers should only accept increasing counters, to reject replayed
+/// advertisements.
+pub fn parse_encrypted_service_data(data: &[u8], key: &[u8; 16], mac: &[u8; 6]) -> Result<(ServiceData, u32), Error> {
+    use ccm::aead::{AeadInPlace, KeyInit};
+
+    let (&device_info, rest) = data.split_first().ok_or(Error::UnexpectedEnd)?;
+    let info = read_device_info(&mut Reader::new(data), false)?;
+    if !info.encrypted {
+        return Err(Error::NotEncrypted);
+    }
+    // Ciphertext, 4 bytes counter and 4 bytes MIC
+    if rest.len() < 8 {
+        return Err(Error::UnexpectedEnd);
+    }
+    let (ciphertext, rest) = rest.split_at(rest.len() - 8);
+    let (counter, mic) = rest.split_at(4);
+    let counter: [u8; 4] = counter.try_into().map_err(|_| Error::UnexpectedEnd)?;
+    let cipher = Cipher::new(key.into());
+    let mut plaintext = Vec::from(ciphertext);
+    cipher
+        .decrypt_in_place_detached(&nonce(mac, device_info, &counter).into(), &[], &mut plaintext, mic.into())
+        .map_err(|_| Error::DecryptionFailed)?;
+    let service_data = ServiceData {
+        encrypted: true,
+        trigger_based: info.trigger_based,
+        version: info.version,
+        objects: read_objects(&plaintext, &ParseOptions::default(), &mut Vec::new())?,
+    };
+    Ok((service_data, u32::from_le_bytes(counter)))
+}
+
+/// Encodes service data, the inverse of [`parse_service_data`]. Encryption is not supported.
+pub fn encode_service_data(service_data: &ServiceData) -> Result<Vec<u8>, Error> {
+    if service_data.encrypted {
+        return Err(Error::Encrypted);
+    }
+    let mut data = vec![service_data.device_info(false)];
+    for object in service_data.sorted_objects() {
+        object.write(&mut data)?;
+    }
+    Ok(data)
+}
+
+/// Encodes unencrypted BTHome v2 service data into `buf` without allocating, for firmware without
+/// a heap. Returns the number of bytes written. The objects are written sorted by id as the
+/// specification asks for, objects with the same id in the order they are given.
+pub fn encode_service_data_into(trigger_based: bool, objects: &[ObjectRef<'_>], buf: &mut [u8]) -> Result<usize, Error> {
+    if objects.len() > MAX_OBJECTS {
+        return Err(Error::TooManyObjects);
+    }
+    let device_info = DeviceInfo {
+        encrypted: false,
+        trigger_based,
+        version: 2,
+        reserved: 0,
+    };
+    let mut out = SliceOutput { buf, len: 0 };
+    out.put(&[u8::from(device_info)])?;
+    // Sorting would need a copy of the objects, there are only a few of them
+    for id in 0..=u8::MAX {
+        for object in objects.iter().filter(|object| object.object_id as u8 == id) {
+            value_to_raw(object.object_id, &object.value, &mut out)?;
+        }
+    }
+    Ok(out.len)
+}
+
+impl ServiceData {
+    /// The objects sorted by id as the specification asks for, objects with the same id by their
+    /// instance.
+    fn sorted_objects(&self) -> Vec<&Object> {
+        let mut objects: Vec<&Object> = self.objects.iter().collect();
+        objects.sort_by_key(|object| (object.object_id as u8, object.instance));
+        objects
+    }
+
+    /// The packet id, which devices increase with every new packet and repeat in its copies.
+    pub fn packet_id(&self) -> Option<u8> {
+        self.objects.iter().find_map(|object| match (object.object_id, &object.value) {
+            (ObjectId::PacketId, ObjectValue::Int(id)) => Some(*id as u8),
+            _ => None,
+        })
+    }
+
+    /// The service data as sent in an advertisement, see [`encode_service_data`].
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        encode_service_data(self)
+    }
+
+    /// Encodes and encrypts the service data for the device with `mac`, the inverse of
+    /// [`parse_encrypted_service_data`]. The `encrypted` flag is ignored. The counter has to
+    /// increase with every advertisement, receivers drop those with a counter they have seen.
+    pub fn encrypt(&self, key: &[u8; 16], mac: &[u8; 6], counter: u32) -> Result<Vec<u8>, Error> {
+        use ccm::aead::{AeadInPlace, KeyInit};
+
+        let device_info = self.device_info(true);
+        let mut plaintext = Vec::new();
+        for object in self.sorted_objects() {
+            object.write(&mut plaintext)?;
+        }
+        let counter = counter.to_le_bytes();
+        let mic = Cipher::new(key.into())
+            .encrypt_in_place_detached(&nonce(mac, device_info, &counter).into(), &[], &mut plaintext)
+            // Only for data longer than the length field of CCM allows, far more than MAX_OBJECTS
+            .map_err(|_| Error::TooManyObjects)?;
+        let mut data = vec![device_info];
+        data.extend_from_slice(&plaintext);
+        data.extend_from_slice(&counter);
+        data.extend_from_slice(&mic);
+        Ok(data)
+    }
+
+    /// Splits the objects into parts that encode into at most `max_len` bytes each, e.g. 24 bytes
+    /// for legacy advertisements, which have 31 bytes for the flags, the UUID and the service data.
+    ///
+    /// Every part gets a packet id, counting up from the one of this service data or from 0. The
+    /// objects stay sorted by id and objects with the same id in the same part, so that they keep
+    /// their instances. With the `encrypted` flag room is left for the counter and MIC added by
+    /// [`ServiceData::encrypt`]. Fails with [`Error::BufferTooSmall`] if objects with the same id
+    /// don't fit into one part.
+    pub fn split_into_advertisements(&self, max_len: usize) -> Result<Vec<ServiceData>, Error> {
+        // The device information, the counter and MIC, and the packet id
+        let overhead = if self.encrypted { 9 } else { 1 } + 2;
+        let mut packet_id = self.packet_id().unwrap_or(0);
+        let mut next_part = || {
+            let part = ServiceData {
+                encrypted: self.encrypted,
+                trigger_based: self.trigger_based,
+                version: self.version,
+                objects: vec![Object::new(ObjectId::PacketId, ObjectValue::Int(packet_id.into()))],
+            };
+            packet_id = packet_id.wrapping_add(1);
+            part
+        };
+        if overhead > max_len {
+            return Err(Error::BufferTooSmall);
+        }
+        let mut parts = vec![next_part()];
+        let mut len = overhead;
+        let mut objects = self.sorted_objects();
+        objects.retain(|object| object.object_id != ObjectId::PacketId);
+        for group in objects.chunk_by(|a, b| a.object_id == b.object_id) {
+            let mut data = Vec::new();
+            for object in group {
+                object.write(&mut data)?;
+            }
+            if overhead + data.len() > max_len {
+                return Err(Error::BufferTooSmall);
+            }
+            if len + data.len() > max_len {
+                parts.push(next_part());
+                len = overhead;
+            }
+            len += data.len();
+            let part = parts.last_mut().expect("A part to be started");
+            part.objects.extend(group.iter().map(|object| (*object).clone()));
+        }
+        Ok(parts)
+    }
+
+    fn device_info(&self, encrypted: bool) -> u8 {
+        u8::from(DeviceInfo {
+            encrypted,
+            trigger_based: self.trigger_based,
+            version: self.version,
+            reserved: 0,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn parse_example() {
+        let example: [u8; 7] = [0x40, 0x02, 0xC4, 0x09, 0x03, 0xBF, 0x13];
+        let parsed = parse_service_data(&example).expect("Example to parse successfully");
+        assert_eq!(parsed, ServiceData {
+            encrypted: false,
+            trigger_based: false,
+            version: 2,
+            objects: vec![ 
+                Object::new(ObjectId::Temperature4, ObjectValue::Float(25.0)),
+                Object::new(ObjectId::HumidityU16, ObjectValue::Float(50.55))
+            ]
+        })
+    }
+
+    #[test]
+    fn encode_example() {
+        let service_data = ServiceData {
+            encrypted: false,
+            trigger_based: false,
+            version: 2,
+            objects: vec![
+                Object::new(ObjectId::Temperature4, ObjectValue::Float(25.0)),
+                Object::new(ObjectId::HumidityU16, ObjectValue::Float(50.55))
+            ]
+        };
+        let encoded = encode_service_data(&service_data).expect("Example to encode successfully");
+        assert_eq!(encoded, vec![0x40, 0x02, 0xC4, 0x09, 0x03, 0xBF, 0x13]);
+        assert_eq!(parse_service_data(&encoded).expect("Encoded data to parse"), service_data);
+    }
+
+    #[test]
+    fn encode_invalid_values() {
+        let encode = |object_id, value| encode_service_data(&ServiceData {
+            encrypted: false,
+            trigger_based: false,
+            version: 2,
+            objects: vec![Object::new(object_id, value)],
+        });
+        let out_of_range = |object, value| Err(Error::ValueOutOfRange { object, value });
+        assert_eq!(encode(ObjectId::Battery, ObjectValue::Int(256)), out_of_range(ObjectId::Battery, 256.0));
+        assert_eq!(encode(ObjectId::Temperature4, ObjectValue::Float(400.0)), out_of_range(ObjectId::Temperature4, 400.0));
+        // Valid for the encoding, but not as a measurement
+        assert_eq!(encode(ObjectId::Battery, ObjectValue::Int(101)), out_of_range(ObjectId::Battery, 101.0));
+        assert_eq!(encode(ObjectId::HumidityU16, ObjectValue::Float(100.5)), out_of_range(ObjectId::HumidityU16, 100.5));
+        assert_eq!(encode(ObjectId::HumidityU8, ObjectValue::Int(-1)), out_of_range(ObjectId::HumidityU8, -1.0));
+        assert_eq!(encode(ObjectId::Text, ObjectValue::Text("x".repeat(256))), out_of_range(ObjectId::Text, 256.0));
+        assert!(encode(ObjectId::HumidityU16, ObjectValue::Float(100.0)).is_ok());
+        assert!(matches!(encode(ObjectId::Battery, ObjectValue::Text("full".to_string())), Err(Error::InvalidValue)));
+        assert_eq!(encode(ObjectId::Temperature4, ObjectValue::Float(-1.5)).unwrap(), vec![0x40, 0x02, 0x6A, 0xFF]);
+    }
+
+    #[test]
+    fn valid_range() {
+        assert_eq!(ObjectId::Temperature4.valid_range(), Some(-327.68..=327.67));
+        assert_eq!(ObjectId::Temperature1.valid_range(), Some(-128.0..=127.0));
+        assert_eq!(ObjectId::HumidityU16.valid_range(), Some(0.0..=100.0));
+        assert_eq!(ObjectId::Battery.valid_range(), Some(0.0..=100.0));
+        assert_eq!(ObjectId::Direction.valid_range(), Some(0.0..=360.0));
+        assert_eq!(ObjectId::Timestamp.valid_range(), Some(0.0..=281474976710655.0));
+        assert_eq!(ObjectId::Text.valid_range(), None);
+        assert_eq!(ObjectId::BatteryLow.valid_range(), None);
+    }
+
+    #[test]
+    fn encode_symmetric_to_parse() {
+        let service_data = ServiceData {
+            encrypted: false,
+            trigger_based: true,
+            version: 2,
+            objects: vec![
+                Object::new(ObjectId::PacketId, ObjectValue::Int(9)),
+                Object::new(ObjectId::Button, ObjectValue::ButtonEvent(ButtonEvent::DoublePress)),
+                Object::new(ObjectId::Dimmer, ObjectValue::DimmerEvent(DimmerEvent::RotateLeft, 3)),
+                Object::new(ObjectId::Text, ObjectValue::Text("Hi".to_string())),
+                Object::new(ObjectId::Raw, ObjectValue::Raw(vec![0xAB, 0xCD])),
+            ]
+        };
+        let encoded = service_data.to_bytes().expect("Example to encode successfully");
+        assert_eq!(encoded, vec![0x44, 0x00, 0x09, 0x3A, 0x02, 0x3C, 0x01, 0x03, 0x53, 0x02, b'H', b'i', 0x54, 0x02, 0xAB, 0xCD]);
+        assert_eq!(parse_service_data(&encoded).expect("Encoded data to parse"), service_data);
+    }
+
+    #[test]
+    fn decrypt_example() {
+        // The example of the BTHome specification
+        let key = [0x23, 0x1d, 0x39, 0xc1, 0xd7, 0xcc, 0x1a, 0xb1, 0xae, 0xe2, 0x24, 0xcd, 0x09, 0x6d, 0xb9, 0x32];
+        let mac = [0x54, 0x48, 0xE6, 0x8F, 0x80, 0xA5];
+        let data = [0x41, 0xa4, 0x72, 0x66, 0xc9, 0x5f, 0x73, 0x00, 0x11, 0x22, 0x33, 0x78, 0x23, 0x72, 0x14];
+        let (parsed, counter) = parse_encrypted_service_data(&data, &key, &mac).expect("Example to decrypt");
+        assert_eq!(counter, 0x33221100);
+        assert!(parsed.encrypted);
+        assert_eq!(parsed.objects, vec![
+            Object::new(ObjectId::Temperature4, ObjectValue::Float(25.06)),
+            Object::new(ObjectId::HumidityU16, ObjectValue::Float(50.55)),
+        ]);
+
+        // Encrypting the objects again gives the example
+        assert_eq!(parsed.encrypt(&key, &mac, counter), Ok(data.to_vec()));
+
+        let mut altered = data;
+        altered[3] ^= 1;
+        assert_eq!(parse_encrypted_service_data(&altered, &key, &mac), Err(Error::DecryptionFailed));
+        assert_eq!(parse_encrypted_service_data(&data, &key, &[0; 6]), Err(Error::DecryptionFailed));
+        assert_eq!(parse_encrypted_service_data(&data[..8], &key, &mac), Err(Error::UnexpectedEnd));
+        assert_eq!(parse_encrypted_service_data(&[0x40, 0x01, 0x61], &key, &mac), Err(Error::NotEncrypted));
+    }
+
+    #[test]
+    fn parse_borrowed() {
+        let data = [0x40, 0x01, 0x61, 0x53, 0x02, b'H', b'i', 0x54, 0x02, 0xAB, 0xCD];
+        let borrowed = parse_service_data_borrowed(&data).expect("Example to parse successfully");
+        assert_eq!(borrowed.objects[1].value, ObjectValueRef::Text("Hi"));
+        assert_eq!(borrowed.objects[2].value, ObjectValueRef::Raw(&data[9..]));
+        assert_eq!(ServiceData::from(borrowed), parse_service_data(&data).unwrap());
+        let err = parse_service_data_borrowed(&[0x40, 0x53, 0x01, 0xFF]).unwrap_err();
+        assert_eq!(err.kind(), &Error::InvalidTextEncoding);
+    }
+
+    #[test]
+    fn parse_unknown_objects() {
+        // Battery, signed speed, which isn't decoded yet, and temperature
+        let data = [0x40, 0x01, 0x61, 0x62, 0x2C, 0x01, 0x00, 0x00, 0x02, 0xCA, 0x09];
+        let err = parse_service_data(&data).unwrap_err();
+        assert_eq!(err.kind(), &Error::InvalidObjectId(0x62));
+
+        let skip = ParseOptions { on_unknown: OnUnknown::Skip, ..Default::default() };
+        let (service_data, unknown) = parse_service_data_with(&data, &skip).unwrap();
+        assert_eq!(service_data.objects.len(), 2);
+        assert!(unknown.is_empty());
+
+        let raw = ParseOptions { on_unknown: OnUnknown::Raw, ..Default::default() };
+        let (_, unknown) = parse_service_data_with(&data, &raw).unwrap();
+        assert_eq!(unknown, vec![UnknownObject { id: 0x62, index: 1, data: vec![0x2C, 0x01, 0x00, 0x00] }]);
+
+        // Without a known length there is no way to find the next object
+        assert!(parse_service_data_with(&[0x40, 0x7F, 0x01, 0x01, 0x61], &skip).is_err());
+    }
+
+    #[test]
+    fn parse_v1() {
+        // Packet id, temperature, humidity and battery as sent by ATC firmwares
+        let data = [0x02, 0x00, 0x0B, 0x23, 0x02, 0xC4, 0x09, 0x03, 0x03, 0xBF, 0x13, 0x02, 0x01, 0x61];
+        let service_data = parse_service_data_by_uuid(BTHOME_V1_UUID16, &data).unwrap();
+        assert_eq!(service_data.version, 1);
+        assert_eq!(
+            service_data.objects,
+            vec![
+                Object::new(ObjectId::PacketId, ObjectValue::Int(11)),
+                Object::new(ObjectId::Temperature4, ObjectValue::Float(25.0)),
+                Object::new(ObjectId::HumidityU16, ObjectValue::Float(50.55)),
+                Object::new(ObjectId::Battery, ObjectValue::Int(97)),
+            ]
+        );
+
+        let err = parse_service_data_v1(&[0x02, 0x01, 0x61, 0x24, 0x02, 0xC4, 0x09, 0x00]).unwrap_err();
+        assert_eq!(err.kind(), &Error::InvalidLength);
+        assert_eq!(parse_service_data_by_uuid(0x180F, &data), Err(Error::UnknownUuid(0x180F)));
+    }
+
+    #[test]
+    fn encode_without_allocating() {
+        let objects = [
+            ObjectRef::new(ObjectId::Temperature4, ObjectValueRef::Float(25.0)),
+            ObjectRef::new(ObjectId::Battery, ObjectValueRef::Int(97)),
+            ObjectRef::new(ObjectId::Temperature4, ObjectValueRef::Float(100.0)),
+            ObjectRef::new(ObjectId::Text, ObjectValueRef::Text("Hi")),
+        ];
+        let mut buf = [0u8; 31];
+        let len = encode_service_data_into(false, &objects, &mut buf).unwrap();
+        let service_data = ServiceData {
+            encrypted: false,
+            trigger_based: false,
+            version: 2,
+            objects: objects.iter().map(|object| Object::from(*object)).collect(),
+        };
+        assert_eq!(&buf[..len], encode_service_data(&service_data).unwrap());
+        assert_eq!(&buf[..len], [0x40, 0x01, 0x61, 0x02, 0xC4, 0x09, 0x02, 0x10, 0x27, 0x53, 0x02, b'H', b'i']);
+
+        assert_eq!(encode_service_data_into(true, &objects[..1], &mut buf), Ok(4));
+        assert_eq!(buf[0], 0x44);
+        assert_eq!(encode_service_data_into(false, &objects, &mut [0u8; 12]), Err(Error::BufferTooSmall));
+        let wrong = [ObjectRef::new(ObjectId::Battery, ObjectValueRef::Bool(true))];
+        assert_eq!(encode_service_data_into(false, &wrong, &mut buf), Err(Error::InvalidValue));
+    }
+
+    #[test]
+    fn extract_from_advertising_data() {
+        // Flags, BTHome service data and a truncated name
+        let adv = [0x02, 0x01, 0x06, 0x07, 0x16, 0xD2, 0xFC, 0x40, 0x02, 0xC4, 0x09, 0x05, 0x09, b'B', b'T'];
+        assert_eq!(extract_bthome_from_adv(&adv), Some(&[0x40, 0x02, 0xC4, 0x09][..]));
+
+        let mut adv = vec![0x14, 0x21];
+        adv.extend_from_slice(&BTHOME_UUID.to_le_bytes());
+        adv.extend_from_slice(&[0x40, 0x01, 0x61]);
+        assert_eq!(extract_bthome_from_adv(&adv), Some(&[0x40, 0x01, 0x61][..]));
+
+        // Other service data, a truncated structure, padding and nothing at all
+        assert_eq!(extract_bthome_from_adv(&[0x02, 0x01, 0x06, 0x05, 0x16, 0x1A, 0x18, 0x01, 0x02]), None);
+        assert_eq!(extract_bthome_from_adv(&[0x05, 0x16, 0xD2]), None);
+        assert_eq!(extract_bthome_from_adv(&[0x00, 0x03, 0x16, 0xD2, 0xFC]), None);
+        assert_eq!(extract_bthome_from_adv(&[0x01, 0x16]), None);
+        assert_eq!(extract_bthome_from_adv(&[]), None);
+    }
+
+    #[test]
+    fn round_trip_cloned() {
+        let data = [0x44, 0x00, 0x07, 0x02, 0xCA, 0x09, 0x3A, 0x01, 0x53, 0x02, b'H', b'i'];
+        let service_data = parse_service_data(&data).unwrap();
+        let mut changed = service_data.clone();
+        changed.objects[0].value = ObjectValue::Int(8);
+        assert_eq!(parse_service_data(&changed.to_bytes().unwrap()).unwrap(), changed);
+        assert_eq!(service_data.to_bytes().unwrap(), data);
+
+        // Objects as keys, e.g. for the latest value of each object of a device
+        let latest: alloc::collections::BTreeMap<ObjectId, ObjectValue> =
+            service_data.objects.iter().map(|object| (object.object_id, object.value.clone())).collect();
+        assert_eq!(latest[&ObjectId::Button], ObjectValue::ButtonEvent(ButtonEvent::Press));
+    }
+
+    #[test]
+    fn object_order() {
+        let unsorted = [0x40, 0x02, 0xCA, 0x09, 0x01, 0x61, 0x01, 0x62];
+        let service_data = parse_service_data(&unsorted).unwrap();
+        assert_eq!(service_data.to_bytes().unwrap(), vec![0x40, 0x01, 0x61, 0x01, 0x62, 0x02, 0xCA, 0x09]);
+
+        let strict = ParseOptions { strict_order: true, ..Default::default() };
+        let err = parse_service_data_with(&unsorted, &strict).unwrap_err();
+        assert_eq!(err.kind(), &Error::OutOfOrder(0x01));
+        assert!(parse_service_data_with(&service_data.to_bytes().unwrap(), &strict).is_ok());
+    }
+
+    #[test]
+    fn repeated_objects() {
+        // Two temperatures around a battery
+        let data = [0x40, 0x02, 0xCA, 0x09, 0x01, 0x61, 0x02, 0x10, 0x27];
+        let service_data = parse_service_data(&data).unwrap();
+        let instances: Vec<_> = service_data.objects.iter().map(|object| (object.object_id, object.instance)).collect();
+        assert_eq!(instances, vec![(ObjectId::Temperature4, 0), (ObjectId::Battery, 0), (ObjectId::Temperature4, 1)]);
+        assert_eq!(ServiceData::from(parse_service_data_borrowed(&data).unwrap()), service_data);
+        assert_eq!(ServiceDataIter::new(&data).unwrap().last().unwrap().unwrap().instance, 1);
+
+        // The instances keep their order when sorting by id
+        let mut swapped = service_data.clone();
+        swapped.objects.swap(0, 2);
+        assert_eq!(swapped.to_bytes().unwrap(), vec![0x40, 0x01, 0x61, 0x02, 0xCA, 0x09, 0x02, 0x10, 0x27]);
+    }
+
+    #[test]
+    fn dimmer_rotation() {
+        // The event byte is followed by the steps, as in the example of the specification
+        let service_data = parse_service_data(&[0x40, 0x3C, 0x01, 0x03]).unwrap();
+        assert_eq!(service_data.objects[0].value, ObjectValue::DimmerEvent(DimmerEvent::RotateLeft, 3));
+        assert_eq!(service_data.objects[0].rotation(), Some(-3));
+        let right = parse_service_data(&[0x40, 0x3C, 0x02, 0xFF]).unwrap();
+        assert_eq!(right.objects[0].rotation(), Some(255));
+        assert_eq!(right.to_bytes().unwrap(), vec![0x40, 0x3C, 0x02, 0xFF]);
+        assert_eq!(Object::new(ObjectId::Battery, ObjectValue::Int(5)).rotation(), None);
+        assert_eq!(parse_service_data(&[0x40, 0x3C, 0x03, 0x01]).unwrap_err().kind(), &Error::InvalidDimmerEvent(0x03));
+
+        for rotation in [-255, -3, 0, 3, 255] {
+            let (event, steps) = DimmerEvent::from_rotation(rotation).unwrap();
+            assert_eq!(event.rotation(steps), rotation);
+        }
+        assert_eq!(DimmerEvent::from_rotation(-3), Ok((DimmerEvent::RotateLeft, 3)));
+        assert_eq!(DimmerEvent::from_rotation(0), Ok((DimmerEvent::None, 0)));
+        assert_eq!(DimmerEvent::from_rotation(256), Err(Error::ValueOutOfRange { object: ObjectId::Dimmer, value: 256.0 }));
+    }
+
+    #[test]
+    fn display() {
+        let data = [0x40, 0x02, 0x62, 0x08, 0x02, 0x10, 0x27, 0x1A, 0x01, 0x3A, 0x02, 0x3C, 0x01, 0x03, 0x54, 0x02, 0xAB, 0xCD];
+        let lines: Vec<String> = parse_service_data(&data).unwrap().objects.iter().map(|object| object.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "temperature: 21.46 °C",
+                "temperature_2: 100 °C",
+                "door: open",
+                "button: double_press",
+                "dimmer: rotate_left by 3 steps",
+                "raw: abcd",
+            ]
+        );
+        let mut battery = Object::new(ObjectId::Battery, ObjectValue::Int(97));
+        assert_eq!(battery.to_string(), "battery: 97 %");
+        // Only repeated objects are numbered
+        battery.instance = 1;
+        assert_eq!(battery.to_string(), "battery_2: 97 %");
+        assert_eq!(ObjectValue::Bool(true).to_string(), "true");
+        assert_eq!(ObjectValue::Text(String::from("hi")).to_string(), "hi");
+    }
+
+    #[test]
+    fn multiple_buttons() {
+        // A Shelly BLU RC Button 4: nothing on the first, a double press on the second, a hold
+        // press on the third and held down on the fourth
+        let data = [0x44, 0x00, 0x21, 0x3A, 0x00, 0x3A, 0x02, 0x3A, 0x80, 0x3A, 0xFE];
+        let service_data = parse_service_data(&data).unwrap();
+        let buttons: Vec<_> = service_data.objects[1..].iter().map(|object| (object.instance, &object.value)).collect();
+        assert_eq!(
+            buttons,
+            vec![
+                (0, &ObjectValue::ButtonEvent(ButtonEvent::None)),
+                (1, &ObjectValue::ButtonEvent(ButtonEvent::DoublePress)),
+                (2, &ObjectValue::ButtonEvent(ButtonEvent::HoldPress)),
+                (3, &ObjectValue::ButtonEvent(ButtonEvent::Hold)),
+            ]
+        );
+        assert_eq!(service_data.to_bytes().unwrap(), data);
+        assert_eq!(parse_service_data(&[0x40, 0x3A, 0x07]).unwrap_err().kind(), &Error::InvalidButtonEvent(0x07));
+    }
+
+    #[test]
+    fn split_into_advertisements() {
+        // Packet id, battery, two temperatures, pressure, illuminance and a text
+        let data = [
+            0x40, 0x00, 0x07, 0x01, 0x61, 0x02, 0xCA, 0x09, 0x02, 0x10, 0x27, 0x04, 0x13, 0x8A, 0x01, 0x05, 0x13, 0x8A,
+            0x14, 0x53, 0x02, b'h', b'i',
+        ];
+        let service_data = parse_service_data(&data).unwrap();
+        let parts = service_data.split_into_advertisements(12).unwrap();
+        let encoded: Vec<Vec<u8>> = parts.iter().map(|part| part.to_bytes().unwrap()).collect();
+        assert_eq!(
+            encoded,
+            vec![
+                vec![0x40, 0x00, 0x07, 0x01, 0x61, 0x02, 0xCA, 0x09, 0x02, 0x10, 0x27],
+                vec![0x40, 0x00, 0x08, 0x04, 0x13, 0x8A, 0x01, 0x05, 0x13, 0x8A, 0x14],
+                vec![0x40, 0x00, 0x09, 0x53, 0x02, b'h', b'i'],
+            ]
+        );
+        assert_eq!(parts[0].objects[3].instance, 1);
+
+        assert_eq!(service_data.split_into_advertisements(24).unwrap().len(), 1);
+        // The temperatures don't fit into one part
+        assert_eq!(service_data.split_into_advertisements(8), Err(Error::BufferTooSmall));
+        let encrypted = ServiceData { encrypted: true, ..service_data };
+        assert_eq!(encrypted.split_into_advertisements(12), Err(Error::BufferTooSmall));
+        assert_eq!(encrypted.split_into_advertisements(20).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn resolution() {
+        let best = |object_id, value| ObjectRef::with_resolution(object_id, value, Resolution::Best);
+        let temperature = |value| best(ObjectId::Temperature4, ObjectValueRef::Float(value));
+        assert_eq!(temperature(21.0), Ok(ObjectRef::new(ObjectId::Temperature1, ObjectValueRef::Int(21))));
+        assert_eq!(temperature(21.5).unwrap().object_id, ObjectId::Temperature4);
+        // None is exact, the most precise one comes closest
+        assert_eq!(temperature(21.123).unwrap().object_id, ObjectId::Temperature4);
+        assert_eq!(temperature(400.5).unwrap().object_id, ObjectId::Temperature3);
+        assert_eq!(temperature(4000.0), Err(Error::ValueOutOfRange { object: ObjectId::Temperature4, value: 4000.0 }));
+        assert!(best(ObjectId::HumidityU8, ObjectValueRef::Float(120.0)).is_err());
+
+        assert_eq!(best(ObjectId::CountU32, ObjectValueRef::Int(-5)).unwrap().object_id, ObjectId::CountI8);
+        // Kilograms are not turned into pounds
+        assert_eq!(best(ObjectId::MassKg, ObjectValueRef::Float(10.0)).unwrap().object_id, ObjectId::MassKg);
+        assert_eq!(best(ObjectId::Text, ObjectValueRef::Text("hi")).unwrap().value, ObjectValueRef::Text("hi"));
+        assert_eq!(best(ObjectId::Temperature4, ObjectValueRef::Text("hi")), Err(Error::InvalidValue));
+
+        let exact = |object_id, value| {
+            ObjectRef::with_resolution(ObjectId::Temperature4, ObjectValueRef::Float(value), Resolution::Exact(object_id))
+        };
+        assert_eq!(exact(ObjectId::Temperature3, 21.0), Ok(ObjectRef::new(ObjectId::Temperature3, ObjectValueRef::Float(21.0))));
+        assert_eq!(exact(ObjectId::Temperature4, 400.0), Err(Error::ValueOutOfRange { object: ObjectId::Temperature4, value: 400.0 }));
+    }
+
+    #[test]
+    fn malformed_payloads() {
+        // A text claiming 255 bytes ends the data like any truncated object
+        assert_eq!(parse_service_data(&[0x40, 0x01, 0x61, 0x53, 0xFF, 0x41]).unwrap().objects.len(), 1);
+
+        let mut data = vec![0x40];
+        data.extend([0x01, 0x61].repeat(MAX_OBJECTS));
+        assert_eq!(parse_service_data(&data).unwrap().objects.len(), MAX_OBJECTS);
+        data.extend([0x01, 0x61]);
+        assert_eq!(parse_service_data(&data), Err(Error::TooManyObjects));
+        assert_eq!(parse_service_data_v1(&[0x02, 0x01, 0x61].repeat(MAX_OBJECTS + 1)), Err(Error::TooManyObjects));
+    }
+
+    #[test]
+    fn display_errors() {
+        let err = parse_service_data(&[0x40, 0x01, 0x61, 0x7F, 0x00]).unwrap_err();
+        assert_eq!(alloc::format!("{}", err), "unknown object id 0x7f in object 1 at byte 3");
+        assert_eq!(err.kind(), &Error::InvalidObjectId(0x7F));
+        match err {
+            Error::Object { parsed, .. } => assert_eq!(parsed[0].object_id, ObjectId::Battery),
+            _ => panic!("Expected the error to have the object context"),
+        }
+        let boxed: alloc::boxed::Box<dyn core::error::Error> = alloc::boxed::Box::new(Error::DecryptionFailed);
+        assert!(boxed.source().is_none());
+    }
+
+    #[test]
+    fn iterate_lazily() {
+        let data = [0x44, 0x00, 0x07, 0x01, 0x61, 0x53, 0x02, b'H', b'i'];
+        let mut objects = ServiceDataIter::new(&data).expect("Header to parse successfully");
+        assert!(objects.trigger_based);
+        let packet_id = objects.next().unwrap().unwrap();
+        assert_eq!((packet_id.object_id as u8, packet_id.value), (0x00, ObjectValueRef::Int(7)));
+        assert_eq!(objects.count(), 2);
+
+        let mut objects = ServiceDataIter::new(&[0x40, 0x01, 0x61, 0xFE, 0x01, 0x61]).unwrap();
+        assert!(objects.next().unwrap().is_ok());
+        assert_eq!(objects.next().unwrap(), Err(Error::InvalidObjectId(0xFE)));
+        assert!(objects.next().is_none());
+        assert!(ServiceDataIter::new(&[0x41, 0x01, 0x61]).is_err());
+    }
+
+    #[test]
+    fn round_like_std() {
+        for value in [2.5, -2.5, 0.49999, -0.4, 1e300, 12345.6789] {
+            assert_eq!(round(value), value.round(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn object_names() {
+        assert_eq!(ObjectId::from_name("temperature"), Some(ObjectId::Temperature4));
+        assert_eq!(ObjectId::from_name("door"), Some(ObjectId::DoorOpen));
+        assert_eq!(ObjectId::from_name("loudness"), None);
+        assert!(ObjectId::names().all(|name| ObjectId::from_name(name).is_some()));
+    }
+
+    #[test]
+    fn device_info_flags() {
+        for byte in 0..=u8::MAX {
+            let info = DeviceInfo::from(byte);
+            assert_eq!(info.encrypted, byte & 0x01 != 0, "{:#04x}", byte);
+            assert_eq!(info.trigger_based, byte & 0x04 != 0, "{:#04x}", byte);
+            assert_eq!(info.version, byte >> 5, "{:#04x}", byte);
+            assert_eq!(info.reserved, byte & 0x1A, "{:#04x}", byte);
+            assert_eq!(u8::from(info), byte);
+        }
+        let trigger_based = parse_service_data(&[0x44, 0x01, 0x61]).unwrap();
+        assert!(trigger_based.trigger_based);
+        assert_eq!(trigger_based.to_bytes().unwrap()[0], 0x44);
+        assert!(!parse_service_data(&[0x40, 0x01, 0x61]).unwrap().trigger_based);
+    }
+
+    #[test]
+    fn reject_other_versions() {
+        let version_3 = [0x60, 0x01, 0x61];
+        assert_eq!(parse_service_data(&version_3), Err(Error::UnsupportedVersion(3)));
+        assert_eq!(ServiceDataIter::new(&version_3).unwrap_err(), Error::UnsupportedVersion(3));
+        let lenient = ParseOptions { lenient_version: true, ..Default::default() };
+        let (service_data, _) = parse_service_data_with(&version_3, &lenient).unwrap();
+        assert_eq!((service_data.version, service_data.objects.len()), (3, 1));
+    }
+
+    #[test]
+    fn device_types() {
+        let service_data = parse_service_data(&[0x40, 0xF0, 0x01, 0x00]).unwrap();
+        let value = &service_data.objects[0].value;
+        assert_eq!(value, &ObjectValue::DeviceType(DeviceType::ShellyBluButton1));
+        assert_eq!(DeviceType::ShellyBluButton1.to_string(), "Shelly BLU Button1");
+        assert_eq!(service_data.to_bytes().unwrap(), vec![0x40, 0xF0, 0x01, 0x00]);
+
+        let unknown = DeviceType::from(0x1234);
+        assert_eq!(unknown, DeviceType::Unknown(0x1234));
+        assert_eq!((unknown.id(), unknown.name()), (0x1234, None));
+        assert_eq!(unknown.to_string(), "unknown device type 0x1234");
+        assert!(DEVICE_TYPES.iter().all(|(id, device_type, _)| device_type.id() == *id));
+    }
+
+    #[test]
+    fn firmware_versions() {
+        let data = [0x40, 0xF1, 0x00, 0x01, 0x02, 0x04, 0xF2, 0x00, 0x01, 0x06];
+        let service_data = parse_service_data(&data).unwrap();
+        assert_eq!(service_data.objects.len(), 2);
+        assert_eq!(service_data.to_bytes().unwrap(), data);
+
+        let version: FirmwareVersion = "6.1.0".parse().unwrap();
+        assert_eq!(version.to_string(), "6.1.0");
+        assert_eq!("4.2.1.0".parse::<FirmwareVersion>().unwrap().to_string(), "4.2.1.0");
+        assert!("4.2".parse::<FirmwareVersion>().is_err());
+        assert!("4.2.1.0.7".parse::<FirmwareVersion>().is_err());
+        let with_build = FirmwareVersion { build: Some(3), ..version };
+        let encode = |object_id| Object::new(object_id, ObjectValue::FirmwareVersion(with_build)).write(&mut Vec::new());
+        assert_eq!(encode(ObjectId::FirmwareVersionSmall), Err(Error::ValueOutOfRange { object: ObjectId::FirmwareVersionSmall, value: 3.0 }));
+        assert!(encode(ObjectId::FirmwareVersionLarge).is_ok());
+
+        let max = int_from::uint64(&mut Reader::new(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F])).unwrap();
+        assert_eq!(max, ObjectValueRef::UInt(i64::MAX as u64));
+        assert!(int_from::uint64(&mut Reader::new(&[0xFF; 6])).is_err());
+    }
+
+    #[test]
+    fn unsigned_values() {
+        let max = int_from::uint64(&mut Reader::new(&[0xFF; 8])).unwrap();
+        assert_eq!(max, ObjectValueRef::UInt(u64::MAX));
+
+        let data = [0x40, 0x50, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        let service_data = parse_service_data(&data).unwrap();
+        assert_eq!(service_data.objects[0].value, ObjectValue::UInt(0xFFFF_FFFF_FFFF));
+        assert_eq!(service_data.to_bytes().unwrap(), data);
+
+        let timestamp = |value| Object::new(ObjectId::Timestamp, value).write(&mut Vec::new());
+        assert!(timestamp(ObjectValue::Int(1_700_000_000)).is_ok());
+        let out_of_range = |value| Err(Error::ValueOutOfRange { object: ObjectId::Timestamp, value });
+        assert_eq!(timestamp(ObjectValue::Int(-1)), out_of_range(-1.0));
+        assert_eq!(timestamp(ObjectValue::UInt(1 << 48)), out_of_range((1u64 << 48) as f64));
+    }
+
+    #[test]
+    fn parse_objects() {
+        let examples = [
+            (vec![ 0x51, 0x87, 0x56], Object::new(ObjectId::Acceleration, ObjectValue::Float(22.151))),
+            (vec![0x01, 0x61], Object::new(ObjectId::Battery, ObjectValue::Int(97))),
+            (vec![0x4D, 0xFF, 0xFF, 0xFF, 0xFF], Object::new(ObjectId::EnergyU32, ObjectValue::Float(4294967.295))),
+            (vec![0x5E, 0x9F, 0x8C], Object::new(ObjectId::Direction, ObjectValue::Float(359.99))),
+            (vec![0x5F, 0x6C, 0x01], Object::new(ObjectId::Precipitation, ObjectValue::Float(36.4))),
+            (vec![0x60, 0x08], Object::new(ObjectId::Channel, ObjectValue::Int(8))),
+            (vec![0x61, 0x3E, 0x80], Object::new(ObjectId::RotationalSpeed, ObjectValue::Int(32830))),
+            (
+                vec![0xF1, 0x00, 0x01, 0x02, 0x04],
+                Object::new(
+                    ObjectId::FirmwareVersionLarge,
+                    ObjectValue::FirmwareVersion(FirmwareVersion { major: 4, minor: 2, patch: 1, build: Some(0) }),
+                ),
+            ),
+            (
+                vec![0xF2, 0x00, 0x01, 0x06],
+                Object::new(
+                    ObjectId::FirmwareVersionSmall,
+                    ObjectValue::FirmwareVersion(FirmwareVersion { major: 6, minor: 1, patch: 0, build: None }),
+                ),
+            ),
+        ];
+        for (data, expected) in examples.iter() {
+            let mut reader = Reader::new(data);
+            let parsed = Object::read(&mut reader).expect("Example to parse successfully");
+            assert_eq!(&parsed, expected)
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,466 @@
+//! Typed measurements, so that consumers don't have to match on the object id and then interpret
+//! the value themselves. Objects differing only in precision or range map to the same variant.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use crate::{ButtonEvent, DeviceType, DimmerEvent, Error, FirmwareVersion, Object, ObjectId, ObjectValue};
+
+macro_rules! units {
+    ($($(#[$meta:meta])* $name:ident = $symbol:literal,)*) => {
+        $(
+            $(#[$meta])*
+            #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+            #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+            pub struct $name(pub f64);
+
+            impl $name {
+                pub const SYMBOL: &'static str = $symbol;
+            }
+
+            impl fmt::Display for $name {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    write!(f, "{} {}", self.0, $symbol)
+                }
+            }
+        )*
+    }
+}
+
+units! {
+    Ampere = "A",
+    Celsius = "°C",
+    CubicMetre = "m³",
+    CubicMetrePerHour = "m³/h",
+    Degree = "°",
+    DegreePerSecond = "°/s",
+    Hectopascal = "hPa",
+    Kilogram = "kg",
+    KilowattHour = "kWh",
+    Litre = "L",
+    Lux = "lx",
+    Metre = "m",
+    MetrePerSecond = "m/s",
+    MetrePerSecondSquared = "m/s²",
+    MicrogramPerCubicMetre = "µg/m³",
+    MicrosiemensPerCentimetre = "µS/cm",
+    Millimetre = "mm",
+    PartsPerMillion = "ppm",
+    Percent = "%",
+    Pound = "lb",
+    RevolutionsPerMinute = "rpm",
+    Second = "s",
+    Volt = "V",
+    Watt = "W",
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Measurement {
+    /* Sensor data */
+    Acceleration(MetrePerSecondSquared),
+    /// In percent
+    Battery(u8),
+    Co2(PartsPerMillion),
+    Channel(u8),
+    Conductivity(MicrosiemensPerCentimetre),
+    Count(i64),
+    Current(Ampere),
+    Dewpoint(Celsius),
+    Direction(Degree),
+    /// Both in millimetres and in metres
+    Distance(Metre),
+    Duration(Second),
+    Energy(KilowattHour),
+    Gas(CubicMetre),
+    Gyroscope(DegreePerSecond),
+    Humidity(Percent),
+    Illuminance(Lux),
+    Mass(Kilogram),
+    MassPounds(Pound),
+    Moisture(Percent),
+    Pm2_5(MicrogramPerCubicMetre),
+    Pm10(MicrogramPerCubicMetre),
+    Power(Watt),
+    Precipitation(Millimetre),
+    Pressure(Hectopascal),
+    Raw(Vec<u8>),
+    Rotation(Degree),
+    RotationalSpeed(RevolutionsPerMinute),
+    Speed(MetrePerSecond),
+    Temperature(Celsius),
+    Text(String),
+    /// Seconds since the Unix epoch
+    Timestamp(u64),
+    Tvoc(MicrogramPerCubicMetre),
+    UvIndex(f64),
+    Voltage(Volt),
+    /// Both in millilitres and in litres
+    Volume(Litre),
+    VolumeFlowRate(CubicMetrePerHour),
+    VolumeStorage(Litre),
+    Water(Litre),
+
+    /* Binary sensor data */
+    BatteryLow(bool),
+    BatteryCharging(bool),
+    CarbonMonoxideDetected(bool),
+    Cold(bool),
+    Connectivity(bool),
+    DoorOpen(bool),
+    GarageDoorOpen(bool),
+    GasDetected(bool),
+    GenericBoolean(bool),
+    Heat(bool),
+    LightDetected(bool),
+    LockUnlocked(bool),
+    MoistureDetected(bool),
+    MotionDetected(bool),
+    MovementDetected(bool),
+    OccupancyDetected(bool),
+    IsOpen(bool),
+    PluggedIn(bool),
+    PowerOn(bool),
+    PresenceAtHome(bool),
+    ProblemDetected(bool),
+    IsRunning(bool),
+    IsSafe(bool),
+    SmokeDetected(bool),
+    SoundDetected(bool),
+    TamperDetected(bool),
+    VibrationDetected(bool),
+    WindowOpen(bool),
+
+    /* Events */
+    Button(ButtonEvent),
+    /// The event and the number of steps
+    Dimmer(DimmerEvent, u8),
+
+    /* Device information */
+    DeviceType(DeviceType),
+    FirmwareVersion(FirmwareVersion),
+
+    /* Misc data */
+    PacketId(u8),
+}
+
+/// The state of a binary sensor named like in the specification, so that consumers don't have
+/// to remember what true means for each sensor, e.g. that a true lock is unlocked.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryState {
+    Normal,
+    Low,
+    NotCharging,
+    Charging,
+    NotDetected,
+    Detected,
+    Cold,
+    Hot,
+    Disconnected,
+    Connected,
+    Closed,
+    Open,
+    Clear,
+    Off,
+    On,
+    NoLight,
+    LightDetected,
+    Locked,
+    Unlocked,
+    Dry,
+    Wet,
+    NotMoving,
+    Moving,
+    Unplugged,
+    PluggedIn,
+    Away,
+    Home,
+    Ok,
+    Problem,
+    NotRunning,
+    Running,
+    Unsafe,
+    Safe,
+}
+
+/// The states of the binary sensors for false and true.
+const BINARY_STATES: &[(ObjectId, BinaryState, BinaryState)] = {
+    use BinaryState::*;
+    use ObjectId as Id;
+    &[
+        (Id::GenericBoolean, Off, On),
+        (Id::PowerOn, Off, On),
+        (Id::IsOpen, Closed, Open),
+        (Id::BatteryLow, Normal, Low),
+        (Id::BatteryCharging, NotCharging, Charging),
+        (Id::CarbonMonoxideDetected, NotDetected, Detected),
+        (Id::Cold, Normal, Cold),
+        (Id::Connectivity, Disconnected, Connected),
+        (Id::DoorOpen, Closed, Open),
+        (Id::GarageDoorOpen, Closed, Open),
+        (Id::GasDetected, Clear, Detected),
+        (Id::Heat, Normal, Hot),
+        (Id::LightDetected, NoLight, LightDetected),
+        (Id::LockUnlocked, Locked, Unlocked),
+        (Id::MoistureDetected, Dry, Wet),
+        (Id::MotionDetected, Clear, Detected),
+        (Id::MovementDetected, NotMoving, Moving),
+        (Id::OccupancyDetected, Clear, Detected),
+        (Id::PluggedIn, Unplugged, PluggedIn),
+        (Id::PresenceAtHome, Away, Home),
+        (Id::ProblemDetected, Ok, Problem),
+        (Id::IsRunning, NotRunning, Running),
+        (Id::IsSafe, Unsafe, Safe),
+        (Id::SmokeDetected, Clear, Detected),
+        (Id::SoundDetected, Clear, Detected),
+        (Id::TamperDetected, Off, On),
+        (Id::VibrationDetected, Clear, Detected),
+        (Id::WindowOpen, Closed, Open),
+    ]
+};
+
+impl BinaryState {
+    /// The state of the binary sensor `object_id` for `value`, `None` for other objects.
+    pub fn of(object_id: ObjectId, value: bool) -> Option<BinaryState> {
+        BINARY_STATES
+            .iter()
+            .find(|(id, ..)| *id == object_id)
+            .map(|(_, off, on)| if value { *on } else { *off })
+    }
+
+    /// The label of the specification, e.g. `not charging`.
+    pub fn label(self) -> &'static str {
+        use BinaryState::*;
+        match self {
+            Normal => "normal",
+            Low => "low",
+            NotCharging => "not charging",
+            Charging => "charging",
+            NotDetected => "not detected",
+            Detected => "detected",
+            Cold => "cold",
+            Hot => "hot",
+            Disconnected => "disconnected",
+            Connected => "connected",
+            Closed => "closed",
+            Open => "open",
+            Clear => "clear",
+            Off => "off",
+            On => "on",
+            NoLight => "no light",
+            LightDetected => "light detected",
+            Locked => "locked",
+            Unlocked => "unlocked",
+            Dry => "dry",
+            Wet => "wet",
+            NotMoving => "not moving",
+            Moving => "moving",
+            Unplugged => "unplugged",
+            PluggedIn => "plugged in",
+            Away => "away",
+            Home => "home",
+            Ok => "ok",
+            Problem => "problem",
+            NotRunning => "not running",
+            Running => "running",
+            Unsafe => "unsafe",
+            Safe => "safe",
+        }
+    }
+}
+
+impl fmt::Display for BinaryState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl Object {
+    /// The state of a binary sensor, `None` for other objects.
+    pub fn binary_state(&self) -> Option<BinaryState> {
+        match self.value {
+            ObjectValue::Bool(value) => BinaryState::of(self.object_id, value),
+            _ => None,
+        }
+    }
+}
+
+fn number(value: &ObjectValue) -> Result<f64, Error> {
+    match value {
+        ObjectValue::Float(value) => Ok(*value),
+        ObjectValue::Int(value) => Ok(*value as f64),
+        ObjectValue::UInt(value) => Ok(*value as f64),
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+fn int<T: TryFrom<i64> + TryFrom<u64>>(object: &Object) -> Result<T, Error> {
+    let out_of_range = |value| Error::ValueOutOfRange { object: object.object_id, value };
+    match object.value {
+        ObjectValue::Int(value) => T::try_from(value).map_err(|_| out_of_range(value as f64)),
+        ObjectValue::UInt(value) => T::try_from(value).map_err(|_| out_of_range(value as f64)),
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+fn boolean(value: &ObjectValue) -> Result<bool, Error> {
+    match value {
+        ObjectValue::Bool(value) => Ok(*value),
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+impl TryFrom<Object> for Measurement {
+    type Error = Error;
+
+    /// Fails only for objects built by hand with a value of the wrong type for the object.
+    fn try_from(object: Object) -> Result<Self, Self::Error> {
+        use Measurement as M;
+        use ObjectId as Id;
+
+        let value = &object.value;
+        Ok(match object.object_id {
+            Id::Acceleration => M::Acceleration(MetrePerSecondSquared(number(value)?)),
+            Id::Battery => M::Battery(int(&object)?),
+            Id::CO2 => M::Co2(PartsPerMillion(number(value)?)),
+            Id::Channel => M::Channel(int(&object)?),
+            Id::Conductivity => M::Conductivity(MicrosiemensPerCentimetre(number(value)?)),
+            Id::CountU8 | Id::CountU16 | Id::CountU32 | Id::CountI8 | Id::CountI16 | Id::CountI32 => {
+                M::Count(int(&object)?)
+            }
+            Id::CurrentU16 | Id::CurrentI16 => M::Current(Ampere(number(value)?)),
+            Id::Dewpoint => M::Dewpoint(Celsius(number(value)?)),
+            Id::Direction => M::Direction(Degree(number(value)?)),
+            Id::DistanceMM => M::Distance(Metre(number(value)? / 1000.0)),
+            Id::DistanceM => M::Distance(Metre(number(value)?)),
+            Id::Duration => M::Duration(Second(number(value)?)),
+            Id::EnergyU32 | Id::EngergyU24 => M::Energy(KilowattHour(number(value)?)),
+            Id::GasU24 | Id::GasU32 => M::Gas(CubicMetre(number(value)?)),
+            Id::Gyroscope => M::Gyroscope(DegreePerSecond(number(value)?)),
+            Id::HumidityU16 | Id::HumidityU8 => M::Humidity(Percent(number(value)?)),
+            Id::Illuminance => M::Illuminance(Lux(number(value)?)),
+            Id::MassKg => M::Mass(Kilogram(number(value)?)),
+            Id::MassLb => M::MassPounds(Pound(number(value)?)),
+            Id::MoistureSmall | Id::MoistureLarge => M::Moisture(Percent(number(value)?)),
+            Id::PM2d5 => M::Pm2_5(MicrogramPerCubicMetre(number(value)?)),
+            Id::PM10 => M::Pm10(MicrogramPerCubicMetre(number(value)?)),
+            Id::PowerSmall | Id::PowerLarge => M::Power(Watt(number(value)?)),
+            Id::Precipitation => M::Precipitation(Millimetre(number(value)?)),
+            Id::Pressure => M::Pressure(Hectopascal(number(value)?)),
+            Id::Raw => match object.value {
+                ObjectValue::Raw(bytes) => M::Raw(bytes),
+                _ => return Err(Error::InvalidValue),
+            },
+            Id::Rotation => M::Rotation(Degree(number(value)?)),
+            Id::RotationalSpeed => M::RotationalSpeed(RevolutionsPerMinute(number(value)?)),
+            Id::Speed => M::Speed(MetrePerSecond(number(value)?)),
+            Id::Temperature1 | Id::Temperature2 | Id::Temperature3 | Id::Temperature4 => {
+                M::Temperature(Celsius(number(value)?))
+            }
+            Id::Text => match object.value {
+                ObjectValue::Text(text) => M::Text(text),
+                _ => return Err(Error::InvalidValue),
+            },
+            Id::Timestamp => M::Timestamp(int(&object)?),
+            Id::Tvoc => M::Tvoc(MicrogramPerCubicMetre(number(value)?)),
+            Id::VoltageSmall | Id::VoltageLarge => M::Voltage(Volt(number(value)?)),
+            Id::Volume1 | Id::Volume2 => M::Volume(Litre(number(value)?)),
+            Id::Volume3 => M::Volume(Litre(number(value)? / 1000.0)),
+            Id::VolumeStorage => M::VolumeStorage(Litre(number(value)?)),
+            Id::VolumeFlowRate => M::VolumeFlowRate(CubicMetrePerHour(number(value)?)),
+            Id::UVIndex => M::UvIndex(number(value)?),
+            Id::Water => M::Water(Litre(number(value)?)),
+
+            Id::BatteryLow => M::BatteryLow(boolean(value)?),
+            Id::BatteryCharging => M::BatteryCharging(boolean(value)?),
+            Id::CarbonMonoxideDetected => M::CarbonMonoxideDetected(boolean(value)?),
+            Id::Cold => M::Cold(boolean(value)?),
+            Id::Connectivity => M::Connectivity(boolean(value)?),
+            Id::DoorOpen => M::DoorOpen(boolean(value)?),
+            Id::GarageDoorOpen => M::GarageDoorOpen(boolean(value)?),
+            Id::GasDetected => M::GasDetected(boolean(value)?),
+            Id::GenericBoolean => M::GenericBoolean(boolean(value)?),
+            Id::Heat => M::Heat(boolean(value)?),
+            Id::LightDetected => M::LightDetected(boolean(value)?),
+            Id::LockUnlocked => M::LockUnlocked(boolean(value)?),
+            Id::MoistureDetected => M::MoistureDetected(boolean(value)?),
+            Id::MotionDetected => M::MotionDetected(boolean(value)?),
+            Id::MovementDetected => M::MovementDetected(boolean(value)?),
+            Id::OccupancyDetected => M::OccupancyDetected(boolean(value)?),
+            Id::IsOpen => M::IsOpen(boolean(value)?),
+            Id::PluggedIn => M::PluggedIn(boolean(value)?),
+            Id::PowerOn => M::PowerOn(boolean(value)?),
+            Id::PresenceAtHome => M::PresenceAtHome(boolean(value)?),
+            Id::ProblemDetected => M::ProblemDetected(boolean(value)?),
+            Id::IsRunning => M::IsRunning(boolean(value)?),
+            Id::IsSafe => M::IsSafe(boolean(value)?),
+            Id::SmokeDetected => M::SmokeDetected(boolean(value)?),
+            Id::SoundDetected => M::SoundDetected(boolean(value)?),
+            Id::TamperDetected => M::TamperDetected(boolean(value)?),
+            Id::VibrationDetected => M::VibrationDetected(boolean(value)?),
+            Id::WindowOpen => M::WindowOpen(boolean(value)?),
+
+            Id::Button => match value {
+                ObjectValue::ButtonEvent(event) => M::Button(*event),
+                _ => return Err(Error::InvalidValue),
+            },
+            Id::Dimmer => match value {
+                ObjectValue::DimmerEvent(event, steps) => M::Dimmer(*event, *steps),
+                _ => return Err(Error::InvalidValue),
+            },
+
+            Id::DeviceTypeId => match value {
+                ObjectValue::DeviceType(device_type) => M::DeviceType(*device_type),
+                _ => return Err(Error::InvalidValue),
+            },
+            Id::FirmwareVersionLarge | Id::FirmwareVersionSmall => match value {
+                ObjectValue::FirmwareVersion(version) => M::FirmwareVersion(*version),
+                _ => return Err(Error::InvalidValue),
+            },
+
+            Id::PacketId => M::PacketId(int(&object)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse_service_data;
+
+    #[test]
+    fn from_objects() {
+        let data = [0x40, 0x00, 0x07, 0x01, 0x61, 0x02, 0xCA, 0x09, 0x2E, 0x32, 0x40, 0xC4, 0x09, 0x3A, 0x01];
+        let measurements: Result<Vec<Measurement>, Error> =
+            parse_service_data(&data).unwrap().objects.into_iter().map(Measurement::try_from).collect();
+        assert_eq!(
+            measurements,
+            Ok(alloc::vec![
+                Measurement::PacketId(7),
+                Measurement::Battery(97),
+                Measurement::Temperature(Celsius(25.06)),
+                Measurement::Humidity(Percent(50.0)),
+                Measurement::Distance(Metre(2.5)),
+                Measurement::Button(ButtonEvent::Press),
+            ])
+        );
+        let text = Object::new(ObjectId::Temperature4, ObjectValue::Text(String::from("warm")));
+        assert_eq!(Measurement::try_from(text), Err(Error::InvalidValue));
+        assert_eq!(alloc::format!("{}", Celsius(21.5)), "21.5 °C");
+    }
+
+    #[test]
+    fn binary_states() {
+        // Door open, lock locked and a window with a value that is neither open nor closed
+        let objects = parse_service_data(&[0x40, 0x1A, 0x01, 0x1F, 0x00]).unwrap().objects;
+        assert_eq!(objects[0].value, ObjectValue::Bool(true));
+        assert_eq!(objects[0].binary_state(), Some(BinaryState::Open));
+        assert_eq!(objects[1].binary_state(), Some(BinaryState::Locked));
+        assert_eq!(parse_service_data(&[0x40, 0x2D, 0x02]).unwrap_err().kind(), &Error::InvalidValue);
+
+        assert_eq!(BinaryState::of(ObjectId::BatteryCharging, false).map(BinaryState::label), Some("not charging"));
+        assert_eq!(BinaryState::of(ObjectId::Battery, true), None);
+        assert_eq!(alloc::format!("{}", BinaryState::PluggedIn), "plugged in");
+    }
+}
@@ -0,0 +1,211 @@
+//! The `serde` representation of objects: `{"name": "temperature", "id": 2, "unit": "°C",
+//! "value": 21.5}`. The id identifies the object, name and unit are for readers and ignored when
+//! deserializing. Raw values are hex strings, button events snake case names like `long_press` and
+//! dimmer events `{"event": "rotate_left", "steps": ...}`.
+//! Repeated objects have their `"instance"`, it's left out for the first one.
+
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::Write;
+
+use serde::{
+    de::{self, IntoDeserializer},
+    ser::SerializeStruct,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{value_from_raw, ButtonEvent, DeviceType, DimmerEvent, FirmwareVersion, Object, ObjectId, ObjectRef, ObjectValue, ObjectValueRef, Reader};
+
+struct Value<'a>(ObjectValueRef<'a>);
+
+impl Serialize for Value<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            ObjectValueRef::Float(value) => serializer.serialize_f64(value),
+            ObjectValueRef::Int(value) => serializer.serialize_i64(value),
+            ObjectValueRef::UInt(value) => serializer.serialize_u64(value),
+            ObjectValueRef::Bool(value) => serializer.serialize_bool(value),
+            ObjectValueRef::Raw(bytes) => {
+                let mut hex = String::with_capacity(bytes.len() * 2);
+                for byte in bytes {
+                    let _ = write!(hex, "{:02x}", byte);
+                }
+                serializer.serialize_str(&hex)
+            }
+            ObjectValueRef::ButtonEvent(event) => event.serialize(serializer),
+            ObjectValueRef::DimmerEvent(event, steps) => {
+                let mut dimmer = serializer.serialize_struct("DimmerEvent", 2)?;
+                dimmer.serialize_field("event", &event)?;
+                dimmer.serialize_field("steps", &steps)?;
+                dimmer.end()
+            }
+            ObjectValueRef::Text(text) => serializer.serialize_str(text),
+            ObjectValueRef::FirmwareVersion(version) => serializer.collect_str(&version),
+            ObjectValueRef::DeviceType(device_type) => serializer.serialize_u16(device_type.id()),
+        }
+    }
+}
+
+fn serialize_object<S: Serializer>(
+    object_id: ObjectId,
+    value: ObjectValueRef,
+    instance: u8,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut object = serializer.serialize_struct("Object", 5)?;
+    object.serialize_field("name", object_id.name())?;
+    object.serialize_field("id", &(object_id as u8))?;
+    if instance > 0 {
+        object.serialize_field("instance", &instance)?;
+    } else {
+        object.skip_field("instance")?;
+    }
+    object.serialize_field("unit", &object_id.unit())?;
+    object.serialize_field("value", &Value(value))?;
+    object.end()
+}
+
+impl Serialize for ObjectRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_object(self.object_id, self.value, self.instance, serializer)
+    }
+}
+
+impl Serialize for Object {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let value = match &self.value {
+            ObjectValue::Float(value) => ObjectValueRef::Float(*value),
+            ObjectValue::Int(value) => ObjectValueRef::Int(*value),
+            ObjectValue::UInt(value) => ObjectValueRef::UInt(*value),
+            ObjectValue::Bool(value) => ObjectValueRef::Bool(*value),
+            ObjectValue::Raw(bytes) => ObjectValueRef::Raw(bytes),
+            ObjectValue::ButtonEvent(event) => ObjectValueRef::ButtonEvent(*event),
+            ObjectValue::DimmerEvent(event, steps) => ObjectValueRef::DimmerEvent(*event, *steps),
+            ObjectValue::Text(text) => ObjectValueRef::Text(text),
+            ObjectValue::FirmwareVersion(version) => ObjectValueRef::FirmwareVersion(*version),
+            ObjectValue::DeviceType(device_type) => ObjectValueRef::DeviceType(*device_type),
+        };
+        serialize_object(self.object_id, value, self.instance, serializer)
+    }
+}
+
+#[derive(Deserialize)]
+struct ObjectRepr {
+    id: u8,
+    #[serde(default)]
+    instance: u8,
+    value: ValueRepr,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ValueRepr {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Text(String),
+    Dimmer { event: DimmerEvent, steps: u8 },
+}
+
+impl<'de> Deserialize<'de> for Object {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = ObjectRepr::deserialize(deserializer)?;
+        let object_id = ObjectId::try_from(repr.id).map_err(|_| de::Error::custom(format!("unknown object id {:#04x}", repr.id)))?;
+        // Which value an object has only depends on its id, so reading all zeros gives an example
+        let example = value_from_raw(object_id, &mut Reader::new(&[0u8; 8]))
+            .map_err(|_| de::Error::custom("object without example value"))?;
+        let value = match (example.value, repr.value) {
+            (ObjectValueRef::Float(_), ValueRepr::Float(value)) => ObjectValue::Float(value),
+            (ObjectValueRef::Float(_), ValueRepr::Int(value)) => ObjectValue::Float(value as f64),
+            (ObjectValueRef::Int(_), ValueRepr::Int(value)) => ObjectValue::Int(value),
+            (ObjectValueRef::UInt(_), ValueRepr::Int(value)) => {
+                ObjectValue::UInt(u64::try_from(value).map_err(|_| de::Error::custom("negative value for unsigned object"))?)
+            }
+            (ObjectValueRef::UInt(_), ValueRepr::UInt(value)) => ObjectValue::UInt(value),
+            (ObjectValueRef::Bool(_), ValueRepr::Bool(value)) => ObjectValue::Bool(value),
+            (ObjectValueRef::Raw(_), ValueRepr::Text(hex)) => {
+                ObjectValue::Raw(decode_hex(&hex).ok_or_else(|| de::Error::custom("invalid hex in raw value"))?)
+            }
+            (ObjectValueRef::ButtonEvent(_), ValueRepr::Text(event)) => {
+                let event: Result<ButtonEvent, de::value::Error> = ButtonEvent::deserialize(event.as_str().into_deserializer());
+                ObjectValue::ButtonEvent(event.map_err(de::Error::custom)?)
+            }
+            (ObjectValueRef::DimmerEvent(..), ValueRepr::Dimmer { event, steps }) => ObjectValue::DimmerEvent(event, steps),
+            (ObjectValueRef::Text(_), ValueRepr::Text(text)) => ObjectValue::Text(text),
+            (ObjectValueRef::DeviceType(_), ValueRepr::Int(id)) => {
+                let id = u16::try_from(id).map_err(|_| de::Error::custom("device type id out of range"))?;
+                ObjectValue::DeviceType(DeviceType::from(id))
+            }
+            (ObjectValueRef::FirmwareVersion(example), ValueRepr::Text(text)) => {
+                let version: FirmwareVersion = text.parse().map_err(|_| de::Error::custom("invalid firmware version"))?;
+                if version.build.is_some() != example.build.is_some() {
+                    return Err(de::Error::custom("firmware version with the wrong number of parts"));
+                }
+                ObjectValue::FirmwareVersion(version)
+            }
+            _ => return Err(de::Error::custom(format!("invalid value for object {}", object_id.name()))),
+        };
+        Ok(Object {
+            object_id,
+            value,
+            instance: repr.instance,
+        })
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parse_service_data, ServiceData};
+    use serde_json::json;
+
+    #[test]
+    fn json_representation() {
+        let data = [0x40, 0x02, 0xCA, 0x09, 0x15, 0x01, 0x3C, 0x01, 0x03, 0x54, 0x02, 0xAB, 0xCD];
+        let service_data = parse_service_data(&data).unwrap();
+        let json = serde_json::to_value(&service_data).unwrap();
+        assert_eq!(
+            json["objects"],
+            json!([
+                {"name": "temperature", "id": 2, "unit": "°C", "value": 25.06},
+                {"name": "battery_low", "id": 21, "unit": null, "value": true},
+                {"name": "dimmer", "id": 60, "unit": null, "value": {"event": "rotate_left", "steps": 3}},
+                {"name": "raw", "id": 84, "unit": null, "value": "abcd"},
+            ])
+        );
+        let parsed: ServiceData = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, service_data);
+
+        let button: Object = serde_json::from_value(json!({"id": 0x3A, "value": "press"})).unwrap();
+        assert_eq!(button.value, ObjectValue::ButtonEvent(ButtonEvent::Press));
+        let battery: Object = serde_json::from_value(json!({"id": 0x01, "value": 97})).unwrap();
+        assert_eq!(battery.value, ObjectValue::Int(97));
+        let timestamp: Object = serde_json::from_value(json!({"id": 0x50, "value": 1_700_000_000})).unwrap();
+        assert_eq!(timestamp.value, ObjectValue::UInt(1_700_000_000));
+        assert!(serde_json::from_value::<Object>(json!({"id": 0x50, "value": -1})).is_err());
+        assert!(serde_json::from_value::<Object>(json!({"id": 0x01, "value": "full"})).is_err());
+        assert!(serde_json::from_value::<Object>(json!({"id": 0x7F, "value": 1})).is_err());
+
+        let service_data = parse_service_data(&[0x40, 0x02, 0xCA, 0x09, 0x02, 0x10, 0x27]).unwrap();
+        let json = serde_json::to_value(&service_data).unwrap();
+        assert!(json["objects"][0].get("instance").is_none());
+        assert_eq!(json["objects"][1]["instance"], 1);
+        assert_eq!(serde_json::from_value::<ServiceData>(json).unwrap(), service_data);
+
+        let service_data = parse_service_data(&[0x40, 0xF2, 0x00, 0x01, 0x06]).unwrap();
+        let json = serde_json::to_value(&service_data).unwrap();
+        assert_eq!(json["objects"][0]["value"], "6.1.0");
+        assert_eq!(serde_json::from_value::<ServiceData>(json).unwrap(), service_data);
+        assert!(serde_json::from_value::<Object>(json!({"id": 0xF2, "value": "6.1.0.1"})).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! Rendering of decoded payloads for humans and scripts.
+
+use bthome::{encode_service_data, hex, Error, Object, ObjectValue, ServiceData};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// The decoded objects, one per line
+    #[default]
+    Pretty,
+    /// One JSON object per payload
+    Json,
+    /// The bytes of each object next to its decoded value
+    Annotated,
+}
+
+pub fn render(payload: &[u8], result: Result<ServiceData, Error>, format: Format) -> String {
+    let service_data = match result {
+        Ok(service_data) => service_data,
+        Err(err) => {
+            return match format {
+                Format::Json => json!({"payload": hex::encode(payload), "error": format!("{:?}", err)}).to_string(),
+                Format::Pretty | Format::Annotated => format!("{}\n  error: {:?}", hex::encode(payload), err),
+            }
+        }
+    };
+    let header = format!(
+        "BTHome v{}, {}encrypted, {}trigger based",
+        service_data.version,
+        if service_data.encrypted { "" } else { "not " },
+        if service_data.trigger_based { "" } else { "not " },
+    );
+    match format {
+        Format::Pretty => {
+            let mut lines = vec![hex::encode(payload), format!("  {}", header)];
+            for object in &service_data.objects {
+                lines.push(format!("  {:?}: {}", object.object_id, value_text(&object.value)));
+            }
+            lines.join("\n")
+        }
+        Format::Json => {
+            let objects: Vec<Value> = service_data
+                .objects
+                .into_iter()
+                .map(|object| {
+                    let name = format!("{:?}", object.object_id);
+                    json!({
+                        "id": object.object_id as u8,
+                        "name": name,
+                        "value": value_json(&object.value),
+                    })
+                })
+                .collect();
+            json!({
+                "payload": hex::encode(payload),
+                "version": service_data.version,
+                "encrypted": service_data.encrypted,
+                "trigger_based": service_data.trigger_based,
+                "objects": objects,
+            })
+            .to_string()
+        }
+        Format::Annotated => annotate(payload, service_data, &header),
+    }
+}
+
+pub fn invalid_hex(line: &str, format: Format) -> String {
+    match format {
+        Format::Json => json!({"payload": line, "error": "invalid hex"}).to_string(),
+        Format::Pretty | Format::Annotated => format!("{}\n  error: invalid hex", line),
+    }
+}
+
+/// Lists the bytes of the device info and of each object with their meaning.
+fn annotate(payload: &[u8], service_data: ServiceData, header: &str) -> String {
+    let mut rows = vec![(hex::encode(&payload[..1]), header.to_string())];
+    let mut offset = 1;
+    for object in service_data.objects {
+        let description = format!("{:?}: {}", object.object_id, value_text(&object.value));
+        // The parser does not report where an object ended, but the encoded size of an object
+        // only depends on its type
+        let length = encoded_length(object).unwrap_or(payload.len() - offset);
+        let bytes = &payload[offset..(offset + length).min(payload.len())];
+        rows.push((spaced_hex(bytes), description));
+        offset += length;
+    }
+    let width = rows.iter().map(|(bytes, _)| bytes.len()).max().unwrap_or(0);
+    rows.iter()
+        .map(|(bytes, description)| format!("{:width$}  {}", bytes, description, width = width))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn encoded_length(object: Object) -> Option<usize> {
+    let service_data = ServiceData {
+        encrypted: false,
+        trigger_based: false,
+        version: 2,
+        objects: vec![object],
+    };
+    // Without the device info byte
+    encode_service_data(&service_data).ok().map(|data| data.len() - 1)
+}
+
+fn spaced_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ")
+}
+
+fn value_text(value: &ObjectValue) -> String {
+    match value {
+        ObjectValue::Float(value) => value.to_string(),
+        ObjectValue::Int(value) => value.to_string(),
+        ObjectValue::UInt(value) => value.to_string(),
+        ObjectValue::Bool(value) => value.to_string(),
+        ObjectValue::Raw(bytes) => hex::encode(bytes),
+        ObjectValue::Text(text) => format!("{:?}", text),
+        ObjectValue::FirmwareVersion(version) => version.to_string(),
+        ObjectValue::DeviceType(device_type) => device_type.to_string(),
+        ObjectValue::ButtonEvent(event) => format!("{:?}", event),
+        ObjectValue::DimmerEvent(event, steps) => format!("{:?} by {} steps", event, steps),
+    }
+}
+
+fn value_json(value: &ObjectValue) -> Value {
+    match value {
+        ObjectValue::Float(value) => json!(value),
+        ObjectValue::Int(value) => json!(value),
+        ObjectValue::UInt(value) => json!(value),
+        ObjectValue::Bool(value) => json!(value),
+        ObjectValue::Raw(bytes) => json!(hex::encode(bytes)),
+        ObjectValue::Text(text) => json!(text),
+        ObjectValue::FirmwareVersion(version) => json!(version.to_string()),
+        ObjectValue::DeviceType(device_type) => json!(device_type.to_string()),
+        ObjectValue::ButtonEvent(event) => json!(format!("{:?}", event)),
+        ObjectValue::DimmerEvent(event, steps) => json!({"event": format!("{:?}", event), "steps": steps}),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bthome::parse_service_data;
+
+    use super::*;
+
+    const EXAMPLE: [u8; 7] = [0x40, 0x02, 0xC4, 0x09, 0x03, 0xBF, 0x13];
+
+    #[test]
+    fn render_formats() {
+        assert_eq!(
+            render(&EXAMPLE, parse_service_data(&EXAMPLE), Format::Pretty),
+            "4002c40903bf13\n  BTHome v2, not encrypted, not trigger based\n  Temperature4: 25\n  HumidityU16: 50.55"
+        );
+        assert_eq!(
+            render(&EXAMPLE, parse_service_data(&EXAMPLE), Format::Annotated),
+            "40        BTHome v2, not encrypted, not trigger based\n02 c4 09  Temperature4: 25\n03 bf 13  HumidityU16: 50.55"
+        );
+        let json: Value = serde_json::from_str(&render(&EXAMPLE, parse_service_data(&EXAMPLE), Format::Json)).unwrap();
+        assert_eq!(json["objects"][0], json!({"id": 2, "name": "Temperature4", "value": 25.0}));
+    }
+
+    #[test]
+    fn render_errors() {
+        let payload = [0x41, 0x02];
+        assert_eq!(
+            render(&payload, parse_service_data(&payload), Format::Pretty),
+            "4102\n  error: Encrypted"
+        );
+        assert_eq!(
+            invalid_hex("40zz", Format::Json),
+            r#"{"error":"invalid hex","payload":"40zz"}"#
+        );
+    }
+}
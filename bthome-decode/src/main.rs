@@ -0,0 +1,85 @@
+//! Decodes hex encoded BTHome payloads from the command line, files or stdin, e.g. copied from
+//! firmware logs or bug reports.
+
+use std::{
+    io::BufRead,
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use bthome::{hex, parse_service_data};
+use clap::Parser;
+
+mod format;
+
+use format::Format;
+
+/// Decode hex encoded BTHome service data.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Hex encoded service data, read from stdin one per line if neither payloads nor files are given
+    payloads: Vec<String>,
+
+    /// Decode the payloads in a file, one per line, can be given multiple times
+    #[arg(short, long, value_name = "FILE")]
+    file: Vec<PathBuf>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t)]
+    format: Format,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let mut failed = false;
+    let mut decode = |line: &str| {
+        let line = line.trim();
+        // Blank lines and comments in files are skipped
+        if line.is_empty() || line.starts_with('#') {
+            return;
+        }
+        let output = match hex::decode(line) {
+            Some(payload) => {
+                let result = parse_service_data(&payload);
+                failed |= result.is_err();
+                format::render(&payload, result, args.format)
+            }
+            None => {
+                failed = true;
+                format::invalid_hex(line, args.format)
+            }
+        };
+        println!("{}", output);
+    };
+
+    for payload in &args.payloads {
+        decode(payload);
+    }
+    for path in &args.file {
+        match std::fs::read_to_string(path) {
+            Ok(content) => content.lines().for_each(&mut decode),
+            Err(err) => {
+                eprintln!("Error reading {}: {}", path.display(), err);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if args.payloads.is_empty() && args.file.is_empty() {
+        for line in std::io::stdin().lock().lines() {
+            match line {
+                Ok(line) => decode(&line),
+                Err(err) => {
+                    eprintln!("Error reading stdin: {}", err);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,265 @@
+//! Description of a payload, read from JSON or YAML and the command line:
+//!
+//! ```yaml
+//! packet_id: 12
+//! trigger_based: false
+//! objects:
+//!   temperature: 21.5
+//!   humidity: 40
+//! ```
+//!
+//! Objects that occur more than once are given as a list instead, e.g.
+//! `objects: [{temperature: 21.5}, {temperature: 19}]`.
+//!
+//! Objects given by name are encoded with the most precise object of that name, with
+//! `smallest: true` with the smallest one that holds the value exactly. Objects given by id like
+//! `0x45` are always encoded with that object.
+
+use std::collections::BTreeMap;
+
+use bthome::{
+    ButtonEvent, DimmerEvent, Object, ObjectId, ObjectRef, ObjectValue, ObjectValueRef, Resolution, ServiceData,
+};
+use serde::Deserialize;
+use serde_yaml::Value;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Description {
+    #[serde(default)]
+    pub trigger_based: bool,
+    pub packet_id: Option<u8>,
+    /// Encode objects given by name with the smallest object that holds their value exactly, e.g.
+    /// a temperature of 21 °C in one byte instead of two
+    #[serde(default)]
+    pub smallest: bool,
+    /// Split the objects into payloads of at most this many bytes, each with its own packet id
+    pub max_len: Option<usize>,
+    #[serde(default)]
+    objects: Objects,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Objects {
+    Map(BTreeMap<String, Value>),
+    List(Vec<BTreeMap<String, Value>>),
+}
+
+/// What encrypted payloads are encrypted with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Encryption {
+    pub key: [u8; 16],
+    pub mac: [u8; 6],
+    pub counter: u32,
+}
+
+impl Default for Objects {
+    fn default() -> Self {
+        Objects::List(Vec::new())
+    }
+}
+
+impl Description {
+    /// Parses a description in YAML, which includes JSON.
+    pub fn parse(content: &str) -> Result<Description, String> {
+        serde_yaml::from_str(content).map_err(|err| format!("invalid description: {}", err))
+    }
+
+    /// Adds a measurement given as `NAME=VALUE`.
+    pub fn add_value(&mut self, spec: &str) -> Result<(), String> {
+        let (name, value) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=VALUE, got {:?}", spec))?;
+        let value: Value = serde_yaml::from_str(value).map_err(|_| format!("invalid value {:?}", value))?;
+        let mut objects = match std::mem::take(&mut self.objects) {
+            Objects::Map(map) => map.into_iter().map(|entry| BTreeMap::from([entry])).collect(),
+            Objects::List(list) => list,
+        };
+        objects.push(BTreeMap::from([(name.to_string(), value)]));
+        self.objects = Objects::List(objects);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packet_id.is_none()
+            && match &self.objects {
+                Objects::Map(map) => map.is_empty(),
+                Objects::List(list) => list.iter().all(|entry| entry.is_empty()),
+            }
+    }
+
+    /// Encodes the payload as hex, the objects are ordered by id as required by the specification.
+    /// Payloads split with `max_len` are given one per line, encrypted ones with increasing counters.
+    pub fn encode(&self, encryption: Option<&Encryption>) -> Result<String, String> {
+        let entries: Vec<(&String, &Value)> = match &self.objects {
+            Objects::Map(map) => map.iter().collect(),
+            Objects::List(list) => list.iter().flatten().collect(),
+        };
+        let mut objects = Vec::new();
+        if let Some(packet_id) = self.packet_id {
+            objects.push((0x00, ObjectValue::Int(packet_id as i64)));
+        }
+        for (name, value) in entries {
+            let id = object_id(name)?;
+            let value = object_value(id, value).map_err(|err| format!("{}: {}", name, err))?;
+            if self.smallest && !name.starts_with("0x") {
+                let object_id = ObjectId::try_from(id).expect("Object id to be checked");
+                let object = ObjectRef::with_resolution(object_id, ObjectValueRef::from(&value), Resolution::Best)
+                    .map_err(|err| format!("{}: {}", name, err))?;
+                objects.push((object.object_id as u8, object.value.into()));
+            } else {
+                objects.push((id, value));
+            }
+        }
+        objects.sort_by_key(|(id, _)| *id);
+        let service_data = ServiceData {
+            encrypted: false,
+            trigger_based: self.trigger_based,
+            version: 2,
+            objects: objects
+                .into_iter()
+                .map(|(id, value)| Object::new(ObjectId::try_from(id).expect("Object id to be checked"), value))
+                .collect(),
+        };
+        let parts = match self.max_len {
+            Some(max_len) => ServiceData { encrypted: encryption.is_some(), ..service_data }
+                .split_into_advertisements(max_len)
+                .map_err(|err| format!("error splitting: {:?}", err))?,
+            None => vec![service_data],
+        };
+        let mut payloads = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            let data = match encryption {
+                Some(encryption) => {
+                    part.encrypt(&encryption.key, &encryption.mac, encryption.counter.wrapping_add(i as u32))
+                }
+                None => part.to_bytes(),
+            }
+            .map_err(|err| format!("error encoding: {:?}", err))?;
+            payloads.push(data.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+        }
+        Ok(payloads.join("\n"))
+    }
+}
+
+/// Whether `name` is the name of an object, like `temperature`.
+pub fn is_object(name: &str) -> bool {
+    ObjectId::from_name(name).is_some()
+}
+
+/// Looks up an object by name or by id like `0x45`.
+fn object_id(name: &str) -> Result<u8, String> {
+    let id = match name.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).map_err(|_| format!("invalid object id {:?}", name))?,
+        None => ObjectId::from_name(name)
+            .map(|object| object as u8)
+            .ok_or_else(|| format!("unknown object {:?}", name))?,
+    };
+    ObjectId::try_from(id).map_err(|_| format!("unknown object id {:?}", name))?;
+    Ok(id)
+}
+
+fn object_value(id: u8, value: &Value) -> Result<ObjectValue, String> {
+    let object = ObjectId::try_from(id).map_err(|err| format!("{:?}", err))?;
+    match (object, value) {
+        (ObjectId::Text, Value::String(text)) => Ok(ObjectValue::Text(text.clone())),
+        (ObjectId::Raw, Value::String(hex)) => decode_hex(hex)
+            .map(ObjectValue::Raw)
+            .ok_or_else(|| format!("invalid hex {:?}", hex)),
+        (ObjectId::Button, Value::String(event)) => button_event(event).map(ObjectValue::ButtonEvent),
+        // Negative steps turn left
+        (ObjectId::Dimmer, Value::Number(steps)) => {
+            let steps = steps.as_i64().ok_or("the steps of a dimmer have to be an integer")?;
+            let event = if steps < 0 { DimmerEvent::RotateLeft } else { DimmerEvent::RotateRight };
+            let steps = u8::try_from(steps.unsigned_abs()).map_err(|_| "at most 255 steps are possible")?;
+            Ok(ObjectValue::DimmerEvent(event, steps))
+        }
+        (_, Value::Bool(value)) => Ok(ObjectValue::Bool(*value)),
+        (_, Value::Number(number)) => match number.as_i64() {
+            Some(value) => Ok(ObjectValue::Int(value)),
+            None => Ok(ObjectValue::Float(number.as_f64().unwrap_or(f64::NAN))),
+        },
+        (_, value) => Err(format!("unsupported value {:?}", value)),
+    }
+}
+
+fn button_event(name: &str) -> Result<ButtonEvent, String> {
+    Ok(match name {
+        "none" => ButtonEvent::None,
+        "press" => ButtonEvent::Press,
+        "double_press" => ButtonEvent::DoublePress,
+        "triple_press" => ButtonEvent::TriplePress,
+        "long_press" => ButtonEvent::LongPress,
+        "long_double_press" => ButtonEvent::LongDoublePress,
+        "long_triple_press" => ButtonEvent::LongTriplePress,
+        "hold_press" => ButtonEvent::HoldPress,
+        "hold" => ButtonEvent::Hold,
+        _ => return Err(format!("unknown button event {:?}", name)),
+    })
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_descriptions() {
+        let json = Description::parse(r#"{"objects": {"humidity": 50.55, "temperature": 25}}"#).unwrap();
+        assert_eq!(json.encode(None), Ok("4002c40903bf13".to_string()));
+
+        let yaml = Description::parse("packet_id: 9\ntrigger_based: true\nobjects:\n  - button: press\n  - dimmer: -3\n")
+            .unwrap();
+        assert_eq!(yaml.encode(None), Ok("4400093a013c0103".to_string()));
+
+        // The example of the BTHome specification
+        let encryption = Encryption {
+            key: [0x23, 0x1d, 0x39, 0xc1, 0xd7, 0xcc, 0x1a, 0xb1, 0xae, 0xe2, 0x24, 0xcd, 0x09, 0x6d, 0xb9, 0x32],
+            mac: [0x54, 0x48, 0xE6, 0x8F, 0x80, 0xA5],
+            counter: 0x33221100,
+        };
+        let json = Description::parse(r#"{"objects": {"humidity": 50.55, "temperature": 25.06}}"#).unwrap();
+        assert_eq!(json.encode(Some(&encryption)), Ok("41a47266c95f730011223378237214".to_string()));
+    }
+
+    #[test]
+    fn add_values() {
+        let mut description = Description::default();
+        assert!(description.is_empty());
+        description.add_value("temperature=25").unwrap();
+        description.add_value("temperature=-1.5").unwrap();
+        assert_eq!(description.encode(None), Ok("4002c409026aff".to_string()));
+        assert!(description.add_value("humidity").is_err());
+        description.add_value("battery=lots").unwrap();
+        assert!(description.encode(None).is_err());
+    }
+
+    #[test]
+    fn encode_smallest() {
+        let mut description = Description::parse("smallest: true\nobjects: {temperature: 21, humidity: 50.55}").unwrap();
+        assert_eq!(description.encode(None), Ok("4003bf135715".to_string()));
+        description.add_value("0x02=22").unwrap();
+        assert_eq!(description.encode(None), Ok("4002980803bf135715".to_string()));
+        description.add_value("temperature=4000").unwrap();
+        assert!(description.encode(None).is_err());
+    }
+
+    #[test]
+    fn encode_split() {
+        let mut description = Description::parse("packet_id: 7\nmax_len: 8\nobjects: {temperature: 25, humidity: 50.55}")
+            .unwrap();
+        assert_eq!(description.encode(None), Ok("400007 02c409\n400008 03bf13".replace(' ', "")));
+        description.max_len = Some(5);
+        assert!(description.encode(None).is_err());
+    }
+}
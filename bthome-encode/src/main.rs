@@ -0,0 +1,195 @@
+//! Encodes measurements as BTHome service data, e.g. to create test vectors for firmware or to
+//! check interoperability with other implementations.
+
+use std::{error::Error, io::Read, path::PathBuf};
+
+use clap::Parser;
+
+mod description;
+
+use description::{Description, Encryption};
+
+/// Encode measurements as hex encoded BTHome service data.
+///
+/// Measurements are given as `--NAME VALUE`, e.g. `--temperature 21.5 --humidity 40`, as
+/// `--value NAME=VALUE` or in a JSON or YAML description.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// JSON or YAML description of the payload, `-` reads it from stdin
+    #[arg(short, long, value_name = "FILE")]
+    input: Option<PathBuf>,
+
+    /// A measurement, e.g. temperature=21.5, can be given multiple times
+    #[arg(long = "value", value_name = "NAME=VALUE")]
+    values: Vec<String>,
+
+    /// Packet id included as first object
+    #[arg(long, value_name = "ID")]
+    packet_id: Option<u8>,
+
+    /// Mark the device as trigger based, i.e. only sending when something happens
+    #[arg(long)]
+    trigger_based: bool,
+
+    /// Encode measurements with the smallest object that holds them exactly, e.g. a temperature of
+    /// 21 °C in one byte instead of two
+    #[arg(long)]
+    smallest: bool,
+
+    /// Split the measurements into payloads of at most this many bytes, one per line, each with
+    /// its own packet id, e.g. 24 for legacy advertisements
+    #[arg(long, value_name = "BYTES")]
+    max_len: Option<usize>,
+
+    /// Encrypt the payload with this bind key, 32 hex digits
+    #[arg(long, value_parser = parse_key, requires = "mac")]
+    key: Option<[u8; 16]>,
+
+    /// MAC address of the device the payload is encrypted for, e.g. 54:48:E6:8F:80:A5
+    #[arg(long, value_parser = parse_mac, requires = "key")]
+    mac: Option<[u8; 6]>,
+
+    /// Counter of the encrypted payload, which the device increases with every advertisement
+    #[arg(long, default_value_t = 0, requires = "key")]
+    counter: u32,
+}
+
+/// Options of the tool itself, which take precedence over measurements of the same name.
+const OPTIONS: &[&str] = &[
+    "input",
+    "value",
+    "packet-id",
+    "trigger-based",
+    "smallest",
+    "max-len",
+    "key",
+    "mac",
+    "counter",
+    "help",
+    "version",
+];
+
+/// Rewrites `--NAME VALUE` and `--NAME=VALUE` for measurements into `--value NAME=VALUE`.
+fn expand_measurements(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut expanded = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            expanded.push(arg);
+            continue;
+        };
+        let (name, inline_value) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (flag, None),
+        };
+        let object = name.replace('-', "_");
+        if OPTIONS.contains(&name) || !description::is_object(&object) {
+            expanded.push(arg);
+            continue;
+        }
+        match inline_value.or_else(|| args.next()) {
+            Some(value) => {
+                expanded.push("--value".to_string());
+                expanded.push(format!("{}={}", object, value));
+            }
+            // Let clap report the missing value
+            None => expanded.push(arg),
+        }
+    }
+    expanded
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse_from(expand_measurements(std::env::args()));
+
+    let mut description = match &args.input {
+        Some(path) => {
+            let mut content = String::new();
+            if path.as_os_str() == "-" {
+                std::io::stdin().read_to_string(&mut content)?;
+            } else {
+                content = std::fs::read_to_string(path)?;
+            }
+            Description::parse(&content)?
+        }
+        None => Description::default(),
+    };
+    for value in &args.values {
+        description.add_value(value)?;
+    }
+    if args.packet_id.is_some() {
+        description.packet_id = args.packet_id;
+    }
+    description.trigger_based |= args.trigger_based;
+    description.smallest |= args.smallest;
+    if args.max_len.is_some() {
+        description.max_len = args.max_len;
+    }
+    if description.is_empty() {
+        return Err("Nothing to encode, give measurements like --temperature 21.5 or a description with --input".into());
+    }
+
+    let encryption = match (args.key, args.mac) {
+        (Some(key), Some(mac)) => Some(Encryption {
+            key,
+            mac,
+            counter: args.counter,
+        }),
+        _ => None,
+    };
+    println!("{}", description.encode(encryption.as_ref())?);
+    Ok(())
+}
+
+fn parse_key(s: &str) -> Result<[u8; 16], String> {
+    parse_hex(s, "").ok_or_else(|| format!("invalid key {:?}, expected 32 hex digits", s))
+}
+
+fn parse_mac(s: &str) -> Result<[u8; 6], String> {
+    parse_hex(s, ":").ok_or_else(|| format!("invalid MAC address {:?}, expected e.g. 54:48:E6:8F:80:A5", s))
+}
+
+/// Parses exactly `N` bytes of hex digits, optionally separated by `separator`.
+fn parse_hex<const N: usize>(s: &str, separator: &str) -> Option<[u8; N]> {
+    let digits = if separator.is_empty() { s.to_string() } else { s.replace(separator, "") };
+    if digits.len() != 2 * N {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(digits.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expand_measurement_flags() {
+        let args = ["bthome-encode", "--temperature", "21.5", "--uv-index=3", "--packet-id", "7", "--trigger-based"];
+        assert_eq!(
+            expand_measurements(args.map(String::from)),
+            vec![
+                "bthome-encode",
+                "--value",
+                "temperature=21.5",
+                "--value",
+                "uv_index=3",
+                "--packet-id",
+                "7",
+                "--trigger-based"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_encryption() {
+        assert_eq!(parse_mac("54:48:E6:8F:80:A5"), Ok([0x54, 0x48, 0xE6, 0x8F, 0x80, 0xA5]));
+        assert!(parse_mac("54:48:E6:8F:80").is_err());
+        assert_eq!(parse_key("231d39c1d7cc1ab1aee224cd096db932").unwrap()[..2], [0x23, 0x1d]);
+        assert!(parse_key("231d39c1").is_err());
+    }
+}
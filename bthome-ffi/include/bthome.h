@@ -0,0 +1,128 @@
+/* Generated with cbindgen from bthome-ffi, update with `BTHOME_UPDATE_HEADER=1 cargo test -p bthome-ffi` */
+
+#ifndef BTHOME_H
+#define BTHOME_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The result of [`bthome_parse`].
+enum BthomeStatus
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : uint8_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  BTHOME_STATUS_OK = 0,
+  // `data` or `out` is null
+  BTHOME_STATUS_NULL_POINTER = 1,
+  // The data is encrypted, decryption is not available through this interface yet
+  BTHOME_STATUS_ENCRYPTED = 2,
+  // The device information announces another version than 2
+  BTHOME_STATUS_UNSUPPORTED_VERSION = 3,
+  // The data has an unknown or invalid object
+  BTHOME_STATUS_INVALID_DATA = 4,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum BthomeStatus BthomeStatus;
+#else
+typedef uint8_t BthomeStatus;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+// Which fields of a [`BthomeObject`] hold its value.
+enum BthomeValueType
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : uint8_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  // `number`
+  BTHOME_VALUE_TYPE_FLOAT = 0,
+  // `number`
+  BTHOME_VALUE_TYPE_INT = 1,
+  // `number`
+  BTHOME_VALUE_TYPE_UINT = 2,
+  // `number`, 1 for true and 0 for false
+  BTHOME_VALUE_TYPE_BOOL = 3,
+  // `data` and `data_len`
+  BTHOME_VALUE_TYPE_RAW = 4,
+  // `number`, the event id of the specification, e.g. 0x02 for a double press
+  BTHOME_VALUE_TYPE_BUTTON_EVENT = 5,
+  // `number`, the event id of the specification, and `steps`
+  BTHOME_VALUE_TYPE_DIMMER_EVENT = 6,
+  // `text`
+  BTHOME_VALUE_TYPE_TEXT = 7,
+  // `text`, e.g. `1.2.3`
+  BTHOME_VALUE_TYPE_FIRMWARE_VERSION = 8,
+  // `number`, the id, and `text`, the name of the device
+  BTHOME_VALUE_TYPE_DEVICE_TYPE = 9,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum BthomeValueType BthomeValueType;
+#else
+typedef uint8_t BthomeValueType;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+// An object of the service data.
+typedef struct BthomeObject {
+  // The object id of the specification, e.g. 0x02 for a temperature
+  uint8_t id;
+  // How many objects with the same id come before it, e.g. 1 for the second temperature
+  uint8_t instance;
+  BthomeValueType value_type;
+  // The steps of a dimmer event
+  uint8_t steps;
+  // The value of numbers and binary sensors, exact for all integers as they have at most 48 bits
+  double number;
+  // The value of text objects as UTF-8, null for the other types
+  char *text;
+  // The value of raw objects, null for the other types
+  uint8_t *data;
+  size_t data_len;
+  // The name, e.g. `temperature`, valid for the lifetime of the program
+  const char *name;
+  // The unit in UTF-8, e.g. `°C`, or null, valid for the lifetime of the program
+  const char *unit;
+} BthomeObject;
+
+// Service data parsed by [`bthome_parse`], to be freed with [`bthome_free`].
+typedef struct BthomeServiceData {
+  uint8_t version;
+  bool encrypted;
+  // The device only sends when something happens instead of regularly
+  bool trigger_based;
+  struct BthomeObject *objects;
+  size_t object_count;
+} BthomeServiceData;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Parses the unencrypted service data of `len` bytes at `data` into `out`. Unless the status is
+// `BTHOME_STATUS_OK`, `out` is left empty.
+//
+// # Safety
+//
+// `data` has to point to `len` readable bytes and `out` to a writable `BthomeServiceData`, which
+// has to be freed with [`bthome_free`].
+BthomeStatus bthome_parse(const uint8_t *data, size_t len, struct BthomeServiceData *out);
+
+// Frees the objects of service data filled by [`bthome_parse`] and leaves it empty, so that
+// freeing it twice does no harm. Null is ignored.
+//
+// # Safety
+//
+// `service_data` has to be null or filled by [`bthome_parse`], and not be changed since.
+void bthome_free(struct BthomeServiceData *service_data);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BTHOME_H */
@@ -0,0 +1,299 @@
+//! A C interface to the parser, so that gateways written in C or C++ can link the decoder as a
+//! shared or static library. It is declared in `include/bthome.h`:
+//!
+//! ```c
+//! BthomeServiceData service_data;
+//! if (bthome_parse(data, len, &service_data) == BTHOME_STATUS_OK) {
+//!     for (size_t i = 0; i < service_data.object_count; i++) {
+//!         printf("%s: %g\n", service_data.objects[i].name, service_data.objects[i].number);
+//!     }
+//!     bthome_free(&service_data);
+//! }
+//! ```
+//!
+//! The structs only get fields appended, so that code compiled against an older header keeps
+//! working.
+
+use std::{
+    ffi::{c_char, CStr, CString},
+    ptr, slice,
+    sync::OnceLock,
+};
+
+use bthome::{parse_service_data, Error, Object, ObjectId, ObjectValue, ServiceData};
+
+/// The result of [`bthome_parse`].
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BthomeStatus {
+    Ok = 0,
+    /// `data` or `out` is null
+    NullPointer = 1,
+    /// The data is encrypted, decryption is not available through this interface yet
+    Encrypted = 2,
+    /// The device information announces another version than 2
+    UnsupportedVersion = 3,
+    /// The data has an unknown or invalid object
+    InvalidData = 4,
+}
+
+/// Which fields of a [`BthomeObject`] hold its value.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BthomeValueType {
+    /// `number`
+    Float = 0,
+    /// `number`
+    Int = 1,
+    /// `number`
+    Uint = 2,
+    /// `number`, 1 for true and 0 for false
+    Bool = 3,
+    /// `data` and `data_len`
+    Raw = 4,
+    /// `number`, the event id of the specification, e.g. 0x02 for a double press
+    ButtonEvent = 5,
+    /// `number`, the event id of the specification, and `steps`
+    DimmerEvent = 6,
+    /// `text`
+    Text = 7,
+    /// `text`, e.g. `1.2.3`
+    FirmwareVersion = 8,
+    /// `number`, the id, and `text`, the name of the device
+    DeviceType = 9,
+}
+
+/// An object of the service data.
+#[repr(C)]
+#[derive(Debug)]
+pub struct BthomeObject {
+    /// The object id of the specification, e.g. 0x02 for a temperature
+    pub id: u8,
+    /// How many objects with the same id come before it, e.g. 1 for the second temperature
+    pub instance: u8,
+    pub value_type: BthomeValueType,
+    /// The steps of a dimmer event
+    pub steps: u8,
+    /// The value of numbers and binary sensors, exact for all integers as they have at most 48 bits
+    pub number: f64,
+    /// The value of text objects as UTF-8, null for the other types
+    pub text: *mut c_char,
+    /// The value of raw objects, null for the other types
+    pub data: *mut u8,
+    pub data_len: usize,
+    /// The name, e.g. `temperature`, valid for the lifetime of the program
+    pub name: *const c_char,
+    /// The unit in UTF-8, e.g. `°C`, or null, valid for the lifetime of the program
+    pub unit: *const c_char,
+}
+
+/// Service data parsed by [`bthome_parse`], to be freed with [`bthome_free`].
+#[repr(C)]
+#[derive(Debug)]
+pub struct BthomeServiceData {
+    pub version: u8,
+    pub encrypted: bool,
+    /// The device only sends when something happens instead of regularly
+    pub trigger_based: bool,
+    pub objects: *mut BthomeObject,
+    pub object_count: usize,
+}
+
+impl BthomeServiceData {
+    fn empty() -> Self {
+        BthomeServiceData {
+            version: 0,
+            encrypted: false,
+            trigger_based: false,
+            objects: ptr::null_mut(),
+            object_count: 0,
+        }
+    }
+}
+
+impl From<ServiceData> for BthomeServiceData {
+    fn from(service_data: ServiceData) -> Self {
+        let objects: Box<[BthomeObject]> = service_data.objects.into_iter().map(BthomeObject::from).collect();
+        let object_count = objects.len();
+        BthomeServiceData {
+            version: service_data.version,
+            encrypted: service_data.encrypted,
+            trigger_based: service_data.trigger_based,
+            objects: Box::into_raw(objects).cast(),
+            object_count,
+        }
+    }
+}
+
+impl From<Object> for BthomeObject {
+    fn from(object: Object) -> Self {
+        let (name, unit) = labels(object.object_id);
+        let mut result = BthomeObject {
+            id: object.object_id as u8,
+            instance: object.instance,
+            value_type: BthomeValueType::Float,
+            steps: 0,
+            number: 0.0,
+            text: ptr::null_mut(),
+            data: ptr::null_mut(),
+            data_len: 0,
+            name: name.as_ptr(),
+            unit: unit.map_or(ptr::null(), CStr::as_ptr),
+        };
+        match object.value {
+            ObjectValue::Float(value) => result.number = value,
+            ObjectValue::Int(value) => (result.value_type, result.number) = (BthomeValueType::Int, value as f64),
+            ObjectValue::UInt(value) => (result.value_type, result.number) = (BthomeValueType::Uint, value as f64),
+            ObjectValue::Bool(value) => (result.value_type, result.number) = (BthomeValueType::Bool, f64::from(u8::from(value))),
+            ObjectValue::Raw(bytes) => {
+                result.value_type = BthomeValueType::Raw;
+                result.data_len = bytes.len();
+                result.data = Box::into_raw(bytes.into_boxed_slice()).cast();
+            }
+            ObjectValue::ButtonEvent(event) => {
+                (result.value_type, result.number) = (BthomeValueType::ButtonEvent, f64::from(event as u8))
+            }
+            ObjectValue::DimmerEvent(event, steps) => {
+                (result.value_type, result.number) = (BthomeValueType::DimmerEvent, f64::from(event as u8));
+                result.steps = steps;
+            }
+            ObjectValue::Text(text) => (result.value_type, result.text) = (BthomeValueType::Text, c_string(text)),
+            ObjectValue::FirmwareVersion(version) => {
+                (result.value_type, result.text) = (BthomeValueType::FirmwareVersion, c_string(version.to_string()))
+            }
+            ObjectValue::DeviceType(device_type) => {
+                result.value_type = BthomeValueType::DeviceType;
+                result.number = f64::from(device_type.id());
+                result.text = c_string(device_type.to_string());
+            }
+        }
+        result
+    }
+}
+
+/// The name and unit of an object id as C strings, created once so that objects can point to
+/// them without being freed.
+fn labels(object_id: ObjectId) -> (&'static CStr, Option<&'static CStr>) {
+    static LABELS: OnceLock<Vec<(CString, Option<CString>)>> = OnceLock::new();
+    let labels = LABELS.get_or_init(|| {
+        (0..=u8::MAX)
+            .map(|id| match ObjectId::try_from(id) {
+                Ok(object_id) => (
+                    CString::new(object_id.name()).expect("Names to have no NUL"),
+                    object_id.unit().map(|unit| CString::new(unit).expect("Units to have no NUL")),
+                ),
+                Err(_) => (CString::default(), None),
+            })
+            .collect()
+    });
+    let (name, unit) = &labels[object_id as usize];
+    (name, unit.as_deref())
+}
+
+/// Text as an owned C string, NUL characters in it are dropped.
+fn c_string(text: String) -> *mut c_char {
+    let mut bytes = text.into_bytes();
+    bytes.retain(|byte| *byte != 0);
+    CString::new(bytes).expect("NUL bytes to be removed").into_raw()
+}
+
+fn status(error: &Error) -> BthomeStatus {
+    match error {
+        Error::Encrypted => BthomeStatus::Encrypted,
+        Error::UnsupportedVersion(_) => BthomeStatus::UnsupportedVersion,
+        _ => BthomeStatus::InvalidData,
+    }
+}
+
+/// Parses the unencrypted service data of `len` bytes at `data` into `out`. Unless the status is
+/// `BTHOME_STATUS_OK`, `out` is left empty.
+///
+/// # Safety
+///
+/// `data` has to point to `len` readable bytes and `out` to a writable `BthomeServiceData`, which
+/// has to be freed with [`bthome_free`].
+#[no_mangle]
+pub unsafe extern "C" fn bthome_parse(data: *const u8, len: usize, out: *mut BthomeServiceData) -> BthomeStatus {
+    if out.is_null() {
+        return BthomeStatus::NullPointer;
+    }
+    out.write(BthomeServiceData::empty());
+    if data.is_null() {
+        return BthomeStatus::NullPointer;
+    }
+    match parse_service_data(slice::from_raw_parts(data, len)) {
+        Ok(service_data) => {
+            out.write(service_data.into());
+            BthomeStatus::Ok
+        }
+        Err(error) => status(&error),
+    }
+}
+
+/// Frees the objects of service data filled by [`bthome_parse`] and leaves it empty, so that
+/// freeing it twice does no harm. Null is ignored.
+///
+/// # Safety
+///
+/// `service_data` has to be null or filled by [`bthome_parse`], and not be changed since.
+#[no_mangle]
+pub unsafe extern "C" fn bthome_free(service_data: *mut BthomeServiceData) {
+    let Some(service_data) = service_data.as_mut() else {
+        return;
+    };
+    if !service_data.objects.is_null() {
+        let objects = Box::from_raw(ptr::slice_from_raw_parts_mut(service_data.objects, service_data.object_count));
+        for object in objects.iter() {
+            if !object.text.is_null() {
+                drop(CString::from_raw(object.text));
+            }
+            if !object.data.is_null() {
+                drop(Box::from_raw(ptr::slice_from_raw_parts_mut(object.data, object.data_len)));
+            }
+        }
+    }
+    *service_data = BthomeServiceData::empty();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_and_free() {
+        // Temperature, humidity, a text and a raw object
+        let data = [0x40, 0x02, 0xC4, 0x09, 0x03, 0xBF, 0x13, 0x53, 0x02, 0x68, 0x69, 0x54, 0x02, 0x00, 0xFF];
+        let mut service_data = BthomeServiceData::empty();
+        unsafe {
+            assert_eq!(bthome_parse(data.as_ptr(), data.len(), &mut service_data), BthomeStatus::Ok);
+            assert_eq!(service_data.version, 2);
+            let objects = slice::from_raw_parts(service_data.objects, service_data.object_count);
+            assert_eq!(objects.len(), 4);
+            assert_eq!(objects[0].id, 0x02);
+            assert_eq!(objects[0].number, 25.0);
+            assert_eq!(CStr::from_ptr(objects[0].name), c"temperature");
+            assert_eq!(CStr::from_ptr(objects[0].unit), c"°C");
+            assert_eq!(objects[1].number, 50.55);
+            assert_eq!(objects[2].value_type, BthomeValueType::Text);
+            assert_eq!(CStr::from_ptr(objects[2].text), c"hi");
+            assert!(objects[2].unit.is_null());
+            assert_eq!(slice::from_raw_parts(objects[3].data, objects[3].data_len), &[0x00, 0xFF]);
+
+            bthome_free(&mut service_data);
+            assert!(service_data.objects.is_null());
+            bthome_free(&mut service_data);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let mut service_data = BthomeServiceData::empty();
+        unsafe {
+            assert_eq!(bthome_parse([0x41, 0x00].as_ptr(), 2, &mut service_data), BthomeStatus::Encrypted);
+            assert_eq!(bthome_parse([0x40, 0xFE, 0x00].as_ptr(), 3, &mut service_data), BthomeStatus::InvalidData);
+            assert_eq!(bthome_parse(ptr::null(), 0, &mut service_data), BthomeStatus::NullPointer);
+            assert_eq!(bthome_parse([0x40].as_ptr(), 1, ptr::null_mut()), BthomeStatus::NullPointer);
+        }
+        assert_eq!(service_data.object_count, 0);
+    }
+}
@@ -0,0 +1,60 @@
+//! Checks the interface from C: the header is the one cbindgen generates, and a C program using
+//! it links against the static library. Needs a C compiler as `cc`.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+#[test]
+fn header_is_up_to_date() {
+    let config = cbindgen::Config::from_file(manifest_dir().join("cbindgen.toml")).unwrap();
+    let bindings = cbindgen::Builder::new()
+        .with_src(manifest_dir().join("src/lib.rs"))
+        .with_config(config)
+        .generate()
+        .unwrap();
+    let mut header = Vec::new();
+    bindings.write(&mut header);
+    let path = manifest_dir().join("include/bthome.h");
+    if env::var_os("BTHOME_UPDATE_HEADER").is_some() {
+        fs::write(&path, &header).unwrap();
+    }
+    let committed = fs::read(&path).unwrap_or_default();
+    assert!(committed == header, "include/bthome.h is outdated, update it with BTHOME_UPDATE_HEADER=1");
+}
+
+#[test]
+fn parse_from_c() {
+    // The test binary is in deps, `cargo test` doesn't put the static library next to it, so it
+    // is built into the same target directory
+    let exe = env::current_exe().unwrap();
+    let profile_dir = exe.parent().and_then(|deps| deps.parent()).unwrap();
+    let profile = match profile_dir.file_name().unwrap().to_str().unwrap() {
+        "debug" => "dev",
+        profile => profile,
+    };
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "-p", "bthome-ffi", "--lib", "--profile", profile])
+        .env("CARGO_TARGET_DIR", profile_dir.parent().unwrap())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let program = profile_dir.join("bthome-ffi-parse");
+    let status = Command::new("cc")
+        .arg(manifest_dir().join("tests/parse.c"))
+        .arg("-I")
+        .arg(manifest_dir().join("include"))
+        .arg(profile_dir.join("libbthome_ffi.a"))
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .status()
+        .expect("A C compiler to be installed as cc");
+    assert!(status.success());
+
+    let output = Command::new(&program).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "temperature 25 °C\nhumidity 50.55 %\nbutton 2 -\n");
+}
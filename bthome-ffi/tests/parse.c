@@ -0,0 +1,22 @@
+/* Parses service data through the header and the static library, printing the objects. */
+#include <stdio.h>
+#include <string.h>
+
+#include "bthome.h"
+
+int main(void) {
+    /* Temperature, humidity and a double press */
+    const uint8_t data[] = {0x40, 0x02, 0xC4, 0x09, 0x03, 0xBF, 0x13, 0x3A, 0x02};
+    BthomeServiceData service_data;
+    if (bthome_parse(data, sizeof(data), &service_data) != BTHOME_STATUS_OK) {
+        return 1;
+    }
+    for (size_t i = 0; i < service_data.object_count; i++) {
+        const BthomeObject *object = &service_data.objects[i];
+        printf("%s %g %s\n", object->name, object->number, object->unit ? object->unit : "-");
+    }
+    bthome_free(&service_data);
+
+    const uint8_t encrypted[] = {0x41, 0x00};
+    return bthome_parse(encrypted, sizeof(encrypted), &service_data) == BTHOME_STATUS_ENCRYPTED ? 0 : 2;
+}
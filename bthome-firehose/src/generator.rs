@@ -0,0 +1,197 @@
+//! Synthetic advertisements of many devices, reproducible from a seed.
+
+use bthome::{encode_service_data, ButtonEvent, Object, ObjectId, ObjectValue, ServiceData};
+
+/// The kinds of devices generated, each with the objects it sends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Profile {
+    /// Temperature, humidity and battery, like most thermometers
+    Climate,
+    /// Motion, illuminance and battery
+    Motion,
+    /// Button events with a packet id
+    Button,
+    /// Power, energy and voltage of a plug
+    Plug,
+    /// Window sensor with battery
+    Window,
+}
+
+const PROFILES: [Profile; 5] = [Profile::Climate, Profile::Motion, Profile::Button, Profile::Plug, Profile::Window];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Valid,
+    /// Cut short or with an unknown object id
+    Malformed,
+    /// Flagged as encrypted, with random ciphertext
+    Encrypted,
+}
+
+pub struct Options {
+    pub devices: u32,
+    /// Percentage of malformed advertisements
+    pub malformed: f64,
+    /// Percentage of encrypted advertisements
+    pub encrypted: f64,
+    pub seed: u64,
+}
+
+struct Device {
+    address: [u8; 6],
+    profile: Profile,
+    packet_id: u8,
+}
+
+pub struct Generator {
+    devices: Vec<Device>,
+    malformed: f64,
+    encrypted: f64,
+    state: u64,
+    next_device: usize,
+}
+
+impl Generator {
+    pub fn new(options: &Options) -> Generator {
+        let mut generator = Generator {
+            devices: Vec::new(),
+            malformed: options.malformed / 100.0,
+            encrypted: options.encrypted / 100.0,
+            // xorshift gets stuck at zero
+            state: options.seed.max(1),
+            next_device: 0,
+        };
+        for i in 0..options.devices.max(1) {
+            let [a, b, c, d] = i.to_be_bytes();
+            let profile = PROFILES[generator.random() as usize % PROFILES.len()];
+            // Locally administered addresses, so they can't clash with real devices
+            generator.devices.push(Device {
+                address: [0xF2, 0xB7, a, b, c, d],
+                profile,
+                packet_id: 0,
+            });
+        }
+        generator
+    }
+
+    /// The next advertisement, the devices take turns.
+    pub fn next_advertisement(&mut self) -> ([u8; 6], Vec<u8>, Kind) {
+        let index = self.next_device;
+        self.next_device = (self.next_device + 1) % self.devices.len();
+        let address = self.devices[index].address;
+        let roll = self.uniform();
+        if roll < self.encrypted {
+            // Device info with the encryption flag, ciphertext, counter and MIC
+            let mut payload = vec![0x41];
+            payload.extend((0..14).map(|_| self.random() as u8));
+            return (address, payload, Kind::Encrypted);
+        }
+        let mut payload = self.payload(index);
+        if roll < self.encrypted + self.malformed {
+            if self.random().is_multiple_of(2) {
+                payload.truncate(payload.len() - 1);
+            } else {
+                // Not assigned by the specification
+                payload.push(0xFE);
+            }
+            return (address, payload, Kind::Malformed);
+        }
+        (address, payload, Kind::Valid)
+    }
+
+    fn payload(&mut self, index: usize) -> Vec<u8> {
+        let device = &mut self.devices[index];
+        device.packet_id = device.packet_id.wrapping_add(1);
+        let (profile, packet_id) = (device.profile, device.packet_id);
+        let battery = ObjectValue::Int(50 + (self.random() % 51) as i64);
+        let objects = match profile {
+            Profile::Climate => vec![
+                (ObjectId::Battery, battery),
+                (ObjectId::Temperature4, ObjectValue::Float(self.between(-10.0, 35.0))),
+                (ObjectId::HumidityU16, ObjectValue::Float(self.between(20.0, 90.0))),
+            ],
+            Profile::Motion => vec![
+                (ObjectId::Battery, battery),
+                (ObjectId::Illuminance, ObjectValue::Float(self.between(0.0, 2000.0))),
+                (ObjectId::MotionDetected, ObjectValue::Bool(self.random().is_multiple_of(2))),
+            ],
+            Profile::Button => vec![
+                (ObjectId::PacketId, ObjectValue::Int(packet_id.into())),
+                (ObjectId::Battery, battery),
+                (ObjectId::Button, ObjectValue::ButtonEvent(ButtonEvent::Press)),
+            ],
+            Profile::Plug => vec![
+                (ObjectId::PowerSmall, ObjectValue::Float(self.between(0.0, 2500.0))),
+                (ObjectId::EngergyU24, ObjectValue::Float(self.between(0.0, 5000.0))),
+                (ObjectId::VoltageLarge, ObjectValue::Float(self.between(220.0, 240.0))),
+            ],
+            Profile::Window => vec![
+                (ObjectId::Battery, battery),
+                (ObjectId::WindowOpen, ObjectValue::Bool(self.random().is_multiple_of(2))),
+            ],
+        };
+        let service_data = ServiceData {
+            encrypted: false,
+            trigger_based: profile == Profile::Button,
+            version: 2,
+            objects: objects
+                .into_iter()
+                .map(|(object_id, value)| Object::new(object_id, value))
+                .collect(),
+        };
+        encode_service_data(&service_data).expect("Generated values to be in range")
+    }
+
+    fn random(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A random number in [0, 1).
+    fn uniform(&mut self) -> f64 {
+        (self.random() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A random value rounded to two decimals, which all generated objects can represent.
+    fn between(&mut self, low: f64, high: f64) -> f64 {
+        ((low + self.uniform() * (high - low)) * 100.0).round() / 100.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bthome::parse_service_data;
+
+    use super::*;
+
+    #[test]
+    fn generate_mix() {
+        let options = Options {
+            devices: 50,
+            malformed: 10.0,
+            encrypted: 5.0,
+            seed: 42,
+        };
+        let mut generator = Generator::new(&options);
+        let advertisements: Vec<_> = (0..10_000).map(|_| generator.next_advertisement()).collect();
+        let count = |kind| advertisements.iter().filter(|(_, _, k)| *k == kind).count();
+        assert!((800..1200).contains(&count(Kind::Malformed)), "{}", count(Kind::Malformed));
+        assert!((350..650).contains(&count(Kind::Encrypted)), "{}", count(Kind::Encrypted));
+        for (_, payload, kind) in &advertisements {
+            let result = parse_service_data(payload);
+            match kind {
+                Kind::Valid => assert!(result.is_ok(), "{:02x?}", payload),
+                Kind::Encrypted => assert_eq!(result, Err(bthome::Error::Encrypted)),
+                Kind::Malformed => {}
+            }
+        }
+        assert_eq!(advertisements[0].0, advertisements[50].0);
+        assert_ne!(advertisements[0].0, advertisements[1].0);
+
+        // The same seed gives the same advertisements
+        let mut again = Generator::new(&options);
+        assert_eq!(again.next_advertisement(), advertisements[0]);
+    }
+}
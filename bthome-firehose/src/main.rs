@@ -0,0 +1,148 @@
+//! Generates thousands of synthetic BTHome advertisements per second, to size MQTT brokers and
+//! databases behind the sniffer or to profile the parser with a reproducible workload.
+
+use std::{
+    error::Error,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+mod generator;
+mod output;
+
+use generator::{Generator, Kind, Options};
+use output::{Output, Target};
+
+/// Send a firehose of synthetic BTHome advertisements.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Where to send the advertisements: stdout (for bthome-sniffer --stdin), tcp://HOST:PORT or
+    /// udp://HOST:PORT (for bthome-sniffer --listen), or parse to only run the parser
+    #[arg(default_value = "stdout")]
+    target: Target,
+
+    /// Number of simulated devices, each with a fixed set of objects
+    #[arg(long, default_value_t = 1000)]
+    devices: u32,
+
+    /// Advertisements per second, 0 for as fast as possible
+    #[arg(long, default_value_t = 1000)]
+    rate: u64,
+
+    /// Stop after this many advertisements
+    #[arg(long)]
+    count: Option<u64>,
+
+    /// Stop after this many seconds
+    #[arg(long)]
+    seconds: Option<u64>,
+
+    /// Percentage of malformed advertisements
+    #[arg(long, default_value_t = 0.0, value_parser = parse_percentage)]
+    malformed: f64,
+
+    /// Percentage of encrypted advertisements
+    #[arg(long, default_value_t = 0.0, value_parser = parse_percentage)]
+    encrypted: f64,
+
+    /// Seed of the generator, the same seed generates the same advertisements
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+}
+
+/// How often advertisements are sent, those due in between are sent in a burst.
+const TICK: Duration = Duration::from_millis(10);
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct Counters {
+    sent: u64,
+    malformed: u64,
+    encrypted: u64,
+    /// Advertisements the parser rejected, with the parse target
+    rejected: u64,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with_writer(std::io::stderr)
+        .init();
+    let args = Args::parse();
+    if args.malformed + args.encrypted > 100.0 {
+        return Err("--malformed and --encrypted add up to more than 100%".into());
+    }
+
+    let mut output = Output::open(&args.target)
+        .await
+        .map_err(|err| format!("Error opening {:?}: {}", args.target, err))?;
+    let mut generator = Generator::new(&Options {
+        devices: args.devices,
+        malformed: args.malformed,
+        encrypted: args.encrypted,
+        seed: args.seed,
+    });
+    info!(target = ?args.target, devices = args.devices, rate = args.rate, "Starting firehose");
+
+    let start = Instant::now();
+    let limit = args.count.unwrap_or(u64::MAX);
+    let deadline = args.seconds.map(|seconds| start + Duration::from_secs(seconds));
+    let mut counters = Counters::default();
+    let mut last_report = (start, 0);
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
+    let mut tick = tokio::time::interval(TICK);
+    while counters.sent < limit && deadline.is_none_or(|deadline| Instant::now() < deadline) {
+        let due = match args.rate {
+            0 => counters.sent + 10_000,
+            rate => (start.elapsed().as_secs_f64() * rate as f64) as u64,
+        };
+        for _ in counters.sent..due.min(limit) {
+            let (address, payload, kind) = generator.next_advertisement();
+            let parsed = output.send(&address, &payload).await?;
+            counters.sent += 1;
+            counters.malformed += (kind == Kind::Malformed) as u64;
+            counters.encrypted += (kind == Kind::Encrypted) as u64;
+            counters.rejected += !parsed as u64;
+        }
+        output.flush().await?;
+
+        let (reported_at, reported) = last_report;
+        if reported_at.elapsed() >= REPORT_INTERVAL {
+            let rate = (counters.sent - reported) as f64 / reported_at.elapsed().as_secs_f64();
+            info!(sent = counters.sent, rate = rate.round(), "Sending");
+            last_report = (Instant::now(), counters.sent);
+        }
+        tokio::select! {
+            biased;
+            _ = &mut shutdown => break,
+            _ = tick.tick(), if args.rate != 0 => {}
+            // Running flat out, only give the shutdown signal a chance
+            _ = tokio::task::yield_now(), if args.rate == 0 => {}
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    info!(
+        sent = counters.sent,
+        malformed = counters.malformed,
+        encrypted = counters.encrypted,
+        rejected = counters.rejected,
+        seconds = (elapsed * 100.0).round() / 100.0,
+        rate = (counters.sent as f64 / elapsed).round(),
+        "Done"
+    );
+    Ok(())
+}
+
+fn parse_percentage(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(value) if (0.0..=100.0).contains(&value) => Ok(value),
+        _ => Err(format!("invalid percentage {:?}, expected a number from 0 to 100", s)),
+    }
+}
@@ -0,0 +1,140 @@
+//! Where the generated advertisements go.
+
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt, BufWriter},
+    net::{lookup_host, TcpStream, UdpSocket},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Lines of address and hex payload, as read by `bthome-sniffer --stdin`
+    Stdout,
+    /// The forwarding protocol of the sniffer, to an instance started with `--listen`
+    Tcp(String),
+    Udp(String),
+    /// Only parse the advertisements, to measure the parser
+    Parse,
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "-" || s == "stdout" {
+            Ok(Target::Stdout)
+        } else if s == "parse" {
+            Ok(Target::Parse)
+        } else if let Some(address) = s.strip_prefix("tcp://") {
+            Ok(Target::Tcp(address.to_string()))
+        } else if let Some(address) = s.strip_prefix("udp://") {
+            Ok(Target::Udp(address.to_string()))
+        } else {
+            Err(format!("expected stdout, parse, tcp://HOST:PORT or udp://HOST:PORT, got {:?}", s))
+        }
+    }
+}
+
+pub enum Output {
+    Stream(BufWriter<Box<dyn AsyncWrite + Unpin + Send>>, Format),
+    Udp(UdpSocket),
+    Parse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Lines,
+    Forwarded,
+}
+
+impl Output {
+    pub async fn open(target: &Target) -> std::io::Result<Output> {
+        Ok(match target {
+            Target::Stdout => Output::Stream(BufWriter::new(Box::new(tokio::io::stdout())), Format::Lines),
+            Target::Tcp(address) => {
+                let stream = TcpStream::connect(address).await?;
+                stream.set_nodelay(true)?;
+                Output::Stream(BufWriter::new(Box::new(stream)), Format::Forwarded)
+            }
+            Target::Udp(address) => {
+                let target = lookup_host(address)
+                    .await?
+                    .next()
+                    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "host not found"))?;
+                let socket = UdpSocket::bind(if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }).await?;
+                socket.connect(target).await?;
+                Output::Udp(socket)
+            }
+            Target::Parse => Output::Parse,
+        })
+    }
+
+    /// Sends one advertisement, returns whether it was parsed successfully when only parsing.
+    pub async fn send(&mut self, address: &[u8; 6], payload: &[u8]) -> std::io::Result<bool> {
+        match self {
+            Output::Stream(writer, Format::Lines) => {
+                writer.write_all(format!("{} {}\n", address_text(address), hex(payload)).as_bytes()).await?
+            }
+            Output::Stream(writer, Format::Forwarded) => {
+                let line = forwarded(address, payload).to_string() + "\n";
+                writer.write_all(line.as_bytes()).await?
+            }
+            Output::Udp(socket) => {
+                // The receiving end drops datagrams it can't keep up with, like real radios
+                socket.send(forwarded(address, payload).to_string().as_bytes()).await?;
+            }
+            Output::Parse => return Ok(bthome::parse_service_data(payload).is_ok()),
+        }
+        Ok(true)
+    }
+
+    pub async fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Stream(writer, _) => writer.flush().await,
+            Output::Udp(_) | Output::Parse => Ok(()),
+        }
+    }
+}
+
+/// An advertisement in the forwarding protocol of the sniffer.
+fn forwarded(address: &[u8; 6], payload: &[u8]) -> serde_json::Value {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+    json!({
+        "satellite": "firehose",
+        "source": "synthetic",
+        "address": address_text(address),
+        "rssi": -60,
+        "timestamp": timestamp,
+        "data": hex(payload),
+    })
+}
+
+fn address_text(address: &[u8; 6]) -> String {
+    address.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":")
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_target() {
+        assert_eq!("-".parse(), Ok(Target::Stdout));
+        assert_eq!("parse".parse(), Ok(Target::Parse));
+        assert_eq!("tcp://central:7000".parse(), Ok(Target::Tcp("central:7000".to_string())));
+        assert!("central:7000".parse::<Target>().is_err());
+
+        let forwarded = forwarded(&[0xF2, 0xB7, 0, 0, 0, 1], &[0x40, 0x01, 0x61]);
+        assert_eq!(forwarded["address"], "F2:B7:00:00:00:01");
+        assert_eq!(forwarded["data"], "400161");
+    }
+}
@@ -0,0 +1,42 @@
+//! Reading GATT characteristics with BlueZ, advertisements are received with `bthome_listener`.
+
+use bluer::Address;
+
+use crate::gatt::{self, GattReading};
+
+/// Connects to a device and reads its battery level and device information.
+pub async fn read_gatt(adapter: Option<&str>, address: &str) -> bluer::Result<GattReading> {
+    let session = bluer::Session::new().await?;
+    let adapter = match adapter {
+        Some(name) => session.adapter(name)?,
+        None => session.default_adapter().await?,
+    };
+    let mut bytes = [0u8; 6];
+    for (byte, digits) in bytes.iter_mut().zip(address.split(':')) {
+        *byte = u8::from_str_radix(digits, 16).unwrap_or_default();
+    }
+    let device = adapter.device(Address(bytes))?;
+    device.connect().await?;
+    let result = async {
+        let mut reading = GattReading::default();
+        for service in device.services().await? {
+            let service_uuid = gatt::uuid16(service.uuid().await?.as_u128());
+            if !matches!(service_uuid, Some(gatt::BATTERY_SERVICE | gatt::DEVICE_INFORMATION_SERVICE)) {
+                continue;
+            }
+            for characteristic in service.characteristics().await? {
+                if let Some(uuid) = gatt::uuid16(characteristic.uuid().await?.as_u128()) {
+                    // Not every characteristic is readable without pairing, take what is there
+                    if let Ok(value) = characteristic.read().await {
+                        reading.add(uuid, &value);
+                    }
+                }
+            }
+        }
+        Ok(reading)
+    }
+    .await;
+    // The device stops advertising while connected
+    let _ = device.disconnect().await;
+    result
+}
@@ -0,0 +1,175 @@
+//! Commands received on the command topic, to manage a gateway without shell access:
+//!
+//! ```json
+//! {"id": 1, "command": "set_key", "address": "A4:C1:38:12:34:56", "key": "231d39c1d7cc1ab1aee224cd096db932"}
+//! {"command": "remove_key", "address": "A4:C1:38:12:34:56"}
+//! {"command": "rename", "address": "A4:C1:38:12:34:56", "name": "Kitchen"}
+//! {"command": "announce"}
+//! {"command": "status"}
+//! ```
+//!
+//! The answer is published on the response topic, with the `id` of the command if it had one.
+
+use std::fmt;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::{normalize_address, parse_key};
+
+#[derive(PartialEq)]
+pub enum Command {
+    /// Sets the bind key of a device, adding it to the registry if necessary
+    SetKey {
+        address: String,
+        key: [u8; 16],
+    },
+    RemoveKey {
+        address: String,
+    },
+    /// Renames a device, adding it to the registry if necessary
+    Rename {
+        address: String,
+        name: String,
+    },
+    /// Publishes the discovery configurations and retained states again
+    Announce,
+    /// Publishes the devices and metrics of the gateway
+    Status,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Message {
+    id: Option<Value>,
+    command: String,
+    address: Option<String>,
+    key: Option<String>,
+    name: Option<String>,
+}
+
+impl Command {
+    /// Parses a command, returns its id with the command or the error to answer with.
+    pub fn parse(payload: &[u8]) -> (Value, Result<Command, String>) {
+        let message: Message = match serde_json::from_slice(payload) {
+            Ok(message) => message,
+            Err(err) => return (Value::Null, Err(format!("invalid command: {}", err))),
+        };
+        let id = message.id.clone().unwrap_or_default();
+        (id, Command::from_message(message))
+    }
+
+    fn from_message(message: Message) -> Result<Command, String> {
+        let address = || {
+            let address = message.address.as_deref().ok_or("address is missing")?;
+            normalize_address(address).ok_or_else(|| format!("invalid address {:?}", address))
+        };
+        match message.command.as_str() {
+            "set_key" => {
+                let key = message.key.as_deref().ok_or("key is missing")?;
+                let key = parse_key(key).ok_or("invalid key, expected 32 hex digits")?;
+                Ok(Command::SetKey {
+                    address: address()?,
+                    key,
+                })
+            }
+            "remove_key" => Ok(Command::RemoveKey { address: address()? }),
+            "rename" => {
+                let name = message.name.as_deref().map(str::trim).unwrap_or_default();
+                if name.is_empty() {
+                    return Err("name is missing".to_string());
+                }
+                Ok(Command::Rename {
+                    address: address()?,
+                    name: name.to_string(),
+                })
+            }
+            "announce" => Ok(Command::Announce),
+            "status" => Ok(Command::Status),
+            other => Err(format!("unknown command {:?}", other)),
+        }
+    }
+}
+
+/// Keeps keys out of the log.
+impl fmt::Debug for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::SetKey { address, .. } => f
+                .debug_struct("SetKey")
+                .field("address", address)
+                .finish_non_exhaustive(),
+            Command::RemoveKey { address } => f.debug_struct("RemoveKey").field("address", address).finish(),
+            Command::Rename { address, name } => f
+                .debug_struct("Rename")
+                .field("address", address)
+                .field("name", name)
+                .finish(),
+            Command::Announce => f.write_str("Announce"),
+            Command::Status => f.write_str("Status"),
+        }
+    }
+}
+
+/// The answer to a command, `result` is merged into it on success.
+pub fn response(id: &Value, result: Result<Value, String>) -> Value {
+    match result {
+        Ok(mut result) => {
+            result["id"] = id.clone();
+            result["ok"] = json!(true);
+            result
+        }
+        Err(error) => json!({"id": id, "ok": false, "error": error}),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        let (id, command) = Command::parse(
+            br#"{"id": "a", "command": "set_key", "address": "a4:c1:38:12:34:56", "key": "231d39c1d7cc1ab1aee224cd096db932"}"#,
+        );
+        assert_eq!(id, json!("a"));
+        assert_eq!(
+            command,
+            Ok(Command::SetKey {
+                address: "A4:C1:38:12:34:56".to_string(),
+                key: parse_key("231d39c1d7cc1ab1aee224cd096db932").unwrap(),
+            })
+        );
+        let (_, command) =
+            Command::parse(br#"{"command": "rename", "address": "A4:C1:38:12:34:56", "name": " Hall "}"#);
+        assert_eq!(
+            command,
+            Ok(Command::Rename {
+                address: "A4:C1:38:12:34:56".to_string(),
+                name: "Hall".to_string(),
+            })
+        );
+        assert_eq!(
+            Command::parse(br#"{"command": "status"}"#),
+            (Value::Null, Ok(Command::Status))
+        );
+
+        assert!(
+            Command::parse(br#"{"id": 2, "command": "set_key", "address": "A4:C1"}"#)
+                .1
+                .is_err()
+        );
+        assert!(Command::parse(br#"{"command": "remove_key"}"#).1.is_err());
+        assert!(Command::parse(br#"{"command": "reboot"}"#).1.is_err());
+        assert!(Command::parse(b"status").1.is_err());
+    }
+
+    #[test]
+    fn responses() {
+        assert_eq!(response(&json!(1), Ok(json!({}))), json!({"id": 1, "ok": true}));
+        assert_eq!(
+            response(&Value::Null, Err("unknown command".to_string())),
+            json!({"id": null, "ok": false, "error": "unknown command"})
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! The configuration file of the gateway:
+//!
+//! ```toml
+//! state_file = "/var/lib/bthome-gateway/state.json"
+//!
+//! [mqtt]
+//! host = "localhost"
+//! # Manage devices remotely, see the command module
+//! commands = true
+//!
+//! # Instead of or in addition to MQTT
+//! [homeassistant]
+//! url = "http://homeassistant.local:8123"
+//! token = "<long-lived access token>"
+//!
+//! # Real-time dashboards, panels subscribe to stream/bthome/<channel>
+//! [grafana]
+//! url = "http://grafana.local:3000"
+//! token = "<service account token>"
+//! [grafana.channels]
+//! temperature = "climate"
+//! humidity = "climate"
+//!
+//! [devices."A4:C1:38:12:34:56"]
+//! name = "Living room"
+//! key = "231d39c1d7cc1ab1aee224cd096db932"
+//! # Connect now and then to read the battery level and device information
+//! gatt = true
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Bluetooth adapter to scan on (default: the system's default adapter)
+    pub adapter: Option<String>,
+    /// Where the state of the devices is kept across restarts
+    pub state_file: Option<PathBuf>,
+    /// Publish devices that are not in the registry, named by their address
+    #[serde(default)]
+    pub allow_unknown: bool,
+    /// Seconds without advertisements after which a device is reported as offline
+    #[serde(default = "default_availability_timeout")]
+    pub availability_timeout: u64,
+    /// Seconds between publishing the metrics of the gateway
+    #[serde(default = "default_metrics_interval")]
+    pub metrics_interval: u64,
+    /// Seconds between reading the GATT characteristics of devices with `gatt` enabled
+    #[serde(default = "default_gatt_interval")]
+    pub gatt_interval: u64,
+    pub mqtt: Option<MqttConfig>,
+    /// Pushes the states directly to Home Assistant's REST API
+    pub homeassistant: Option<HomeAssistantConfig>,
+    /// Pushes the measurements to Grafana Live
+    pub grafana: Option<GrafanaConfig>,
+    /// The device registry, keyed by the MAC address of the devices
+    #[serde(default)]
+    pub devices: HashMap<String, DeviceConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    /// Prefix of the topics the states are published on
+    #[serde(default = "default_base_topic")]
+    pub base_topic: String,
+    /// Prefix of the Home Assistant discovery topics, discovery is disabled if empty
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: String,
+    /// Accept commands on `<base_topic>/gateway/command`, anyone allowed to publish there can
+    /// change the keys and names of devices
+    #[serde(default)]
+    pub commands: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HomeAssistantConfig {
+    /// Base URL of Home Assistant, e.g. `http://homeassistant.local:8123`
+    pub url: String,
+    /// A long-lived access token, created in the profile of a Home Assistant user
+    pub token: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GrafanaConfig {
+    /// Base URL of Grafana, e.g. `http://grafana.local:3000`
+    pub url: String,
+    /// A service account token with permission to publish to Grafana Live
+    pub token: String,
+    /// Stream id, the first part of the channels after `stream/`
+    #[serde(default = "default_stream")]
+    pub stream: String,
+    /// Measurement of the values not listed in `channels`, they are not pushed if empty
+    #[serde(default = "default_channel")]
+    pub channel: String,
+    /// Measurement by key in the state, e.g. `temperature = "climate"`, an empty one drops the key
+    #[serde(default)]
+    pub channels: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub name: String,
+    /// Bind key of encrypted devices, 32 hex digits
+    pub key: Option<String>,
+    /// Read the Battery and Device Information services by connecting to the device
+    #[serde(default)]
+    pub gatt: bool,
+}
+
+fn default_availability_timeout() -> u64 {
+    15 * 60
+}
+
+fn default_metrics_interval() -> u64 {
+    60
+}
+
+fn default_gatt_interval() -> u64 {
+    6 * 60 * 60
+}
+
+fn default_stream() -> String {
+    "bthome".to_string()
+}
+
+fn default_channel() -> String {
+    "sensors".to_string()
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "bthome-gateway".to_string()
+}
+
+fn default_base_topic() -> String {
+    "bthome".to_string()
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+impl Config {
+    pub fn parse(content: &str) -> Result<Config, String> {
+        let mut config: Config = toml::from_str(content).map_err(|err| format!("invalid configuration: {}", err))?;
+        if config.mqtt.is_none() && config.homeassistant.is_none() && config.grafana.is_none() {
+            return Err("configure at least one of [mqtt], [homeassistant] and [grafana]".to_string());
+        }
+        if let Some(homeassistant) = &mut config.homeassistant {
+            homeassistant.url = normalize_url(&homeassistant.url)
+                .ok_or_else(|| format!("invalid Home Assistant URL {:?}", homeassistant.url))?;
+        }
+        if let Some(grafana) = &mut config.grafana {
+            grafana.url = normalize_url(&grafana.url).ok_or_else(|| format!("invalid Grafana URL {:?}", grafana.url))?;
+            let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '-');
+            if grafana.stream.is_empty() || !grafana.stream.chars().all(valid) {
+                return Err(format!("invalid Grafana stream {:?}, use letters, digits, - and _", grafana.stream));
+            }
+        }
+        let mut devices = HashMap::new();
+        for (address, device) in config.devices {
+            let normalized = normalize_address(&address).ok_or_else(|| format!("invalid address {:?}", address))?;
+            if let Some(key) = &device.key {
+                parse_key(key).ok_or_else(|| format!("invalid key for {}, expected 32 hex digits", address))?;
+            }
+            if devices.insert(normalized, device).is_some() {
+                return Err(format!("device {} is configured twice", address));
+            }
+        }
+        config.devices = devices;
+        Ok(config)
+    }
+}
+
+/// The URL without a trailing slash, if it is an HTTP(S) URL.
+fn normalize_url(url: &str) -> Option<String> {
+    (url.starts_with("http://") || url.starts_with("https://")).then(|| url.trim_end_matches('/').to_string())
+}
+
+/// Normalizes a MAC address to upper case hex digits separated by colons, as reported by BlueZ.
+pub fn normalize_address(address: &str) -> Option<String> {
+    let digits: String = address.chars().filter(|c| !matches!(c, ':' | '-')).collect();
+    if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let bytes: Vec<String> = (0..12).step_by(2).map(|i| digits[i..i + 2].to_ascii_uppercase()).collect();
+    Some(bytes.join(":"))
+}
+
+/// The bytes of a normalized address, in the order they are written.
+pub fn address_bytes(address: &str) -> Option<[u8; 6]> {
+    let mut bytes = [0; 6];
+    let mut parts = address.split(':');
+    for byte in bytes.iter_mut() {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    parts.next().is_none().then_some(bytes)
+}
+
+pub fn parse_key(key: &str) -> Option<[u8; 16]> {
+    if key.len() != 32 {
+        return None;
+    }
+    let mut bytes = [0; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(key.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config = Config::parse(
+            "[mqtt]\nhost = \"broker\"\n\n[devices.\"a4-c1-38-12-34-56\"]\nname = \"Living room\"\nkey = \"231d39c1d7cc1ab1aee224cd096db932\"\n",
+        )
+        .unwrap();
+        let mqtt = config.mqtt.unwrap();
+        assert_eq!(mqtt.port, 1883);
+        assert_eq!(mqtt.discovery_prefix, "homeassistant");
+        assert!(!mqtt.commands);
+        assert_eq!(config.availability_timeout, 900);
+        assert_eq!(config.devices["A4:C1:38:12:34:56"].name, "Living room");
+        assert!(!config.devices["A4:C1:38:12:34:56"].gatt);
+        assert!(config.homeassistant.is_none());
+
+        let config = Config::parse("[homeassistant]\nurl = \"http://ha.local:8123/\"\ntoken = \"abc\"\n").unwrap();
+        assert!(config.mqtt.is_none());
+        assert_eq!(config.homeassistant.unwrap().url, "http://ha.local:8123");
+        assert!(Config::parse("[homeassistant]\nurl = \"ha.local\"\ntoken = \"abc\"\n").is_err());
+        assert!(Config::parse("state_file = \"state.json\"\n").is_err());
+
+        let config = Config::parse(
+            "[grafana]\nurl = \"http://grafana:3000/\"\ntoken = \"abc\"\n[grafana.channels]\ntemperature = \"climate\"\n",
+        )
+        .unwrap();
+        let grafana = config.grafana.unwrap();
+        assert_eq!(grafana.url, "http://grafana:3000");
+        assert_eq!((grafana.stream.as_str(), grafana.channel.as_str()), ("bthome", "sensors"));
+        assert_eq!(grafana.channels["temperature"], "climate");
+        assert!(Config::parse("[grafana]\nurl = \"http://grafana:3000\"\ntoken = \"abc\"\nstream = \"a/b\"\n").is_err());
+
+        assert_eq!(address_bytes("A4:C1:38:12:34:56"), Some([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]));
+        assert_eq!(address_bytes("A4:C1:38:12:34"), None);
+
+        assert!(Config::parse("[mqtt]\nhost = \"broker\"\n[devices.\"A4:C1\"]\nname = \"x\"\n").is_err());
+        assert!(Config::parse("[mqtt]\nhost = \"broker\"\n[devices.\"A4:C1:38:12:34:56\"]\nname = \"x\"\nkey = \"abc\"\n").is_err());
+        assert!(Config::parse(
+            "[mqtt]\nhost = \"broker\"\n[devices.\"A4:C1:38:12:34:56\"]\nname = \"x\"\n[devices.\"a4:c1:38:12:34:56\"]\nname = \"y\"\n"
+        )
+        .is_err());
+    }
+}
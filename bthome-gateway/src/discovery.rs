@@ -0,0 +1,389 @@
+//! Mapping of BTHome objects to JSON states and Home Assistant entities.
+
+use std::collections::BTreeMap;
+
+use bthome::{ButtonEvent, DimmerEvent, Object, ObjectValue};
+use serde_json::{json, Map, Value};
+
+use crate::mqtt::Topics;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    Sensor,
+    BinarySensor,
+    Event,
+}
+
+impl Component {
+    fn name(self) -> &'static str {
+        match self {
+            Component::Sensor => "sensor",
+            Component::BinarySensor => "binary_sensor",
+            Component::Event => "event",
+        }
+    }
+}
+
+use Component::*;
+
+/// Object id, key in the state, component, device class and unit.
+type Entity = (u8, &'static str, Component, Option<&'static str>, Option<&'static str>);
+
+/// Objects with the same meaning but a different precision share their key.
+#[rustfmt::skip]
+const ENTITIES: &[Entity] = &[
+    (0x01, "battery", Sensor, Some("battery"), Some("%")),
+    (0x02, "temperature", Sensor, Some("temperature"), Some("°C")),
+    (0x03, "humidity", Sensor, Some("humidity"), Some("%")),
+    (0x04, "pressure", Sensor, Some("pressure"), Some("hPa")),
+    (0x05, "illuminance", Sensor, Some("illuminance"), Some("lx")),
+    (0x06, "mass", Sensor, Some("weight"), Some("kg")),
+    (0x07, "mass", Sensor, Some("weight"), Some("lb")),
+    (0x08, "dewpoint", Sensor, Some("temperature"), Some("°C")),
+    (0x09, "count", Sensor, None, None),
+    (0x0A, "energy", Sensor, Some("energy"), Some("kWh")),
+    (0x0B, "power", Sensor, Some("power"), Some("W")),
+    (0x0C, "voltage", Sensor, Some("voltage"), Some("V")),
+    (0x0D, "pm2_5", Sensor, Some("pm25"), Some("µg/m³")),
+    (0x0E, "pm10", Sensor, Some("pm10"), Some("µg/m³")),
+    (0x0F, "generic_boolean", BinarySensor, None, None),
+    (0x10, "power_on", BinarySensor, Some("power"), None),
+    (0x11, "opening", BinarySensor, Some("opening"), None),
+    (0x12, "co2", Sensor, Some("carbon_dioxide"), Some("ppm")),
+    (0x13, "tvoc", Sensor, Some("volatile_organic_compounds"), Some("µg/m³")),
+    (0x14, "moisture", Sensor, Some("moisture"), Some("%")),
+    (0x15, "battery_low", BinarySensor, Some("battery"), None),
+    (0x16, "battery_charging", BinarySensor, Some("battery_charging"), None),
+    (0x17, "carbon_monoxide", BinarySensor, Some("carbon_monoxide"), None),
+    (0x18, "cold", BinarySensor, Some("cold"), None),
+    (0x19, "connectivity", BinarySensor, Some("connectivity"), None),
+    (0x1A, "door", BinarySensor, Some("door"), None),
+    (0x1B, "garage_door", BinarySensor, Some("garage_door"), None),
+    (0x1C, "gas_detected", BinarySensor, Some("gas"), None),
+    (0x1D, "heat", BinarySensor, Some("heat"), None),
+    (0x1E, "light", BinarySensor, Some("light"), None),
+    (0x1F, "lock", BinarySensor, Some("lock"), None),
+    (0x20, "moisture_detected", BinarySensor, Some("moisture"), None),
+    (0x21, "motion", BinarySensor, Some("motion"), None),
+    (0x22, "moving", BinarySensor, Some("moving"), None),
+    (0x23, "occupancy", BinarySensor, Some("occupancy"), None),
+    (0x24, "plug", BinarySensor, Some("plug"), None),
+    (0x25, "presence", BinarySensor, Some("presence"), None),
+    (0x26, "problem", BinarySensor, Some("problem"), None),
+    (0x27, "running", BinarySensor, Some("running"), None),
+    // BTHome reports true for safe, Home Assistant's safety class means unsafe when on
+    (0x28, "safety", BinarySensor, None, None),
+    (0x29, "smoke", BinarySensor, Some("smoke"), None),
+    (0x2A, "sound", BinarySensor, Some("sound"), None),
+    (0x2B, "tamper", BinarySensor, Some("tamper"), None),
+    (0x2C, "vibration", BinarySensor, Some("vibration"), None),
+    (0x2D, "window", BinarySensor, Some("window"), None),
+    (0x2E, "humidity", Sensor, Some("humidity"), Some("%")),
+    (0x2F, "moisture", Sensor, Some("moisture"), Some("%")),
+    (0x3A, "button", Event, Some("button"), None),
+    (0x3C, "dimmer", Event, None, None),
+    (0x3D, "count", Sensor, None, None),
+    (0x3E, "count", Sensor, None, None),
+    (0x3F, "rotation", Sensor, None, Some("°")),
+    (0x40, "distance", Sensor, Some("distance"), Some("mm")),
+    (0x41, "distance", Sensor, Some("distance"), Some("m")),
+    (0x42, "duration", Sensor, Some("duration"), Some("s")),
+    (0x43, "current", Sensor, Some("current"), Some("A")),
+    (0x44, "speed", Sensor, Some("speed"), Some("m/s")),
+    (0x45, "temperature", Sensor, Some("temperature"), Some("°C")),
+    (0x46, "uv_index", Sensor, None, None),
+    (0x47, "volume", Sensor, Some("volume"), Some("L")),
+    (0x48, "volume", Sensor, Some("volume"), Some("mL")),
+    (0x49, "volume_flow_rate", Sensor, Some("volume_flow_rate"), Some("m³/h")),
+    (0x4A, "voltage", Sensor, Some("voltage"), Some("V")),
+    (0x4B, "gas", Sensor, Some("gas"), Some("m³")),
+    (0x4C, "gas", Sensor, Some("gas"), Some("m³")),
+    (0x4D, "energy", Sensor, Some("energy"), Some("kWh")),
+    (0x4E, "volume", Sensor, Some("volume"), Some("L")),
+    (0x4F, "water", Sensor, Some("water"), Some("L")),
+    (0x50, "timestamp", Sensor, None, Some("s")),
+    (0x51, "acceleration", Sensor, None, Some("m/s²")),
+    (0x52, "gyroscope", Sensor, None, Some("°/s")),
+    (0x53, "text", Sensor, None, None),
+    (0x54, "raw", Sensor, None, None),
+    (0x55, "volume_storage", Sensor, Some("volume_storage"), Some("L")),
+    (0x56, "conductivity", Sensor, Some("conductivity"), Some("µS/cm")),
+    (0x57, "temperature", Sensor, Some("temperature"), Some("°C")),
+    (0x58, "temperature", Sensor, Some("temperature"), Some("°C")),
+    (0x59, "count", Sensor, None, None),
+    (0x5A, "count", Sensor, None, None),
+    (0x5B, "count", Sensor, None, None),
+    (0x5C, "power", Sensor, Some("power"), Some("W")),
+    (0x5D, "current", Sensor, Some("current"), Some("A")),
+];
+
+const BUTTON_EVENTS: &[&str] = &[
+    "press",
+    "double_press",
+    "triple_press",
+    "long_press",
+    "long_double_press",
+    "long_triple_press",
+    "hold_press",
+    "hold",
+];
+
+const DIMMER_EVENTS: &[&str] = &["rotate_left", "rotate_right"];
+
+fn entity(id: u8) -> Option<&'static Entity> {
+    ENTITIES.iter().find(|(object, ..)| *object == id)
+}
+
+/// The values of one advertisement.
+#[derive(Debug, Default, PartialEq)]
+pub struct Reading {
+    pub packet_id: Option<u8>,
+    /// Values of sensors and binary sensors by key
+    pub state: Map<String, Value>,
+    /// Button and dimmer events by key
+    pub events: Vec<(String, Value)>,
+    /// The object id behind each key, repeated objects get numbered keys like `temperature_2`
+    pub entities: BTreeMap<String, u8>,
+}
+
+impl Reading {
+    pub fn new(objects: Vec<Object>) -> Reading {
+        let mut reading = Reading::default();
+        for object in objects {
+            let id = object.object_id as u8;
+            if id == 0x00 {
+                if let ObjectValue::Int(packet_id) = object.value {
+                    reading.packet_id = u8::try_from(packet_id).ok();
+                }
+                continue;
+            }
+            let Some((_, name, component, ..)) = entity(id) else {
+                continue;
+            };
+            let mut key = name.to_string();
+            let mut n = 1;
+            while reading.entities.contains_key(&key) {
+                n += 1;
+                key = format!("{}_{}", name, n);
+            }
+            reading.entities.insert(key.clone(), id);
+            match (component, object.value) {
+                (Event, ObjectValue::ButtonEvent(ButtonEvent::None)) => {}
+                (Event, ObjectValue::ButtonEvent(event)) => {
+                    reading.events.push((key, json!({"event_type": snake_case(&format!("{:?}", event))})))
+                }
+                (Event, ObjectValue::DimmerEvent(event, steps)) => {
+                    let event_type = match event {
+                        DimmerEvent::RotateLeft => "rotate_left",
+                        DimmerEvent::RotateRight => "rotate_right",
+                        DimmerEvent::None => continue,
+                    };
+                    reading.events.push((key, json!({"event_type": event_type, "steps": steps})))
+                }
+                (_, value) => {
+                    reading.state.insert(key, state_value(value));
+                }
+            }
+        }
+        reading
+    }
+}
+
+fn state_value(value: ObjectValue) -> Value {
+    match value {
+        ObjectValue::Float(value) => json!(value),
+        ObjectValue::Int(value) => json!(value),
+        ObjectValue::UInt(value) => json!(value),
+        ObjectValue::Bool(value) => json!(value),
+        ObjectValue::Text(text) => json!(text),
+        ObjectValue::FirmwareVersion(version) => json!(version.to_string()),
+        ObjectValue::DeviceType(device_type) => json!(device_type.to_string()),
+        ObjectValue::Raw(bytes) => json!(bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+        ObjectValue::ButtonEvent(event) => json!(snake_case(&format!("{:?}", event))),
+        ObjectValue::DimmerEvent(_, steps) => json!(steps),
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut result = String::new();
+    for c in name.chars() {
+        if c.is_ascii_uppercase() && !result.is_empty() {
+            result.push('_');
+        }
+        result.push(c.to_ascii_lowercase());
+    }
+    result
+}
+
+/// A device as shown in Home Assistant.
+pub struct Device<'a> {
+    pub node: &'a str,
+    pub address: &'a str,
+    pub name: &'a str,
+}
+
+/// The discovery topic and configuration of the entity for `key`, which is the object `id`.
+pub fn entity_config(topics: &Topics, device: &Device, key: &str, id: u8) -> Option<(String, Value)> {
+    let (_, _, component, device_class, unit) = entity(id)?;
+    let mut config = json!({
+        "name": title(key),
+        "unique_id": format!("bthome_{}_{}", device.node, key),
+        "availability": [
+            {"topic": topics.availability(device.node)},
+            {"topic": topics.gateway_status()},
+        ],
+        "availability_mode": "all",
+        "device": {
+            "identifiers": [format!("bthome_{}", device.node)],
+            "connections": [["mac", device.address]],
+            "name": device.name,
+        },
+    });
+    match component {
+        Sensor => {
+            config["state_topic"] = json!(topics.state(device.node));
+            config["value_template"] = json!(format!("{{{{ value_json.{} }}}}", key));
+            if unit.is_some() {
+                config["state_class"] = json!("measurement");
+            }
+        }
+        BinarySensor => {
+            config["state_topic"] = json!(topics.state(device.node));
+            config["value_template"] = json!(format!("{{{{ 'ON' if value_json.{} else 'OFF' }}}}", key));
+        }
+        Event => {
+            config["state_topic"] = json!(topics.event(device.node, key));
+            config["event_types"] = json!(if id == 0x3C { DIMMER_EVENTS } else { BUTTON_EVENTS });
+        }
+    }
+    if let Some(device_class) = device_class {
+        config["device_class"] = json!(device_class);
+    }
+    if let Some(unit) = unit {
+        config["unit_of_measurement"] = json!(unit);
+    }
+    let topic = format!("{}/{}/{}/{}/config", topics.discovery_prefix, component.name(), device.node, key);
+    Some((topic, config))
+}
+
+/// The entity id and the body of `POST /api/states/<entity_id>` for the value of `key`, for
+/// pushing states to Home Assistant without MQTT. `None` as value marks the entity unavailable.
+pub fn entity_state(device: &Device, key: &str, id: u8, value: Option<&Value>) -> Option<(String, Value)> {
+    let (_, _, component, device_class, unit) = entity(id)?;
+    let state = match (component, value) {
+        (Event, _) => return None,
+        (_, None) => json!("unavailable"),
+        (BinarySensor, Some(value)) => json!(if value.as_bool() == Some(true) { "on" } else { "off" }),
+        (Sensor, Some(Value::String(text))) => json!(text),
+        (Sensor, Some(value)) => json!(value.to_string()),
+    };
+    let mut attributes = json!({
+        "friendly_name": format!("{} {}", device.name, title(key).to_lowercase()),
+        "address": device.address,
+    });
+    if let Some(device_class) = device_class {
+        attributes["device_class"] = json!(device_class);
+    }
+    if let Some(unit) = unit {
+        attributes["unit_of_measurement"] = json!(unit);
+        attributes["state_class"] = json!("measurement");
+    }
+    let entity_id = format!("{}.bthome_{}_{}", component.name(), device.node, key);
+    Some((entity_id, json!({"state": state, "attributes": attributes})))
+}
+
+/// The data of a `bthome_event` fired in Home Assistant for a button or dimmer event.
+pub fn event_data(device: &Device, key: &str, event: &Value) -> Value {
+    let mut data = json!({
+        "address": device.address,
+        "name": device.name,
+        "key": key,
+    });
+    if let (Some(data), Some(event)) = (data.as_object_mut(), event.as_object()) {
+        data.extend(event.clone());
+    }
+    data
+}
+
+/// Turns a key like `battery_low_2` into `Battery low 2`.
+fn title(key: &str) -> String {
+    let text = key.replace('_', " ");
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bthome::parse_service_data;
+
+    use super::*;
+
+    #[test]
+    fn reading_from_objects() {
+        // Packet id 9, two temperatures, humidity and a double press
+        let data = [0x44, 0x00, 0x09, 0x02, 0xC4, 0x09, 0x02, 0x6A, 0xFF, 0x03, 0xBF, 0x13, 0x3A, 0x02];
+        let reading = Reading::new(parse_service_data(&data).unwrap().objects);
+        assert_eq!(reading.packet_id, Some(9));
+        assert_eq!(
+            Value::Object(reading.state),
+            json!({"temperature": 25.0, "temperature_2": -1.5, "humidity": 50.55})
+        );
+        assert_eq!(reading.events, vec![("button".to_string(), json!({"event_type": "double_press"}))]);
+        assert_eq!(reading.entities["temperature_2"], 0x02);
+    }
+
+    #[test]
+    fn discovery_config() {
+        let topics = Topics::new("bthome", "homeassistant");
+        let device = Device {
+            node: "a4c138123456",
+            address: "A4:C1:38:12:34:56",
+            name: "Living room",
+        };
+        let (topic, config) = entity_config(&topics, &device, "temperature", 0x02).unwrap();
+        assert_eq!(topic, "homeassistant/sensor/a4c138123456/temperature/config");
+        assert_eq!(config["state_topic"], "bthome/a4c138123456/state");
+        assert_eq!(config["value_template"], "{{ value_json.temperature }}");
+        assert_eq!(config["unit_of_measurement"], "°C");
+        assert_eq!(config["device"]["name"], "Living room");
+
+        let (topic, config) = entity_config(&topics, &device, "window", 0x2D).unwrap();
+        assert_eq!(topic, "homeassistant/binary_sensor/a4c138123456/window/config");
+        assert_eq!(config["value_template"], "{{ 'ON' if value_json.window else 'OFF' }}");
+
+        let (_, config) = entity_config(&topics, &device, "button_2", 0x3A).unwrap();
+        assert_eq!(config["state_topic"], "bthome/a4c138123456/button_2");
+        assert_eq!(config["name"], "Button 2");
+        assert!(entity_config(&topics, &device, "packet_id", 0x00).is_none());
+    }
+
+    #[test]
+    fn pushed_states() {
+        let device = Device {
+            node: "a4c138123456",
+            address: "A4:C1:38:12:34:56",
+            name: "Living room",
+        };
+        let (entity_id, body) = entity_state(&device, "temperature_2", 0x02, Some(&json!(21.5))).unwrap();
+        assert_eq!(entity_id, "sensor.bthome_a4c138123456_temperature_2");
+        assert_eq!(body["state"], "21.5");
+        assert_eq!(body["attributes"]["friendly_name"], "Living room temperature 2");
+        assert_eq!(body["attributes"]["unit_of_measurement"], "°C");
+
+        let (entity_id, body) = entity_state(&device, "window", 0x2D, Some(&json!(true))).unwrap();
+        assert_eq!(entity_id, "binary_sensor.bthome_a4c138123456_window");
+        assert_eq!(body["state"], "on");
+        let (_, body) = entity_state(&device, "window", 0x2D, None).unwrap();
+        assert_eq!(body["state"], "unavailable");
+        assert!(entity_state(&device, "button", 0x3A, Some(&json!("press"))).is_none());
+
+        let data = event_data(&device, "dimmer", &json!({"event_type": "rotate_left", "steps": 3}));
+        assert_eq!(
+            data,
+            json!({"address": "A4:C1:38:12:34:56", "name": "Living room", "key": "dimmer", "event_type": "rotate_left", "steps": 3})
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Values some devices only expose over GATT, read by connecting to them now and then. Only the
+//! standard Battery Service and Device Information Service are read.
+
+use serde_json::json;
+
+use crate::discovery::Reading;
+
+/// Base of the 16 bit UUIDs assigned by the Bluetooth SIG.
+const BASE_UUID: u128 = 0x0000_0000_0000_1000_8000_0080_5F9B_34FB;
+
+pub const BATTERY_SERVICE: u16 = 0x180F;
+pub const DEVICE_INFORMATION_SERVICE: u16 = 0x180A;
+const BATTERY_LEVEL: u16 = 0x2A19;
+
+/// Characteristics of the Device Information Service and their keys in the state.
+const DEVICE_INFORMATION: &[(u16, &str)] = &[
+    (0x2A29, "manufacturer"),
+    (0x2A24, "model"),
+    (0x2A25, "serial_number"),
+    (0x2A27, "hardware_version"),
+    (0x2A26, "firmware_version"),
+    (0x2A28, "software_version"),
+];
+
+/// The 16 bit UUID of a service or characteristic, if it is one assigned by the Bluetooth SIG.
+pub fn uuid16(uuid: u128) -> Option<u16> {
+    (uuid & !(0xFFFF << 96) == BASE_UUID).then_some((uuid >> 96) as u16)
+}
+
+/// The characteristics read from a device.
+#[derive(Debug, Default, PartialEq)]
+pub struct GattReading {
+    /// Battery level in percent
+    pub battery: Option<u8>,
+    /// Strings of the Device Information Service by key
+    pub info: Vec<(&'static str, String)>,
+}
+
+impl GattReading {
+    /// Records the value of a characteristic, those that are not of interest are ignored.
+    pub fn add(&mut self, characteristic: u16, value: &[u8]) {
+        if characteristic == BATTERY_LEVEL {
+            self.battery = value.first().copied().filter(|level| *level <= 100);
+        } else if let Some((_, key)) = DEVICE_INFORMATION.iter().find(|(uuid, _)| *uuid == characteristic) {
+            let text = String::from_utf8_lossy(value).trim_end_matches('\0').trim().to_string();
+            if !text.is_empty() {
+                self.info.push((key, text));
+            }
+        }
+    }
+
+    /// The values as a reading, so that they are merged into the state like an advertisement.
+    pub fn into_reading(self) -> Reading {
+        let mut reading = Reading::default();
+        if let Some(battery) = self.battery {
+            reading.state.insert("battery".to_string(), json!(battery));
+            reading.entities.insert("battery".to_string(), 0x01);
+        }
+        for (key, text) in self.info {
+            reading.state.insert(key.to_string(), json!(text));
+        }
+        reading
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn read_characteristics() {
+        assert_eq!(uuid16(0x0000_180F_0000_1000_8000_0080_5F9B_34FB), Some(BATTERY_SERVICE));
+        assert_eq!(uuid16(0x0000_FCD2_0000_1000_8000_0080_5F9B_34FC), None);
+
+        let mut reading = GattReading::default();
+        reading.add(BATTERY_LEVEL, &[87]);
+        reading.add(0x2A29, b"Shelly\0");
+        reading.add(0x2A26, b"");
+        reading.add(0x2A00, b"Device name");
+        let reading = reading.into_reading();
+        assert_eq!(Value::Object(reading.state), json!({"battery": 87, "manufacturer": "Shelly"}));
+        assert_eq!(reading.entities["battery"], 0x01);
+
+        let mut invalid = GattReading::default();
+        invalid.add(BATTERY_LEVEL, &[255]);
+        assert_eq!(invalid.battery, None);
+    }
+}
@@ -0,0 +1,133 @@
+//! Pushing measurements to Grafana Live, for real-time dashboards without a database in between.
+//!
+//! The values are pushed as Influx line protocol to `/api/live/push/<stream>`, each measurement
+//! becomes a channel `stream/<stream>/<measurement>` that panels can subscribe to.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use serde_json::{Map, Value};
+use tokio::{
+    sync::mpsc::{channel, Sender},
+    task::JoinHandle,
+};
+use tracing::{info, warn};
+
+use crate::config::GrafanaConfig;
+
+/// Queues measurements for a background task, like [`crate::homeassistant::HomeAssistant`].
+pub struct Grafana {
+    tx: Sender<String>,
+    channel: String,
+    channels: BTreeMap<String, String>,
+}
+
+impl Grafana {
+    /// Starts the task sending the measurements, it ends when the client is dropped.
+    pub fn new(config: &GrafanaConfig) -> Result<(Grafana, JoinHandle<()>), reqwest::Error> {
+        let client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?;
+        let (tx, mut rx) = channel::<String>(100);
+        let url = format!("{}/api/live/push/{}", config.url, config.stream);
+        let token = config.token.clone();
+        let task = tokio::spawn(async move {
+            let mut failing = false;
+            while let Some(lines) = rx.recv().await {
+                let result = client
+                    .post(&url)
+                    .bearer_auth(&token)
+                    .body(lines)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                match result {
+                    Ok(_) if failing => {
+                        info!(url, "Grafana is reachable again");
+                        failing = false;
+                    }
+                    Ok(_) => {}
+                    Err(err) if !failing => {
+                        warn!(url, error = %err, "Error pushing to Grafana");
+                        failing = true;
+                    }
+                    Err(_) => {}
+                }
+            }
+        });
+        let grafana = Grafana {
+            tx,
+            channel: config.channel.clone(),
+            channels: config.channels.clone(),
+        };
+        Ok((grafana, task))
+    }
+
+    /// Pushes the numeric and boolean values of a state, returns false if the queue is full.
+    pub fn push(&self, address: &str, name: &str, state: &Map<String, Value>) -> bool {
+        let lines = self.lines(address, name, state);
+        lines.is_empty() || self.tx.try_send(lines).is_ok()
+    }
+
+    /// One line per measurement, with the values of the state configured for its channel.
+    fn lines(&self, address: &str, name: &str, state: &Map<String, Value>) -> String {
+        let mut measurements: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for (key, value) in state {
+            let channel = self.channels.get(key).unwrap_or(&self.channel);
+            let value = match value {
+                Value::Number(number) => number.to_string(),
+                Value::Bool(value) => value.to_string(),
+                _ => continue,
+            };
+            if !channel.is_empty() {
+                measurements.entry(channel).or_default().push(format!("{}={}", escape(key), value));
+            }
+        }
+        measurements
+            .into_iter()
+            .map(|(measurement, fields)| {
+                format!(
+                    "{},address={},name={} {}\n",
+                    escape(measurement),
+                    escape(address),
+                    escape(name),
+                    fields.join(",")
+                )
+            })
+            .collect()
+    }
+}
+
+/// Escapes the characters with a meaning in line protocol.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn line_protocol() {
+        let (tx, _rx) = channel(1);
+        let grafana = Grafana {
+            tx,
+            channel: "sensors".to_string(),
+            channels: BTreeMap::from([
+                ("humidity".to_string(), "climate".to_string()),
+                ("temperature".to_string(), "climate".to_string()),
+                ("count".to_string(), String::new()),
+            ]),
+        };
+        let state = json!({"temperature": 21.5, "humidity": 48, "count": 3, "window": true, "firmware_version": "1.2"});
+        let Value::Object(state) = state else { unreachable!() };
+        assert_eq!(
+            grafana.lines("A4:C1:38:12:34:56", "Living room", &state),
+            "climate,address=A4:C1:38:12:34:56,name=Living\\ room humidity=48,temperature=21.5\n\
+             sensors,address=A4:C1:38:12:34:56,name=Living\\ room window=true\n"
+        );
+        assert_eq!(grafana.lines("A4:C1:38:12:34:56", "x", &Map::new()), "");
+    }
+}
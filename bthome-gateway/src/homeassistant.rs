@@ -0,0 +1,74 @@
+//! Pushing states and events to Home Assistant's REST API, for setups without an MQTT broker.
+
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::{
+    sync::mpsc::{channel, Sender},
+    task::JoinHandle,
+};
+use tracing::{info, warn};
+
+use crate::config::HomeAssistantConfig;
+
+/// The event fired for button and dimmer events.
+pub const EVENT_TYPE: &str = "bthome_event";
+
+struct Request {
+    path: String,
+    body: Value,
+}
+
+/// Queues requests for a background task, so that a slow or unreachable Home Assistant does not
+/// hold up receiving advertisements.
+pub struct HomeAssistant {
+    tx: Sender<Request>,
+}
+
+impl HomeAssistant {
+    /// Starts the task sending the requests, it ends when the client is dropped.
+    pub fn new(config: &HomeAssistantConfig) -> Result<(HomeAssistant, JoinHandle<()>), reqwest::Error> {
+        let client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?;
+        let (tx, mut rx) = channel::<Request>(100);
+        let url = config.url.clone();
+        let token = config.token.clone();
+        let task = tokio::spawn(async move {
+            let mut failing = false;
+            while let Some(request) = rx.recv().await {
+                let result = client
+                    .post(format!("{}{}", url, request.path))
+                    .bearer_auth(&token)
+                    .json(&request.body)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                match result {
+                    Ok(_) if failing => {
+                        info!(url, "Home Assistant is reachable again");
+                        failing = false;
+                    }
+                    Ok(_) => {}
+                    Err(err) if !failing => {
+                        warn!(url, error = %err, "Error pushing to Home Assistant");
+                        failing = true;
+                    }
+                    Err(_) => {}
+                }
+            }
+        });
+        Ok((HomeAssistant { tx }, task))
+    }
+
+    /// Creates or updates an entity, returns false if the queue is full.
+    pub fn set_state(&self, entity_id: &str, body: Value) -> bool {
+        self.send(format!("/api/states/{}", entity_id), body)
+    }
+
+    pub fn fire_event(&self, data: Value) -> bool {
+        self.send(format!("/api/events/{}", EVENT_TYPE), data)
+    }
+
+    fn send(&self, path: String, body: Value) -> bool {
+        self.tx.try_send(Request { path, body }).is_ok()
+    }
+}
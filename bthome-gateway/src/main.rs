@@ -0,0 +1,615 @@
+//! Bridges the BTHome devices of a registry to MQTT and Home Assistant, meant to run unattended
+//! as a service. For looking at the advertisements around, use bthome-sniffer.
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use bthome::{parse_encrypted_service_data, parse_service_data};
+use clap::Parser;
+use rumqttc::{AsyncClient, QoS};
+use serde_json::{json, Value};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+#[cfg(target_os = "linux")]
+mod ble;
+mod command;
+mod config;
+mod discovery;
+mod gatt;
+mod grafana;
+mod homeassistant;
+mod metrics;
+mod mqtt;
+mod registry;
+
+use command::Command;
+use config::{address_bytes, Config};
+use discovery::Reading;
+use gatt::GattReading;
+use grafana::Grafana;
+use homeassistant::HomeAssistant;
+use metrics::Metrics;
+use mqtt::{node_id, Topics};
+use registry::{Device, Registry};
+
+/// Publish BTHome devices to MQTT, including Home Assistant discovery and availability, or push
+/// them to Home Assistant directly.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Path to the TOML configuration file
+    #[arg(short, long)]
+    config: PathBuf,
+}
+
+pub struct Advertisement {
+    /// Upper case and colon separated, like in the configuration
+    pub address: String,
+    pub service_data: Vec<u8>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with_writer(std::io::stderr)
+        .init();
+    let args = Args::parse();
+
+    let content = std::fs::read_to_string(&args.config)
+        .map_err(|err| format!("Error reading {}: {}", args.config.display(), err))?;
+    let config = Config::parse(&content)?;
+    let mut registry = Registry::new(&config);
+    if let Some(path) = &config.state_file {
+        registry
+            .load(path)
+            .map_err(|err| format!("Error loading state from {}: {}", path.display(), err))?;
+    }
+    let (mqtt, mut mqtt_events, mqtt_task) = match &config.mqtt {
+        Some(mqtt_config) => {
+            let topics = Topics::new(&mqtt_config.base_topic, &mqtt_config.discovery_prefix);
+            let (client, events, task) = mqtt::connect(mqtt_config, &topics);
+            (Some(Mqtt { topics, client }), Some(events), Some(task))
+        }
+        None => (None, None, None),
+    };
+    let (homeassistant, homeassistant_task) = match &config.homeassistant {
+        Some(homeassistant_config) => {
+            let (homeassistant, task) = HomeAssistant::new(homeassistant_config)?;
+            info!(url = homeassistant_config.url, "Pushing states to Home Assistant");
+            (Some(homeassistant), Some(task))
+        }
+        None => (None, None),
+    };
+    let (grafana, grafana_task) = match &config.grafana {
+        Some(grafana_config) => {
+            let (grafana, task) = Grafana::new(grafana_config)?;
+            info!(url = grafana_config.url, stream = grafana_config.stream, "Pushing measurements to Grafana Live");
+            (Some(grafana), Some(task))
+        }
+        None => (None, None),
+    };
+
+    let (tx, mut advertisements) = unbounded_channel();
+    let scanner = scan(config.adapter.clone(), tx);
+    tokio::pin!(scanner);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut availability = tokio::time::interval(Duration::from_secs(10));
+    let mut metrics = tokio::time::interval(Duration::from_secs(config.metrics_interval.max(1)));
+    let mut gatt_poll = tokio::time::interval(GATT_POLL_INTERVAL);
+    let (gatt_tx, mut gatt_readings) = unbounded_channel();
+
+    let mut gateway = Gateway {
+        config,
+        mqtt,
+        homeassistant,
+        grafana,
+        registry,
+        metrics: Metrics::default(),
+        warned: HashSet::new(),
+        reading_gatt: false,
+    };
+    let result = loop {
+        tokio::select! {
+            Some(advertisement) = advertisements.recv() => gateway.handle(advertisement, SystemTime::now()),
+            Some(event) = next_event(&mut mqtt_events) => match event {
+                mqtt::Event::Command(payload) => gateway.command(&payload),
+                event => gateway.announce(event),
+            },
+            _ = availability.tick() => gateway.expire(SystemTime::now()),
+            _ = gatt_poll.tick() => gateway.poll_gatt(SystemTime::now(), &gatt_tx),
+            Some((address, result)) = gatt_readings.recv() => gateway.handle_gatt(address, result),
+            _ = metrics.tick() => {
+                gateway.publish_metrics();
+                gateway.save();
+            }
+            result = &mut scanner => {
+                break match result {
+                    Ok(()) => Err("Scanning stopped unexpectedly".into()),
+                    Err(err) => Err(format!("Error scanning: {}", err).into()),
+                };
+            }
+            result = &mut shutdown => {
+                if let Err(err) = result {
+                    error!(error = %err, "Error waiting for shutdown signals");
+                }
+                info!("Stopping");
+                break Ok(());
+            }
+        }
+    };
+
+    gateway.save();
+    let Gateway {
+        mqtt,
+        homeassistant,
+        grafana,
+        mut metrics,
+        ..
+    } = gateway;
+    if let Some(Mqtt { topics, client }) = mqtt {
+        publish(&client, &mut metrics, topics.gateway_status(), "offline", true);
+        let _ = client.disconnect().await;
+    }
+    // Give the connections a moment to deliver the last messages
+    drop(homeassistant);
+    drop(grafana);
+    for task in mqtt_task.into_iter().chain(homeassistant_task).chain(grafana_task) {
+        let _ = tokio::time::timeout(Duration::from_secs(2), task).await;
+    }
+    result
+}
+
+/// The next event of the MQTT connection, never resolves without MQTT.
+async fn next_event(events: &mut Option<UnboundedReceiver<mqtt::Event>>) -> Option<mqtt::Event> {
+    match events {
+        Some(events) => events.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// How often to check whether the GATT characteristics of a device are due.
+const GATT_POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Time a device has to connect and answer all reads.
+const GATT_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Mqtt {
+    topics: Topics,
+    client: AsyncClient,
+}
+
+struct Gateway {
+    config: Config,
+    mqtt: Option<Mqtt>,
+    homeassistant: Option<HomeAssistant>,
+    grafana: Option<Grafana>,
+    registry: Registry,
+    metrics: Metrics,
+    /// Devices that were already warned about, to not flood the log
+    warned: HashSet<String>,
+    /// Whether a device is being connected to, only one connection is made at a time
+    reading_gatt: bool,
+}
+
+impl Gateway {
+    fn handle(&mut self, advertisement: Advertisement, now: SystemTime) {
+        let Gateway {
+            mqtt,
+            homeassistant,
+            grafana,
+            registry,
+            metrics,
+            warned,
+            ..
+        } = self;
+        metrics.advertisements += 1;
+        let address = advertisement.address;
+        let Some(device) = registry.device(&address) else {
+            metrics.unknown_devices += 1;
+            return;
+        };
+        let node = node_id(&address);
+        if device.seen(now) {
+            info!(device = device.name, address, "Device is online");
+            if let Some(Mqtt { topics, client }) = mqtt {
+                publish(client, metrics, topics.availability(&node), "online", true);
+            }
+        }
+
+        let data = &advertisement.service_data;
+        let result = match (&device.key, address_bytes(&address)) {
+            (Some(key), Some(mac)) if data.first().is_some_and(|device_info| device_info & 1 == 1) => {
+                parse_encrypted_service_data(data, key, &mac).map(|(service_data, _)| service_data)
+            }
+            _ => parse_service_data(data),
+        };
+        let service_data = match result {
+            Ok(service_data) => service_data,
+            Err(err @ (bthome::Error::Encrypted | bthome::Error::DecryptionFailed)) => {
+                metrics.decryption_failures += 1;
+                if warned.insert(address.clone()) {
+                    if err == bthome::Error::Encrypted {
+                        warn!(device = device.name, address, "Received encrypted data, but no key is configured");
+                    } else {
+                        warn!(device = device.name, address, "Error decrypting BTHome data, is the key right?");
+                    }
+                }
+                return;
+            }
+            Err(err) => {
+                metrics.parse_errors += 1;
+                debug!(device = device.name, address, error = ?err, "Error parsing service data");
+                return;
+            }
+        };
+        let reading = Reading::new(service_data.objects);
+        if device.is_repeated(&advertisement.service_data, reading.packet_id.is_some(), now) {
+            metrics.repeated += 1;
+            return;
+        }
+        device.published(&advertisement.service_data, now);
+        metrics.published += 1;
+        publish_reading(mqtt, homeassistant, grafana, metrics, &address, device, reading);
+    }
+
+    /// Starts reading the GATT characteristics of the next device that is due, if any.
+    fn poll_gatt(&mut self, now: SystemTime, tx: &UnboundedSender<(String, Result<GattReading, String>)>) {
+        if self.reading_gatt {
+            return;
+        }
+        let interval = Duration::from_secs(self.config.gatt_interval);
+        let Some(address) = self.registry.gatt_due(now, interval) else {
+            return;
+        };
+        debug!(address, "Reading GATT characteristics");
+        self.reading_gatt = true;
+        let adapter = self.config.adapter.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let result = match tokio::time::timeout(GATT_TIMEOUT, read_gatt(adapter, &address)).await {
+                Ok(result) => result,
+                Err(_) => Err("timed out".to_string()),
+            };
+            let _ = tx.send((address, result));
+        });
+    }
+
+    fn handle_gatt(&mut self, address: String, result: Result<GattReading, String>) {
+        self.reading_gatt = false;
+        let Gateway {
+            mqtt,
+            homeassistant,
+            grafana,
+            registry,
+            metrics,
+            ..
+        } = self;
+        let Some(device) = registry.device(&address) else {
+            return;
+        };
+        match result {
+            Ok(reading) => {
+                debug!(device = device.name, address, ?reading, "Read GATT characteristics");
+                publish_reading(mqtt, homeassistant, grafana, metrics, &address, device, reading.into_reading());
+            }
+            Err(err) => warn!(device = device.name, address, error = err, "Error reading GATT characteristics"),
+        }
+    }
+
+    /// Publishes everything retained again, e.g. after reconnecting to the broker.
+    fn announce(&mut self, event: mqtt::Event) {
+        let Some(Mqtt { topics, client }) = &self.mqtt else {
+            return;
+        };
+        if event == mqtt::Event::Connected {
+            publish(client, &mut self.metrics, topics.gateway_status(), "online", true);
+            let mut subscriptions = Vec::new();
+            if !topics.discovery_prefix.is_empty() {
+                subscriptions.push(topics.homeassistant_status());
+            }
+            if self.config.mqtt.as_ref().is_some_and(|mqtt| mqtt.commands) {
+                subscriptions.push(topics.command());
+            }
+            for topic in subscriptions {
+                if let Err(err) = client.try_subscribe(&topic, QoS::AtLeastOnce) {
+                    warn!(topic, error = %err, "Error subscribing");
+                }
+            }
+        } else {
+            info!("Home Assistant restarted, announcing devices again");
+        }
+        self.republish();
+    }
+
+    fn republish(&mut self) {
+        let Gateway {
+            mqtt,
+            registry,
+            metrics,
+            ..
+        } = self;
+        let Some(Mqtt { topics, client }) = mqtt else {
+            return;
+        };
+        for (address, device) in registry.devices() {
+            announce_device(topics, client, metrics, address, device);
+        }
+    }
+
+    /// Runs a command received over MQTT and publishes the answer.
+    fn command(&mut self, payload: &[u8]) {
+        let (id, command) = Command::parse(payload);
+        let result = command.and_then(|command| {
+            info!(?command, "Running command");
+            self.run(command)
+        });
+        if let Err(err) = &result {
+            warn!(error = err, "Error running command");
+        }
+        let Some(Mqtt { topics, client }) = &self.mqtt else {
+            return;
+        };
+        let response = command::response(&id, result).to_string();
+        publish(client, &mut self.metrics, topics.response(), response, false);
+    }
+
+    fn run(&mut self, command: Command) -> Result<Value, String> {
+        match command {
+            Command::SetKey { address, key } => {
+                self.registry.set_key(&address, Some(key));
+                self.warned.remove(&address);
+            }
+            Command::RemoveKey { address } => {
+                if !self.registry.contains(&address) {
+                    return Err(format!("unknown device {}", address));
+                }
+                self.registry.set_key(&address, None);
+            }
+            Command::Rename { address, name } => {
+                let device = self.registry.rename(&address, &name);
+                // Home Assistant picks the new name up from the discovery configurations
+                if let Some(Mqtt { topics, client }) = &self.mqtt {
+                    announce_device(topics, client, &mut self.metrics, &address, device);
+                }
+            }
+            Command::Announce => self.republish(),
+            Command::Status => {
+                self.count_online();
+                let devices: serde_json::Map<String, Value> = self
+                    .registry
+                    .devices()
+                    .map(|(address, device)| {
+                        let status = json!({
+                            "name": device.name,
+                            "online": device.online,
+                            "last_seen": device.state.last_seen,
+                            "key": device.key.is_some(),
+                        });
+                        (address.clone(), status)
+                    })
+                    .collect();
+                return Ok(json!({
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "metrics": self.metrics,
+                    "devices": devices,
+                }));
+            }
+        }
+        self.save();
+        Ok(json!({}))
+    }
+
+    fn expire(&mut self, now: SystemTime) {
+        let timeout = Duration::from_secs(self.config.availability_timeout);
+        for address in self.registry.expire(now, timeout) {
+            info!(address, "Device is offline");
+            let node = node_id(&address);
+            if let Some(Mqtt { topics, client }) = &self.mqtt {
+                publish(client, &mut self.metrics, topics.availability(&node), "offline", true);
+            }
+            let (Some(homeassistant), Some(device)) = (&self.homeassistant, self.registry.device(&address)) else {
+                continue;
+            };
+            let discovery_device = discovery::Device {
+                node: &node,
+                address: &address,
+                name: &device.name,
+            };
+            for (key, id) in &device.state.entities {
+                if let Some((entity_id, body)) = discovery::entity_state(&discovery_device, key, *id, None) {
+                    count_failure(&mut self.metrics, homeassistant.set_state(&entity_id, body));
+                }
+            }
+        }
+    }
+
+    fn count_online(&mut self) {
+        self.metrics.devices_online = self.registry.devices().filter(|(_, device)| device.online).count() as u64;
+    }
+
+    fn publish_metrics(&mut self) {
+        self.count_online();
+        let Some(Mqtt { topics, client }) = &self.mqtt else {
+            return;
+        };
+        let Ok(payload) = serde_json::to_string(&self.metrics) else {
+            return;
+        };
+        publish(client, &mut self.metrics, topics.metrics(), payload, false);
+    }
+
+    fn save(&self) {
+        if let Some(path) = &self.config.state_file {
+            if let Err(err) = self.registry.save(path) {
+                error!(path = %path.display(), error = %err, "Error saving state");
+            }
+        }
+    }
+}
+
+/// Merges a reading into the state of a device and publishes it, announcing new entities.
+fn publish_reading(
+    mqtt: &Option<Mqtt>,
+    homeassistant: &Option<HomeAssistant>,
+    grafana: &Option<Grafana>,
+    metrics: &mut Metrics,
+    address: &str,
+    device: &mut Device,
+    reading: Reading,
+) {
+    let node = node_id(address);
+    let new_entities = device.add_entities(&reading.entities);
+    let discovery_device = discovery::Device {
+        node: &node,
+        address,
+        name: &device.name,
+    };
+    if let Some(homeassistant) = homeassistant {
+        for (key, value) in &reading.state {
+            let Some(id) = reading.entities.get(key) else {
+                continue;
+            };
+            if let Some((entity_id, body)) = discovery::entity_state(&discovery_device, key, *id, Some(value)) {
+                count_failure(metrics, homeassistant.set_state(&entity_id, body));
+            }
+        }
+        for (key, event) in &reading.events {
+            count_failure(metrics, homeassistant.fire_event(discovery::event_data(&discovery_device, key, event)));
+        }
+    }
+    if let Some(grafana) = grafana {
+        count_failure(metrics, grafana.push(address, &device.name, &reading.state));
+    }
+    let Some(Mqtt { topics, client }) = mqtt else {
+        device.state.values.extend(reading.state);
+        return;
+    };
+    for (key, id) in new_entities {
+        if let Some((topic, config)) = entity_config(topics, &discovery_device, &key, id) {
+            publish(client, metrics, topic, config.to_string(), true);
+        }
+    }
+    if !reading.state.is_empty() {
+        device.state.values.extend(reading.state);
+        let state = Value::Object(device.state.values.clone()).to_string();
+        publish(client, metrics, topics.state(&node), state, true);
+    }
+    for (key, event) in reading.events {
+        publish(client, metrics, topics.event(&node, &key), event.to_string(), false);
+    }
+}
+
+/// Publishes the discovery configurations, availability and state of a device.
+fn announce_device(topics: &Topics, client: &AsyncClient, metrics: &mut Metrics, address: &str, device: &Device) {
+    let node = node_id(address);
+    let discovery_device = discovery::Device {
+        node: &node,
+        address,
+        name: &device.name,
+    };
+    for (key, id) in &device.state.entities {
+        if let Some((topic, config)) = entity_config(topics, &discovery_device, key, *id) {
+            publish(client, metrics, topic, config.to_string(), true);
+        }
+    }
+    let availability = if device.online { "online" } else { "offline" };
+    publish(client, metrics, topics.availability(&node), availability, true);
+    if !device.state.values.is_empty() {
+        let state = Value::Object(device.state.values.clone()).to_string();
+        publish(client, metrics, topics.state(&node), state, true);
+    }
+}
+
+fn entity_config(
+    topics: &Topics,
+    device: &discovery::Device,
+    key: &str,
+    id: u8,
+) -> Option<(String, serde_json::Value)> {
+    if topics.discovery_prefix.is_empty() {
+        return None;
+    }
+    discovery::entity_config(topics, device, key, id)
+}
+
+/// Hands a message to the MQTT client without waiting, messages are dropped while the queue of
+/// the client is full, e.g. because the broker is unreachable.
+fn publish(client: &AsyncClient, metrics: &mut Metrics, topic: String, payload: impl Into<Vec<u8>>, retain: bool) {
+    if let Err(err) = client.try_publish(&topic, QoS::AtLeastOnce, retain, payload) {
+        metrics.publish_failures += 1;
+        debug!(topic, error = %err, "Error publishing");
+    }
+}
+
+/// Counts requests for Home Assistant that could not be queued.
+fn count_failure(metrics: &mut Metrics, queued: bool) {
+    if !queued {
+        metrics.publish_failures += 1;
+    }
+}
+
+/// Receives BTHome advertisements until the receiver is gone. Scanning is restarted when it stops,
+/// e.g. because bluetoothd restarted, so only failing to find the adapter is an error.
+#[cfg(target_os = "linux")]
+async fn scan(
+    adapter: Option<String>,
+    tx: tokio::sync::mpsc::UnboundedSender<Advertisement>,
+) -> Result<(), bluer::Error> {
+    use bthome_listener::Listener;
+    use futures::StreamExt;
+
+    let listener = match adapter {
+        Some(name) => Listener::named_adapter(&name).await?,
+        None => Listener::default_adapter().await?,
+    };
+    let mut advertisements = listener.advertisements();
+    while let Some(advertisement) = advertisements.next().await {
+        let advertisement = Advertisement {
+            address: advertisement.mac.to_string(),
+            service_data: advertisement.service_data,
+        };
+        if tx.send(advertisement).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+async fn read_gatt(adapter: Option<String>, address: &str) -> Result<GattReading, String> {
+    ble::read_gatt(adapter.as_deref(), address).await.map_err(|err| err.to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn read_gatt(_adapter: Option<String>, _address: &str) -> Result<GattReading, String> {
+    Err("reading GATT characteristics is only supported on Linux with bluez".to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn scan(
+    _adapter: Option<String>,
+    _tx: tokio::sync::mpsc::UnboundedSender<Advertisement>,
+) -> Result<(), &'static str> {
+    Err("scanning is only supported on Linux with bluez")
+}
+
+/// Resolves on SIGINT or SIGTERM.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
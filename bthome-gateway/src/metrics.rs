@@ -0,0 +1,21 @@
+//! Counters published by the gateway, to tell whether it is working as expected.
+
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Default)]
+pub struct Metrics {
+    /// BTHome advertisements received
+    pub advertisements: u64,
+    /// Advertisements published, i.e. not repeated ones
+    pub published: u64,
+    /// Advertisements dropped because they were already published
+    pub repeated: u64,
+    /// Advertisements of devices not in the registry
+    pub unknown_devices: u64,
+    pub parse_errors: u64,
+    pub decryption_failures: u64,
+    /// Messages that could not be handed to the MQTT client or queued for Home Assistant, e.g.
+    /// while the broker is unreachable
+    pub publish_failures: u64,
+    pub devices_online: u64,
+}
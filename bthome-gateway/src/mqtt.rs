@@ -0,0 +1,147 @@
+//! Connection to the MQTT broker and the topics the gateway publishes on.
+
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, ConnectionError, Event as MqttEvent, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+    task::JoinHandle,
+};
+use tracing::{info, warn};
+
+use crate::config::MqttConfig;
+
+pub struct Topics {
+    base: String,
+    pub discovery_prefix: String,
+}
+
+impl Topics {
+    pub fn new(base: &str, discovery_prefix: &str) -> Topics {
+        Topics {
+            base: base.trim_end_matches('/').to_string(),
+            discovery_prefix: discovery_prefix.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Retained JSON object with the latest values of a device
+    pub fn state(&self, node: &str) -> String {
+        format!("{}/{}/state", self.base, node)
+    }
+
+    /// Retained `online` or `offline` of a device
+    pub fn availability(&self, node: &str) -> String {
+        format!("{}/{}/availability", self.base, node)
+    }
+
+    /// Button and dimmer events of a device, not retained
+    pub fn event(&self, node: &str, key: &str) -> String {
+        format!("{}/{}/{}", self.base, node, key)
+    }
+
+    /// Retained `online` or `offline` of the gateway, `offline` is the last will
+    pub fn gateway_status(&self) -> String {
+        format!("{}/gateway/status", self.base)
+    }
+
+    pub fn metrics(&self) -> String {
+        format!("{}/gateway/metrics", self.base)
+    }
+
+    /// Commands to the gateway, see [`crate::command`]
+    pub fn command(&self) -> String {
+        format!("{}/gateway/command", self.base)
+    }
+
+    /// Answers to commands, not retained
+    pub fn response(&self) -> String {
+        format!("{}/gateway/response", self.base)
+    }
+
+    /// Home Assistant publishes `online` here when it starts and expects discovery again
+    pub fn homeassistant_status(&self) -> String {
+        format!("{}/status", self.discovery_prefix)
+    }
+}
+
+/// The id of a device in topics, its address without separators, e.g. `a4c138123456`.
+pub fn node_id(address: &str) -> String {
+    address.replace(':', "").to_ascii_lowercase()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    /// (Re)connected to the broker, subscriptions and retained messages have to be renewed
+    Connected,
+    HomeAssistantOnline,
+    /// The payload of a message on the command topic
+    Command(Vec<u8>),
+}
+
+/// Connects to the broker, the connection is kept and re-established in the background until
+/// the client disconnects.
+pub fn connect(config: &MqttConfig, topics: &Topics) -> (AsyncClient, UnboundedReceiver<Event>, JoinHandle<()>) {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(topics.gateway_status(), "offline", QoS::AtLeastOnce, true));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.as_deref().unwrap_or_default());
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, 100);
+    let (tx, rx) = unbounded_channel();
+    let homeassistant_status = topics.homeassistant_status();
+    let command = config.commands.then(|| topics.command());
+    let host = config.host.clone();
+    let task = tokio::spawn(async move {
+        let mut connected = false;
+        loop {
+            let event = match eventloop.poll().await {
+                Ok(MqttEvent::Incoming(Packet::ConnAck(_))) => {
+                    info!(broker = host, "Connected to MQTT broker");
+                    connected = true;
+                    Event::Connected
+                }
+                Ok(MqttEvent::Incoming(Packet::Publish(publish)))
+                    if publish.topic == homeassistant_status && publish.payload.as_ref() == b"online" =>
+                {
+                    Event::HomeAssistantOnline
+                }
+                Ok(MqttEvent::Incoming(Packet::Publish(publish))) if command.as_ref() == Some(&publish.topic) => {
+                    Event::Command(publish.payload.to_vec())
+                }
+                Ok(MqttEvent::Outgoing(Outgoing::Disconnect)) | Err(ConnectionError::RequestsDone) => return,
+                Ok(_) => continue,
+                Err(err) => {
+                    if connected {
+                        warn!(broker = host, error = %err, "Lost connection to MQTT broker, reconnecting");
+                    } else {
+                        warn!(broker = host, error = %err, "Error connecting to MQTT broker, retrying");
+                    }
+                    connected = false;
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+            if tx.send(event).is_err() {
+                return;
+            }
+        }
+    });
+    (client, rx, task)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn topics() {
+        let topics = Topics::new("home/bthome/", "homeassistant");
+        assert_eq!(node_id("A4:C1:38:12:34:56"), "a4c138123456");
+        assert_eq!(topics.state("a4c138123456"), "home/bthome/a4c138123456/state");
+        assert_eq!(topics.event("a4c138123456", "button"), "home/bthome/a4c138123456/button");
+        assert_eq!(topics.gateway_status(), "home/bthome/gateway/status");
+        assert_eq!(topics.command(), "home/bthome/gateway/command");
+        assert_eq!(topics.homeassistant_status(), "homeassistant/status");
+    }
+}
@@ -0,0 +1,336 @@
+//! The devices known to the gateway and their state, which is kept across restarts.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::config::{parse_key, Config};
+
+/// Repeated advertisements without a packet id are only published again after this time.
+const REPEAT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What is persisted of a device.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct DeviceState {
+    /// Seconds since the epoch of the last advertisement
+    pub last_seen: u64,
+    /// Seconds since the epoch of the last published advertisement
+    pub updated: u64,
+    /// Hex encoded service data of the last published advertisement
+    pub data: String,
+    /// The entities announced to Home Assistant, key to object id
+    pub entities: BTreeMap<String, u8>,
+    /// The latest value of every key
+    pub values: Map<String, Value>,
+    /// Seconds since the epoch of the last attempt to read the GATT characteristics
+    pub gatt_read: u64,
+    /// Name set by a command, replaces the configured one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Bind key set by a command, replaces the configured one, empty if it was removed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Device {
+    pub name: String,
+    pub key: Option<[u8; 16]>,
+    /// Whether the GATT characteristics are read
+    pub gatt: bool,
+    pub state: DeviceState,
+    pub online: bool,
+}
+
+impl Device {
+    fn new(name: String, key: Option<[u8; 16]>, gatt: bool) -> Device {
+        Device {
+            name,
+            key,
+            gatt,
+            state: DeviceState::default(),
+            online: false,
+        }
+    }
+
+    /// Applies the name and key set by commands.
+    fn apply_overrides(&mut self) {
+        if let Some(name) = &self.state.name {
+            self.name = name.clone();
+        }
+        if let Some(key) = &self.state.key {
+            self.key = parse_key(key);
+        }
+    }
+
+    /// Records an advertisement, returns true if the device was offline.
+    pub fn seen(&mut self, now: SystemTime) -> bool {
+        self.state.last_seen = seconds(now);
+        !std::mem::replace(&mut self.online, true)
+    }
+
+    /// Whether `data` was already published. Payloads with a packet id are the same packet if
+    /// they are equal, others are published again after a while.
+    pub fn is_repeated(&self, data: &[u8], has_packet_id: bool, now: SystemTime) -> bool {
+        self.state.data == hex(data)
+            && (has_packet_id || seconds(now) < self.state.updated + REPEAT_INTERVAL.as_secs())
+    }
+
+    pub fn published(&mut self, data: &[u8], now: SystemTime) {
+        self.state.data = hex(data);
+        self.state.updated = seconds(now);
+    }
+
+    /// Adds the entities of an advertisement, returns those that are new.
+    pub fn add_entities(&mut self, entities: &BTreeMap<String, u8>) -> Vec<(String, u8)> {
+        let mut added = Vec::new();
+        for (key, id) in entities {
+            if self.state.entities.get(key) != Some(id) {
+                self.state.entities.insert(key.clone(), *id);
+                added.push((key.clone(), *id));
+            }
+        }
+        added
+    }
+}
+
+pub struct Registry {
+    devices: BTreeMap<String, Device>,
+    allow_unknown: bool,
+}
+
+impl Registry {
+    pub fn new(config: &Config) -> Registry {
+        let devices = config
+            .devices
+            .iter()
+            .map(|(address, device)| {
+                let key = device.key.as_deref().and_then(parse_key);
+                (address.clone(), Device::new(device.name.clone(), key, device.gatt))
+            })
+            .collect();
+        Registry {
+            devices,
+            allow_unknown: config.allow_unknown,
+        }
+    }
+
+    /// The device with `address`, unknown devices are only added if allowed.
+    pub fn device(&mut self, address: &str) -> Option<&mut Device> {
+        if self.allow_unknown && !self.devices.contains_key(address) {
+            self.devices
+                .insert(address.to_string(), Device::new(address.to_string(), None, false));
+        }
+        self.devices.get_mut(address)
+    }
+
+    /// Sets or removes the bind key of a device, adding the device if necessary. The key is kept
+    /// in the state, so that it survives restarts.
+    pub fn set_key(&mut self, address: &str, key: Option<[u8; 16]>) -> &mut Device {
+        let device = self.add(address);
+        device.key = key;
+        device.state.key = Some(key.map(|key| hex(&key)).unwrap_or_default());
+        device
+    }
+
+    /// Renames a device, adding it if necessary. The name is kept in the state, like keys.
+    pub fn rename(&mut self, address: &str, name: &str) -> &mut Device {
+        let device = self.add(address);
+        device.name = name.to_string();
+        device.state.name = Some(name.to_string());
+        device
+    }
+
+    pub fn contains(&self, address: &str) -> bool {
+        self.devices.contains_key(address)
+    }
+
+    fn add(&mut self, address: &str) -> &mut Device {
+        self.devices
+            .entry(address.to_string())
+            .or_insert_with(|| Device::new(address.to_string(), None, false))
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = (&String, &Device)> {
+        self.devices.iter()
+    }
+
+    /// Marks devices as offline that have not been seen for `timeout`, returns their addresses.
+    pub fn expire(&mut self, now: SystemTime, timeout: Duration) -> Vec<String> {
+        let cutoff = seconds(now).saturating_sub(timeout.as_secs());
+        self.devices
+            .iter_mut()
+            .filter(|(_, device)| device.online && device.state.last_seen < cutoff)
+            .map(|(address, device)| {
+                device.online = false;
+                address.clone()
+            })
+            .collect()
+    }
+
+    /// An online device whose GATT characteristics were not read for `interval`, the attempt is
+    /// recorded so that the device is not returned again until the next interval.
+    pub fn gatt_due(&mut self, now: SystemTime, interval: Duration) -> Option<String> {
+        let now = seconds(now);
+        let (address, device) = self.devices.iter_mut().find(|(_, device)| {
+            device.gatt && device.online && now >= device.state.gatt_read + interval.as_secs()
+        })?;
+        device.state.gatt_read = now;
+        Some(address.clone())
+    }
+
+    /// Restores the state saved by [`Registry::save`], devices that are no longer in the
+    /// registry are dropped unless they were added by a command.
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let states: HashMap<String, DeviceState> = serde_json::from_str(&content)?;
+        for (address, state) in states {
+            let device = if state.name.is_some() || state.key.is_some() {
+                Some(self.add(&address))
+            } else {
+                self.device(&address)
+            };
+            if let Some(device) = device {
+                device.state = state;
+                device.apply_overrides();
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the state of all devices, the file is replaced atomically.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let states: BTreeMap<&String, &DeviceState> =
+            self.devices.iter().map(|(address, device)| (address, &device.state)).collect();
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&states)?)?;
+        std::fs::rename(tmp, path)
+    }
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn registry(allow_unknown: bool) -> Registry {
+        let mut config = Config::parse("[mqtt]\nhost = \"broker\"\n[devices.\"A4:C1:38:12:34:56\"]\nname = \"Kitchen\"\n")
+            .unwrap();
+        config.allow_unknown = allow_unknown;
+        Registry::new(&config)
+    }
+
+    #[test]
+    fn known_and_unknown_devices() {
+        assert_eq!(registry(false).device("A4:C1:38:12:34:56").unwrap().name, "Kitchen");
+        assert!(registry(false).device("11:22:33:44:55:66").is_none());
+        assert_eq!(registry(true).device("11:22:33:44:55:66").unwrap().name, "11:22:33:44:55:66");
+    }
+
+    #[test]
+    fn repeated_advertisements() {
+        let mut registry = registry(false);
+        let device = registry.device("A4:C1:38:12:34:56").unwrap();
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        assert!(!device.is_repeated(&[0x40, 0x01, 0x64], false, now));
+        device.published(&[0x40, 0x01, 0x64], now);
+        assert!(device.is_repeated(&[0x40, 0x01, 0x64], false, now + Duration::from_secs(10)));
+        assert!(!device.is_repeated(&[0x40, 0x01, 0x63], false, now + Duration::from_secs(10)));
+        assert!(!device.is_repeated(&[0x40, 0x01, 0x64], false, now + Duration::from_secs(60)));
+        assert!(device.is_repeated(&[0x40, 0x01, 0x64], true, now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn availability() {
+        let mut registry = registry(false);
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let device = registry.device("A4:C1:38:12:34:56").unwrap();
+        assert!(device.seen(now));
+        assert!(!device.seen(now));
+        assert!(registry.expire(now + Duration::from_secs(60), Duration::from_secs(300)).is_empty());
+        assert_eq!(
+            registry.expire(now + Duration::from_secs(301), Duration::from_secs(300)),
+            vec!["A4:C1:38:12:34:56".to_string()]
+        );
+        assert!(registry.device("A4:C1:38:12:34:56").unwrap().seen(now));
+    }
+
+    #[test]
+    fn gatt_polling() {
+        let mut config = Config::parse(
+            "[mqtt]\nhost = \"broker\"\n[devices.\"A4:C1:38:12:34:56\"]\nname = \"Kitchen\"\ngatt = true\n",
+        )
+        .unwrap();
+        config.allow_unknown = true;
+        let mut registry = Registry::new(&config);
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let interval = Duration::from_secs(3600);
+        assert_eq!(registry.gatt_due(now, interval), None);
+        registry.device("11:22:33:44:55:66").unwrap().seen(now);
+        registry.device("A4:C1:38:12:34:56").unwrap().seen(now);
+        assert_eq!(registry.gatt_due(now, interval), Some("A4:C1:38:12:34:56".to_string()));
+        assert_eq!(registry.gatt_due(now + Duration::from_secs(60), interval), None);
+        assert!(registry.gatt_due(now + interval, interval).is_some());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("bthome-gateway-test-{}.json", std::process::id()));
+        let mut registry = registry(false);
+        let device = registry.device("A4:C1:38:12:34:56").unwrap();
+        device.seen(UNIX_EPOCH + Duration::from_secs(5));
+        assert_eq!(device.add_entities(&BTreeMap::from([("battery".to_string(), 0x01)])).len(), 1);
+        assert!(device.add_entities(&BTreeMap::from([("battery".to_string(), 0x01)])).is_empty());
+        registry.save(&path).unwrap();
+
+        let mut restored = self::registry(false);
+        restored.load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let device = restored.device("A4:C1:38:12:34:56").unwrap();
+        assert_eq!(device.state.last_seen, 5);
+        assert_eq!(device.state.entities["battery"], 0x01);
+        assert!(!device.online);
+    }
+
+    #[test]
+    fn keep_commanded_changes() {
+        let path = std::env::temp_dir().join(format!("bthome-gateway-test-commands-{}.json", std::process::id()));
+        let key = parse_key("231d39c1d7cc1ab1aee224cd096db932");
+        let mut registry = registry(false);
+        assert_eq!(registry.rename("A4:C1:38:12:34:56", "Hall").name, "Hall");
+        assert_eq!(registry.set_key("11:22:33:44:55:66", key).key, key);
+        assert!(registry.contains("11:22:33:44:55:66"));
+        registry.save(&path).unwrap();
+
+        let mut restored = self::registry(false);
+        restored.load(&path).unwrap();
+        assert_eq!(restored.device("A4:C1:38:12:34:56").unwrap().name, "Hall");
+        let device = restored.device("11:22:33:44:55:66").unwrap();
+        assert_eq!((device.name.as_str(), device.key), ("11:22:33:44:55:66", key));
+
+        restored.set_key("11:22:33:44:55:66", None);
+        restored.save(&path).unwrap();
+        let mut restored = self::registry(false);
+        restored.load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored.device("11:22:33:44:55:66").unwrap().key, None);
+    }
+}
@@ -0,0 +1,505 @@
+//! The accessory database: the bridge and one bridged accessory per BTHome device, with HomeKit
+//! services for the measurements the device reported.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    time::{Duration, Instant},
+};
+
+use bthome::{ButtonEvent, Object, ObjectValue};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// The HAP status of a characteristic of an unreachable accessory.
+const SERVICE_COMMUNICATION_FAILURE: i64 = -70402;
+const READ_ONLY: i64 = -70404;
+const WRITE_ONLY: i64 = -70405;
+const NOTIFICATION_NOT_SUPPORTED: i64 = -70406;
+const RESOURCE_DOES_NOT_EXIST: i64 = -70409;
+
+const BRIDGE_AID: u64 = 1;
+const IDENTIFY_IID: u64 = 2;
+
+/// The services a BTHome device can be exposed with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Service {
+    Temperature,
+    Humidity,
+    Contact,
+    Motion,
+    Occupancy,
+    Light,
+    Leak,
+    Smoke,
+    Battery,
+    Button,
+}
+
+struct Characteristic {
+    kind: &'static str,
+    format: &'static str,
+    perms: &'static [&'static str],
+    /// Additional metadata like unit and range
+    metadata: fn() -> Value,
+    /// Value until the device reports one
+    initial: Option<fn() -> Value>,
+}
+
+const fn sensor(kind: &'static str, format: &'static str, metadata: fn() -> Value) -> Characteristic {
+    Characteristic {
+        kind,
+        format,
+        perms: &["pr", "ev"],
+        metadata,
+        initial: None,
+    }
+}
+
+fn none() -> Value {
+    json!({})
+}
+
+impl Service {
+    /// The short type of the service and its characteristics.
+    fn definition(self) -> (&'static str, &'static [Characteristic]) {
+        const TEMPERATURE: &[Characteristic] = &[sensor(
+            "11",
+            "float",
+            || json!({"unit": "celsius", "minValue": -100, "maxValue": 100, "minStep": 0.1}),
+        )];
+        const HUMIDITY: &[Characteristic] = &[sensor(
+            "10",
+            "float",
+            || json!({"unit": "percentage", "minValue": 0, "maxValue": 100, "minStep": 1}),
+        )];
+        const CONTACT: &[Characteristic] = &[sensor("6A", "uint8", || json!({"minValue": 0, "maxValue": 1}))];
+        const MOTION: &[Characteristic] = &[sensor("22", "bool", none)];
+        const OCCUPANCY: &[Characteristic] = &[sensor("71", "uint8", || json!({"minValue": 0, "maxValue": 1}))];
+        const LIGHT: &[Characteristic] = &[sensor(
+            "6B",
+            "float",
+            || json!({"unit": "lux", "minValue": 0.0001, "maxValue": 100000}),
+        )];
+        const LEAK: &[Characteristic] = &[sensor("70", "uint8", || json!({"minValue": 0, "maxValue": 1}))];
+        const SMOKE: &[Characteristic] = &[sensor("76", "uint8", || json!({"minValue": 0, "maxValue": 1}))];
+        const BATTERY: &[Characteristic] = &[
+            sensor(
+                "68",
+                "uint8",
+                || json!({"unit": "percentage", "minValue": 0, "maxValue": 100, "minStep": 1}),
+            ),
+            // Not chargeable, unless the device reports charging
+            Characteristic {
+                initial: Some(|| json!(2)),
+                ..sensor("8F", "uint8", || json!({"minValue": 0, "maxValue": 2}))
+            },
+            Characteristic {
+                initial: Some(|| json!(0)),
+                ..sensor("79", "uint8", || json!({"minValue": 0, "maxValue": 1}))
+            },
+        ];
+        // Single press, double press and long press
+        const BUTTON: &[Characteristic] = &[sensor("73", "uint8", || json!({"minValue": 0, "maxValue": 2}))];
+        match self {
+            Service::Temperature => ("8A", TEMPERATURE),
+            Service::Humidity => ("82", HUMIDITY),
+            Service::Contact => ("80", CONTACT),
+            Service::Motion => ("85", MOTION),
+            Service::Occupancy => ("86", OCCUPANCY),
+            Service::Light => ("84", LIGHT),
+            Service::Leak => ("83", LEAK),
+            Service::Smoke => ("87", SMOKE),
+            Service::Battery => ("96", BATTERY),
+            Service::Button => ("89", BUTTON),
+        }
+    }
+
+    /// Instance ids are fixed per service, so they don't change when services are added.
+    fn iid(self) -> u64 {
+        10 * (self as u64 + 1)
+    }
+}
+
+/// The service, characteristic index and HomeKit value for a BTHome object.
+fn characteristic_values(object: Object) -> Vec<(Service, usize, Value)> {
+    let id = object.object_id as u8;
+    let value = &object.value;
+    let number = match value {
+        ObjectValue::Float(value) => Some(*value),
+        ObjectValue::Int(value) => Some(*value as f64),
+        _ => None,
+    };
+    let flag = match value {
+        ObjectValue::Bool(value) => Some(*value),
+        _ => None,
+    };
+    match (id, number, flag) {
+        (0x02 | 0x45 | 0x57 | 0x58, Some(number), _) => vec![(Service::Temperature, 0, json!(number))],
+        (0x03 | 0x2E, Some(number), _) => vec![(Service::Humidity, 0, json!(number))],
+        (0x05, Some(number), _) => vec![(Service::Light, 0, json!(number.max(0.0001)))],
+        (0x01, Some(number), _) => vec![
+            (Service::Battery, 0, json!(number.clamp(0.0, 100.0) as u8)),
+            (Service::Battery, 2, json!(u8::from(number < 20.0))),
+        ],
+        (0x15, _, Some(low)) => vec![(Service::Battery, 2, json!(u8::from(low)))],
+        (0x16, _, Some(charging)) => vec![(Service::Battery, 1, json!(u8::from(charging)))],
+        // Door, garage door, window and generic opening, open means no contact
+        (0x1A | 0x1B | 0x2D | 0x11, _, Some(open)) => vec![(Service::Contact, 0, json!(u8::from(open)))],
+        (0x21, _, Some(motion)) => vec![(Service::Motion, 0, json!(motion))],
+        (0x23, _, Some(occupied)) => vec![(Service::Occupancy, 0, json!(u8::from(occupied)))],
+        (0x20, _, Some(leak)) => vec![(Service::Leak, 0, json!(u8::from(leak)))],
+        (0x29, _, Some(smoke)) => vec![(Service::Smoke, 0, json!(u8::from(smoke)))],
+        (0x3A, ..) => match value {
+            ObjectValue::ButtonEvent(ButtonEvent::Press) => vec![(Service::Button, 0, json!(0))],
+            ObjectValue::ButtonEvent(ButtonEvent::DoublePress) => vec![(Service::Button, 0, json!(1))],
+            ObjectValue::ButtonEvent(ButtonEvent::LongPress) => vec![(Service::Button, 0, json!(2))],
+            _ => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+/// A characteristic that changed, `(aid, iid, value)`.
+pub type Change = (u64, u64, Value);
+
+pub struct Accessory {
+    pub address: String,
+    pub name: String,
+    pub services: BTreeSet<Service>,
+    values: BTreeMap<u64, Value>,
+    last_seen: Option<Instant>,
+}
+
+pub struct Database {
+    name: String,
+    accessories: BTreeMap<u64, Accessory>,
+    availability_timeout: Duration,
+}
+
+impl Database {
+    pub fn new(name: &str, availability_timeout: Duration) -> Database {
+        Database {
+            name: name.to_string(),
+            accessories: BTreeMap::new(),
+            availability_timeout,
+        }
+    }
+
+    pub fn add(&mut self, aid: u64, address: &str, name: &str, services: BTreeSet<Service>) {
+        let mut accessory = Accessory {
+            address: address.to_string(),
+            name: name.to_string(),
+            services: BTreeSet::new(),
+            values: BTreeMap::new(),
+            last_seen: None,
+        };
+        for service in services {
+            accessory.add_service(service);
+        }
+        self.accessories.insert(aid, accessory);
+    }
+
+    pub fn aid(&self, address: &str) -> Option<u64> {
+        self.accessories
+            .iter()
+            .find(|(_, accessory)| accessory.address == address)
+            .map(|(aid, _)| *aid)
+    }
+
+    pub fn accessory(&self, aid: u64) -> Option<&Accessory> {
+        self.accessories.get(&aid)
+    }
+
+    /// Applies the objects of an advertisement, returns the changed characteristics and
+    /// whether services were added.
+    pub fn update(&mut self, aid: u64, objects: Vec<Object>, now: Instant) -> (Vec<Change>, bool) {
+        let Some(accessory) = self.accessories.get_mut(&aid) else {
+            return (vec![], false);
+        };
+        accessory.last_seen = Some(now);
+        let mut changes = Vec::new();
+        let mut added = false;
+        for object in objects {
+            for (service, index, value) in characteristic_values(object) {
+                if !accessory.services.contains(&service) {
+                    accessory.add_service(service);
+                    added = true;
+                }
+                let iid = service.iid() + 1 + index as u64;
+                // Button presses are events without a state
+                if service == Service::Button {
+                    changes.push((aid, iid, value));
+                } else if accessory.values.get(&iid) != Some(&value) {
+                    accessory.values.insert(iid, value.clone());
+                    changes.push((aid, iid, value));
+                }
+            }
+        }
+        (changes, added)
+    }
+
+    fn is_reachable(&self, accessory: &Accessory, now: Instant) -> bool {
+        accessory
+            .last_seen
+            .is_some_and(|last_seen| now.duration_since(last_seen) < self.availability_timeout)
+    }
+
+    /// The response to `GET /accessories`.
+    pub fn to_json(&self) -> Value {
+        let mut accessories = vec![json!({
+            "aid": BRIDGE_AID,
+            "services": [
+                information(&self.name, "BTHome bridge", "BTHome bridge"),
+                {
+                    "iid": 8,
+                    "type": "A2",
+                    "characteristics": [
+                        {"iid": 9, "type": "37", "perms": ["pr"], "format": "string", "value": "1.1.0"}
+                    ]
+                }
+            ]
+        })];
+        for (aid, accessory) in &self.accessories {
+            let mut services = vec![information(&accessory.name, "BTHome device", &accessory.address)];
+            for service in &accessory.services {
+                let (kind, characteristics) = service.definition();
+                let characteristics: Vec<Value> = characteristics
+                    .iter()
+                    .enumerate()
+                    .map(|(index, characteristic)| {
+                        let iid = service.iid() + 1 + index as u64;
+                        let mut json = (characteristic.metadata)();
+                        json["iid"] = json!(iid);
+                        json["type"] = json!(characteristic.kind);
+                        json["format"] = json!(characteristic.format);
+                        json["perms"] = json!(characteristic.perms);
+                        json["value"] = accessory.values.get(&iid).cloned().unwrap_or(Value::Null);
+                        json
+                    })
+                    .collect();
+                services.push(json!({"iid": service.iid(), "type": kind, "characteristics": characteristics}));
+            }
+            accessories.push(json!({"aid": aid, "services": services}));
+        }
+        json!({ "accessories": accessories })
+    }
+
+    /// The response to `GET /characteristics`, `true` if all characteristics could be read.
+    pub fn read(&self, ids: &[(u64, u64)], now: Instant) -> (bool, Value) {
+        let mut all_ok = true;
+        let characteristics: Vec<Value> = ids
+            .iter()
+            .map(|(aid, iid)| match self.value(*aid, *iid, now) {
+                Ok(value) => json!({"aid": aid, "iid": iid, "value": value}),
+                Err(status) => {
+                    all_ok = false;
+                    json!({"aid": aid, "iid": iid, "status": status})
+                }
+            })
+            .collect();
+        if !all_ok {
+            // With errors every characteristic has a status
+            let characteristics: Vec<Value> = characteristics
+                .into_iter()
+                .map(|mut characteristic| {
+                    if characteristic.get("status").is_none() {
+                        characteristic["status"] = json!(0);
+                    }
+                    characteristic
+                })
+                .collect();
+            return (false, json!({ "characteristics": characteristics }));
+        }
+        (true, json!({ "characteristics": characteristics }))
+    }
+
+    fn value(&self, aid: u64, iid: u64, now: Instant) -> Result<Value, i64> {
+        if aid == BRIDGE_AID {
+            return match iid {
+                IDENTIFY_IID => Err(WRITE_ONLY),
+                3..=7 => Ok(information_value(iid, &self.name, "BTHome bridge", "BTHome bridge")),
+                9 => Ok(json!("1.1.0")),
+                _ => Err(RESOURCE_DOES_NOT_EXIST),
+            };
+        }
+        let accessory = self.accessories.get(&aid).ok_or(RESOURCE_DOES_NOT_EXIST)?;
+        match iid {
+            IDENTIFY_IID => return Err(WRITE_ONLY),
+            3..=7 => {
+                return Ok(information_value(
+                    iid,
+                    &accessory.name,
+                    "BTHome device",
+                    &accessory.address,
+                ))
+            }
+            _ => {}
+        }
+        accessory.characteristic(iid).ok_or(RESOURCE_DOES_NOT_EXIST)?;
+        if !self.is_reachable(accessory, now) {
+            return Err(SERVICE_COMMUNICATION_FAILURE);
+        }
+        Ok(accessory.values.get(&iid).cloned().unwrap_or(Value::Null))
+    }
+
+    /// Checks a write of `PUT /characteristics`, only identify can be written and only the
+    /// characteristics of services support notifications. Returns the HAP status.
+    pub fn check_write(&self, aid: u64, iid: u64, value: Option<&Value>, events: Option<bool>) -> i64 {
+        let exists = if aid == BRIDGE_AID {
+            matches!(iid, IDENTIFY_IID | 3..=7 | 9)
+        } else {
+            self.accessories
+                .get(&aid)
+                .is_some_and(|accessory| (IDENTIFY_IID..=7).contains(&iid) || accessory.characteristic(iid).is_some())
+        };
+        if !exists {
+            return RESOURCE_DOES_NOT_EXIST;
+        }
+        if value.is_some() && iid != IDENTIFY_IID {
+            return READ_ONLY;
+        }
+        let notifies = aid != BRIDGE_AID && iid > 7;
+        if events == Some(true) && !notifies {
+            return NOTIFICATION_NOT_SUPPORTED;
+        }
+        0
+    }
+
+    /// The services of every accessory by address, to keep the database stable across restarts.
+    pub fn services(&self) -> BTreeMap<String, BTreeSet<Service>> {
+        self.accessories
+            .values()
+            .map(|accessory| (accessory.address.clone(), accessory.services.clone()))
+            .collect()
+    }
+}
+
+impl Accessory {
+    fn add_service(&mut self, service: Service) {
+        let (_, characteristics) = service.definition();
+        for (index, characteristic) in characteristics.iter().enumerate() {
+            if let Some(initial) = characteristic.initial {
+                self.values.insert(service.iid() + 1 + index as u64, initial());
+            }
+        }
+        self.services.insert(service);
+    }
+
+    fn characteristic(&self, iid: u64) -> Option<&'static Characteristic> {
+        self.services.iter().find_map(|service| {
+            let index = iid.checked_sub(service.iid() + 1)? as usize;
+            service.definition().1.get(index)
+        })
+    }
+}
+
+/// The accessory information service, with iids 1 to 7.
+fn information(name: &str, model: &str, serial: &str) -> Value {
+    let characteristic = |iid: u64, kind: &str| json!({"iid": iid, "type": kind, "perms": ["pr"], "format": "string", "value": information_value(iid, name, model, serial)});
+    json!({
+        "iid": 1,
+        "type": "3E",
+        "characteristics": [
+            {"iid": IDENTIFY_IID, "type": "14", "perms": ["pw"], "format": "bool"},
+            characteristic(3, "20"),
+            characteristic(4, "21"),
+            characteristic(5, "23"),
+            characteristic(6, "30"),
+            characteristic(7, "52"),
+        ]
+    })
+}
+
+fn information_value(iid: u64, name: &str, model: &str, serial: &str) -> Value {
+    match iid {
+        3 => json!("BTHome"),
+        4 => json!(model),
+        5 => json!(name),
+        6 => json!(serial),
+        _ => json!(env!("CARGO_PKG_VERSION")),
+    }
+}
+
+/// Parses the `id` parameter of `GET /characteristics`, e.g. `1.2,2.11`.
+pub fn parse_ids(ids: &str) -> Option<Vec<(u64, u64)>> {
+    ids.split(',')
+        .map(|id| {
+            let (aid, iid) = id.split_once('.')?;
+            Some((aid.parse().ok()?, iid.parse().ok()?))
+        })
+        .collect()
+}
+
+/// The characteristics a connection wants to be notified about.
+pub type Subscriptions = HashSet<(u64, u64)>;
+
+#[cfg(test)]
+mod test {
+    use bthome::parse_service_data;
+
+    use super::*;
+
+    fn database() -> (Database, Instant) {
+        let mut database = Database::new("Bridge", Duration::from_secs(60));
+        database.add(2, "A4:C1:38:12:34:56", "Living room", BTreeSet::new());
+        (database, Instant::now())
+    }
+
+    #[test]
+    fn services_from_advertisements() {
+        let (mut database, now) = database();
+        // Temperature 25 °C, humidity 50.55 %
+        let objects = || {
+            parse_service_data(&[0x40, 0x02, 0xC4, 0x09, 0x03, 0xBF, 0x13])
+                .unwrap()
+                .objects
+        };
+        let (changes, added) = database.update(2, objects(), now);
+        assert!(added);
+        assert_eq!(changes, vec![(2, 11, json!(25.0)), (2, 21, json!(50.55))]);
+        let (changes, added) = database.update(2, objects(), now);
+        assert!(!added);
+        assert!(changes.is_empty());
+
+        let json = database.to_json();
+        let services = &json["accessories"][1]["services"];
+        assert_eq!(services[1]["type"], "8A");
+        assert_eq!(services[1]["characteristics"][0]["value"], 25.0);
+        assert_eq!(services[2]["characteristics"][0]["unit"], "percentage");
+        assert_eq!(database.services()["A4:C1:38:12:34:56"].len(), 2);
+    }
+
+    #[test]
+    fn read_characteristics() {
+        let (mut database, now) = database();
+        database.update(2, parse_service_data(&[0x40, 0x01, 0x0A]).unwrap().objects, now);
+        let (ok, json) = database.read(&parse_ids("2.91,2.92,2.93,1.9").unwrap(), now);
+        assert!(ok);
+        assert_eq!(json["characteristics"][0]["value"], 10);
+        assert_eq!(json["characteristics"][1]["value"], 2);
+        assert_eq!(json["characteristics"][2]["value"], 1);
+        assert_eq!(json["characteristics"][3]["value"], "1.1.0");
+
+        let (ok, json) = database.read(&[(2, 91), (2, 11)], now);
+        assert!(!ok);
+        assert_eq!(json["characteristics"][0]["status"], 0);
+        assert_eq!(json["characteristics"][1]["status"], RESOURCE_DOES_NOT_EXIST);
+
+        let (ok, json) = database.read(&[(2, 91)], now + Duration::from_secs(61));
+        assert!(!ok);
+        assert_eq!(json["characteristics"][0]["status"], SERVICE_COMMUNICATION_FAILURE);
+    }
+
+    #[test]
+    fn writes() {
+        let (mut database, now) = database();
+        let objects = parse_service_data(&[0x40, 0x3A, 0x01]).unwrap().objects;
+        assert_eq!(database.update(2, objects, now).0, vec![(2, 101, json!(0))]);
+        assert_eq!(database.check_write(2, 101, None, Some(true)), 0);
+        assert_eq!(database.check_write(2, 101, Some(&json!(1)), None), READ_ONLY);
+        assert_eq!(database.check_write(2, IDENTIFY_IID, Some(&json!(true)), None), 0);
+        assert_eq!(database.check_write(1, 9, None, Some(true)), NOTIFICATION_NOT_SUPPORTED);
+        assert_eq!(database.check_write(3, 11, None, Some(true)), RESOURCE_DOES_NOT_EXIST);
+    }
+}
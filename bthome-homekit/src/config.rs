@@ -0,0 +1,122 @@
+//! The configuration file of the bridge:
+//!
+//! ```toml
+//! name = "BTHome"
+//! pin = "031-45-154"
+//! storage = "/var/lib/bthome-homekit/store.json"
+//!
+//! [devices."A4:C1:38:12:34:56"]
+//! name = "Living room"
+//! ```
+
+use std::{collections::HashMap, path::PathBuf};
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Name of the bridge in the Home app
+    #[serde(default = "default_name")]
+    pub name: String,
+    /// Setup code to enter when adding the bridge, `XXX-XX-XXX`
+    pub pin: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Where the pairings and the accessories are kept, losing it means pairing again
+    pub storage: PathBuf,
+    /// Bluetooth adapter to scan on (default: the system's default adapter)
+    pub adapter: Option<String>,
+    /// Expose devices that are not in the registry, named by their address
+    #[serde(default)]
+    pub allow_unknown: bool,
+    /// Seconds without advertisements after which the accessories of a device are not responding
+    #[serde(default = "default_availability_timeout")]
+    pub availability_timeout: u64,
+    /// The device registry, keyed by the MAC address of the devices
+    #[serde(default)]
+    pub devices: HashMap<String, DeviceConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub name: String,
+}
+
+fn default_name() -> String {
+    "BTHome".to_string()
+}
+
+fn default_port() -> u16 {
+    51826
+}
+
+fn default_availability_timeout() -> u64 {
+    15 * 60
+}
+
+impl Config {
+    pub fn parse(content: &str) -> Result<Config, String> {
+        let mut config: Config = toml::from_str(content).map_err(|err| format!("invalid configuration: {}", err))?;
+        if !is_valid_pin(&config.pin) {
+            return Err(format!(
+                "invalid pin {:?}, expected XXX-XX-XXX and not a trivial code",
+                config.pin
+            ));
+        }
+        let mut devices = HashMap::new();
+        for (address, device) in config.devices {
+            let normalized = normalize_address(&address).ok_or_else(|| format!("invalid address {:?}", address))?;
+            if devices.insert(normalized, device).is_some() {
+                return Err(format!("device {} is configured twice", address));
+            }
+        }
+        config.devices = devices;
+        Ok(config)
+    }
+}
+
+/// Setup codes are 8 digits formatted as `XXX-XX-XXX`, HomeKit rejects repeated digits and
+/// the obvious sequences.
+fn is_valid_pin(pin: &str) -> bool {
+    let digits: String = pin.chars().filter(|c| *c != '-').collect();
+    let formatted = pin.len() == 10 && pin.chars().enumerate().all(|(i, c)| (i == 3 || i == 6) == (c == '-'));
+    let repeated = digits.bytes().all(|digit| digit == digits.as_bytes()[0]);
+    let trivial = repeated || digits == "12345678" || digits == "87654321";
+    formatted && digits.chars().all(|c| c.is_ascii_digit()) && !trivial
+}
+
+/// Normalizes a MAC address to upper case hex digits separated by colons, as reported by BlueZ.
+pub fn normalize_address(address: &str) -> Option<String> {
+    let digits: String = address.chars().filter(|c| !matches!(c, ':' | '-')).collect();
+    if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let bytes: Vec<String> = (0..12)
+        .step_by(2)
+        .map(|i| digits[i..i + 2].to_ascii_uppercase())
+        .collect();
+    Some(bytes.join(":"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config = Config::parse(
+            "pin = \"031-45-154\"\nstorage = \"store.json\"\n\n[devices.\"a4-c1-38-12-34-56\"]\nname = \"Living room\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.name, "BTHome");
+        assert_eq!(config.port, 51826);
+        assert_eq!(config.devices["A4:C1:38:12:34:56"].name, "Living room");
+
+        assert!(Config::parse("pin = \"111-11-111\"\nstorage = \"store.json\"\n").is_err());
+        assert!(Config::parse("pin = \"123-45-678\"\nstorage = \"store.json\"\n").is_err());
+        assert!(Config::parse("pin = \"03145154\"\nstorage = \"store.json\"\n").is_err());
+        assert!(Config::parse("pin = \"031-45-15a\"\nstorage = \"store.json\"\n").is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Key derivation and the ChaCha20-Poly1305 encryption of pairing messages and sessions.
+
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    ChaCha20Poly1305, KeyInit,
+};
+use hkdf::Hkdf;
+use sha2::Sha512;
+
+/// The largest plain text of an encrypted frame.
+const FRAME_LENGTH: usize = 1024;
+const TAG_LENGTH: usize = 16;
+
+/// HKDF-SHA-512 with a 32 byte output, as used for all keys.
+pub fn derive(secret: &[u8], salt: &str, info: &str) -> [u8; 32] {
+    let mut key = [0; 32];
+    Hkdf::<Sha512>::new(Some(salt.as_bytes()), secret)
+        .expand(info.as_bytes(), &mut key)
+        .expect("32 bytes are a valid length");
+    key
+}
+
+/// Nonces are 12 bytes, of which the first four are zero.
+fn nonce(suffix: &[u8; 8]) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(suffix);
+    nonce
+}
+
+/// Encrypts a pairing message, `label` is e.g. `PS-Msg06`.
+pub fn seal(key: &[u8; 32], label: &[u8; 8], data: &[u8]) -> Vec<u8> {
+    ChaCha20Poly1305::new(key.into())
+        .encrypt(&nonce(label).into(), data)
+        .expect("encrypting to a Vec can't fail")
+}
+
+pub fn open(key: &[u8; 32], label: &[u8; 8], data: &[u8]) -> Option<Vec<u8>> {
+    ChaCha20Poly1305::new(key.into())
+        .decrypt(&nonce(label).into(), data)
+        .ok()
+}
+
+/// The encryption of a verified connection: frames of a little endian two byte length, which is
+/// also the associated data, the cipher text and the tag. Each direction counts its frames.
+pub struct Session {
+    read_key: [u8; 32],
+    write_key: [u8; 32],
+    read_count: u64,
+    write_count: u64,
+    buffer: Vec<u8>,
+}
+
+impl Session {
+    /// The keys of the session of the accessory, derived from the secret of pair verify.
+    pub fn new(shared_secret: &[u8]) -> Session {
+        Session {
+            read_key: derive(shared_secret, "Control-Salt", "Control-Write-Encryption-Key"),
+            write_key: derive(shared_secret, "Control-Salt", "Control-Read-Encryption-Key"),
+            read_count: 0,
+            write_count: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn encrypt(&mut self, data: &[u8]) -> Vec<u8> {
+        let cipher = ChaCha20Poly1305::new(&self.write_key.into());
+        let mut out = Vec::new();
+        for chunk in data.chunks(FRAME_LENGTH) {
+            let length = (chunk.len() as u16).to_le_bytes();
+            let nonce = nonce(&self.write_count.to_le_bytes());
+            self.write_count += 1;
+            let sealed = cipher
+                .encrypt(
+                    &nonce.into(),
+                    Payload {
+                        msg: chunk,
+                        aad: &length,
+                    },
+                )
+                .expect("encrypting to a Vec can't fail");
+            out.extend_from_slice(&length);
+            out.extend(sealed);
+        }
+        out
+    }
+
+    /// Adds received bytes, returns the plain text of the complete frames received so far or
+    /// `None` if a frame could not be authenticated.
+    pub fn decrypt(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+        let cipher = ChaCha20Poly1305::new(&self.read_key.into());
+        let mut out = Vec::new();
+        while self.buffer.len() >= 2 {
+            let length = u16::from_le_bytes([self.buffer[0], self.buffer[1]]) as usize;
+            if length > FRAME_LENGTH {
+                return None;
+            }
+            if self.buffer.len() < 2 + length + TAG_LENGTH {
+                break;
+            }
+            let frame: Vec<u8> = self.buffer.drain(..2 + length + TAG_LENGTH).collect();
+            let nonce = nonce(&self.read_count.to_le_bytes());
+            self.read_count += 1;
+            let plain = cipher
+                .decrypt(
+                    &nonce.into(),
+                    Payload {
+                        msg: &frame[2..],
+                        aad: &frame[..2],
+                    },
+                )
+                .ok()?;
+            out.extend(plain);
+        }
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pairing_messages() {
+        let key = derive(b"secret", "Pair-Setup-Encrypt-Salt", "Pair-Setup-Encrypt-Info");
+        let sealed = seal(&key, b"PS-Msg06", b"hello");
+        assert_eq!(sealed.len(), 5 + 16);
+        assert_eq!(open(&key, b"PS-Msg06", &sealed), Some(b"hello".to_vec()));
+        assert_eq!(open(&key, b"PS-Msg05", &sealed), None);
+    }
+
+    #[test]
+    fn session_frames() {
+        let mut accessory = Session::new(b"shared");
+        // The controller reads with the key the accessory writes with and vice versa
+        let mut controller = Session::new(b"shared");
+        std::mem::swap(&mut controller.read_key, &mut controller.write_key);
+
+        let data = vec![0x61; 1500];
+        let encrypted = controller.encrypt(&data);
+        assert_eq!(encrypted.len(), 2 + 1024 + 16 + 2 + 476 + 16);
+        // Frames may arrive in pieces
+        assert_eq!(accessory.decrypt(&encrypted[..1000]), Some(vec![]));
+        assert_eq!(accessory.decrypt(&encrypted[1000..]), Some(data));
+
+        let response = accessory.encrypt(b"HTTP/1.1 204 No Content\r\n\r\n");
+        assert_eq!(
+            controller.decrypt(&response),
+            Some(b"HTTP/1.1 204 No Content\r\n\r\n".to_vec())
+        );
+        // Replaying a frame fails, the counter moved on
+        assert_eq!(controller.decrypt(&response), None);
+    }
+}
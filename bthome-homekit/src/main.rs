@@ -0,0 +1,282 @@
+//! Exposes BTHome devices as HomeKit accessories of a bridge, so that they show up in the Home
+//! app without Home Assistant in between.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    error::Error,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bthome::{parse_service_data, ObjectId};
+use clap::Parser;
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, mpsc::unbounded_channel},
+};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+mod accessory;
+mod config;
+mod crypto;
+mod mdns;
+mod pairing;
+mod server;
+mod srp;
+mod store;
+mod tlv;
+
+use accessory::Database;
+use config::Config;
+use server::Shared;
+use store::Store;
+
+/// Bridge BTHome devices to HomeKit.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Path to the TOML configuration file
+    #[arg(short, long)]
+    config: PathBuf,
+}
+
+pub struct Advertisement {
+    /// Upper case and colon separated, like in the configuration
+    pub address: String,
+    pub service_data: Vec<u8>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with_writer(std::io::stderr)
+        .init();
+    let args = Args::parse();
+
+    let content = std::fs::read_to_string(&args.config)
+        .map_err(|err| format!("Error reading {}: {}", args.config.display(), err))?;
+    let config = Config::parse(&content)?;
+    let mut store =
+        Store::load(&config.storage).map_err(|err| format!("Error loading {}: {}", config.storage.display(), err))?;
+    store
+        .save()
+        .map_err(|err| format!("Error saving {}: {}", config.storage.display(), err))?;
+
+    // The accessories known from previous runs, so that their ids and services stay the same
+    let mut database = Database::new(&config.name, Duration::from_secs(config.availability_timeout));
+    for (address, services) in store.services.clone() {
+        if let Some(name) = device_name(&config, &address) {
+            database.add(store.aid(&address), &address, &name, services);
+        }
+    }
+
+    let listener = TcpListener::bind(("0.0.0.0", config.port))
+        .await
+        .map_err(|err| format!("Error listening on port {}: {}", config.port, err))?;
+    let mut advertiser = mdns::Advertiser::new(&config.name, config.port)?;
+    advertiser.update(&store)?;
+    if store.is_paired() {
+        info!(name = config.name, id = store.device_id, "Bridge is ready");
+    } else {
+        info!(
+            name = config.name,
+            id = store.device_id,
+            "Bridge is ready for pairing, add it in the Home app with the setup code {}",
+            config.pin
+        );
+    }
+
+    let (pairings_tx, mut pairings_changed) = unbounded_channel();
+    let shared = Arc::new(Shared {
+        store: Mutex::new(store),
+        database: Mutex::new(database),
+        pin: config.pin.clone(),
+        events: broadcast::channel(64).0,
+        pairings_changed: pairings_tx,
+    });
+    let server = server::serve(listener, shared.clone());
+    tokio::pin!(server);
+
+    let (tx, mut advertisements) = unbounded_channel();
+    let scanner = scan(config.adapter.clone(), tx);
+    tokio::pin!(scanner);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    let mut bridge = Bridge {
+        config,
+        shared,
+        advertiser,
+        last_data: HashMap::new(),
+        warned: HashSet::new(),
+    };
+    let result = loop {
+        tokio::select! {
+            Some(advertisement) = advertisements.recv() => bridge.handle(advertisement, Instant::now()),
+            Some(()) = pairings_changed.recv() => bridge.save(),
+            result = &mut server => {
+                break match result {
+                    Ok(()) => Err("Server stopped unexpectedly".into()),
+                    Err(err) => Err(format!("Error accepting connections: {}", err).into()),
+                };
+            }
+            result = &mut scanner => {
+                break match result {
+                    Ok(()) => Err("Scanning stopped unexpectedly".into()),
+                    Err(err) => Err(format!("Error scanning: {}", err).into()),
+                };
+            }
+            result = &mut shutdown => {
+                if let Err(err) = result {
+                    error!(error = %err, "Error waiting for shutdown signals");
+                }
+                info!("Stopping");
+                break Ok(());
+            }
+        }
+    };
+    bridge.advertiser.shutdown();
+    result
+}
+
+/// The name of a device if it is exposed.
+fn device_name(config: &Config, address: &str) -> Option<String> {
+    match config.devices.get(address) {
+        Some(device) => Some(device.name.clone()),
+        None if config.allow_unknown => Some(address.to_string()),
+        None => None,
+    }
+}
+
+struct Bridge {
+    config: Config,
+    shared: Arc<Shared>,
+    advertiser: mdns::Advertiser,
+    /// The last service data of each device, to skip repeated advertisements
+    last_data: HashMap<String, Vec<u8>>,
+    /// Devices that were already warned about, to not flood the log
+    warned: HashSet<String>,
+}
+
+impl Bridge {
+    fn handle(&mut self, advertisement: Advertisement, now: Instant) {
+        let address = advertisement.address;
+        let Some(name) = device_name(&self.config, &address) else {
+            return;
+        };
+        let service_data = match parse_service_data(&advertisement.service_data) {
+            Ok(service_data) => service_data,
+            Err(bthome::Error::Encrypted) => {
+                if self.warned.insert(address.clone()) {
+                    warn!(device = name, address, "Decrypting BTHome data is not supported yet");
+                }
+                return;
+            }
+            Err(err) => {
+                debug!(device = name, address, error = ?err, "Error parsing service data");
+                return;
+            }
+        };
+        // Devices repeat each packet, which would trigger button presses several times
+        let has_packet_id = service_data
+            .objects
+            .iter()
+            .any(|object| object.object_id == ObjectId::PacketId);
+        let previous = self
+            .last_data
+            .insert(address.clone(), advertisement.service_data.clone());
+        if has_packet_id && previous.as_ref() == Some(&advertisement.service_data) {
+            return;
+        }
+
+        let (changes, added) = {
+            let mut store = self.shared.store.lock().unwrap();
+            let mut database = self.shared.database.lock().unwrap();
+            let aid = match database.aid(&address) {
+                Some(aid) => aid,
+                None => {
+                    let aid = store.aid(&address);
+                    database.add(aid, &address, &name, BTreeSet::new());
+                    aid
+                }
+            };
+            let (changes, added) = database.update(aid, service_data.objects, now);
+            if added {
+                info!(device = name, address, services = ?database.accessory(aid).map(|accessory| &accessory.services), "Exposing device");
+                store.services = database.services();
+                // Controllers reload the accessories when the configuration number changes
+                store.config_number = store.config_number % u16::MAX as u32 + 1;
+            }
+            (changes, added)
+        };
+        if added {
+            self.save();
+        }
+        if !changes.is_empty() {
+            let _ = self.shared.events.send(changes);
+        }
+    }
+
+    /// Saves the store and announces the bridge with the new state.
+    fn save(&mut self) {
+        let store = self.shared.store.lock().unwrap();
+        if let Err(err) = store.save() {
+            error!(path = %self.config.storage.display(), error = %err, "Error saving store");
+        }
+        if let Err(err) = self.advertiser.update(&store) {
+            error!(error = %err, "Error announcing the bridge");
+        }
+    }
+}
+
+/// Receives BTHome advertisements until the receiver is gone. Scanning is restarted when it stops,
+/// e.g. because bluetoothd restarted, so only failing to find the adapter is an error.
+#[cfg(target_os = "linux")]
+async fn scan(
+    adapter: Option<String>,
+    tx: tokio::sync::mpsc::UnboundedSender<Advertisement>,
+) -> Result<(), bluer::Error> {
+    use bthome_listener::Listener;
+    use futures::StreamExt;
+
+    let listener = match adapter {
+        Some(name) => Listener::named_adapter(&name).await?,
+        None => Listener::default_adapter().await?,
+    };
+    let mut advertisements = listener.advertisements();
+    while let Some(advertisement) = advertisements.next().await {
+        let advertisement = Advertisement {
+            address: advertisement.mac.to_string(),
+            service_data: advertisement.service_data,
+        };
+        if tx.send(advertisement).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn scan(
+    _adapter: Option<String>,
+    _tx: tokio::sync::mpsc::UnboundedSender<Advertisement>,
+) -> Result<(), &'static str> {
+    Err("scanning is only supported on Linux with bluez")
+}
+
+/// Resolves on SIGINT or SIGTERM.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
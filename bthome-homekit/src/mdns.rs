@@ -0,0 +1,85 @@
+//! Announcing the bridge with Bonjour, controllers find it as `_hap._tcp` service. The TXT
+//! records tell whether it can be paired and when the accessory database changed.
+
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+use crate::store::Store;
+
+const SERVICE_TYPE: &str = "_hap._tcp.local.";
+/// Bridges are shown with the bridge icon
+const CATEGORY_BRIDGE: u8 = 2;
+
+pub struct Advertiser {
+    daemon: ServiceDaemon,
+    name: String,
+    port: u16,
+    fullname: Option<String>,
+}
+
+impl Advertiser {
+    pub fn new(name: &str, port: u16) -> Result<Advertiser, mdns_sd::Error> {
+        Ok(Advertiser {
+            daemon: ServiceDaemon::new()?,
+            name: name.to_string(),
+            port,
+            fullname: None,
+        })
+    }
+
+    /// Announces the service with the current state of the store, again if it was announced.
+    pub fn update(&mut self, store: &Store) -> Result<(), mdns_sd::Error> {
+        let host = format!(
+            "bthome-{}.local.",
+            store.device_id.replace(':', "").to_ascii_lowercase()
+        );
+        let properties = properties(&self.name, store);
+        let info =
+            ServiceInfo::new(SERVICE_TYPE, &self.name, &host, "", self.port, properties.as_slice())?.enable_addr_auto();
+        self.fullname = Some(info.get_fullname().to_string());
+        self.daemon.register(info)
+    }
+
+    /// Withdraws the announcement, so that controllers don't wait for the bridge.
+    pub fn shutdown(self) {
+        if let Some(fullname) = &self.fullname {
+            if let Ok(receiver) = self.daemon.unregister(fullname) {
+                let _ = receiver.recv_timeout(std::time::Duration::from_secs(1));
+            }
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+fn properties(name: &str, store: &Store) -> Vec<(&'static str, String)> {
+    vec![
+        ("c#", store.config_number.to_string()),
+        ("ff", "0".to_string()),
+        ("id", store.device_id.clone()),
+        ("md", name.to_string()),
+        ("pv", "1.1".to_string()),
+        ("s#", "1".to_string()),
+        // Discoverable for pairing until the first controller is paired
+        ("sf", u8::from(!store.is_paired()).to_string()),
+        ("ci", CATEGORY_BRIDGE.to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pairing_flag() {
+        let mut store = Store::generate();
+        let flag = |store: &Store| {
+            properties("BTHome", store)
+                .into_iter()
+                .find(|(key, _)| *key == "sf")
+                .unwrap()
+                .1
+        };
+        assert_eq!(flag(&store), "1");
+        store.add_pairing("controller", &[1; 32], true);
+        assert_eq!(flag(&store), "0");
+    }
+}
@@ -0,0 +1,392 @@
+//! Pair setup, pair verify and the management of pairings. The requests and responses are TLV8
+//! encoded, errors are reported in the response with the state the controller expects next.
+
+use ed25519_dalek::{Signature, Signer, Verifier, VerifyingKey};
+use rand::{rngs::OsRng, RngCore};
+use tracing::{info, warn};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use crate::{
+    crypto::{derive, open, seal},
+    srp,
+    store::Store,
+    tlv,
+};
+
+const METHOD_ADD_PAIRING: u8 = 3;
+const METHOD_REMOVE_PAIRING: u8 = 4;
+const METHOD_LIST_PAIRINGS: u8 = 5;
+/// Controllers that can be paired at the same time
+const MAX_PAIRINGS: usize = 16;
+
+fn error(state: u8, error: u8) -> Vec<u8> {
+    tlv::encode(&[(tlv::STATE, &[state]), (tlv::ERROR, &[error])])
+}
+
+/// The progress of pair setup on a connection.
+#[derive(Default)]
+pub enum PairSetup {
+    #[default]
+    Idle,
+    /// M2 was sent, waiting for the proof of the controller
+    Started(Box<srp::Server>),
+    /// The proofs matched, waiting for the identity of the controller
+    Verified(Vec<u8>),
+}
+
+impl PairSetup {
+    /// Handles a request, a successful M5 adds the controller as admin to the store.
+    pub fn handle(&mut self, store: &mut Store, pin: &str, body: &[u8]) -> Vec<u8> {
+        let Some(request) = tlv::decode(body) else {
+            return error(2, tlv::ERROR_UNKNOWN);
+        };
+        let state = request.get(&tlv::STATE).and_then(|state| state.first().copied());
+        match (state, std::mem::take(self)) {
+            (Some(1), _) => {
+                if store.is_paired() {
+                    return error(2, tlv::ERROR_UNAVAILABLE);
+                }
+                let mut salt = [0; 16];
+                let mut private = [0; 32];
+                OsRng.fill_bytes(&mut salt);
+                OsRng.fill_bytes(&mut private);
+                let server = srp::Server::new(pin, &salt, &private);
+                let response = tlv::encode(&[
+                    (tlv::STATE, &[2]),
+                    (tlv::SALT, &salt),
+                    (tlv::PUBLIC_KEY, &server.public_key()),
+                ]);
+                *self = PairSetup::Started(Box::new(server));
+                response
+            }
+            (Some(3), PairSetup::Started(server)) => {
+                let (Some(public), Some(proof)) = (request.get(&tlv::PUBLIC_KEY), request.get(&tlv::PROOF)) else {
+                    return error(4, tlv::ERROR_UNKNOWN);
+                };
+                match server.verify(public, proof) {
+                    Some((key, server_proof)) => {
+                        *self = PairSetup::Verified(key);
+                        tlv::encode(&[(tlv::STATE, &[4]), (tlv::PROOF, &server_proof)])
+                    }
+                    None => {
+                        warn!("Pair setup failed, wrong setup code");
+                        error(4, tlv::ERROR_AUTHENTICATION)
+                    }
+                }
+            }
+            (Some(5), PairSetup::Verified(key)) => exchange(store, &key, &request).unwrap_or_else(|| {
+                warn!("Pair setup failed, the controller could not be verified");
+                error(6, tlv::ERROR_AUTHENTICATION)
+            }),
+            (state, _) => error(state.unwrap_or(0).saturating_add(1), tlv::ERROR_UNKNOWN),
+        }
+    }
+}
+
+/// M5 and M6 of pair setup, the exchange of the long term keys.
+fn exchange(store: &mut Store, key: &[u8], request: &tlv::Items) -> Option<Vec<u8>> {
+    let session_key = derive(key, "Pair-Setup-Encrypt-Salt", "Pair-Setup-Encrypt-Info");
+    let data = open(&session_key, b"PS-Msg05", request.get(&tlv::ENCRYPTED_DATA)?)?;
+    let sub = tlv::decode(&data)?;
+    let (id, ltpk, signature) = (
+        sub.get(&tlv::IDENTIFIER)?,
+        sub.get(&tlv::PUBLIC_KEY)?,
+        sub.get(&tlv::SIGNATURE)?,
+    );
+    let controller_x = derive(
+        key,
+        "Pair-Setup-Controller-Sign-Salt",
+        "Pair-Setup-Controller-Sign-Info",
+    );
+    let public_key = VerifyingKey::from_bytes(ltpk.as_slice().try_into().ok()?).ok()?;
+    let signature = Signature::from_slice(signature).ok()?;
+    public_key
+        .verify(&[&controller_x[..], id, ltpk].concat(), &signature)
+        .ok()?;
+    let controller = String::from_utf8(id.clone()).ok()?;
+    store.add_pairing(&controller, ltpk, true);
+    info!(controller, "Paired");
+
+    let accessory_x = derive(key, "Pair-Setup-Accessory-Sign-Salt", "Pair-Setup-Accessory-Sign-Info");
+    let signing_key = store.signing_key();
+    let accessory_ltpk = signing_key.verifying_key().to_bytes();
+    let info = [&accessory_x[..], store.device_id.as_bytes(), &accessory_ltpk].concat();
+    let signature = signing_key.sign(&info).to_bytes();
+    let sub = tlv::encode(&[
+        (tlv::IDENTIFIER, store.device_id.as_bytes()),
+        (tlv::PUBLIC_KEY, &accessory_ltpk),
+        (tlv::SIGNATURE, &signature),
+    ]);
+    let encrypted = seal(&session_key, b"PS-Msg06", &sub);
+    Some(tlv::encode(&[(tlv::STATE, &[6]), (tlv::ENCRYPTED_DATA, &encrypted)]))
+}
+
+/// A controller that completed pair verify, the connection is encrypted from now on.
+pub struct Verified {
+    pub controller: String,
+    pub shared_secret: [u8; 32],
+}
+
+/// The progress of pair verify on a connection.
+#[derive(Default)]
+pub enum PairVerify {
+    #[default]
+    Idle,
+    /// M2 was sent, with the shared secret and both ephemeral public keys
+    Started {
+        shared_secret: [u8; 32],
+        accessory_public: [u8; 32],
+        controller_public: [u8; 32],
+    },
+}
+
+impl PairVerify {
+    pub fn handle(&mut self, store: &Store, body: &[u8]) -> (Vec<u8>, Option<Verified>) {
+        let Some(request) = tlv::decode(body) else {
+            return (error(2, tlv::ERROR_UNKNOWN), None);
+        };
+        let state = request.get(&tlv::STATE).and_then(|state| state.first().copied());
+        match (state, std::mem::take(self)) {
+            (Some(1), _) => {
+                let Some(controller_public) = request
+                    .get(&tlv::PUBLIC_KEY)
+                    .and_then(|key| <[u8; 32]>::try_from(key.as_slice()).ok())
+                else {
+                    return (error(2, tlv::ERROR_UNKNOWN), None);
+                };
+                let secret = EphemeralSecret::random_from_rng(OsRng);
+                let accessory_public = PublicKey::from(&secret).to_bytes();
+                let shared_secret = secret.diffie_hellman(&PublicKey::from(controller_public)).to_bytes();
+                let info = [&accessory_public[..], store.device_id.as_bytes(), &controller_public].concat();
+                let signature = store.signing_key().sign(&info).to_bytes();
+                let sub = tlv::encode(&[
+                    (tlv::IDENTIFIER, store.device_id.as_bytes()),
+                    (tlv::SIGNATURE, &signature),
+                ]);
+                let encrypted = seal(&verify_key(&shared_secret), b"PV-Msg02", &sub);
+                *self = PairVerify::Started {
+                    shared_secret,
+                    accessory_public,
+                    controller_public,
+                };
+                let response = tlv::encode(&[
+                    (tlv::STATE, &[2]),
+                    (tlv::PUBLIC_KEY, &accessory_public),
+                    (tlv::ENCRYPTED_DATA, &encrypted),
+                ]);
+                (response, None)
+            }
+            (
+                Some(3),
+                PairVerify::Started {
+                    shared_secret,
+                    accessory_public,
+                    controller_public,
+                },
+            ) => {
+                let verified =
+                    verify_controller(store, &shared_secret, &accessory_public, &controller_public, &request);
+                match verified {
+                    Some(controller) => (
+                        tlv::encode(&[(tlv::STATE, &[4])]),
+                        Some(Verified {
+                            controller,
+                            shared_secret,
+                        }),
+                    ),
+                    None => {
+                        warn!("Pair verify failed, unknown controller or invalid signature");
+                        (error(4, tlv::ERROR_AUTHENTICATION), None)
+                    }
+                }
+            }
+            (state, _) => (error(state.unwrap_or(0).saturating_add(1), tlv::ERROR_UNKNOWN), None),
+        }
+    }
+}
+
+/// M3 of pair verify, returns the controller if it is paired and its signature matches.
+fn verify_controller(
+    store: &Store,
+    shared_secret: &[u8; 32],
+    accessory_public: &[u8; 32],
+    controller_public: &[u8; 32],
+    request: &tlv::Items,
+) -> Option<String> {
+    let data = open(
+        &verify_key(shared_secret),
+        b"PV-Msg03",
+        request.get(&tlv::ENCRYPTED_DATA)?,
+    )?;
+    let sub = tlv::decode(&data)?;
+    let id = sub.get(&tlv::IDENTIFIER)?;
+    let controller = String::from_utf8(id.clone()).ok()?;
+    let signature = Signature::from_slice(sub.get(&tlv::SIGNATURE)?).ok()?;
+    let info = [&controller_public[..], id, &accessory_public[..]].concat();
+    store.public_key(&controller)?.verify(&info, &signature).ok()?;
+    Some(controller)
+}
+
+fn verify_key(shared_secret: &[u8]) -> [u8; 32] {
+    derive(shared_secret, "Pair-Verify-Encrypt-Salt", "Pair-Verify-Encrypt-Info")
+}
+
+/// Adds, removes and lists pairings, which only admins may do.
+pub fn pairings(store: &mut Store, controller: &str, body: &[u8]) -> Vec<u8> {
+    let Some(request) = tlv::decode(body) else {
+        return error(2, tlv::ERROR_UNKNOWN);
+    };
+    if !store.is_admin(controller) {
+        return error(2, tlv::ERROR_AUTHENTICATION);
+    }
+    let method = request.get(&tlv::METHOD).and_then(|method| method.first().copied());
+    let id = request
+        .get(&tlv::IDENTIFIER)
+        .and_then(|id| String::from_utf8(id.clone()).ok());
+    match (method, id) {
+        (Some(METHOD_ADD_PAIRING), Some(id)) => {
+            let Some(public_key) = request.get(&tlv::PUBLIC_KEY).filter(|key| key.len() == 32) else {
+                return error(2, tlv::ERROR_UNKNOWN);
+            };
+            if store
+                .public_key(&id)
+                .is_some_and(|known| known.as_bytes() != public_key.as_slice())
+            {
+                return error(2, tlv::ERROR_UNKNOWN);
+            }
+            if !store.pairings.contains_key(&id) && store.pairings.len() >= MAX_PAIRINGS {
+                return error(2, tlv::ERROR_MAX_PEERS);
+            }
+            let admin = request
+                .get(&tlv::PERMISSIONS)
+                .is_some_and(|permissions| permissions.first() == Some(&1));
+            store.add_pairing(&id, public_key, admin);
+            info!(controller = id, admin, "Added pairing");
+            tlv::encode(&[(tlv::STATE, &[2])])
+        }
+        (Some(METHOD_REMOVE_PAIRING), Some(id)) => {
+            store.remove_pairing(&id);
+            info!(controller = id, "Removed pairing");
+            tlv::encode(&[(tlv::STATE, &[2])])
+        }
+        (Some(METHOD_LIST_PAIRINGS), _) => {
+            let pairings: Vec<(String, [u8; 32], u8)> = store
+                .pairings
+                .keys()
+                .filter_map(|id| {
+                    Some((
+                        id.clone(),
+                        store.public_key(id)?.to_bytes(),
+                        u8::from(store.is_admin(id)),
+                    ))
+                })
+                .collect();
+            let mut items: Vec<(u8, &[u8])> = vec![(tlv::STATE, &[2])];
+            for (i, (id, public_key, admin)) in pairings.iter().enumerate() {
+                if i > 0 {
+                    items.push((tlv::SEPARATOR, &[]));
+                }
+                items.push((tlv::IDENTIFIER, id.as_bytes()));
+                items.push((tlv::PUBLIC_KEY, public_key));
+                items.push((tlv::PERMISSIONS, std::slice::from_ref(admin)));
+            }
+            tlv::encode(&items)
+        }
+        _ => error(2, tlv::ERROR_UNKNOWN),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ed25519_dalek::SigningKey;
+
+    use super::*;
+
+    #[test]
+    fn verify_paired_controller() {
+        let mut store = Store::generate();
+        let controller_key = SigningKey::from_bytes(&[0x11; 32]);
+        store.add_pairing("controller", controller_key.verifying_key().as_bytes(), true);
+        let mut verify = PairVerify::default();
+
+        // M1: the ephemeral key of the controller
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let controller_public = PublicKey::from(&secret).to_bytes();
+        let (response, verified) = verify.handle(
+            &store,
+            &tlv::encode(&[(tlv::STATE, &[1]), (tlv::PUBLIC_KEY, &controller_public)]),
+        );
+        assert!(verified.is_none());
+        let response = tlv::decode(&response).unwrap();
+        assert_eq!(response[&tlv::STATE], vec![2]);
+        let accessory_public: [u8; 32] = response[&tlv::PUBLIC_KEY].as_slice().try_into().unwrap();
+        let shared_secret = secret.diffie_hellman(&PublicKey::from(accessory_public)).to_bytes();
+        let sub = open(
+            &verify_key(&shared_secret),
+            b"PV-Msg02",
+            &response[&tlv::ENCRYPTED_DATA],
+        )
+        .unwrap();
+        let sub = tlv::decode(&sub).unwrap();
+        assert_eq!(sub[&tlv::IDENTIFIER], store.device_id.as_bytes());
+        let signature = Signature::from_slice(&sub[&tlv::SIGNATURE]).unwrap();
+        let info = [&accessory_public[..], store.device_id.as_bytes(), &controller_public].concat();
+        store.signing_key().verifying_key().verify(&info, &signature).unwrap();
+
+        // M3: the controller proves its identity
+        let info = [&controller_public[..], b"controller", &accessory_public].concat();
+        let sub = tlv::encode(&[
+            (tlv::IDENTIFIER, b"controller"),
+            (tlv::SIGNATURE, &controller_key.sign(&info).to_bytes()),
+        ]);
+        let encrypted = seal(&verify_key(&shared_secret), b"PV-Msg03", &sub);
+        let request = tlv::encode(&[(tlv::STATE, &[3]), (tlv::ENCRYPTED_DATA, &encrypted)]);
+        let (response, verified) = verify.handle(&store, &request);
+        assert_eq!(response, tlv::encode(&[(tlv::STATE, &[4])]));
+        let verified = verified.unwrap();
+        assert_eq!(verified.controller, "controller");
+        assert_eq!(verified.shared_secret, shared_secret);
+
+        // The exchange can't be completed twice
+        let (response, verified) = verify.handle(&store, &request);
+        assert_eq!(response, error(4, tlv::ERROR_UNKNOWN));
+        assert!(verified.is_none());
+    }
+
+    #[test]
+    fn manage_pairings() {
+        let mut store = Store::generate();
+        store.add_pairing(
+            "admin",
+            SigningKey::from_bytes(&[1; 32]).verifying_key().as_bytes(),
+            true,
+        );
+        let add = tlv::encode(&[
+            (tlv::STATE, &[1]),
+            (tlv::METHOD, &[METHOD_ADD_PAIRING]),
+            (tlv::IDENTIFIER, b"guest"),
+            (
+                tlv::PUBLIC_KEY,
+                SigningKey::from_bytes(&[2; 32]).verifying_key().as_bytes(),
+            ),
+            (tlv::PERMISSIONS, &[0]),
+        ]);
+        assert_eq!(pairings(&mut store, "admin", &add), tlv::encode(&[(tlv::STATE, &[2])]));
+        assert!(!store.is_admin("guest"));
+        assert_eq!(pairings(&mut store, "guest", &add), error(2, tlv::ERROR_AUTHENTICATION));
+
+        let list = tlv::encode(&[(tlv::STATE, &[1]), (tlv::METHOD, &[METHOD_LIST_PAIRINGS])]);
+        let response = pairings(&mut store, "admin", &list);
+        // The state, two pairings of identifier, public key and permissions, and a separator
+        assert_eq!(response.len(), 3 + 2 * (7 + 34 + 3) + 2);
+        assert!(response.windows(2).any(|item| item == [tlv::SEPARATOR, 0]));
+
+        let remove = tlv::encode(&[
+            (tlv::STATE, &[1]),
+            (tlv::METHOD, &[METHOD_REMOVE_PAIRING]),
+            (tlv::IDENTIFIER, b"admin"),
+        ]);
+        pairings(&mut store, "admin", &remove);
+        assert!(!store.is_paired());
+    }
+}
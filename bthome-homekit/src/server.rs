@@ -0,0 +1,394 @@
+//! The HTTP server controllers talk to. Connections start in plain text for pairing, after pair
+//! verify every request, response and event is encrypted.
+
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc::UnboundedSender,
+    },
+};
+use tracing::{debug, info};
+
+use crate::{
+    accessory::{parse_ids, Change, Database, Subscriptions},
+    crypto::Session,
+    pairing::{self, PairSetup, PairVerify, Verified},
+    store::Store,
+};
+
+const TLV: &str = "application/pairing+tlv8";
+const JSON: &str = "application/hap+json";
+/// Requests and headers larger than this are not accepted
+const MAX_REQUEST_LENGTH: usize = 64 * 1024;
+
+const INSUFFICIENT_PRIVILEGES: i64 = -70401;
+const INVALID_REQUEST: i64 = -70409;
+
+/// The state shared by all connections.
+pub struct Shared {
+    pub store: Mutex<Store>,
+    pub database: Mutex<Database>,
+    /// The setup code
+    pub pin: String,
+    /// Characteristics that changed, for the subscribed controllers
+    pub events: broadcast::Sender<Vec<Change>>,
+    /// Pairings were added or removed, the store has to be saved
+    pub pairings_changed: UnboundedSender<()>,
+}
+
+/// Accepts connections until an error occurs.
+pub async fn serve(listener: TcpListener, shared: Arc<Shared>) -> io::Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let connection = Connection::new(shared.clone());
+        tokio::spawn(async move {
+            debug!(%peer, "Controller connected");
+            match connection.run(stream).await {
+                Ok(()) => debug!(%peer, "Controller disconnected"),
+                Err(err) => debug!(%peer, error = %err, "Connection closed"),
+            }
+        });
+    }
+}
+
+struct Connection {
+    shared: Arc<Shared>,
+    pair_setup: PairSetup,
+    pair_verify: PairVerify,
+    /// Set after pair verify
+    controller: Option<String>,
+    session: Option<Session>,
+    subscriptions: Subscriptions,
+}
+
+impl Connection {
+    fn new(shared: Arc<Shared>) -> Connection {
+        Connection {
+            shared,
+            pair_setup: PairSetup::default(),
+            pair_verify: PairVerify::default(),
+            controller: None,
+            session: None,
+            subscriptions: Subscriptions::new(),
+        }
+    }
+
+    async fn run(mut self, mut stream: TcpStream) -> io::Result<()> {
+        let mut events = self.shared.events.subscribe();
+        let mut buffer = [0; 4096];
+        let mut received = Vec::new();
+        loop {
+            tokio::select! {
+                read = stream.read(&mut buffer) => {
+                    let length = read?;
+                    if length == 0 {
+                        return Ok(());
+                    }
+                    match &mut self.session {
+                        Some(session) => received.extend(
+                            session
+                                .decrypt(&buffer[..length])
+                                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid frame"))?,
+                        ),
+                        None => received.extend_from_slice(&buffer[..length]),
+                    }
+                    while let Some(request) =
+                        Request::parse(&mut received).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+                    {
+                        let (response, verified) = self.handle(request);
+                        self.send(&mut stream, &response).await?;
+                        if let Some(Verified { controller, shared_secret }) = verified {
+                            debug!(controller, "Controller verified");
+                            self.session = Some(Session::new(&shared_secret));
+                            self.controller = Some(controller);
+                        }
+                        // Connections of removed controllers are closed
+                        if let Some(controller) = &self.controller {
+                            if !self.shared.store.lock().unwrap().pairings.contains_key(controller) {
+                                return Ok(());
+                            }
+                        }
+                    }
+                }
+                changes = events.recv() => {
+                    let changes = match changes {
+                        Ok(changes) => changes,
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return Ok(()),
+                    };
+                    let characteristics: Vec<Value> = changes
+                        .into_iter()
+                        .filter(|(aid, iid, _)| self.subscriptions.contains(&(*aid, *iid)))
+                        .map(|(aid, iid, value)| json!({"aid": aid, "iid": iid, "value": value}))
+                        .collect();
+                    if !characteristics.is_empty() && self.session.is_some() {
+                        let body = json!({ "characteristics": characteristics }).to_string();
+                        self.send(&mut stream, &message("EVENT/1.0 200 OK", JSON, body.as_bytes())).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn send(&mut self, stream: &mut TcpStream, data: &[u8]) -> io::Result<()> {
+        match &mut self.session {
+            Some(session) => stream.write_all(&session.encrypt(data)).await,
+            None => stream.write_all(data).await,
+        }
+    }
+
+    /// Returns the response and whether pair verify completed.
+    fn handle(&mut self, request: Request) -> (Vec<u8>, Option<Verified>) {
+        let shared = self.shared.clone();
+        let response = match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/pair-setup") => {
+                let mut store = shared.store.lock().unwrap();
+                let paired = store.is_paired();
+                let body = self.pair_setup.handle(&mut store, &shared.pin, &request.body);
+                if store.is_paired() != paired {
+                    let _ = shared.pairings_changed.send(());
+                }
+                response(200, TLV, &body)
+            }
+            ("POST", "/pair-verify") => {
+                let store = shared.store.lock().unwrap();
+                let (body, verified) = self.pair_verify.handle(&store, &request.body);
+                return (response(200, TLV, &body), verified);
+            }
+            _ if self.controller.is_none() => json_response(470, json!({ "status": INSUFFICIENT_PRIVILEGES })),
+            ("GET", "/accessories") => json_response(200, shared.database.lock().unwrap().to_json()),
+            ("GET", "/characteristics") => self.read(&request.query),
+            ("PUT", "/characteristics") => self.write(&request.body),
+            ("POST", "/pairings") => {
+                let mut store = shared.store.lock().unwrap();
+                let pairings = store.pairings.clone();
+                let controller = self.controller.as_deref().unwrap_or_default();
+                let body = pairing::pairings(&mut store, controller, &request.body);
+                if store.pairings != pairings {
+                    let _ = shared.pairings_changed.send(());
+                }
+                response(200, TLV, &body)
+            }
+            _ => response(404, JSON, b""),
+        };
+        (response, None)
+    }
+
+    fn read(&self, query: &str) -> Vec<u8> {
+        let ids = query
+            .split('&')
+            .find_map(|parameter| parameter.strip_prefix("id="))
+            .and_then(parse_ids);
+        let Some(ids) = ids else {
+            return json_response(400, json!({ "status": INVALID_REQUEST }));
+        };
+        let (ok, body) = self.shared.database.lock().unwrap().read(&ids, Instant::now());
+        json_response(if ok { 200 } else { 207 }, body)
+    }
+
+    fn write(&mut self, body: &[u8]) -> Vec<u8> {
+        #[derive(Deserialize)]
+        struct Write {
+            aid: u64,
+            iid: u64,
+            value: Option<Value>,
+            ev: Option<bool>,
+        }
+        #[derive(Deserialize)]
+        struct Writes {
+            characteristics: Vec<Write>,
+        }
+        let Ok(writes) = serde_json::from_slice::<Writes>(body) else {
+            return json_response(400, json!({ "status": INVALID_REQUEST }));
+        };
+        let database = self.shared.database.lock().unwrap();
+        let mut statuses = Vec::new();
+        for write in writes.characteristics {
+            let status = database.check_write(write.aid, write.iid, write.value.as_ref(), write.ev);
+            if status == 0 {
+                match write.ev {
+                    Some(true) => {
+                        self.subscriptions.insert((write.aid, write.iid));
+                    }
+                    Some(false) => {
+                        self.subscriptions.remove(&(write.aid, write.iid));
+                    }
+                    None => {}
+                }
+                // Identify is the only characteristic that can be written
+                if write.value.is_some() {
+                    let name = database
+                        .accessory(write.aid)
+                        .map_or("bridge", |accessory| &accessory.name);
+                    info!(accessory = name, "Identify requested");
+                }
+            }
+            statuses.push(json!({"aid": write.aid, "iid": write.iid, "status": status}));
+        }
+        if statuses.iter().all(|status| status["status"] == 0) {
+            response(204, JSON, b"")
+        } else {
+            json_response(207, json!({ "characteristics": statuses }))
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Takes the next complete request out of `buffer`, if any.
+    fn parse(buffer: &mut Vec<u8>) -> Result<Option<Request>, &'static str> {
+        let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") else {
+            if buffer.len() > MAX_REQUEST_LENGTH {
+                return Err("header too long");
+            }
+            return Ok(None);
+        };
+        let head = std::str::from_utf8(&buffer[..end]).map_err(|_| "invalid header")?;
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next().unwrap_or_default().split(' ');
+        let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+            return Err("invalid request line");
+        };
+        let mut length = 0;
+        for line in lines {
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().map_err(|_| "invalid content length")?;
+                }
+            }
+        }
+        if length > MAX_REQUEST_LENGTH {
+            return Err("request too long");
+        }
+        if buffer.len() < end + 4 + length {
+            return Ok(None);
+        }
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let request = Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: query.to_string(),
+            body: buffer[end + 4..end + 4 + length].to_vec(),
+        };
+        buffer.drain(..end + 4 + length);
+        Ok(Some(request))
+    }
+}
+
+fn response(status: u16, content_type: &str, body: &[u8]) -> Vec<u8> {
+    let reason = match status {
+        200 => "OK",
+        204 => "No Content",
+        207 => "Multi-Status",
+        400 => "Bad Request",
+        404 => "Not Found",
+        470 => "Connection Authorization Required",
+        _ => "Error",
+    };
+    message(&format!("HTTP/1.1 {} {}", status, reason), content_type, body)
+}
+
+fn json_response(status: u16, body: Value) -> Vec<u8> {
+    response(status, JSON, body.to_string().as_bytes())
+}
+
+fn message(status_line: &str, content_type: &str, body: &[u8]) -> Vec<u8> {
+    let head = if body.is_empty() {
+        format!("{}\r\nContent-Length: 0\r\n\r\n", status_line)
+    } else {
+        format!(
+            "{}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            status_line,
+            content_type,
+            body.len()
+        )
+    };
+    let mut message = head.into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeSet, time::Duration};
+
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+
+    fn connection() -> Connection {
+        let mut database = Database::new("Bridge", Duration::from_secs(60));
+        database.add(2, "A4:C1:38:12:34:56", "Living room", BTreeSet::new());
+        Connection::new(Arc::new(Shared {
+            store: Mutex::new(Store::generate()),
+            database: Mutex::new(database),
+            pin: "031-45-154".to_string(),
+            events: broadcast::channel(16).0,
+            pairings_changed: unbounded_channel().0,
+        }))
+    }
+
+    #[test]
+    fn parse_requests() {
+        let mut buffer = b"PUT /characteristics HTTP/1.1\r\nHost: bridge\r\nContent-Length: 4\r\n\r\n{}".to_vec();
+        assert!(Request::parse(&mut buffer).unwrap().is_none());
+        buffer.extend_from_slice(b"\r\nGET /characteristics?id=1.9 HTTP/1.1\r\n\r\n");
+        let request = Request::parse(&mut buffer).unwrap().unwrap();
+        assert_eq!(
+            (request.method.as_str(), request.path.as_str()),
+            ("PUT", "/characteristics")
+        );
+        assert_eq!(request.body, b"{}\r\n");
+        let request = Request::parse(&mut buffer).unwrap().unwrap();
+        assert_eq!(
+            (request.path.as_str(), request.query.as_str()),
+            ("/characteristics", "id=1.9")
+        );
+        assert!(buffer.is_empty());
+        assert!(Request::parse(&mut b"GET\r\n\r\n".to_vec()).is_err());
+    }
+
+    fn get(connection: &mut Connection, request: &[u8]) -> String {
+        let request = Request::parse(&mut request.to_vec()).unwrap().unwrap();
+        String::from_utf8(connection.handle(request).0).unwrap()
+    }
+
+    #[test]
+    fn requests() {
+        let mut connection = connection();
+        let response = get(&mut connection, b"GET /accessories HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 470 "));
+
+        connection.controller = Some("controller".to_string());
+        let response = get(&mut connection, b"GET /characteristics?id=1.9 HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(r#"{"characteristics":[{"aid":1,"iid":9,"value":"1.1.0"}]}"#));
+
+        let body = r#"{"characteristics":[{"aid":2,"iid":2,"value":true},{"aid":1,"iid":9,"ev":true}]}"#;
+        let request = format!(
+            "PUT /characteristics HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let response = get(&mut connection, request.as_bytes());
+        assert!(response.starts_with("HTTP/1.1 207 "));
+        assert!(response.contains(r#"{"aid":1,"iid":9,"status":-70406}"#));
+        assert!(connection.subscriptions.is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+//! The server side of SRP-6a as used by pair setup: the 3072 bit group of RFC 5054, SHA-512 and
+//! the user name `Pair-Setup`.
+
+use num_bigint::BigUint;
+use sha2::{Digest, Sha512};
+use subtle::ConstantTimeEq;
+
+const N_HEX: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A0879\
+    8E3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B\
+    0BFF5CB6F406B7EDEE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF0598DA4836\
+    1C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB9ED529077096966D670C354E4ABC9804\
+    F1746C08CA18217C32905E462E36CE3BE39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF6\
+    955817183995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33A85521ABDF1CBA64\
+    ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7ABF5AE8CDB0933D71E8C94E04A25619DCEE3D226\
+    1AD2EE6BF12FFA06D98A0864D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2\
+    08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A93AD2CAFFFFFFFFFFFFFFFF";
+const G: u32 = 5;
+const LENGTH: usize = 384;
+pub const USERNAME: &str = "Pair-Setup";
+
+fn n() -> BigUint {
+    BigUint::parse_bytes(N_HEX.as_bytes(), 16).expect("valid prime")
+}
+
+fn hash(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Big endian bytes padded to the length of N.
+fn pad(value: &BigUint) -> Vec<u8> {
+    let bytes = value.to_bytes_be();
+    let mut padded = vec![0; LENGTH.saturating_sub(bytes.len())];
+    padded.extend(bytes);
+    padded
+}
+
+/// k = H(N | PAD(g))
+fn k() -> BigUint {
+    BigUint::from_bytes_be(&hash(&[&pad(&n()), &pad(&BigUint::from(G))]))
+}
+
+/// x = H(s | H(I | ":" | P))
+fn x(salt: &[u8], username: &str, password: &str) -> BigUint {
+    let inner = hash(&[username.as_bytes(), b":", password.as_bytes()]);
+    BigUint::from_bytes_be(&hash(&[salt, &inner]))
+}
+
+pub struct Server {
+    salt: Vec<u8>,
+    verifier: BigUint,
+    private: BigUint,
+    public: BigUint,
+}
+
+impl Server {
+    /// Starts an exchange for `password` with a random `salt` and random `private` key.
+    pub fn new(password: &str, salt: &[u8], private: &[u8]) -> Server {
+        let n = n();
+        let verifier = BigUint::from(G).modpow(&x(salt, USERNAME, password), &n);
+        let private = BigUint::from_bytes_be(private);
+        let public = (k() * &verifier + BigUint::from(G).modpow(&private, &n)) % &n;
+        Server {
+            salt: salt.to_vec(),
+            verifier,
+            private,
+            public,
+        }
+    }
+
+    /// B, padded to the length of N
+    pub fn public_key(&self) -> Vec<u8> {
+        pad(&self.public)
+    }
+
+    /// Checks the proof M1 of the client for its public key A. Returns the shared secret K and
+    /// the proof M2 of the server.
+    pub fn verify(&self, client_public: &[u8], client_proof: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+        let n = n();
+        let a = BigUint::from_bytes_be(client_public);
+        if (&a % &n) == BigUint::ZERO {
+            return None;
+        }
+        let b = self.public_key();
+        let u = BigUint::from_bytes_be(&hash(&[&pad(&a), &b]));
+        let s = (a * self.verifier.modpow(&u, &n)).modpow(&self.private, &n);
+        let key = hash(&[&pad(&s)]);
+        let proof = client_proof_for(&self.salt, client_public, &b, &key);
+        // In constant time, so that the timing doesn't tell how much of a guessed proof matches
+        if !bool::from(proof.ct_eq(client_proof)) {
+            return None;
+        }
+        let server_proof = hash(&[client_public, &proof, &key]);
+        Some((key, server_proof))
+    }
+}
+
+/// M1 = H(H(N) xor H(g) | H(I) | s | A | B | K)
+fn client_proof_for(salt: &[u8], client_public: &[u8], server_public: &[u8], key: &[u8]) -> Vec<u8> {
+    let hash_n = hash(&[&n().to_bytes_be()]);
+    let hash_g = hash(&[&BigUint::from(G).to_bytes_be()]);
+    let xor: Vec<u8> = hash_n.iter().zip(&hash_g).map(|(a, b)| a ^ b).collect();
+    hash(&[
+        &xor,
+        &hash(&[USERNAME.as_bytes()]),
+        salt,
+        client_public,
+        server_public,
+        key,
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The client side of the exchange, as done by the controller.
+    fn client(password: &str, salt: &[u8], server_public: &[u8], private: &[u8]) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let n = n();
+        let a = BigUint::from_bytes_be(private);
+        let public = pad(&BigUint::from(G).modpow(&a, &n));
+        let b = BigUint::from_bytes_be(server_public);
+        let u = BigUint::from_bytes_be(&hash(&[&public, server_public]));
+        let x = x(salt, USERNAME, password);
+        let kgx = (k() * BigUint::from(G).modpow(&x, &n)) % &n;
+        let base = (b + &n - kgx) % &n;
+        let s = base.modpow(&(a + u * x), &n);
+        let key = hash(&[&pad(&s)]);
+        let proof = client_proof_for(salt, &public, server_public, &key);
+        (public, proof, key)
+    }
+
+    #[test]
+    fn exchange() {
+        let salt = [0x42; 16];
+        let server = Server::new("031-45-154", &salt, &[0x17; 32]);
+        assert_eq!(server.public_key().len(), 384);
+
+        let (public, proof, key) = client("031-45-154", &salt, &server.public_key(), &[0x23; 32]);
+        let (server_key, server_proof) = server.verify(&public, &proof).unwrap();
+        assert_eq!(server_key, key);
+        assert_eq!(server_proof, hash(&[&public, &proof, &key]));
+
+        let (public, proof, _) = client("031-45-155", &salt, &server.public_key(), &[0x23; 32]);
+        assert!(server.verify(&public, &proof).is_none());
+        assert!(server.verify(&pad(&n()), &proof).is_none());
+    }
+}
@@ -0,0 +1,184 @@
+//! What the bridge has to remember across restarts: its identity, the paired controllers and
+//! the accessory database, which must not change without changing the configuration number.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::{Path, PathBuf},
+};
+
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::accessory::Service;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Pairing {
+    /// Long term public key of the controller, hex
+    pub public_key: String,
+    pub admin: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Store {
+    /// The pairing identifier of the bridge, formatted like a MAC address
+    pub device_id: String,
+    /// Seed of the long term Ed25519 key of the bridge, hex
+    secret_key: String,
+    #[serde(default)]
+    pub pairings: BTreeMap<String, Pairing>,
+    /// Has to change whenever the accessory database changes
+    #[serde(default = "first_config_number")]
+    pub config_number: u32,
+    /// Accessory ids by device address
+    #[serde(default)]
+    pub accessories: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub services: BTreeMap<String, BTreeSet<Service>>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+fn first_config_number() -> u32 {
+    1
+}
+
+impl Store {
+    /// Loads the store, a new identity is created if it does not exist.
+    pub fn load(path: &Path) -> io::Result<Store> {
+        let mut store = match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Store::generate(),
+            Err(err) => return Err(err),
+        };
+        store.path = path.to_path_buf();
+        if decode_key(&store.secret_key).is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid secret key"));
+        }
+        Ok(store)
+    }
+
+    /// A new identity without pairings.
+    pub fn generate() -> Store {
+        let mut id = [0u8; 6];
+        let mut seed = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut id);
+        rand::thread_rng().fill_bytes(&mut seed);
+        Store {
+            device_id: id.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":"),
+            secret_key: hex(&seed),
+            pairings: BTreeMap::new(),
+            config_number: first_config_number(),
+            accessories: BTreeMap::new(),
+            services: BTreeMap::new(),
+            path: PathBuf::new(),
+        }
+    }
+
+    /// Saves the store, the file is replaced atomically.
+    pub fn save(&self) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(tmp, &self.path)
+    }
+
+    pub fn signing_key(&self) -> SigningKey {
+        SigningKey::from_bytes(&decode_key(&self.secret_key).expect("key checked when loading"))
+    }
+
+    pub fn is_paired(&self) -> bool {
+        !self.pairings.is_empty()
+    }
+
+    pub fn public_key(&self, controller: &str) -> Option<VerifyingKey> {
+        let pairing = self.pairings.get(controller)?;
+        VerifyingKey::from_bytes(&decode_key(&pairing.public_key)?).ok()
+    }
+
+    pub fn is_admin(&self, controller: &str) -> bool {
+        self.pairings.get(controller).is_some_and(|pairing| pairing.admin)
+    }
+
+    pub fn add_pairing(&mut self, controller: &str, public_key: &[u8], admin: bool) {
+        self.pairings.insert(
+            controller.to_string(),
+            Pairing {
+                public_key: hex(public_key),
+                admin,
+            },
+        );
+    }
+
+    /// Removes a controller, without admins left all pairings are removed.
+    pub fn remove_pairing(&mut self, controller: &str) {
+        self.pairings.remove(controller);
+        if !self.pairings.values().any(|pairing| pairing.admin) {
+            self.pairings.clear();
+        }
+    }
+
+    /// The accessory id of a device, new devices get the next free one, 1 is the bridge.
+    pub fn aid(&mut self, address: &str) -> u64 {
+        if let Some(aid) = self.accessories.get(address) {
+            return *aid;
+        }
+        let aid = self.accessories.values().max().map_or(2, |aid| aid + 1);
+        self.accessories.insert(address.to_string(), aid);
+        aid
+    }
+}
+
+pub fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_key(text: &str) -> Option<[u8; 32]> {
+    let mut key = [0; 32];
+    if text.len() != 64 {
+        return None;
+    }
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn persisted_identity() {
+        let path = std::env::temp_dir().join(format!("bthome-homekit-test-{}.json", std::process::id()));
+        let mut store = Store::load(&path).unwrap();
+        assert_eq!(store.device_id.len(), 17);
+        assert_eq!(store.aid("A4:C1:38:12:34:56"), 2);
+        assert_eq!(store.aid("11:22:33:44:55:66"), 3);
+        assert_eq!(store.aid("A4:C1:38:12:34:56"), 2);
+        store.add_pairing("controller", store.signing_key().verifying_key().as_bytes(), true);
+        store.save().unwrap();
+
+        let restored = Store::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored.device_id, store.device_id);
+        assert_eq!(restored.signing_key(), store.signing_key());
+        assert_eq!(
+            restored.public_key("controller"),
+            Some(store.signing_key().verifying_key())
+        );
+        assert!(restored.is_admin("controller"));
+    }
+
+    #[test]
+    fn remove_pairings() {
+        let mut store = Store::generate();
+        store.add_pairing("admin", &[1; 32], true);
+        store.add_pairing("guest", &[2; 32], false);
+        store.remove_pairing("guest");
+        assert!(store.is_paired());
+        store.add_pairing("guest", &[2; 32], false);
+        store.remove_pairing("admin");
+        assert!(!store.is_paired());
+    }
+}
@@ -0,0 +1,80 @@
+//! The TLV8 encoding of the pairing messages: a type byte, a length byte and up to 255 bytes of
+//! value, longer values are split into consecutive items of the same type.
+
+use std::collections::BTreeMap;
+
+pub const METHOD: u8 = 0x00;
+pub const IDENTIFIER: u8 = 0x01;
+pub const SALT: u8 = 0x02;
+pub const PUBLIC_KEY: u8 = 0x03;
+pub const PROOF: u8 = 0x04;
+pub const ENCRYPTED_DATA: u8 = 0x05;
+pub const STATE: u8 = 0x06;
+pub const ERROR: u8 = 0x07;
+pub const SIGNATURE: u8 = 0x0A;
+pub const PERMISSIONS: u8 = 0x0B;
+pub const SEPARATOR: u8 = 0xFF;
+
+pub const ERROR_UNKNOWN: u8 = 0x01;
+pub const ERROR_AUTHENTICATION: u8 = 0x02;
+pub const ERROR_MAX_PEERS: u8 = 0x04;
+pub const ERROR_UNAVAILABLE: u8 = 0x06;
+
+/// Decoded items, by type.
+pub type Items = BTreeMap<u8, Vec<u8>>;
+
+/// Encodes the items in order, an item with an empty value is encoded as such, e.g. separators.
+pub fn encode(items: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (kind, value) in items {
+        if value.is_empty() {
+            out.extend([*kind, 0]);
+        }
+        for chunk in value.chunks(255) {
+            out.push(*kind);
+            out.push(chunk.len() as u8);
+            out.extend_from_slice(chunk);
+        }
+    }
+    out
+}
+
+/// Decodes the items by type, fragments of a value are joined. Returns `None` if an item is
+/// truncated.
+pub fn decode(data: &[u8]) -> Option<Items> {
+    let mut items = Items::new();
+    let mut previous = None;
+    let mut rest = data;
+    while let [kind, length, tail @ ..] = rest {
+        let value = tail.get(..*length as usize)?;
+        if previous == Some(*kind) {
+            items.get_mut(kind)?.extend_from_slice(value);
+        } else {
+            items.insert(*kind, value.to_vec());
+        }
+        previous = Some(*kind);
+        rest = &tail[*length as usize..];
+    }
+    if !rest.is_empty() {
+        return None;
+    }
+    Some(items)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let long = vec![7; 300];
+        let data = encode(&[(STATE, &[2]), (PUBLIC_KEY, &long)]);
+        assert_eq!(&data[..5], &[STATE, 1, 2, PUBLIC_KEY, 255]);
+        assert_eq!(data.len(), 3 + 2 + 255 + 2 + 45);
+        let items = decode(&data).unwrap();
+        assert_eq!(items[&STATE], vec![2]);
+        assert_eq!(items[&PUBLIC_KEY], long);
+        assert!(decode(&[STATE, 2, 1]).is_none());
+        assert_eq!(encode(&[(SEPARATOR, &[])]), vec![SEPARATOR, 0]);
+    }
+}
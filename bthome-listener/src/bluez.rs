@@ -0,0 +1,341 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant, SystemTime},
+};
+
+use bluer::{
+    monitor::{Monitor, MonitorEvent, MonitorHandle, Pattern, RssiSamplingPeriod},
+    Adapter, AdapterEvent, Address, AddressType, DeviceEvent, DeviceProperty, DiscoveryFilter, DiscoveryTransport,
+    Session, Uuid,
+};
+use bthome::{parse_service_data, ServiceData, BTHOME_UUID, BTHOME_UUID16};
+use futures::{future, stream, Stream, StreamExt};
+use tokio::{
+    sync::mpsc::{self, UnboundedSender},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+
+use crate::Backoff;
+
+const SERVICE_DATA_UUID16: u8 = 0x16;
+
+/// How advertisements are received from BlueZ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScanMode {
+    /// Use the advertisement monitor if BlueZ supports it, otherwise fall back to discovery
+    #[default]
+    Auto,
+    /// Use the advertisement monitor API, which scans passively, requires BlueZ experimental features
+    Monitor,
+    /// Use regular device discovery, which scans actively, and watch the service data property.
+    /// Works with every BlueZ, but devices receive scan requests.
+    Discovery,
+}
+
+/// Settings of the advertisement monitor. High thresholds report only nearby devices and save the
+/// battery of the host, the default is to report every advertisement of every device in range.
+/// BlueZ rejects monitors with only one of the thresholds.
+#[derive(Debug, Clone, Default)]
+pub struct MonitorSettings {
+    pub rssi_low_threshold: Option<i16>,
+    pub rssi_high_threshold: Option<i16>,
+    pub rssi_low_timeout: Option<Duration>,
+    pub rssi_high_timeout: Option<Duration>,
+    /// Which advertisements of a matched device are reported, all by default
+    pub rssi_sampling_period: Option<RssiSamplingPeriod>,
+}
+
+/// BTHome service data as received, e.g. to decrypt or record it.
+#[derive(Debug, Clone)]
+pub struct Advertisement {
+    /// The adapter that received the advertisement, e.g. `hci0`
+    pub adapter: String,
+    pub mac: Address,
+    pub address_type: Option<AddressType>,
+    /// Signal strength in dBm, if known
+    pub rssi: Option<i16>,
+    pub received: SystemTime,
+    pub service_data: Vec<u8>,
+}
+
+/// A decoded advertisement.
+#[derive(Debug, Clone)]
+pub struct BtHomeEvent {
+    pub mac: Address,
+    /// Signal strength in dBm, if known
+    pub rssi: Option<i16>,
+    pub data: ServiceData,
+}
+
+/// Scans for BTHome devices on a BlueZ adapter.
+pub struct Listener {
+    adapter: Adapter,
+    scan_mode: ScanMode,
+    monitor: MonitorSettings,
+}
+
+impl Listener {
+    /// Listens on `adapter`, with the advertisement monitor if available and without thresholds.
+    pub fn new(adapter: Adapter) -> Listener {
+        Listener {
+            adapter,
+            scan_mode: ScanMode::default(),
+            monitor: MonitorSettings::default(),
+        }
+    }
+
+    /// Listens on the default adapter of BlueZ, usually `hci0`.
+    pub async fn default_adapter() -> bluer::Result<Listener> {
+        let session = Session::new().await?;
+        Ok(Listener::new(session.default_adapter().await?))
+    }
+
+    /// Listens on the adapter called `name`, e.g. `hci1`.
+    pub async fn named_adapter(name: &str) -> bluer::Result<Listener> {
+        let session = Session::new().await?;
+        Ok(Listener::new(session.adapter(name)?))
+    }
+
+    pub fn scan_mode(mut self, scan_mode: ScanMode) -> Listener {
+        self.scan_mode = scan_mode;
+        self
+    }
+
+    pub fn monitor(mut self, settings: MonitorSettings) -> Listener {
+        self.monitor = settings;
+        self
+    }
+
+    pub fn adapter(&self) -> &Adapter {
+        &self.adapter
+    }
+
+    /// The BTHome service data of every advertisement received. Scanning starts in the background
+    /// right away and stops when the stream is dropped.
+    pub fn advertisements(self) -> impl Stream<Item = Advertisement> + Send + Unpin + 'static {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            tokio::select! {
+                () = self.run(&tx) => {}
+                () = tx.closed() => {}
+            }
+        });
+        stream::poll_fn(move |cx| rx.poll_recv(cx))
+    }
+
+    /// The advertisements that decode, encrypted ones and ones failing to parse are skipped.
+    pub fn events(self) -> impl Stream<Item = BtHomeEvent> + Send + Unpin + 'static {
+        self.advertisements().filter_map(|advertisement| {
+            let event = match parse_service_data(&advertisement.service_data) {
+                Ok(data) => Some(BtHomeEvent {
+                    mac: advertisement.mac,
+                    rssi: advertisement.rssi,
+                    data,
+                }),
+                Err(err) => {
+                    debug!(device = %advertisement.mac, error = %err, "Skipping advertisement");
+                    None
+                }
+            };
+            future::ready(event)
+        })
+    }
+
+    /// Scans until the receiver is dropped, restarting with increasing delays when scanning stops.
+    async fn run(&self, tx: &UnboundedSender<Advertisement>) {
+        let name = self.adapter.name();
+        let mut backoff = Backoff::new();
+        while !tx.is_closed() {
+            let started = Instant::now();
+            let result = self.scan(tx.clone()).await;
+            if tx.is_closed() {
+                break;
+            }
+            let delay = backoff.next(started);
+            match result {
+                Ok(()) => warn!(adapter = name, ?delay, "Receiving advertisements stopped, restarting"),
+                Err(err) => warn!(adapter = name, error = %err, ?delay, "Receiving advertisements failed, restarting"),
+            }
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Scans for BTHome devices and forwards all received BTHome service data to `tx`.
+    async fn scan(&self, tx: UnboundedSender<Advertisement>) -> bluer::Result<()> {
+        let adapter = self.adapter.clone();
+        adapter.set_powered(true).await?;
+
+        match self.scan_mode {
+            ScanMode::Monitor => {
+                let monitor_handle = register_monitor(&adapter, &self.monitor).await?;
+                run_monitor(adapter, monitor_handle, tx).await
+            }
+            ScanMode::Discovery => run_discovery(adapter, tx).await,
+            ScanMode::Auto => match register_monitor(&adapter, &self.monitor).await {
+                Ok(monitor_handle) => run_monitor(adapter, monitor_handle, tx).await,
+                Err(err) => {
+                    warn!(adapter = adapter.name(), error = %err, "Advertisement monitor not available, falling back to discovery");
+                    run_discovery(adapter, tx).await
+                }
+            },
+        }
+    }
+}
+
+async fn register_monitor(adapter: &Adapter, settings: &MonitorSettings) -> bluer::Result<MonitorHandle> {
+    let patterns = vec![
+        Pattern { data_type: SERVICE_DATA_UUID16, start_position: 0x00, content: BTHOME_UUID16.to_le_bytes().to_vec() }
+    ];
+
+    let mm = adapter.monitor().await?;
+    mm.register(Monitor {
+        monitor_type: bluer::monitor::Type::OrPatterns,
+        rssi_low_threshold: settings.rssi_low_threshold,
+        rssi_high_threshold: settings.rssi_high_threshold,
+        rssi_low_timeout: settings.rssi_low_timeout,
+        rssi_high_timeout: settings.rssi_high_timeout,
+        rssi_sampling_period: Some(settings.rssi_sampling_period.unwrap_or(RssiSamplingPeriod::All)),
+        patterns: Some(patterns),
+        ..Default::default()
+    })
+    .await
+}
+
+async fn run_monitor(
+    adapter: Adapter,
+    mut monitor_handle: MonitorHandle,
+    tx: UnboundedSender<Advertisement>,
+) -> bluer::Result<()> {
+    let mut sessions = Sessions::default();
+    while let Some(mevt) = monitor_handle.next().await {
+        if let MonitorEvent::DeviceFound(devid) = &mevt {
+            device_found(&adapter, devid.device, &tx, &mut sessions).await?;
+        } else if let MonitorEvent::DeviceLost(devid) = &mevt {
+            sessions.stop(&devid.device);
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_discovery(adapter: Adapter, tx: UnboundedSender<Advertisement>) -> bluer::Result<()> {
+    // BlueZ only matches the UUID filter against advertised service UUIDs and not against
+    // service data, so all LE devices are watched and filtered by their service data.
+    adapter
+        .set_discovery_filter(DiscoveryFilter {
+            transport: DiscoveryTransport::Le,
+            duplicate_data: true,
+            ..Default::default()
+        })
+        .await?;
+
+    let mut sessions = Sessions::default();
+    let mut events = adapter.discover_devices().await?;
+    while let Some(evt) = events.next().await {
+        match evt {
+            AdapterEvent::DeviceAdded(address) => device_found(&adapter, address, &tx, &mut sessions).await?,
+            AdapterEvent::DeviceRemoved(address) => sessions.stop(&address),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// The tasks watching the properties of the devices found on one adapter, there is exactly one
+/// per device as long as BlueZ knows the device.
+#[derive(Default)]
+struct Sessions {
+    watchers: HashMap<Address, JoinHandle<()>>,
+}
+
+impl Sessions {
+    fn is_watching(&self, address: &Address) -> bool {
+        self.watchers.get(address).is_some_and(|watcher| !watcher.is_finished())
+    }
+
+    fn start(&mut self, address: Address, watcher: JoinHandle<()>) {
+        if let Some(previous) = self.watchers.insert(address, watcher) {
+            previous.abort();
+        }
+    }
+
+    /// Stops watching a device, e.g. because BlueZ lost or removed it.
+    fn stop(&mut self, address: &Address) {
+        if let Some(watcher) = self.watchers.remove(address) {
+            watcher.abort();
+        }
+    }
+}
+
+impl Drop for Sessions {
+    fn drop(&mut self) {
+        for watcher in self.watchers.values() {
+            watcher.abort();
+        }
+    }
+}
+
+/// Reports the current service data of a newly found device and starts watching it for changes,
+/// unless it is already watched.
+async fn device_found(
+    adapter: &Adapter,
+    address: Address,
+    tx: &UnboundedSender<Advertisement>,
+    sessions: &mut Sessions,
+) -> bluer::Result<()> {
+    if sessions.is_watching(&address) {
+        return Ok(());
+    }
+    let bthome_uuid = Uuid::from_u128(BTHOME_UUID);
+
+    let dev = adapter.device(address)?;
+    let address_type = dev.address_type().await.ok();
+    if let Ok(Some(service_data)) = dev.service_data().await {
+        if let Some(bthome_data) = service_data.get(&bthome_uuid) {
+            let name = dev.name().await?;
+            info!(adapter = adapter.name(), device = %address, ?name, "Discovered BTHome device");
+            let _ = tx.send(Advertisement {
+                adapter: adapter.name().to_string(),
+                mac: address,
+                address_type,
+                rssi: dev.rssi().await.ok().flatten(),
+                received: SystemTime::now(),
+                service_data: bthome_data.clone(),
+            });
+        }
+    }
+
+    let mut events = dev.events().await?;
+    let tx = tx.clone();
+    let adapter_name = adapter.name().to_string();
+    let watcher = tokio::spawn(async move {
+        let mut rssi = dev.rssi().await.ok().flatten();
+        while let Some(ev) = events.next().await {
+            let DeviceEvent::PropertyChanged(dp) = ev;
+            match dp {
+                DeviceProperty::Rssi(value) => rssi = Some(value),
+                DeviceProperty::ServiceData(data) => {
+                    if let Some(raw_data) = data.get(&bthome_uuid) {
+                        let advertisement = Advertisement {
+                            adapter: adapter_name.clone(),
+                            mac: address,
+                            address_type,
+                            rssi,
+                            received: SystemTime::now(),
+                            service_data: raw_data.clone(),
+                        };
+                        if tx.send(advertisement).is_err() {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    });
+    sessions.start(address, watcher);
+
+    Ok(())
+}
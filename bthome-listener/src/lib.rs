@@ -0,0 +1,70 @@
+//! The BTHome advertisements received by a Bluetooth adapter through BlueZ as a stream, for
+//! applications that want the decoded advertisements without dealing with the discovery:
+//!
+//! ```ignore
+//! use bthome_listener::Listener;
+//! use futures::StreamExt;
+//!
+//! let mut events = Listener::default_adapter().await?.events();
+//! while let Some(event) = events.next().await {
+//!     println!("{} at {:?} dBm: {:?}", event.mac, event.rssi, event.data.objects);
+//! }
+//! ```
+//!
+//! Scanning is restarted with increasing delays when it fails, e.g. because the adapter was
+//! unplugged or bluetoothd restarted, so the streams only end when they are dropped. The listener
+//! is only available on Linux, [`Backoff`] on all platforms.
+
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "linux")]
+mod bluez;
+
+#[cfg(target_os = "linux")]
+pub use bluez::{Advertisement, BtHomeEvent, Listener, MonitorSettings, ScanMode};
+
+/// Delays between attempts to restart receiving that stopped, doubling after each failed attempt.
+pub struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    const INITIAL: Duration = Duration::from_secs(1);
+    const MAX: Duration = Duration::from_secs(60);
+
+    pub fn new() -> Self {
+        Backoff { delay: Self::INITIAL }
+    }
+
+    /// Returns how long to wait before the next attempt, given when the last attempt started.
+    ///
+    /// If the last attempt ran for a while it is considered successful and the delay is reset.
+    pub fn next(&mut self, started: Instant) -> Duration {
+        if started.elapsed() >= Self::MAX {
+            self.delay = Self::INITIAL;
+        }
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(Self::MAX);
+        delay
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let mut backoff = Backoff::new();
+        let now = Instant::now();
+        let delays: Vec<_> = (0..8).map(|_| backoff.next(now).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(backoff.next(now - Backoff::MAX), Backoff::INITIAL);
+    }
+}
@@ -0,0 +1,412 @@
+//! The data model of a Matter bridge for BTHome devices: an aggregator endpoint with one bridged
+//! node per device, which has a child endpoint per sensor the device reported.
+//!
+//! This is experimental and only the data model, there is no commissioning, no secure session
+//! and no interaction model yet. It is meant to be plugged into a Matter stack, which reads the
+//! endpoints and attributes and reports the changes returned by [`Bridge::update`].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bthome::{Object, ObjectValue};
+
+/// The root node is endpoint 0, the aggregator of the bridged devices is endpoint 1.
+pub const ROOT_ENDPOINT: u16 = 0;
+pub const AGGREGATOR_ENDPOINT: u16 = 1;
+
+pub mod device_type {
+    pub const AGGREGATOR: u32 = 0x000E;
+    pub const BRIDGED_NODE: u32 = 0x0013;
+    pub const POWER_SOURCE: u32 = 0x0011;
+    pub const CONTACT_SENSOR: u32 = 0x0015;
+    pub const LIGHT_SENSOR: u32 = 0x0106;
+    pub const OCCUPANCY_SENSOR: u32 = 0x0107;
+    pub const TEMPERATURE_SENSOR: u32 = 0x0302;
+    pub const HUMIDITY_SENSOR: u32 = 0x0307;
+}
+
+/// Clusters and the attributes of them that are maintained.
+pub mod cluster {
+    pub const DESCRIPTOR: u32 = 0x001D;
+    pub const POWER_SOURCE: u32 = 0x002F;
+    pub const BRIDGED_DEVICE_BASIC_INFORMATION: u32 = 0x0039;
+    pub const BOOLEAN_STATE: u32 = 0x0045;
+    pub const ILLUMINANCE_MEASUREMENT: u32 = 0x0400;
+    pub const TEMPERATURE_MEASUREMENT: u32 = 0x0402;
+    pub const RELATIVE_HUMIDITY_MEASUREMENT: u32 = 0x0405;
+    pub const OCCUPANCY_SENSING: u32 = 0x0406;
+
+    /// Descriptor
+    pub const PARTS_LIST: u32 = 0x0003;
+    /// Bridged device basic information
+    pub const NODE_LABEL: u32 = 0x0005;
+    pub const REACHABLE: u32 = 0x0011;
+    pub const UNIQUE_ID: u32 = 0x0012;
+    /// Power source, the remaining battery in half percent and OK, warning or critical
+    pub const BAT_PERCENT_REMAINING: u32 = 0x000C;
+    pub const BAT_CHARGE_LEVEL: u32 = 0x000E;
+    /// Measured value of the measurement clusters, boolean state and occupancy
+    pub const MEASURED_VALUE: u32 = 0x0000;
+}
+
+/// The sensors a BTHome device can be bridged with, each gets its own endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Sensor {
+    Temperature,
+    Humidity,
+    Contact,
+    Occupancy,
+    Light,
+    Battery,
+}
+
+impl Sensor {
+    pub fn device_type(self) -> u32 {
+        match self {
+            Sensor::Temperature => device_type::TEMPERATURE_SENSOR,
+            Sensor::Humidity => device_type::HUMIDITY_SENSOR,
+            Sensor::Contact => device_type::CONTACT_SENSOR,
+            Sensor::Occupancy => device_type::OCCUPANCY_SENSOR,
+            Sensor::Light => device_type::LIGHT_SENSOR,
+            Sensor::Battery => device_type::POWER_SOURCE,
+        }
+    }
+
+    pub fn cluster(self) -> u32 {
+        match self {
+            Sensor::Temperature => cluster::TEMPERATURE_MEASUREMENT,
+            Sensor::Humidity => cluster::RELATIVE_HUMIDITY_MEASUREMENT,
+            Sensor::Contact => cluster::BOOLEAN_STATE,
+            Sensor::Occupancy => cluster::OCCUPANCY_SENSING,
+            Sensor::Light => cluster::ILLUMINANCE_MEASUREMENT,
+            Sensor::Battery => cluster::POWER_SOURCE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    /// Unsigned integers, enums and bitmaps
+    Uint(u64),
+    Int(i64),
+    String(String),
+    /// Endpoint lists like the parts list
+    Endpoints(Vec<u16>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AttributePath {
+    pub endpoint: u16,
+    pub cluster: u32,
+    pub attribute: u32,
+}
+
+/// An attribute that changed and has to be reported to subscribers.
+pub type Change = (AttributePath, Value);
+
+#[derive(Debug, PartialEq)]
+pub struct Endpoint {
+    pub id: u16,
+    pub parent: Option<u16>,
+    pub device_types: Vec<u32>,
+    pub clusters: Vec<u32>,
+}
+
+struct Device {
+    name: String,
+    endpoint: u16,
+    sensors: BTreeMap<Sensor, u16>,
+}
+
+/// The endpoints of the bridged devices and their attribute values.
+pub struct Bridge {
+    devices: BTreeMap<String, Device>,
+    attributes: BTreeMap<AttributePath, Value>,
+    next_endpoint: u16,
+}
+
+impl Default for Bridge {
+    fn default() -> Self {
+        Bridge::new()
+    }
+}
+
+impl Bridge {
+    pub fn new() -> Bridge {
+        let mut bridge = Bridge {
+            devices: BTreeMap::new(),
+            attributes: BTreeMap::new(),
+            next_endpoint: AGGREGATOR_ENDPOINT + 1,
+        };
+        bridge.attributes.insert(parts_list(ROOT_ENDPOINT), Value::Endpoints(vec![AGGREGATOR_ENDPOINT]));
+        bridge.attributes.insert(parts_list(AGGREGATOR_ENDPOINT), Value::Endpoints(vec![]));
+        bridge
+    }
+
+    /// Applies the objects of an advertisement of the device with `address`, endpoints are
+    /// added for new devices and sensors. Returns the attributes that changed.
+    pub fn update(&mut self, address: &str, name: &str, objects: Vec<Object>) -> Vec<Change> {
+        let mut changes = Vec::new();
+        if !self.devices.contains_key(address) {
+            let endpoint = self.allocate();
+            self.devices.insert(
+                address.to_string(),
+                Device {
+                    name: name.to_string(),
+                    endpoint,
+                    sensors: BTreeMap::new(),
+                },
+            );
+            let info = |attribute| AttributePath {
+                endpoint,
+                cluster: cluster::BRIDGED_DEVICE_BASIC_INFORMATION,
+                attribute,
+            };
+            self.set(info(cluster::NODE_LABEL), Value::String(name.to_string()), &mut changes);
+            self.set(info(cluster::UNIQUE_ID), Value::String(address.to_string()), &mut changes);
+            self.set(info(cluster::REACHABLE), Value::Bool(true), &mut changes);
+            self.set(parts_list(endpoint), Value::Endpoints(vec![]), &mut changes);
+            self.update_parts_lists(&mut changes);
+        } else {
+            self.set_reachable(address, true, &mut changes);
+        }
+        for object in objects {
+            for (sensor, attribute, value) in attribute_values(object) {
+                let endpoint = self.sensor_endpoint(address, sensor, &mut changes);
+                let path = AttributePath {
+                    endpoint,
+                    cluster: sensor.cluster(),
+                    attribute,
+                };
+                self.set(path, value, &mut changes);
+            }
+        }
+        changes
+    }
+
+    /// Marks a device as unreachable, e.g. after it was not heard from for a while.
+    pub fn unreachable(&mut self, address: &str) -> Vec<Change> {
+        let mut changes = Vec::new();
+        self.set_reachable(address, false, &mut changes);
+        changes
+    }
+
+    pub fn attribute(&self, path: &AttributePath) -> Option<&Value> {
+        self.attributes.get(path)
+    }
+
+    pub fn attributes(&self) -> impl Iterator<Item = (&AttributePath, &Value)> {
+        self.attributes.iter()
+    }
+
+    /// All endpoints with their device types and server clusters.
+    pub fn endpoints(&self) -> Vec<Endpoint> {
+        let mut endpoints = vec![
+            Endpoint {
+                id: AGGREGATOR_ENDPOINT,
+                parent: Some(ROOT_ENDPOINT),
+                device_types: vec![device_type::AGGREGATOR],
+                clusters: vec![cluster::DESCRIPTOR],
+            },
+        ];
+        for device in self.devices.values() {
+            endpoints.push(Endpoint {
+                id: device.endpoint,
+                parent: Some(AGGREGATOR_ENDPOINT),
+                device_types: vec![device_type::BRIDGED_NODE],
+                clusters: vec![cluster::DESCRIPTOR, cluster::BRIDGED_DEVICE_BASIC_INFORMATION],
+            });
+            for (sensor, endpoint) in &device.sensors {
+                endpoints.push(Endpoint {
+                    id: *endpoint,
+                    parent: Some(device.endpoint),
+                    device_types: vec![sensor.device_type()],
+                    clusters: vec![cluster::DESCRIPTOR, sensor.cluster()],
+                });
+            }
+        }
+        endpoints.sort_by_key(|endpoint| endpoint.id);
+        endpoints
+    }
+
+    /// The names of the bridged devices by address.
+    pub fn devices(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.devices.iter().map(|(address, device)| (address.as_str(), device.name.as_str()))
+    }
+
+    fn allocate(&mut self) -> u16 {
+        let endpoint = self.next_endpoint;
+        self.next_endpoint += 1;
+        endpoint
+    }
+
+    fn sensor_endpoint(&mut self, address: &str, sensor: Sensor, changes: &mut Vec<Change>) -> u16 {
+        if let Some(endpoint) = self.devices[address].sensors.get(&sensor) {
+            return *endpoint;
+        }
+        let endpoint = self.allocate();
+        let device = self.devices.get_mut(address).expect("device was added");
+        device.sensors.insert(sensor, endpoint);
+        let device_endpoint = device.endpoint;
+        let children: Vec<u16> = device.sensors.values().copied().collect();
+        self.set(parts_list(device_endpoint), Value::Endpoints(children), changes);
+        self.set(parts_list(endpoint), Value::Endpoints(vec![]), changes);
+        self.update_parts_lists(changes);
+        endpoint
+    }
+
+    /// The aggregator lists every endpoint below it, devices and their sensors, and the root
+    /// node every endpoint but itself.
+    fn update_parts_lists(&mut self, changes: &mut Vec<Change>) {
+        let mut below: BTreeSet<u16> = BTreeSet::new();
+        for device in self.devices.values() {
+            below.insert(device.endpoint);
+            below.extend(device.sensors.values());
+        }
+        let mut all = vec![AGGREGATOR_ENDPOINT];
+        all.extend(&below);
+        self.set(parts_list(AGGREGATOR_ENDPOINT), Value::Endpoints(below.into_iter().collect()), changes);
+        self.set(parts_list(ROOT_ENDPOINT), Value::Endpoints(all), changes);
+    }
+
+    fn set_reachable(&mut self, address: &str, reachable: bool, changes: &mut Vec<Change>) {
+        let Some(device) = self.devices.get(address) else {
+            return;
+        };
+        let path = AttributePath {
+            endpoint: device.endpoint,
+            cluster: cluster::BRIDGED_DEVICE_BASIC_INFORMATION,
+            attribute: cluster::REACHABLE,
+        };
+        self.set(path, Value::Bool(reachable), changes);
+    }
+
+    fn set(&mut self, path: AttributePath, value: Value, changes: &mut Vec<Change>) {
+        if self.attributes.get(&path) != Some(&value) {
+            self.attributes.insert(path, value.clone());
+            changes.push((path, value));
+        }
+    }
+}
+
+fn parts_list(endpoint: u16) -> AttributePath {
+    AttributePath {
+        endpoint,
+        cluster: cluster::DESCRIPTOR,
+        attribute: cluster::PARTS_LIST,
+    }
+}
+
+/// The sensor, attribute and Matter value for a BTHome object.
+fn attribute_values(object: Object) -> Vec<(Sensor, u32, Value)> {
+    let id = object.object_id as u8;
+    let number = match object.value {
+        ObjectValue::Float(value) => Some(value),
+        ObjectValue::Int(value) => Some(value as f64),
+        _ => None,
+    };
+    let flag = match object.value {
+        ObjectValue::Bool(value) => Some(value),
+        _ => None,
+    };
+    match (id, number, flag) {
+        // 0.01 °C
+        (0x02 | 0x45 | 0x57 | 0x58, Some(celsius), _) => vec![(
+            Sensor::Temperature,
+            cluster::MEASURED_VALUE,
+            Value::Int((celsius * 100.0).round().clamp(-27315.0, 32767.0) as i64),
+        )],
+        // 0.01 %
+        (0x03 | 0x2E, Some(percent), _) => vec![(
+            Sensor::Humidity,
+            cluster::MEASURED_VALUE,
+            Value::Uint((percent * 100.0).round().clamp(0.0, 10000.0) as u64),
+        )],
+        // 10000 * log10(lux) + 1, 0 means too low to measure
+        (0x05, Some(lux), _) => {
+            let value = if lux < 1.0 { 0 } else { (10000.0 * lux.log10() + 1.0).min(65534.0) as u64 };
+            vec![(Sensor::Light, cluster::MEASURED_VALUE, Value::Uint(value))]
+        }
+        // Door, garage door, window and generic opening, the state is true while closed
+        (0x1A | 0x1B | 0x2D | 0x11, _, Some(open)) => {
+            vec![(Sensor::Contact, cluster::MEASURED_VALUE, Value::Bool(!open))]
+        }
+        // The occupied bit of the bitmap
+        (0x21 | 0x23, _, Some(occupied)) => {
+            vec![(Sensor::Occupancy, cluster::MEASURED_VALUE, Value::Uint(u64::from(occupied)))]
+        }
+        (0x01, Some(percent), _) => vec![
+            (
+                Sensor::Battery,
+                cluster::BAT_PERCENT_REMAINING,
+                Value::Uint((percent.clamp(0.0, 100.0) * 2.0) as u64),
+            ),
+            (Sensor::Battery, cluster::BAT_CHARGE_LEVEL, Value::Uint(u64::from(percent < 20.0))),
+        ],
+        (0x15, _, Some(low)) => vec![(Sensor::Battery, cluster::BAT_CHARGE_LEVEL, Value::Uint(u64::from(low)))],
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bthome::{parse_service_data, ObjectId};
+
+    use super::*;
+
+    #[test]
+    fn bridged_endpoints() {
+        let mut bridge = Bridge::new();
+        // Temperature 25 °C, humidity 50.55 %
+        let objects = parse_service_data(&[0x40, 0x02, 0xC4, 0x09, 0x03, 0xBF, 0x13]).unwrap().objects;
+        let changes = bridge.update("A4:C1:38:12:34:56", "Living room", objects);
+        let temperature = AttributePath {
+            endpoint: 3,
+            cluster: cluster::TEMPERATURE_MEASUREMENT,
+            attribute: cluster::MEASURED_VALUE,
+        };
+        assert!(changes.contains(&(temperature, Value::Int(2500))));
+        assert_eq!(
+            bridge.attribute(&AttributePath {
+                endpoint: 4,
+                cluster: cluster::RELATIVE_HUMIDITY_MEASUREMENT,
+                attribute: cluster::MEASURED_VALUE,
+            }),
+            Some(&Value::Uint(5055))
+        );
+        assert_eq!(bridge.attribute(&parts_list(2)), Some(&Value::Endpoints(vec![3, 4])));
+        assert_eq!(bridge.attribute(&parts_list(AGGREGATOR_ENDPOINT)), Some(&Value::Endpoints(vec![2, 3, 4])));
+        assert_eq!(bridge.attribute(&parts_list(ROOT_ENDPOINT)), Some(&Value::Endpoints(vec![1, 2, 3, 4])));
+
+        let endpoints = bridge.endpoints();
+        assert_eq!(endpoints.len(), 4);
+        assert_eq!(endpoints[1].device_types, vec![device_type::BRIDGED_NODE]);
+        assert_eq!(endpoints[2].device_types, vec![device_type::TEMPERATURE_SENSOR]);
+        assert_eq!(endpoints[2].parent, Some(2));
+
+        // Nothing changed
+        let objects = parse_service_data(&[0x40, 0x02, 0xC4, 0x09]).unwrap().objects;
+        assert!(bridge.update("A4:C1:38:12:34:56", "Living room", objects).is_empty());
+        assert_eq!(bridge.unreachable("A4:C1:38:12:34:56").len(), 1);
+    }
+
+    #[test]
+    fn contact_and_battery() {
+        let mut bridge = Bridge::new();
+        let objects = vec![
+            Object::new(ObjectId::Battery, ObjectValue::Int(10)),
+            Object::new(ObjectId::WindowOpen, ObjectValue::Bool(true)),
+        ];
+        let changes = bridge.update("11:22:33:44:55:66", "Window", objects);
+        let values: Vec<&Value> = changes.iter().filter(|(path, _)| path.endpoint == 3).map(|(_, value)| value).collect();
+        assert_eq!(values, vec![&Value::Endpoints(vec![]), &Value::Uint(20), &Value::Uint(1)]);
+        assert!(changes.contains(&(
+            AttributePath {
+                endpoint: 4,
+                cluster: cluster::BOOLEAN_STATE,
+                attribute: cluster::MEASURED_VALUE,
+            },
+            Value::Bool(false)
+        )));
+    }
+}
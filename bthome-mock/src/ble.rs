@@ -0,0 +1,43 @@
+//! Advertising the packets of a virtual device with BlueZ.
+
+use std::collections::BTreeMap;
+
+use bluer::{
+    adv::{Advertisement, AdvertisementHandle, Type},
+    Adapter, Session, Uuid,
+};
+
+pub struct Advertiser {
+    adapter: Adapter,
+    name: String,
+    handle: Option<AdvertisementHandle>,
+}
+
+impl Advertiser {
+    pub async fn new(session: &Session, adapter: Option<&str>, name: &str) -> bluer::Result<Advertiser> {
+        let adapter = match adapter {
+            Some(name) => session.adapter(name)?,
+            None => session.default_adapter().await?,
+        };
+        adapter.set_powered(true).await?;
+        Ok(Advertiser {
+            adapter,
+            name: name.to_string(),
+            handle: None,
+        })
+    }
+
+    /// Replaces the current advertisement, the data of a registered advertisement can't be
+    /// changed.
+    pub async fn update(&mut self, service_data: Vec<u8>) -> bluer::Result<()> {
+        drop(self.handle.take());
+        let advertisement = Advertisement {
+            advertisement_type: Type::Broadcast,
+            service_data: BTreeMap::from([(Uuid::from_u128(bthome::BTHOME_UUID), service_data)]),
+            local_name: Some(self.name.clone()),
+            ..Default::default()
+        };
+        self.handle = Some(self.adapter.advertise(advertisement).await?);
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+//! Emulates BTHome devices following scripted scenarios, to test gateways and Home Assistant
+//! setups without physical hardware.
+
+use std::{collections::HashMap, error::Error, path::PathBuf, time::Instant};
+
+use clap::Parser;
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+#[cfg(target_os = "linux")]
+mod ble;
+mod scenario;
+
+use scenario::{DeviceConfig, Scenario};
+
+/// Advertise virtual BTHome devices, whose values change as described in a scenario file.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// TOML file with the devices and their steps
+    scenario: PathBuf,
+
+    /// Print the packets as hex instead of advertising them
+    #[arg(long)]
+    print: bool,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with_writer(std::io::stderr)
+        .init();
+    let args = Args::parse();
+
+    let content = std::fs::read_to_string(&args.scenario)
+        .map_err(|err| format!("Error reading {}: {}", args.scenario.display(), err))?;
+    let scenario = Scenario::parse(&content)?;
+    if !args.print {
+        let mut adapters: HashMap<Option<&str>, Vec<&str>> = HashMap::new();
+        for device in &scenario.device {
+            adapters.entry(device.adapter.as_deref()).or_default().push(&device.name);
+        }
+        for (adapter, devices) in adapters.iter().filter(|(_, devices)| devices.len() > 1) {
+            warn!(
+                adapter = adapter.unwrap_or("default"),
+                ?devices,
+                "Devices on the same adapter share its address, receivers will see them as one device"
+            );
+        }
+    }
+
+    let start = Instant::now();
+    let devices = futures::future::try_join_all(scenario.device.iter().map(|device| run(device, args.print, start)));
+    tokio::select! {
+        result = devices => {
+            result?;
+            // Keep advertising the last packets
+            if !args.print {
+                tokio::signal::ctrl_c().await?;
+            }
+        }
+        result = tokio::signal::ctrl_c() => result?,
+    }
+    Ok(())
+}
+
+/// Plays the steps of a device, over and over if it repeats.
+async fn run(device: &DeviceConfig, print: bool, start: Instant) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut advertiser = if print { None } else { Some(advertiser(device).await?) };
+    let mut packet_id: u8 = 0;
+    loop {
+        let packets = device.packets(packet_id)?;
+        packet_id = packet_id.wrapping_add(packets.len() as u8);
+        for packet in packets {
+            tokio::time::sleep(packet.delay).await;
+            let hex: String = packet.service_data.iter().map(|b| format!("{:02x}", b)).collect();
+            match &mut advertiser {
+                Some(advertiser) => {
+                    info!(device = device.name, data = hex, "Advertising");
+                    advertise(advertiser, packet.service_data).await?;
+                }
+                None => println!("{:8.1} {} {}", start.elapsed().as_secs_f64(), device.name, hex),
+            }
+        }
+        if !device.repeat {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn advertiser(device: &DeviceConfig) -> Result<ble::Advertiser, Box<dyn Error + Send + Sync>> {
+    let session = bluer::Session::new().await?;
+    Ok(ble::Advertiser::new(&session, device.adapter.as_deref(), &device.name).await?)
+}
+
+#[cfg(target_os = "linux")]
+async fn advertise(advertiser: &mut ble::Advertiser, service_data: Vec<u8>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(advertiser.update(service_data).await?)
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn advertiser(_device: &DeviceConfig) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Err("Advertising is only supported on Linux with bluez, use --print to see the packets".into())
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn advertise(_advertiser: &mut (), _service_data: Vec<u8>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+}
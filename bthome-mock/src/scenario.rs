@@ -0,0 +1,222 @@
+//! Scenarios of virtual devices, a list of steps with the values advertised from then on:
+//!
+//! ```toml
+//! [[device]]
+//! name = "Living room"
+//! repeat = true
+//!
+//! [[device.step]]
+//! temperature = 21.5
+//! humidity = 40
+//!
+//! [[device.step]]
+//! after = 60
+//! temperature = 22
+//!
+//! [[device.step]]
+//! after = 5
+//! button = "press"
+//! ```
+
+use std::{collections::BTreeMap, time::Duration};
+
+use bthome::{encode_service_data, ButtonEvent, DimmerEvent, Object, ObjectId, ObjectValue, ServiceData};
+use serde::Deserialize;
+use toml::Value;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub device: Vec<DeviceConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub name: String,
+    /// Bluetooth adapter to advertise on (default: the system's default adapter)
+    pub adapter: Option<String>,
+    /// Start over after the last step
+    #[serde(default)]
+    pub repeat: bool,
+    /// Only advertise when something happens, like buttons do
+    #[serde(default)]
+    pub trigger_based: bool,
+    pub step: Vec<Step>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Step {
+    /// Seconds after the previous step
+    #[serde(default)]
+    pub after: f64,
+    /// Measurements and events by object name, measurements are kept for the following steps,
+    /// events like button presses are only sent once
+    #[serde(flatten)]
+    pub values: BTreeMap<String, Value>,
+}
+
+/// An advertisement of a device, `delay` after the previous one.
+#[derive(Debug, PartialEq)]
+pub struct Packet {
+    pub delay: Duration,
+    pub service_data: Vec<u8>,
+}
+
+impl Scenario {
+    pub fn parse(content: &str) -> Result<Scenario, String> {
+        let scenario: Scenario = toml::from_str(content).map_err(|err| format!("invalid scenario: {}", err))?;
+        if scenario.device.is_empty() {
+            return Err("the scenario has no devices".to_string());
+        }
+        for device in &scenario.device {
+            device.packets(0).map_err(|err| format!("device {:?}: {}", device.name, err))?;
+        }
+        Ok(scenario)
+    }
+}
+
+impl DeviceConfig {
+    /// The advertisements of one pass through the steps, the packet ids count up from
+    /// `first_packet_id`.
+    pub fn packets(&self, first_packet_id: u8) -> Result<Vec<Packet>, String> {
+        if self.step.is_empty() {
+            return Err("no steps".to_string());
+        }
+        let mut measurements: BTreeMap<u8, ObjectValue> = BTreeMap::new();
+        let mut packets = Vec::new();
+        for (i, step) in self.step.iter().enumerate() {
+            let delay = Duration::try_from_secs_f64(step.after)
+                .map_err(|_| format!("step {}: invalid delay {}", i + 1, step.after))?;
+            let mut events = Vec::new();
+            for (name, value) in &step.values {
+                let id = object_id(name).map_err(|err| format!("step {}: {}", i + 1, err))?;
+                let value = object_value(id, value).map_err(|err| format!("step {}: {}: {}", i + 1, name, err))?;
+                if matches!(value, ObjectValue::ButtonEvent(_) | ObjectValue::DimmerEvent(..)) {
+                    events.push((id, value));
+                } else {
+                    measurements.insert(id, value);
+                }
+            }
+            let packet_id = first_packet_id.wrapping_add(i as u8);
+            let mut objects = vec![Object::new(ObjectId::PacketId, ObjectValue::Int(packet_id as i64))];
+            let mut values: Vec<(u8, ObjectValue)> = measurements.iter().map(|(id, value)| (*id, copy(value))).collect();
+            values.extend(events);
+            values.sort_by_key(|(id, _)| *id);
+            for (id, value) in values {
+                let object_id = ObjectId::try_from(id).map_err(|err| format!("{:?}", err))?;
+                objects.push(Object::new(object_id, value));
+            }
+            let service_data = ServiceData {
+                encrypted: false,
+                trigger_based: self.trigger_based,
+                version: 2,
+                objects,
+            };
+            let service_data =
+                encode_service_data(&service_data).map_err(|err| format!("step {}: error encoding: {:?}", i + 1, err))?;
+            packets.push(Packet { delay, service_data });
+        }
+        Ok(packets)
+    }
+}
+
+/// Looks up an object by name or by id like `0x45`.
+fn object_id(name: &str) -> Result<u8, String> {
+    let id = match name.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).map_err(|_| format!("invalid object id {:?}", name))?,
+        None => ObjectId::from_name(name)
+            .map(|object| object as u8)
+            .ok_or_else(|| format!("unknown object {:?}", name))?,
+    };
+    ObjectId::try_from(id).map_err(|_| format!("unknown object id {:?}", name))?;
+    Ok(id)
+}
+
+fn object_value(id: u8, value: &Value) -> Result<ObjectValue, String> {
+    let object = ObjectId::try_from(id).map_err(|err| format!("{:?}", err))?;
+    match (object, value) {
+        (ObjectId::Text, Value::String(text)) => Ok(ObjectValue::Text(text.clone())),
+        (ObjectId::Button, Value::String(event)) => button_event(event).map(ObjectValue::ButtonEvent),
+        // Negative steps turn left
+        (ObjectId::Dimmer, Value::Integer(steps)) => {
+            let event = if *steps < 0 { DimmerEvent::RotateLeft } else { DimmerEvent::RotateRight };
+            let steps = u8::try_from(steps.unsigned_abs()).map_err(|_| "at most 255 steps are possible")?;
+            Ok(ObjectValue::DimmerEvent(event, steps))
+        }
+        (_, Value::Boolean(value)) => Ok(ObjectValue::Bool(*value)),
+        (_, Value::Integer(value)) => Ok(ObjectValue::Int(*value)),
+        (_, Value::Float(value)) => Ok(ObjectValue::Float(*value)),
+        (_, value) => Err(format!("unsupported value {}", value)),
+    }
+}
+
+fn button_event(name: &str) -> Result<ButtonEvent, String> {
+    Ok(match name {
+        "press" => ButtonEvent::Press,
+        "double_press" => ButtonEvent::DoublePress,
+        "triple_press" => ButtonEvent::TriplePress,
+        "long_press" => ButtonEvent::LongPress,
+        "long_double_press" => ButtonEvent::LongDoublePress,
+        "long_triple_press" => ButtonEvent::LongTriplePress,
+        "hold_press" => ButtonEvent::HoldPress,
+        "hold" => ButtonEvent::Hold,
+        _ => return Err(format!("unknown button event {:?}", name)),
+    })
+}
+
+/// The measurements are kept across steps, but values are not `Clone`.
+fn copy(value: &ObjectValue) -> ObjectValue {
+    match value {
+        ObjectValue::Float(value) => ObjectValue::Float(*value),
+        ObjectValue::Int(value) => ObjectValue::Int(*value),
+        ObjectValue::UInt(value) => ObjectValue::UInt(*value),
+        ObjectValue::Bool(value) => ObjectValue::Bool(*value),
+        ObjectValue::Raw(bytes) => ObjectValue::Raw(bytes.clone()),
+        ObjectValue::Text(text) => ObjectValue::Text(text.clone()),
+        ObjectValue::FirmwareVersion(version) => ObjectValue::FirmwareVersion(*version),
+        ObjectValue::DeviceType(device_type) => ObjectValue::DeviceType(*device_type),
+        ObjectValue::ButtonEvent(event) => ObjectValue::ButtonEvent(*event),
+        ObjectValue::DimmerEvent(event, steps) => ObjectValue::DimmerEvent(*event, *steps),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn packets_of_steps() {
+        let scenario = Scenario::parse(
+            "[[device]]\nname = \"test\"\n\n[[device.step]]\ntemperature = 25\nhumidity = 50.55\n\n[[device.step]]\nafter = 1.5\nbutton = \"press\"\n\n[[device.step]]\nafter = 60\ntemperature = -1.5\n",
+        )
+        .unwrap();
+        let packets = scenario.device[0].packets(254).unwrap();
+        assert_eq!(
+            packets,
+            vec![
+                Packet {
+                    delay: Duration::ZERO,
+                    service_data: vec![0x40, 0x00, 0xFE, 0x02, 0xC4, 0x09, 0x03, 0xBF, 0x13],
+                },
+                Packet {
+                    delay: Duration::from_millis(1500),
+                    service_data: vec![0x40, 0x00, 0xFF, 0x02, 0xC4, 0x09, 0x03, 0xBF, 0x13, 0x3A, 0x01],
+                },
+                Packet {
+                    delay: Duration::from_secs(60),
+                    service_data: vec![0x40, 0x00, 0x00, 0x02, 0x6A, 0xFF, 0x03, 0xBF, 0x13],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_scenarios() {
+        assert!(Scenario::parse("device = []").is_err());
+        assert!(Scenario::parse("[[device]]\nname = \"test\"\nstep = []\n").is_err());
+        assert!(Scenario::parse("[[device]]\nname = \"test\"\n[[device.step]]\nloudness = 3\n").is_err());
+        assert!(Scenario::parse("[[device]]\nname = \"test\"\n[[device.step]]\nbutton = \"smash\"\n").is_err());
+        assert!(Scenario::parse("[[device]]\nname = \"test\"\n[[device.step]]\nafter = -1\nbattery = 3\n").is_err());
+    }
+}
@@ -0,0 +1,202 @@
+//! Node.js bindings, so that Node-RED nodes and Electron tools can decode and encode BTHome
+//! service data with this implementation:
+//!
+//! ```js
+//! const bthome = require('bthome');
+//! bthome.parse(Buffer.from('4002c40903bf13', 'hex'));
+//! // { version: 2, encrypted: false, triggerBased: false, objects: [
+//! //   { id: 2, name: 'Temperature4', value: 25 }, { id: 3, name: 'HumidityU16', value: 50.55 } ] }
+//! bthome.encode({ objects: [{ name: 'temperature', value: 25 }] });
+//! ```
+
+use bthome::{encode_service_data, parse_service_data, ButtonEvent, DimmerEvent, Object, ObjectId, ObjectValue, ServiceData};
+use napi::bindgen_prelude::Buffer;
+use napi_derive::napi;
+use serde_json::{json, Value};
+
+const BUTTON_EVENTS: &[(&str, ButtonEvent)] = &[
+    ("none", ButtonEvent::None),
+    ("press", ButtonEvent::Press),
+    ("double_press", ButtonEvent::DoublePress),
+    ("triple_press", ButtonEvent::TriplePress),
+    ("long_press", ButtonEvent::LongPress),
+    ("long_double_press", ButtonEvent::LongDoublePress),
+    ("long_triple_press", ButtonEvent::LongTriplePress),
+    ("hold_press", ButtonEvent::HoldPress),
+    ("hold", ButtonEvent::Hold),
+];
+
+const DIMMER_EVENTS: &[(&str, DimmerEvent)] = &[
+    ("none", DimmerEvent::None),
+    ("rotate_left", DimmerEvent::RotateLeft),
+    ("rotate_right", DimmerEvent::RotateRight),
+];
+
+/// Decodes service data into a plain object, throws if it is invalid or encrypted.
+#[napi]
+pub fn parse(data: Buffer) -> napi::Result<Value> {
+    to_js(&data).map_err(napi::Error::from_reason)
+}
+
+/// Encodes an object shaped like the result of `parse`, objects are given by `id` or by `name`,
+/// e.g. `temperature`. Throws if an object is unknown or its value does not fit.
+#[napi]
+pub fn encode(description: Value) -> napi::Result<Buffer> {
+    from_js(&description).map(Buffer::from).map_err(napi::Error::from_reason)
+}
+
+fn to_js(data: &[u8]) -> Result<Value, String> {
+    let service_data = parse_service_data(data).map_err(|err| format!("invalid BTHome data: {:?}", err))?;
+    let objects: Vec<Value> = service_data
+        .objects
+        .into_iter()
+        .map(|object| {
+            let name = format!("{:?}", object.object_id);
+            json!({"id": object.object_id as u8, "name": name, "value": value_to_js(object.value)})
+        })
+        .collect();
+    Ok(json!({
+        "version": service_data.version,
+        "encrypted": service_data.encrypted,
+        "triggerBased": service_data.trigger_based,
+        "objects": objects,
+    }))
+}
+
+fn value_to_js(value: ObjectValue) -> Value {
+    match value {
+        ObjectValue::Float(value) => json!(value),
+        ObjectValue::Int(value) => json!(value),
+        ObjectValue::UInt(value) => json!(value),
+        ObjectValue::Bool(value) => json!(value),
+        ObjectValue::Raw(bytes) => json!(bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+        ObjectValue::Text(text) => json!(text),
+        ObjectValue::FirmwareVersion(version) => json!(version.to_string()),
+        ObjectValue::DeviceType(device_type) => json!(device_type.to_string()),
+        ObjectValue::ButtonEvent(event) => json!(event_name(BUTTON_EVENTS, event)),
+        ObjectValue::DimmerEvent(event, steps) => json!({"event": event_name(DIMMER_EVENTS, event), "steps": steps}),
+    }
+}
+
+fn event_name<T: PartialEq + Copy>(events: &[(&'static str, T)], event: T) -> &'static str {
+    events.iter().find(|(_, known)| *known == event).map_or("none", |(name, _)| name)
+}
+
+fn from_js(description: &Value) -> Result<Vec<u8>, String> {
+    let objects = match description.get("objects") {
+        Some(Value::Array(objects)) => objects.as_slice(),
+        None => &[],
+        Some(_) => return Err("objects has to be an array".to_string()),
+    };
+    let mut service_data = ServiceData {
+        encrypted: false,
+        trigger_based: description["triggerBased"].as_bool().unwrap_or(false),
+        version: 2,
+        objects: Vec::new(),
+    };
+    let mut numbered = Vec::new();
+    for object in objects {
+        let id = object_id(object)?;
+        let object_id = ObjectId::try_from(id).expect("Object id to be checked");
+        let name = format!("{:?}", object_id);
+        let value = value_from_js(&object_id, &object["value"]).map_err(|err| format!("{}: {}", name, err))?;
+        numbered.push((id, Object::new(object_id, value)));
+    }
+    // The specification requires the objects to be ordered by id
+    numbered.sort_by_key(|(id, _)| *id);
+    service_data.objects = numbered.into_iter().map(|(_, object)| object).collect();
+    encode_service_data(&service_data).map_err(|err| format!("error encoding: {:?}", err))
+}
+
+/// The id of the object given by `id`, by its name in the specification or by the name returned
+/// by `parse`.
+fn object_id(object: &Value) -> Result<u8, String> {
+    if let Some(id) = object["id"].as_u64() {
+        let id = u8::try_from(id).map_err(|_| format!("invalid object id {}", id))?;
+        ObjectId::try_from(id).map_err(|_| format!("unknown object id {:#04x}", id))?;
+        return Ok(id);
+    }
+    let name = object["name"].as_str().ok_or("objects need an id or a name")?;
+    match ObjectId::from_name(name) {
+        Some(object_id) => Ok(object_id as u8),
+        None => (0..=255u8)
+            .find(|id| ObjectId::try_from(*id).is_ok_and(|object_id| format!("{:?}", object_id) == name))
+            .ok_or_else(|| format!("unknown object {:?}", name)),
+    }
+}
+
+fn value_from_js(object_id: &ObjectId, value: &Value) -> Result<ObjectValue, String> {
+    match (object_id, value) {
+        (ObjectId::Text, Value::String(text)) => Ok(ObjectValue::Text(text.clone())),
+        (ObjectId::Raw, Value::String(hex)) => decode_hex(hex)
+            .map(ObjectValue::Raw)
+            .ok_or_else(|| format!("invalid hex {:?}", hex)),
+        (ObjectId::Button, Value::String(name)) => BUTTON_EVENTS
+            .iter()
+            .find(|(known, _)| known == name)
+            .map(|(_, event)| ObjectValue::ButtonEvent(*event))
+            .ok_or_else(|| format!("unknown button event {:?}", name)),
+        (ObjectId::Dimmer, Value::Object(dimmer)) => {
+            let name = dimmer.get("event").and_then(Value::as_str).unwrap_or_default();
+            let (_, event) = DIMMER_EVENTS
+                .iter()
+                .find(|(known, _)| *known == name)
+                .ok_or_else(|| format!("unknown dimmer event {:?}", name))?;
+            let steps = dimmer.get("steps").and_then(Value::as_u64).unwrap_or(0);
+            let steps = u8::try_from(steps).map_err(|_| "at most 255 steps are possible")?;
+            Ok(ObjectValue::DimmerEvent(*event, steps))
+        }
+        (_, Value::Bool(value)) => Ok(ObjectValue::Bool(*value)),
+        (_, Value::Number(number)) => match number.as_i64() {
+            Some(value) => Ok(ObjectValue::Int(value)),
+            None => Ok(ObjectValue::Float(number.as_f64().unwrap_or(f64::NAN))),
+        },
+        (_, value) => Err(format!("unsupported value {}", value)),
+    }
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_to_js() {
+        let parsed = to_js(&[0x44, 0x02, 0xC4, 0x09, 0x03, 0xBF, 0x13, 0x3A, 0x02]).unwrap();
+        assert_eq!(
+            parsed,
+            json!({
+                "version": 2,
+                "encrypted": false,
+                "triggerBased": true,
+                "objects": [
+                    {"id": 0x02, "name": "Temperature4", "value": 25.0},
+                    {"id": 0x03, "name": "HumidityU16", "value": 50.55},
+                    {"id": 0x3A, "name": "Button", "value": "double_press"},
+                ],
+            })
+        );
+        assert!(to_js(&[0x41, 0x00]).is_err());
+    }
+
+    #[test]
+    fn encode_from_js() {
+        let description = json!({"objects": [{"name": "humidity", "value": 50.55}, {"id": 2, "value": 25}]});
+        assert_eq!(from_js(&description).unwrap(), vec![0x40, 0x02, 0xC4, 0x09, 0x03, 0xBF, 0x13]);
+
+        let data = [0x44, 0x02, 0xC4, 0x09, 0x3A, 0x02, 0x3C, 0x01, 0x03];
+        assert_eq!(from_js(&to_js(&data).unwrap()).unwrap(), data);
+
+        assert!(from_js(&json!({"objects": [{"name": "loudness", "value": 3}]})).is_err());
+        assert!(from_js(&json!({"objects": [{"name": "battery", "value": 300}]})).is_err());
+    }
+}
@@ -0,0 +1,46 @@
+//! Parsing of human friendly durations like `30s`, `10m` or `1h30m` in arguments.
+
+use std::time::Duration;
+
+
+pub fn parse(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration {:?}, expected e.g. 30s, 10m or 1h30m", s);
+    let mut total = Duration::ZERO;
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let value: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+        let unit = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let seconds = match &rest[..unit] {
+            "ms" => {
+                total += Duration::from_millis(value);
+                rest = &rest[unit..];
+                continue;
+            }
+            "s" => value,
+            "m" => value * 60,
+            "h" => value * 60 * 60,
+            "d" => value * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        total += Duration::from_secs(seconds);
+        rest = &rest[unit..];
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse("2d"), Ok(Duration::from_secs(172_800)));
+        assert!(parse("10").is_err());
+    }
+}
@@ -0,0 +1,272 @@
+//! Records BTHome advertisements for days or weeks, to analyze problems that only occur now and
+//! then after the fact, and replays the recordings.
+
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::{Parser, Subcommand};
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
+
+mod duration;
+mod record;
+mod rotate;
+
+use record::{Format, Record};
+use rotate::{Limits, Rotating};
+
+/// Record BTHome advertisements to rotated files and replay them.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Record all BTHome advertisements until stopped
+    Record {
+        /// Directory the recordings are written to
+        #[arg(short, long, default_value = ".")]
+        directory: PathBuf,
+
+        /// Bluetooth adapter to use, e.g. hci1, instead of the default one
+        #[arg(long)]
+        adapter: Option<String>,
+
+        #[arg(long, value_enum, default_value_t)]
+        format: Format,
+
+        /// Start a new file after this size, e.g. 512K, 100M or 1G
+        #[arg(long, default_value = "100M", value_parser = parse_size)]
+        max_size: u64,
+
+        /// Start a new file after this time, e.g. 1h or 1d
+        #[arg(long, default_value = "1d", value_parser = duration::parse)]
+        max_age: Duration,
+
+        /// Delete the oldest files so that at most this many are kept
+        #[arg(long)]
+        keep: Option<usize>,
+    },
+    /// Print recorded advertisements as JSONL, decoded with the current version of the library
+    Replay {
+        /// Recordings to replay, directories are replayed with all their recordings in order
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+
+        /// Only replay advertisements of this device
+        #[arg(long)]
+        address: Option<String>,
+
+        /// Wait between advertisements like when they were recorded
+        #[arg(long)]
+        realtime: bool,
+
+        /// Speed up waiting with --realtime, e.g. 60 to replay an hour in a minute
+        #[arg(long, default_value_t = 1.0, requires = "realtime")]
+        speed: f64,
+    },
+}
+
+pub struct Advertisement {
+    pub address: [u8; 6],
+    pub service_data: Vec<u8>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with_writer(std::io::stderr)
+        .init();
+    match Args::parse().command {
+        Command::Record {
+            directory,
+            adapter,
+            format,
+            max_size,
+            max_age,
+            keep,
+        } => {
+            let limits = Limits { max_size, max_age, keep };
+            let rotating = Rotating::new(&directory, format, limits)
+                .map_err(|err| format!("Error creating {}: {}", directory.display(), err))?;
+            record(rotating, adapter).await
+        }
+        Command::Replay {
+            paths,
+            address,
+            realtime,
+            speed,
+        } => {
+            let address = match address {
+                Some(text) => Some(record::parse_address(&text).ok_or_else(|| format!("Invalid address {}", text))?),
+                None => None,
+            };
+            replay(&paths, address, realtime.then_some(speed)).await
+        }
+    }
+}
+
+async fn record(mut rotating: Rotating, adapter: Option<String>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (tx, mut advertisements) = tokio::sync::mpsc::unbounded_channel();
+    let scanner = scan(adapter, tx);
+    tokio::pin!(scanner);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut flush = tokio::time::interval(Duration::from_secs(5));
+    let mut recorded = 0u64;
+
+    let result = loop {
+        tokio::select! {
+            Some(advertisement) = advertisements.recv() => {
+                let record = Record {
+                    time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64,
+                    address: advertisement.address,
+                    payload: advertisement.service_data,
+                };
+                if let Err(err) = rotating.write(&record) {
+                    break Err(format!("Error writing recording: {}", err).into());
+                }
+                recorded += 1;
+            }
+            _ = flush.tick() => {
+                if let Err(err) = rotating.flush() {
+                    break Err(format!("Error writing recording: {}", err).into());
+                }
+            }
+            result = &mut scanner => {
+                break match result {
+                    Ok(()) => Err("Scanning stopped unexpectedly".into()),
+                    Err(err) => Err(format!("Error scanning: {}", err).into()),
+                };
+            }
+            result = &mut shutdown => {
+                if let Err(err) = result {
+                    error!(error = %err, "Error waiting for shutdown signals");
+                }
+                break Ok(());
+            }
+        }
+    };
+    rotating.flush()?;
+    info!(recorded, "Stopped recording");
+    result
+}
+
+async fn replay(paths: &[PathBuf], address: Option<[u8; 6]>, speed: Option<f64>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            files.extend(rotate::recordings(path).map_err(|err| format!("Error reading {}: {}", path.display(), err))?);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    let mut previous: Option<u64> = None;
+    for path in files {
+        let records = File::open(&path)
+            .and_then(record::read_all)
+            .map_err(|err| format!("Error reading {}: {}", path.display(), err))?;
+        for record in records {
+            if address.is_some_and(|address| address != record.address) {
+                continue;
+            }
+            if let (Some(speed), Some(previous)) = (speed, previous) {
+                out.flush()?;
+                let gap = Duration::from_millis(record.time.saturating_sub(previous));
+                tokio::time::sleep(gap.div_f64(speed)).await;
+            }
+            previous = Some(record.time);
+            writeln!(out, "{}", record.to_json())?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Parses a size in bytes with an optional `K`, `M` or `G` suffix.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid size {:?}, expected e.g. 512K, 100M or 1G", s);
+    let (digits, factor) = match s.trim().char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s.trim()[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&s.trim()[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&s.trim()[..i], 1 << 30),
+        _ => (s.trim(), 1),
+    };
+    let value: u64 = digits.parse().map_err(|_| invalid())?;
+    value.checked_mul(factor).filter(|size| *size > 0).ok_or_else(invalid)
+}
+
+/// Receives BTHome advertisements until the receiver is gone. Scanning is restarted when it stops,
+/// e.g. because bluetoothd restarted, so only failing to find the adapter is an error.
+#[cfg(target_os = "linux")]
+async fn scan(
+    adapter: Option<String>,
+    tx: tokio::sync::mpsc::UnboundedSender<Advertisement>,
+) -> Result<(), bluer::Error> {
+    use bthome_listener::Listener;
+    use futures::StreamExt;
+
+    let listener = match adapter {
+        Some(name) => Listener::named_adapter(&name).await?,
+        None => Listener::default_adapter().await?,
+    };
+    let mut advertisements = listener.advertisements();
+    while let Some(advertisement) = advertisements.next().await {
+        let advertisement = Advertisement {
+            address: advertisement.mac.0,
+            service_data: advertisement.service_data,
+        };
+        if tx.send(advertisement).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn scan(
+    _adapter: Option<String>,
+    _tx: tokio::sync::mpsc::UnboundedSender<Advertisement>,
+) -> Result<(), &'static str> {
+    Err("scanning is only supported on Linux with bluez")
+}
+
+/// Resolves on SIGINT or SIGTERM.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512K"), Ok(512 * 1024));
+        assert_eq!(parse_size("100M"), Ok(100 * 1024 * 1024));
+        assert_eq!(parse_size("1g"), Ok(1 << 30));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("10 MB").is_err());
+    }
+}
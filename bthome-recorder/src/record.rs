@@ -0,0 +1,199 @@
+//! The recorded advertisements and the two file formats they are stored in.
+//!
+//! JSONL files contain one JSON object per advertisement, with the payload and what it decoded
+//! to at the time of recording. Compact files start with [`COMPACT_MAGIC`] followed by records of
+//! the time in milliseconds since the Unix epoch as u64 little endian, the six address bytes,
+//! the payload length as u8 and the payload.
+
+use std::io::{self, Read, Write};
+
+use bthome::{hex, parse_service_data, ObjectValue};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+pub const COMPACT_MAGIC: &[u8; 8] = b"BTHREC1\n";
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// One JSON object per advertisement, including the decoded values
+    #[default]
+    Jsonl,
+    /// Binary records of the raw advertisements only, a fraction of the size
+    Compact,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Jsonl => "jsonl",
+            Format::Compact => "btrec",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Milliseconds since the Unix epoch
+    pub time: u64,
+    pub address: [u8; 6],
+    pub payload: Vec<u8>,
+}
+
+impl Record {
+    /// The record with the payload decoded by the current library.
+    pub fn to_json(&self) -> Value {
+        let mut value = json!({
+            "time": self.time,
+            "address": address_text(&self.address),
+            "payload": hex::encode(&self.payload),
+        });
+        match parse_service_data(&self.payload) {
+            Ok(service_data) => {
+                let objects: Vec<Value> = service_data
+                    .objects
+                    .into_iter()
+                    .map(|object| {
+                        let name = format!("{:?}", object.object_id);
+                        json!({"id": object.object_id as u8, "name": name, "value": value_json(object.value)})
+                    })
+                    .collect();
+                value["version"] = json!(service_data.version);
+                value["encrypted"] = json!(service_data.encrypted);
+                value["trigger_based"] = json!(service_data.trigger_based);
+                value["objects"] = json!(objects);
+            }
+            Err(err) => value["error"] = json!(format!("{:?}", err)),
+        }
+        value
+    }
+
+    /// Reads the raw advertisement of a JSONL line, the decoded values are ignored.
+    pub fn from_json(line: &str) -> Option<Record> {
+        let value: Value = serde_json::from_str(line).ok()?;
+        Some(Record {
+            time: value["time"].as_u64()?,
+            address: parse_address(value["address"].as_str()?)?,
+            payload: hex::decode(value["payload"].as_str()?)?,
+        })
+    }
+
+    pub fn write(&self, format: Format, out: &mut impl Write) -> io::Result<()> {
+        match format {
+            Format::Jsonl => writeln!(out, "{}", self.to_json()),
+            Format::Compact => {
+                // Longer payloads don't fit into an advertisement
+                let length = self.payload.len().min(u8::MAX as usize);
+                out.write_all(&self.time.to_le_bytes())?;
+                out.write_all(&self.address)?;
+                out.write_all(&[length as u8])?;
+                out.write_all(&self.payload[..length])
+            }
+        }
+    }
+}
+
+/// Reads all records of a recording, the format is detected by the magic of compact files.
+pub fn read_all(mut input: impl Read) -> io::Result<Vec<Record>> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let Some(mut rest) = data.strip_prefix(COMPACT_MAGIC) else {
+        let text = String::from_utf8(data).map_err(|_| invalid("neither a JSONL nor a compact recording"))?;
+        return text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Record::from_json(line).ok_or_else(|| invalid(&format!("invalid record {:?}", line))))
+            .collect();
+    };
+    let mut records = Vec::new();
+    while !rest.is_empty() {
+        // A record cut short, e.g. by a power failure, ends the recording
+        if rest.len() < 15 || rest.len() < 15 + rest[14] as usize {
+            break;
+        }
+        let length = rest[14] as usize;
+        records.push(Record {
+            time: u64::from_le_bytes(rest[..8].try_into().unwrap()),
+            address: rest[8..14].try_into().unwrap(),
+            payload: rest[15..15 + length].to_vec(),
+        });
+        rest = &rest[15 + length..];
+    }
+    Ok(records)
+}
+
+pub fn address_text(address: &[u8; 6]) -> String {
+    address.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":")
+}
+
+pub fn parse_address(text: &str) -> Option<[u8; 6]> {
+    let bytes: Vec<u8> = text
+        .split(':')
+        .map(|digits| u8::from_str_radix(digits, 16).ok())
+        .collect::<Option<_>>()?;
+    bytes.try_into().ok()
+}
+
+fn value_json(value: ObjectValue) -> Value {
+    match value {
+        ObjectValue::Float(value) => json!(value),
+        ObjectValue::Int(value) => json!(value),
+        ObjectValue::UInt(value) => json!(value),
+        ObjectValue::Bool(value) => json!(value),
+        ObjectValue::Raw(bytes) => json!(hex::encode(&bytes)),
+        ObjectValue::Text(text) => json!(text),
+        ObjectValue::FirmwareVersion(version) => json!(version.to_string()),
+        ObjectValue::DeviceType(device_type) => json!(device_type.to_string()),
+        ObjectValue::ButtonEvent(event) => json!(format!("{:?}", event)),
+        ObjectValue::DimmerEvent(event, steps) => json!({"event": format!("{:?}", event), "steps": steps}),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record {
+                time: 1_700_000_000_123,
+                address: [0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56],
+                payload: vec![0x40, 0x02, 0xC4, 0x09],
+            },
+            Record {
+                time: 1_700_000_001_000,
+                address: [0xA4, 0xC1, 0x38, 0x12, 0x34, 0x57],
+                payload: vec![0x41, 0x00],
+            },
+        ]
+    }
+
+    #[test]
+    fn write_and_read() {
+        for format in [Format::Jsonl, Format::Compact] {
+            let mut file = Vec::new();
+            if format == Format::Compact {
+                file.extend_from_slice(COMPACT_MAGIC);
+            }
+            for record in records() {
+                record.write(format, &mut file).unwrap();
+            }
+            assert_eq!(read_all(file.as_slice()).unwrap(), records());
+        }
+
+        let mut truncated = COMPACT_MAGIC.to_vec();
+        records()[0].write(Format::Compact, &mut truncated).unwrap();
+        truncated.extend_from_slice(&[0x00, 0x01]);
+        assert_eq!(read_all(truncated.as_slice()).unwrap(), records()[..1]);
+        assert!(read_all(&b"not a recording"[..]).is_err());
+    }
+
+    #[test]
+    fn decoded_json() {
+        let records = records();
+        let (valid, encrypted) = (records[0].to_json(), records[1].to_json());
+        assert_eq!(valid["address"], "A4:C1:38:12:34:56");
+        assert_eq!(valid["objects"][0], json!({"id": 2, "name": "Temperature4", "value": 25.0}));
+        assert_eq!(encrypted["error"], "Encrypted");
+    }
+}
@@ -0,0 +1,150 @@
+//! Writing records to files that are rotated by size and age, deleting the oldest ones so that a
+//! recording can run for weeks without filling the disk.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tracing::{info, warn};
+
+use crate::record::{Format, Record, COMPACT_MAGIC};
+
+pub struct Limits {
+    /// Bytes after which a new file is started
+    pub max_size: u64,
+    /// Time after which a new file is started
+    pub max_age: Duration,
+    /// Number of files kept, including the current one
+    pub keep: Option<usize>,
+}
+
+struct Current {
+    writer: BufWriter<File>,
+    size: u64,
+    /// Time of the first record in milliseconds since the Unix epoch
+    started: u64,
+}
+
+pub struct Rotating {
+    directory: PathBuf,
+    format: Format,
+    limits: Limits,
+    current: Option<Current>,
+}
+
+impl Rotating {
+    pub fn new(directory: &Path, format: Format, limits: Limits) -> io::Result<Rotating> {
+        fs::create_dir_all(directory)?;
+        Ok(Rotating {
+            directory: directory.to_path_buf(),
+            format,
+            limits,
+            current: None,
+        })
+    }
+
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        let expired = self.current.as_ref().is_some_and(|current| {
+            current.size >= self.limits.max_size
+                || record.time.saturating_sub(current.started) >= self.limits.max_age.as_millis() as u64
+        });
+        if expired {
+            self.flush()?;
+            self.current = None;
+        }
+        if self.current.is_none() {
+            self.current = Some(self.open(record.time)?);
+        }
+        let current = self.current.as_mut().expect("File to be open");
+        let mut data = Vec::new();
+        record.write(self.format, &mut data)?;
+        current.writer.write_all(&data)?;
+        current.size += data.len() as u64;
+        Ok(())
+    }
+
+    /// Writes buffered records to the file, so that they survive a crash.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(current) => current.writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn open(&self, time: u64) -> io::Result<Current> {
+        let path = self.directory.join(format!("bthome-{}.{}", time, self.format.extension()));
+        info!(path = %path.display(), "Starting new recording file");
+        let mut writer = BufWriter::new(File::create_new(&path)?);
+        let mut size = 0;
+        if self.format == Format::Compact {
+            writer.write_all(COMPACT_MAGIC)?;
+            size = COMPACT_MAGIC.len() as u64;
+        }
+        if let Some(keep) = self.limits.keep {
+            self.remove_old(keep)?;
+        }
+        Ok(Current { writer, size, started: time })
+    }
+
+    fn remove_old(&self, keep: usize) -> io::Result<()> {
+        let files = recordings(&self.directory)?;
+        for path in files.iter().rev().skip(keep) {
+            if let Err(err) = fs::remove_file(path) {
+                warn!(path = %path.display(), %err, "Error removing old recording");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The recording files in a directory, oldest first.
+pub fn recordings(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<(u64, PathBuf)> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?;
+            let (time, extension) = name.strip_prefix("bthome-")?.split_once('.')?;
+            matches!(extension, "jsonl" | "btrec").then_some((time.parse().ok()?, path))
+        })
+        .collect();
+    files.sort();
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record;
+
+    #[test]
+    fn rotate_by_size_and_age() {
+        let directory = std::env::temp_dir().join(format!("bthome-recorder-test-{}", std::process::id()));
+        let limits = Limits {
+            max_size: 30,
+            max_age: Duration::from_secs(60),
+            keep: Some(2),
+        };
+        let mut rotating = Rotating::new(&directory, Format::Compact, limits).unwrap();
+        let record = |time: u64| Record {
+            time,
+            address: [0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56],
+            payload: vec![0x40, 0x01, 0x61],
+        };
+        // Each record takes 18 bytes, the file exceeds the size with the second one
+        for time in [1_000, 2_000, 3_000, 70_000] {
+            rotating.write(&record(time)).unwrap();
+        }
+        rotating.flush().unwrap();
+
+        let files = recordings(&directory).unwrap();
+        let names: Vec<_> = files.iter().map(|path| path.file_name().unwrap().to_str().unwrap()).collect();
+        // The file started at 1000 was removed, 3000 was started by size and 70000 by age
+        assert_eq!(names, ["bthome-3000.btrec", "bthome-70000.btrec"]);
+        let records = record::read_all(File::open(&files[0]).unwrap()).unwrap();
+        assert_eq!(records, [record(3_000)]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
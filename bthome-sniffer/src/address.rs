@@ -0,0 +1,135 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Bluetooth device address, independent of the BLE backend in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Address(pub [u8; 6]);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidAddress;
+
+impl fmt::Display for InvalidAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid Bluetooth address")
+    }
+}
+
+impl std::error::Error for InvalidAddress {}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}", a, b, c, d, e, g)
+    }
+}
+
+impl FromStr for Address {
+    type Err = InvalidAddress;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = [0u8; 6];
+        let mut parts = s.split(':');
+        for byte in bytes.iter_mut() {
+            let part = parts.next().ok_or(InvalidAddress)?;
+            if part.len() != 2 || !part.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(InvalidAddress);
+            }
+            *byte = u8::from_str_radix(part, 16).map_err(|_| InvalidAddress)?;
+        }
+        if parts.next().is_some() {
+            return Err(InvalidAddress);
+        }
+        Ok(Address(bytes))
+    }
+}
+
+/// Whether an address is the public address of the device or a random one, like the resolvable
+/// private addresses of devices that change their address periodically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressType {
+    Public,
+    Random,
+}
+
+impl AddressType {
+    /// The address type of HCI advertising reports and ESPHome, 2 and 3 are identity addresses
+    /// resolved by the controller.
+    pub fn from_hci(value: u8) -> Option<AddressType> {
+        match value {
+            0x00 | 0x02 => Some(AddressType::Public),
+            0x01 | 0x03 => Some(AddressType::Random),
+            _ => None,
+        }
+    }
+
+    /// The address type in HCI advertising reports and ESPHome messages.
+    pub fn hci(self) -> u8 {
+        match self {
+            AddressType::Public => 0x00,
+            AddressType::Random => 0x01,
+        }
+    }
+}
+
+impl fmt::Display for AddressType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressType::Public => write!(f, "public"),
+            AddressType::Random => write!(f, "random"),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AddressType {
+    pub fn from_bluer(value: bluer::AddressType) -> Option<AddressType> {
+        match value {
+            bluer::AddressType::LePublic => Some(AddressType::Public),
+            bluer::AddressType::LeRandom => Some(AddressType::Random),
+            bluer::AddressType::BrEdr => None,
+        }
+    }
+}
+
+#[cfg(feature = "btleplug")]
+impl From<btleplug::api::AddressType> for AddressType {
+    fn from(value: btleplug::api::AddressType) -> Self {
+        match value {
+            btleplug::api::AddressType::Public => AddressType::Public,
+            btleplug::api::AddressType::Random => AddressType::Random,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<bluer::Address> for Address {
+    fn from(value: bluer::Address) -> Self {
+        Address(value.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_and_format() {
+        let address: Address = "a4:C1:38:0b:34:56".parse().expect("Address to parse");
+        assert_eq!(address, Address([0xA4, 0xC1, 0x38, 0x0B, 0x34, 0x56]));
+        assert_eq!(address.to_string(), "A4:C1:38:0B:34:56");
+        assert!("A4:C1:38:0B:34".parse::<Address>().is_err());
+        assert!("A4:C1:38:0B:34:56:78".parse::<Address>().is_err());
+        assert!("A4:C1:38:0B:34:5G".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn address_types() {
+        assert_eq!(AddressType::from_hci(0x00), Some(AddressType::Public));
+        assert_eq!(AddressType::from_hci(0x03), Some(AddressType::Random));
+        assert_eq!(AddressType::from_hci(0xFF), None);
+        assert_eq!(AddressType::from_hci(AddressType::Random.hci()), Some(AddressType::Random));
+        assert_eq!(AddressType::Random.to_string(), "random");
+    }
+}
@@ -0,0 +1,56 @@
+//! The BTHome advertisements of btsnoop, pcap and pcapng captures of HCI traffic, e.g. Android HCI
+//! snoop logs or captures taken with Wireshark or btmon, read with [`bthome::capture`].
+
+use bthome::capture::{hci_advertisements, HciCaptureError};
+
+use crate::{
+    address::{Address, AddressType},
+    hci::AdvertisingKind,
+    source::Advertisement,
+};
+
+/// The BTHome advertisements of a capture, `source` names the capture in them.
+pub fn advertisements(data: &[u8], source: &str) -> Result<Vec<Advertisement>, HciCaptureError> {
+    Ok(hci_advertisements(data)?
+        .into_iter()
+        .map(|captured| Advertisement {
+            source: source.to_string(),
+            address: Address(captured.report.address),
+            address_type: AddressType::from_hci(captured.report.address_type),
+            // 127 is sent if the controller doesn't know the RSSI, or written for unknown RSSIs
+            rssi: Some(captured.report.rssi).filter(|rssi| *rssi != 127).map(i16::from),
+            advertising: Some(AdvertisingKind::of(&captured.report)),
+            received: captured.time,
+            service_data: captured.service_data,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use bthome::capture::PcapWriter;
+
+    use super::*;
+
+    #[test]
+    fn extract_advertisements() {
+        let timestamp = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        let mut writer = PcapWriter::new(Vec::new()).expect("Header to be written");
+        let report = crate::hci::advertising_report(address, None, Some(-60), None, &[0x40, 0x01, 0x61]);
+        writer.write(timestamp, &report).expect("Packet to be written");
+        let report = crate::hci::advertising_report(address, None, None, None, &[0x40, 0x01, 0x62]);
+        writer.write(timestamp, &report).expect("Packet to be written");
+
+        let advertisements = advertisements(&writer.into_inner(), "field.pcap").expect("Capture to be readable");
+        assert_eq!(advertisements.len(), 2);
+        assert_eq!(advertisements[0].address, address);
+        assert_eq!(advertisements[0].rssi, Some(-60));
+        assert_eq!(advertisements[0].advertising, Some(AdvertisingKind::Legacy));
+        assert_eq!(advertisements[0].service_data, [0x40, 0x01, 0x61]);
+        assert_eq!(advertisements[0].source, "field.pcap");
+        assert_eq!(advertisements[1].rssi, None);
+    }
+}
@@ -0,0 +1,478 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+
+use crate::address::Address;
+#[cfg(unix)]
+use crate::control::Command;
+use crate::control::Key;
+use crate::filter::{Filter, FilterConfig};
+use crate::forward::Endpoint;
+use crate::logging::LogFormat;
+use crate::influx::InfluxUrl;
+use crate::mqtt::MqttUrl;
+use crate::output::Output;
+#[cfg(target_os = "linux")]
+use crate::source::bluez::{MonitorConfig, SamplingPeriod, ScanMode};
+use crate::source::{Backend, BackendParser};
+
+/// Sniff BTHome advertisements and print the decoded data.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Args {
+    /// Path to a TOML configuration file
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+
+    /// Format of the decoded packets written to stdout
+    #[arg(long, value_enum, default_value_t)]
+    pub output: Output,
+
+    /// Format of the log messages, the levels are set with RUST_LOG, e.g. RUST_LOG=debug
+    /// [default: text, journald with --daemon under systemd]
+    #[arg(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+
+    /// Run unattended as a service: log to the journal if started by systemd and don't write the
+    /// decoded packets to stdout unless --output asks for JSON, they only go to the sinks
+    #[arg(long)]
+    pub daemon: bool,
+
+    /// Show a live table of the devices with their signal strength and latest measurements
+    /// instead of printing the packets, the log messages are shown below it
+    #[arg(long, conflicts_with_all = ["output", "daemon", "forward", "stats_interval"])]
+    pub tui: bool,
+
+    /// Bluetooth adapter to monitor, can be given multiple times (default: the system's default adapter)
+    #[arg(short, long, value_name = "NAME")]
+    pub adapter: Vec<String>,
+
+    /// Monitor all available Bluetooth adapters concurrently
+    #[arg(long, conflicts_with = "adapter")]
+    pub all_adapters: bool,
+
+    /// Only decode and forward advertisements of this device, can be given multiple times
+    #[arg(long, value_name = "MAC")]
+    pub device: Vec<Address>,
+
+    /// Drop advertisements of this device, can be given multiple times
+    #[arg(long, value_name = "MAC")]
+    pub ignore: Vec<Address>,
+
+    /// BLE stack used to receive advertisements
+    #[arg(long, value_enum, value_parser = BackendParser, default_value_t)]
+    pub backend: Backend,
+
+    /// How to receive advertisements from BlueZ, with `--backend hci` `discovery` scans actively
+    /// to receive scan responses as well
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_enum, default_value_t)]
+    pub scan_mode: ScanMode,
+
+    /// Devices are considered lost by the advertisement monitor below this RSSI in dBm
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_name = "DBM", requires = "rssi_high_threshold", value_parser = clap::value_parser!(i16).range(-127..=20))]
+    pub rssi_low_threshold: Option<i16>,
+
+    /// Devices are only reported by the advertisement monitor above this RSSI in dBm
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_name = "DBM", requires = "rssi_low_threshold", value_parser = clap::value_parser!(i16).range(-127..=20))]
+    pub rssi_high_threshold: Option<i16>,
+
+    /// How long the RSSI has to stay below the low threshold before a device is considered lost
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_name = "DURATION", value_parser = crate::duration::parse)]
+    pub rssi_low_timeout: Option<Duration>,
+
+    /// How long the RSSI has to stay above the high threshold before a device is reported
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_name = "DURATION", value_parser = crate::duration::parse)]
+    pub rssi_high_timeout: Option<Duration>,
+
+    /// Which advertisements the advertisement monitor reports: all, first or one per DURATION
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_name = "all|first|DURATION")]
+    pub rssi_sampling_period: Option<SamplingPeriod>,
+
+    /// Receive advertisements relayed by an ESPHome Bluetooth proxy, can be given multiple times
+    #[arg(long, value_name = "HOST[:PORT]")]
+    pub esphome: Vec<String>,
+
+    /// Password of the ESPHome native API
+    #[arg(long, value_name = "PASSWORD")]
+    pub esphome_password: Option<String>,
+
+    /// Relay the advertisements to Home Assistant by acting as an ESPHome Bluetooth proxy on this address
+    #[arg(long, value_name = "ADDRESS:PORT")]
+    pub esphome_proxy: Option<String>,
+
+    /// Name of the ESPHome proxy in Home Assistant (default: the hostname)
+    #[arg(long, value_name = "NAME", requires = "esphome_proxy")]
+    pub esphome_proxy_name: Option<String>,
+
+    /// Decode the advertisements of a btsnoop, pcap, pcapng or .bthome capture instead of receiving live
+    #[arg(long, value_name = "FILE")]
+    pub replay: Option<PathBuf>,
+
+    /// Decode hex encoded payloads read from stdin, one per line, optionally prefixed by the MAC
+    #[arg(long, conflicts_with = "replay")]
+    pub stdin: bool,
+
+    /// Forward raw advertisements to a central instance instead of decoding them
+    #[arg(long, value_name = "tcp://HOST:PORT|udp://HOST:PORT")]
+    pub forward: Option<Endpoint>,
+
+    /// Name of this instance when forwarding (default: the hostname)
+    #[arg(long, value_name = "NAME")]
+    pub satellite_name: Option<String>,
+
+    /// Receive advertisements forwarded by satellite instances, can be given multiple times
+    #[arg(long, value_name = "tcp://ADDRESS:PORT|udp://ADDRESS:PORT")]
+    pub listen: Vec<Endpoint>,
+
+    /// Publish the decoded measurements to this MQTT broker
+    #[arg(long, value_name = "mqtt://[USER[:PASSWORD]@]HOST[:PORT]", conflicts_with = "forward")]
+    pub mqtt_url: Option<MqttUrl>,
+
+    /// Prefix of the topics, measurements are published on PREFIX/MAC/MEASUREMENT
+    #[arg(long, value_name = "PREFIX", default_value = "bthome", requires = "mqtt_url")]
+    pub mqtt_topic: String,
+
+    /// QoS level of the published measurements
+    #[arg(long, value_name = "0|1|2", default_value = "0", value_parser = crate::mqtt::parse_qos, requires = "mqtt_url")]
+    pub mqtt_qos: rumqttc::QoS,
+
+    /// Announce the measurements to Home Assistant by MQTT discovery
+    #[arg(long, requires = "mqtt_url")]
+    pub mqtt_discovery: bool,
+
+    /// Discovery prefix of Home Assistant
+    #[arg(long, value_name = "PREFIX", default_value = "homeassistant", requires = "mqtt_discovery")]
+    pub mqtt_discovery_prefix: String,
+
+    /// Write the decoded measurements to this InfluxDB server
+    #[arg(long, value_name = "http://[USER[:PASSWORD]@]HOST[:PORT]", conflicts_with = "forward")]
+    pub influx_url: Option<InfluxUrl>,
+
+    /// Database (InfluxDB 1) or bucket (InfluxDB 2) to write to
+    #[arg(long, value_name = "NAME", default_value = "bthome", requires = "influx_url")]
+    pub influx_bucket: String,
+
+    /// Organization owning the bucket, with --influx-token
+    #[arg(long, value_name = "ORG", requires = "influx_token")]
+    pub influx_org: Option<String>,
+
+    /// Write with this API token of InfluxDB 2 instead of the user and password of the URL
+    #[arg(long, value_name = "TOKEN", requires_all = ["influx_url", "influx_org"])]
+    pub influx_token: Option<String>,
+
+    /// Measurement the lines are written to
+    #[arg(long, value_name = "NAME", default_value = "bthome", requires = "influx_url")]
+    pub influx_measurement: String,
+
+    /// Write the batched lines this often
+    #[arg(long, value_name = "DURATION", default_value = "10s", value_parser = crate::duration::parse, requires = "influx_url")]
+    pub influx_flush_interval: Duration,
+
+    /// Write all received advertisements to a pcap file, which can be opened with Wireshark
+    #[arg(long, value_name = "FILE")]
+    pub capture: Option<PathBuf>,
+
+    /// Write the raw service data of all received advertisements to a .bthome capture, to decode
+    /// them again later with --replay
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+
+    /// Report every received copy of a packet instead of only the first one
+    #[arg(long, conflicts_with = "dedupe")]
+    pub keep_duplicates: bool,
+
+    /// Report only the first copy of a packet received within this time, identified by device and
+    /// packet id, or by content if the device sends no packet id. This is the default with 10s
+    #[arg(
+        long,
+        value_name = "TTL",
+        num_args = 0..=1,
+        default_value = "10s",
+        default_missing_value = "10s",
+        value_parser = crate::duration::parse
+    )]
+    pub dedupe: Duration,
+
+    /// Weight of the newest measurement in the moving average of the RSSI, 1 disables smoothing
+    #[arg(long, value_name = "FACTOR", default_value_t = 0.2, value_parser = crate::rssi::parse_factor)]
+    pub rssi_smoothing: f32,
+
+    /// Consider a device offline if it was not received for this long
+    #[arg(long, value_name = "DURATION", default_value = "10m", value_parser = crate::duration::parse)]
+    pub offline_timeout: Duration,
+
+    /// Offline timeout for trigger based devices, which only send when something happens
+    #[arg(long, value_name = "DURATION", default_value = "24h", value_parser = crate::duration::parse)]
+    pub trigger_offline_timeout: Duration,
+
+    /// Periodically print reception statistics of each device, they are always printed at exit
+    #[arg(long, value_name = "DURATION", value_parser = crate::duration::parse)]
+    pub stats_interval: Option<Duration>,
+
+    /// Exit after sniffing for this long and print a summary as JSON
+    #[arg(long, value_name = "DURATION", value_parser = crate::duration::parse)]
+    pub duration: Option<Duration>,
+
+    /// Exit after decoding this many packets and print a summary as JSON
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub count: Option<u64>,
+
+    /// With --duration or --count, exit with status 2 if this device was not received, can be
+    /// given multiple times
+    #[arg(long, value_name = "MAC")]
+    pub expect: Vec<Address>,
+
+    /// Serve /healthz and /metrics over HTTP on this address
+    #[arg(long, value_name = "ADDRESS:PORT")]
+    pub http_listen: Option<String>,
+
+    /// Serve the metrics and the latest measurements of all devices for Prometheus on this
+    /// address, like --http-listen but on the port of an exporter, e.g. 0.0.0.0:9188
+    #[arg(long, value_name = "ADDRESS:PORT")]
+    pub prometheus_listen: Option<String>,
+
+    /// Report the sniffer as unhealthy if no advertisement was received for this long
+    #[arg(long, value_name = "DURATION", default_value = "5m", value_parser = crate::duration::parse)]
+    pub health_timeout: Duration,
+
+    /// Accept commands on this Unix socket, or send the command to the sniffer listening on it
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH")]
+    pub control_socket: Option<PathBuf>,
+
+    /// Instead of sniffing, decode a capture or send a command to a running sniffer
+    #[command(subcommand)]
+    pub command: Option<SnifferCommand>,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum SnifferCommand {
+    /// Decode the BTHome advertisements of a btsnoop, pcap or pcapng capture and exit
+    ///
+    /// Captures of HCI traffic, e.g. Android HCI snoop logs of devices in the field or Wireshark
+    /// captures, are decoded with the keys of --config. The packets have to be Bluetooth HCI H1, H4
+    /// or Linux monitor packets.
+    Decode {
+        #[arg(long, value_name = "FILE")]
+        pcap: PathBuf,
+    },
+    #[cfg(unix)]
+    #[command(flatten)]
+    Control(Command),
+}
+
+impl Args {
+    /// Whether the sniffer exits on its own after a limit, instead of running until stopped.
+    pub fn one_shot(&self) -> bool {
+        self.duration.is_some() || self.count.is_some()
+    }
+
+    /// The format of the log messages, daemons started by systemd log to the journal.
+    pub fn log_format(&self) -> LogFormat {
+        match self.log_format {
+            Some(format) => format,
+            #[cfg(target_os = "linux")]
+            None if self.daemon && std::env::var_os("JOURNAL_STREAM").is_some() => LogFormat::Journald,
+            None => LogFormat::default(),
+        }
+    }
+
+    /// Whether every packet is written to stdout in human readable form.
+    pub fn prints_packets(&self) -> bool {
+        self.output == Output::Pretty && !self.daemon && !self.tui
+    }
+}
+
+/// Per device settings, keyed by MAC address in the configuration file.
+///
+/// ```toml
+/// [devices."A4:C1:38:12:34:56"]
+/// name = "Living room"
+/// offline_timeout = "1h"
+/// key = "231d39c1d7cc1ab1aee224cd096db932"
+/// ```
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct DeviceConfig {
+    /// Human friendly name used instead of the MAC address in all outputs
+    pub name: Option<String>,
+    /// Overrides `--offline-timeout` and `--trigger-offline-timeout` for this device
+    #[serde(default, deserialize_with = "crate::duration::deserialize_option")]
+    pub offline_timeout: Option<Duration>,
+    /// The bind key to decrypt the advertisements of an encrypted device
+    #[serde(default, deserialize_with = "crate::control::deserialize_key")]
+    pub key: Option<Key>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct RawConfig {
+    #[serde(default)]
+    devices: HashMap<String, DeviceConfig>,
+    #[serde(default)]
+    filter: FilterConfig,
+    #[cfg(target_os = "linux")]
+    #[serde(default)]
+    monitor: MonitorConfig,
+}
+
+#[derive(Debug, Default)]
+pub struct Config {
+    pub devices: HashMap<Address, DeviceConfig>,
+    /// The devices to accept, extended by `--device` and `--ignore`
+    pub filter: Filter,
+    /// Settings of the BlueZ advertisement monitor, overridden by the command line
+    #[cfg(target_os = "linux")]
+    pub monitor: MonitorConfig,
+}
+
+impl Config {
+    pub fn load(path: &PathBuf) -> Result<Config, Box<dyn Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Config, Box<dyn Error + Send + Sync>> {
+        let raw: RawConfig = toml::from_str(content)?;
+        let mut devices = HashMap::new();
+        for (address, device) in raw.devices {
+            let parsed: Address = address
+                .parse()
+                .map_err(|_| format!("Invalid device address {:?} in configuration", address))?;
+            devices.insert(parsed, device);
+        }
+        Ok(Config {
+            devices,
+            filter: Filter::try_from(raw.filter)?,
+            #[cfg(target_os = "linux")]
+            monitor: raw.monitor,
+        })
+    }
+
+    pub fn name(&self, address: &Address) -> Option<&str> {
+        self.devices.get(address)?.name.as_deref()
+    }
+
+    pub fn offline_timeout(&self, address: &Address) -> Option<Duration> {
+        self.devices.get(address)?.offline_timeout
+    }
+
+    pub fn key(&self, address: &Address) -> Option<&Key> {
+        self.devices.get(address)?.key.as_ref()
+    }
+
+    /// The name to show for a device, the alias if one is configured, otherwise the address.
+    pub fn label(&self, address: &Address) -> String {
+        match self.name(address) {
+            Some(name) => format!("{} ({})", name, address),
+            None => address.to_string(),
+        }
+    }
+}
+
+/// The current configuration, shared between the sources and replaced when it is reloaded.
+#[derive(Clone, Default)]
+pub struct SharedConfig(Arc<RwLock<Arc<Config>>>);
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        SharedConfig(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.0.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    pub fn set(&self, config: Config) {
+        *self.0.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(config);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_aliases() {
+        let config = Config::parse(
+            r#"
+            [devices."A4:C1:38:12:34:56"]
+            name = "Living room"
+            offline_timeout = "1h"
+            key = "231d39c1d7cc1ab1aee224cd096db932"
+
+            [devices."a4:c1:38:65:43:21"]
+            "#,
+        )
+        .expect("Config to parse");
+        let living_room = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        let unnamed = Address([0xA4, 0xC1, 0x38, 0x65, 0x43, 0x21]);
+        assert_eq!(config.name(&living_room), Some("Living room"));
+        assert_eq!(config.label(&living_room), "Living room (A4:C1:38:12:34:56)");
+        assert_eq!(config.label(&unnamed), "A4:C1:38:65:43:21");
+        assert_eq!(config.offline_timeout(&living_room), Some(Duration::from_secs(3600)));
+        assert_eq!(config.offline_timeout(&unnamed), None);
+        assert_eq!(config.key(&living_room).map(|key| key[..2].to_vec()), Some(vec![0x23, 0x1d]));
+        assert_eq!(config.key(&unnamed), None);
+        assert!(Config::parse("[devices.\"A4:C1:38:12:34:56\"]\nkey = \"231d\"").is_err());
+    }
+
+    #[test]
+    fn reject_invalid_address() {
+        assert!(Config::parse("[devices.kitchen]\nname = \"Kitchen\"").is_err());
+    }
+
+    #[test]
+    fn replace_shared_config() {
+        let address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        let shared = SharedConfig::default();
+        let before = shared.get();
+        shared.set(Config::parse("[devices.\"A4:C1:38:12:34:56\"]\nname = \"Kitchen\"").unwrap());
+        assert_eq!(before.name(&address), None);
+        assert_eq!(shared.clone().get().name(&address), Some("Kitchen"));
+    }
+
+    #[test]
+    fn daemon_mode() {
+        let args = Args::try_parse_from(["bthome-sniffer", "--daemon", "--log-format", "json"]).unwrap();
+        assert_eq!(args.log_format(), LogFormat::Json);
+        assert!(!args.prints_packets());
+        let args = Args::try_parse_from(["bthome-sniffer"]).unwrap();
+        assert_eq!(args.log_format(), LogFormat::Text);
+        assert!(args.prints_packets());
+    }
+
+    #[test]
+    fn decode_command() {
+        let args = Args::try_parse_from(["bthome-sniffer", "--output", "ndjson", "decode", "--pcap", "field.pcap"]).unwrap();
+        assert_eq!(args.command, Some(SnifferCommand::Decode { pcap: PathBuf::from("field.pcap") }));
+        #[cfg(unix)]
+        assert_eq!(
+            Args::try_parse_from(["bthome-sniffer", "devices"]).unwrap().command,
+            Some(SnifferCommand::Control(Command::Devices))
+        );
+    }
+
+    #[test]
+    fn tui_mode() {
+        let args = Args::try_parse_from(["bthome-sniffer", "--tui"]).unwrap();
+        assert!(!args.prints_packets());
+        assert!(Args::try_parse_from(["bthome-sniffer", "--tui", "--output", "json"]).is_err());
+    }
+
+    #[test]
+    fn reject_invalid_timeout() {
+        assert!(Config::parse("[devices.\"A4:C1:38:12:34:56\"]\noffline_timeout = \"soon\"").is_err());
+    }
+}
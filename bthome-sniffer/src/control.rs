@@ -0,0 +1,229 @@
+//! Control socket to inspect and manage a running sniffer, used by the `devices`, `add-key`,
+//! `mute` and `unmute` subcommands.
+//!
+//! The protocol is line based: a client sends a single command per connection, e.g.
+//! `mute A4:C1:38:12:34:56`, the sniffer answers with lines of text and closes the connection.
+//! Failures are answered with one line starting with `error: `.
+
+use std::{fmt, str::FromStr};
+
+use bthome::hex;
+use clap::Subcommand;
+use serde::{Deserialize, Deserializer};
+use tokio::sync::oneshot;
+
+use crate::address::Address;
+
+#[cfg(unix)]
+const ERROR_PREFIX: &str = "error: ";
+/// Longest command that is accepted, so a misbehaving client can't make the sniffer buffer
+/// endlessly.
+#[cfg(unix)]
+const MAX_COMMAND_LENGTH: u64 = 1024;
+
+/// AES key of an encrypted device.
+pub type Key = [u8; 16];
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum Command {
+    /// List the devices received by the running sniffer
+    Devices,
+    /// Set the encryption key of a device
+    AddKey {
+        #[arg(value_name = "MAC")]
+        address: Address,
+        /// 32 hex digits
+        #[arg(value_parser = parse_key)]
+        key: Key,
+    },
+    /// Stop printing the packets of a device
+    Mute {
+        #[arg(value_name = "MAC")]
+        address: Address,
+    },
+    /// Print the packets of a muted device again
+    Unmute {
+        #[arg(value_name = "MAC")]
+        address: Address,
+    },
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Devices => write!(f, "devices"),
+            Command::AddKey { address, key } => write!(f, "add-key {} {}", address, hex::encode(key)),
+            Command::Mute { address } => write!(f, "mute {}", address),
+            Command::Unmute { address } => write!(f, "unmute {}", address),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let address = |word: &str| -> Result<Address, String> {
+            word.parse().map_err(|_| format!("invalid device address {:?}", word))
+        };
+        match words.as_slice() {
+            ["devices"] => Ok(Command::Devices),
+            ["add-key", mac, key] => Ok(Command::AddKey {
+                address: address(mac)?,
+                key: parse_key(key)?,
+            }),
+            ["mute", mac] => Ok(Command::Mute { address: address(mac)? }),
+            ["unmute", mac] => Ok(Command::Unmute { address: address(mac)? }),
+            _ => Err(format!("unknown command {:?}", s)),
+        }
+    }
+}
+
+pub fn parse_key(s: &str) -> Result<Key, String> {
+    hex::decode(s)
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| "the key has to be 32 hex digits".to_string())
+}
+
+/// Deserializes an optional key written as hex digits, like in the configuration file.
+pub fn deserialize_key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Key>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| parse_key(&s).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// A command received on the control socket, answered by the main loop with the text to send
+/// back or an error message.
+pub struct Request {
+    pub command: Command,
+    pub reply: oneshot::Sender<Result<String, String>>,
+}
+
+#[cfg(unix)]
+pub use self::unix::{listen, send};
+
+#[cfg(unix)]
+mod unix {
+    use std::{error::Error, fs::Permissions, io, os::unix::fs::PermissionsExt, path::Path, time::Duration};
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{UnixListener, UnixStream},
+        sync::{mpsc, oneshot},
+    };
+    use tracing::{debug, warn};
+
+    use super::*;
+
+    /// Creates the control socket at `path` and passes the commands received on it to `requests`.
+    pub fn listen(path: &Path, requests: mpsc::UnboundedSender<Request>) -> io::Result<()> {
+        // Left behind by an instance that was killed
+        match std::fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, Permissions::from_mode(0o660))?;
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(serve(stream, requests.clone()));
+                    }
+                    Err(err) => {
+                        warn!(error = %err, "Error accepting control connection");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn serve(mut stream: UnixStream, requests: mpsc::UnboundedSender<Request>) {
+        let (read, mut write) = stream.split();
+        let mut line = String::new();
+        let response = match BufReader::new(read.take(MAX_COMMAND_LENGTH)).read_line(&mut line).await {
+            Ok(_) => match line.parse() {
+                Ok(command) => {
+                    let (reply, response) = oneshot::channel();
+                    if requests.send(Request { command, reply }).is_err() {
+                        Err("the sniffer is shutting down".to_string())
+                    } else {
+                        response
+                            .await
+                            .unwrap_or_else(|_| Err("the sniffer is shutting down".to_string()))
+                    }
+                }
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err.to_string()),
+        };
+        let response = response.unwrap_or_else(|err| format!("{}{}\n", ERROR_PREFIX, err));
+        if let Err(err) = write.write_all(response.as_bytes()).await {
+            debug!(error = %err, "Error answering control connection");
+        }
+    }
+
+    /// Sends `command` to the sniffer listening on the control socket at `path` and returns its
+    /// answer.
+    pub async fn send(path: &Path, command: &Command) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let mut stream = UnixStream::connect(path)
+            .await
+            .map_err(|err| format!("Error connecting to the sniffer at {}: {}", path.display(), err))?;
+        stream.write_all(format!("{}\n", command).as_bytes()).await?;
+        stream.shutdown().await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        match response.strip_prefix(ERROR_PREFIX) {
+            Some(err) => Err(err.trim_end().into()),
+            None => Ok(response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format_and_parse_commands() {
+        let address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        let commands = [
+            Command::Devices,
+            Command::AddKey {
+                address,
+                key: [0x23; 16],
+            },
+            Command::Mute { address },
+            Command::Unmute { address },
+        ];
+        for command in commands {
+            assert_eq!(command.to_string().parse(), Ok(command));
+        }
+        assert!("mute".parse::<Command>().is_err());
+        assert!("add-key A4:C1:38:12:34:56 2323".parse::<Command>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn answer_requests() {
+        let path = std::env::temp_dir().join(format!("bthome-sniffer-test-{}.sock", std::process::id()));
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        listen(&path, tx).expect("Control socket to be created");
+        tokio::spawn(async move {
+            while let Some(request) = rx.recv().await {
+                let response = match request.command {
+                    Command::Devices => Ok("No devices\n".to_string()),
+                    _ => Err("not supported".to_string()),
+                };
+                let _ = request.reply.send(response);
+            }
+        });
+        assert_eq!(send(&path, &Command::Devices).await.expect("Answer"), "No devices\n");
+        let err = send(&path, &Command::Mute { address: Address::default() }).await.unwrap_err();
+        assert_eq!(err.to_string(), "not supported");
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,210 @@
+//! Home Assistant MQTT discovery of the measurements published by [`crate::mqtt`], so that the
+//! sensors of a device appear in Home Assistant with their units once they are received.
+
+use bthome::{Object, ObjectValue};
+use serde_json::{json, Value};
+
+use crate::address::Address;
+
+/// Device classes of Home Assistant by object name, objects missing here have none.
+const DEVICE_CLASSES: &[(&str, &str)] = &[
+    ("battery", "battery"),
+    ("temperature", "temperature"),
+    ("humidity", "humidity"),
+    ("pressure", "pressure"),
+    ("illuminance", "illuminance"),
+    ("mass", "weight"),
+    ("dewpoint", "temperature"),
+    ("energy", "energy"),
+    ("power", "power"),
+    ("voltage", "voltage"),
+    ("pm2_5", "pm25"),
+    ("pm10", "pm10"),
+    ("co2", "carbon_dioxide"),
+    ("tvoc", "volatile_organic_compounds"),
+    ("moisture", "moisture"),
+    ("distance", "distance"),
+    ("duration", "duration"),
+    ("current", "current"),
+    ("speed", "speed"),
+    ("volume", "volume"),
+    ("volume_flow_rate", "volume_flow_rate"),
+    ("gas", "gas"),
+    ("water", "water"),
+    ("volume_storage", "volume_storage"),
+    ("conductivity", "conductivity"),
+    ("precipitation", "precipitation"),
+    // Binary sensors
+    ("power_on", "power"),
+    ("opening", "opening"),
+    ("battery_low", "battery"),
+    ("battery_charging", "battery_charging"),
+    ("carbon_monoxide", "carbon_monoxide"),
+    ("cold", "cold"),
+    ("connectivity", "connectivity"),
+    ("door", "door"),
+    ("garage_door", "garage_door"),
+    ("gas_detected", "gas"),
+    ("heat", "heat"),
+    ("light", "light"),
+    ("lock", "lock"),
+    ("moisture_detected", "moisture"),
+    ("motion", "motion"),
+    ("moving", "moving"),
+    ("occupancy", "occupancy"),
+    ("plug", "plug"),
+    ("presence", "presence"),
+    ("problem", "problem"),
+    ("running", "running"),
+    ("smoke", "smoke"),
+    ("sound", "sound"),
+    ("tamper", "tamper"),
+    ("vibration", "vibration"),
+    ("window", "window"),
+];
+
+/// The id of a device in topics and unique ids, its address without separators.
+pub fn node_id(address: &Address) -> String {
+    address.0.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The key of each object in topics and entity ids: its name, numbered from the second object
+/// with the same name on like `temperature_2`, also if different ids share the name.
+pub fn object_keys(objects: &[Object]) -> Vec<String> {
+    let mut names: Vec<&str> = Vec::new();
+    objects
+        .iter()
+        .map(|object| {
+            let name = object.object_id.name();
+            let instance = names.iter().filter(|seen| **seen == name).count();
+            names.push(name);
+            if instance == 0 {
+                name.to_string()
+            } else {
+                format!("{}_{}", name, instance + 1)
+            }
+        })
+        .collect()
+}
+
+/// The discovery topic and retained configuration of the entity for `object` with `key`, see
+/// [`object_keys`], whose state is published on `state_topic`. The entity is unavailable while
+/// `availability_topic` says the device is `offline`. Packet ids and events have no entity.
+pub fn entity_config(
+    discovery_prefix: &str,
+    state_topic: &str,
+    availability_topic: &str,
+    address: &Address,
+    device_name: Option<&str>,
+    object: &Object,
+    key: &str,
+) -> Option<(String, Value)> {
+    let name = object.object_id.name();
+    let component = match object.value {
+        ObjectValue::Bool(_) => "binary_sensor",
+        ObjectValue::Float(_)
+        | ObjectValue::Int(_)
+        | ObjectValue::UInt(_)
+        | ObjectValue::Text(_)
+        | ObjectValue::Raw(_)
+        | ObjectValue::FirmwareVersion(_)
+        | ObjectValue::DeviceType(_) => "sensor",
+        ObjectValue::ButtonEvent(_) | ObjectValue::DimmerEvent(..) => return None,
+    };
+    if name == "packet_id" {
+        return None;
+    }
+    let node = node_id(address);
+    let mut config = json!({
+        "name": title(key),
+        "unique_id": format!("bthome_sniffer_{}_{}", node, key),
+        "state_topic": state_topic,
+        "availability_topic": availability_topic,
+        "device": {
+            "identifiers": [format!("bthome_sniffer_{}", node)],
+            "connections": [["mac", address.to_string()]],
+            "name": device_name.map_or_else(|| format!("BTHome {}", address), str::to_string),
+        },
+    });
+    if component == "binary_sensor" {
+        config["value_template"] = json!("{{ 'ON' if value_json.value else 'OFF' }}");
+    } else {
+        config["value_template"] = json!("{{ value_json.value }}");
+    }
+    if let Some((_, device_class)) = DEVICE_CLASSES.iter().find(|(object, _)| *object == name) {
+        config["device_class"] = json!(device_class);
+    }
+    if let Some(unit) = object.object_id.unit() {
+        config["unit_of_measurement"] = json!(unit);
+        config["state_class"] = json!("measurement");
+    }
+    let topic = format!("{}/{}/{}/{}/config", discovery_prefix.trim_end_matches('/'), component, node, key);
+    Some((topic, config))
+}
+
+/// Turns a name like `battery_low` into `Battery low`.
+fn title(name: &str) -> String {
+    let text = name.replace('_', " ");
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bthome::parse_service_data;
+
+    use super::*;
+
+    const AVAILABILITY: &str = "bthome/a4c138123456/availability";
+
+    #[test]
+    fn discovery_config() {
+        let address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        // Packet id, temperature, window and a press
+        let objects = parse_service_data(&[0x44, 0x00, 0x09, 0x02, 0xC4, 0x09, 0x2D, 0x01, 0x3A, 0x01]).unwrap().objects;
+        let state_topic = "bthome/a4c138123456/temperature";
+        let keys = object_keys(&objects);
+        let entity = |state_topic, name, index: usize| {
+            entity_config("homeassistant", state_topic, AVAILABILITY, &address, name, &objects[index], &keys[index])
+        };
+        assert!(entity(state_topic, None, 0).is_none());
+
+        let (topic, config) = entity(state_topic, Some("Kitchen"), 1).unwrap();
+        assert_eq!(topic, "homeassistant/sensor/a4c138123456/temperature/config");
+        assert_eq!(config["state_topic"], state_topic);
+        assert_eq!(config["availability_topic"], AVAILABILITY);
+        assert_eq!(config["value_template"], "{{ value_json.value }}");
+        assert_eq!(config["device_class"], "temperature");
+        assert_eq!(config["unit_of_measurement"], "°C");
+        assert_eq!(config["device"]["name"], "Kitchen");
+
+        let (topic, config) = entity("bthome/a4c138123456/window", None, 2).unwrap();
+        assert_eq!(topic, "homeassistant/binary_sensor/a4c138123456/window/config");
+        assert_eq!(config["value_template"], "{{ 'ON' if value_json.value else 'OFF' }}");
+        assert_eq!(config["device"]["name"], "BTHome A4:C1:38:12:34:56");
+        assert!(config.get("unit_of_measurement").is_none());
+
+        assert!(entity("bthome/a4c138123456/button", None, 3).is_none());
+    }
+
+    #[test]
+    fn repeated_objects() {
+        // Two temperatures, a temperature with another id and a humidity
+        let data = [0x40, 0x02, 0xC4, 0x09, 0x02, 0x2B, 0x09, 0x45, 0xFA, 0x00, 0x03, 0xBF, 0x13];
+        let objects = parse_service_data(&data).unwrap().objects;
+        let keys = object_keys(&objects);
+        assert_eq!(keys, ["temperature", "temperature_2", "temperature_3", "humidity"]);
+
+        let address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        let state_topic = "bthome/a4c138123456/temperature_2";
+        let (topic, config) =
+            entity_config("homeassistant", state_topic, AVAILABILITY, &address, None, &objects[1], &keys[1]).unwrap();
+        assert_eq!(topic, "homeassistant/sensor/a4c138123456/temperature_2/config");
+        assert_eq!(config["unique_id"], "bthome_sniffer_a4c138123456_temperature_2");
+        assert_eq!(config["name"], "Temperature 2");
+        assert_eq!(config["device_class"], "temperature");
+    }
+}
@@ -0,0 +1,60 @@
+//! Parsing of human friendly durations like `30s`, `10m` or `1h30m` in arguments and configuration.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+
+pub fn parse(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration {:?}, expected e.g. 30s, 10m or 1h30m", s);
+    let mut total = Duration::ZERO;
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let value: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+        let unit = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let seconds = match &rest[..unit] {
+            "ms" => {
+                total += Duration::from_millis(value);
+                rest = &rest[unit..];
+                continue;
+            }
+            "s" => value,
+            "m" => value * 60,
+            "h" => value * 60 * 60,
+            "d" => value * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        total += Duration::from_secs(seconds);
+        rest = &rest[unit..];
+    }
+    Ok(total)
+}
+
+/// Deserializes an optional duration given as a string, for use with `#[serde(deserialize_with)]`.
+pub fn deserialize_option<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| parse(&s).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse("2d"), Ok(Duration::from_secs(172_800)));
+        assert_eq!(parse("250ms"), Ok(Duration::from_millis(250)));
+        assert!(parse("").is_err());
+        assert!(parse("10").is_err());
+        assert!(parse("m").is_err());
+        assert!(parse("10 minutes").is_err());
+    }
+}
@@ -0,0 +1,393 @@
+//! Serves the ESPHome native API like a Bluetooth proxy, so that Home Assistant receives the
+//! advertisements of the sniffer with its ESPHome integration.
+//!
+//! Only the plaintext protocol and the messages Home Assistant needs to use a passive proxy are
+//! implemented, other requests are ignored. The proxy has no entities and can not connect to
+//! devices.
+
+use std::{io, sync::Arc};
+
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use tokio::{
+    io::{AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, mpsc},
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    address::{Address, AddressType},
+    hci::advertising_data,
+    source::{
+        esphome::{
+            fields, put_bytes, put_string, put_varint_field, read_message, write_message, Value,
+            BLUETOOTH_LE_ADVERTISEMENT_RESPONSE, BLUETOOTH_LE_RAW_ADVERTISEMENTS_RESPONSE, CONNECT_REQUEST,
+            CONNECT_RESPONSE, DISCONNECT_REQUEST, DISCONNECT_RESPONSE, HELLO_REQUEST, PING_REQUEST, PING_RESPONSE,
+            SUBSCRIBE_BLUETOOTH_LE_ADVERTISEMENTS_REQUEST, SUBSCRIPTION_FLAG_RAW_ADVERTISEMENTS,
+        },
+        Advertisement, SourceError,
+    },
+};
+
+const SERVICE_TYPE: &str = "_esphomelib._tcp.local.";
+/// Home Assistant enables features based on the version, so a recent release is claimed
+const ESPHOME_VERSION: &str = "2024.12.0";
+const API_VERSION: (u64, u64) = (1, 10);
+
+const HELLO_RESPONSE: u32 = 2;
+const DEVICE_INFO_REQUEST: u32 = 9;
+const DEVICE_INFO_RESPONSE: u32 = 10;
+const LIST_ENTITIES_REQUEST: u32 = 11;
+const LIST_ENTITIES_DONE_RESPONSE: u32 = 19;
+const SUBSCRIBE_BLUETOOTH_CONNECTIONS_FREE_REQUEST: u32 = 80;
+const BLUETOOTH_CONNECTIONS_FREE_RESPONSE: u32 = 81;
+const UNSUBSCRIBE_BLUETOOTH_LE_ADVERTISEMENTS_REQUEST: u32 = 87;
+
+const FEATURE_PASSIVE_SCAN: u64 = 1;
+const FEATURE_RAW_ADVERTISEMENTS: u64 = 32;
+
+/// Advertisements queued per client, older ones are dropped if a client falls behind.
+const QUEUE_LENGTH: usize = 256;
+/// Most advertisements sent in one message, like ESPHome batches them.
+const MAX_BATCH: usize = 16;
+
+#[derive(Debug, Clone)]
+struct Relayed {
+    address: Address,
+    address_type: Option<AddressType>,
+    rssi: i16,
+    service_data: Vec<u8>,
+}
+
+/// What the proxy tells about itself.
+struct DeviceInfo {
+    /// Host name like name, e.g. `bthome-sniffer`
+    name: String,
+    friendly_name: String,
+    /// Made up from the name, Home Assistant identifies the proxy by it
+    mac: Address,
+}
+
+impl DeviceInfo {
+    fn new(friendly_name: &str) -> Self {
+        let name: String = friendly_name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+            .collect();
+        // FNV-1a, so that the address stays the same across restarts and builds
+        let hash = name
+            .bytes()
+            .fold(0xCBF2_9CE4_8422_2325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100_0000_01B3));
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(&hash.to_be_bytes()[2..]);
+        // Locally administered unicast address
+        mac[0] = mac[0] & 0xFC | 0x02;
+        DeviceInfo {
+            name,
+            friendly_name: friendly_name.to_string(),
+            mac: Address(mac),
+        }
+    }
+
+    fn hello(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        put_varint_field(&mut payload, 1, API_VERSION.0);
+        put_varint_field(&mut payload, 2, API_VERSION.1);
+        put_string(&mut payload, 3, concat!("bthome-sniffer ", env!("CARGO_PKG_VERSION")));
+        put_string(&mut payload, 4, &self.name);
+        payload
+    }
+
+    fn device_info(&self) -> Vec<u8> {
+        let mac = self.mac.to_string();
+        let mut payload = Vec::new();
+        put_string(&mut payload, 2, &self.name);
+        put_string(&mut payload, 3, &mac);
+        put_string(&mut payload, 4, ESPHOME_VERSION);
+        put_string(&mut payload, 6, "bthome-sniffer");
+        put_string(&mut payload, 12, "bthome-rs");
+        put_string(&mut payload, 13, &self.friendly_name);
+        put_varint_field(&mut payload, 15, FEATURE_PASSIVE_SCAN | FEATURE_RAW_ADVERTISEMENTS);
+        put_string(&mut payload, 18, &mac);
+        payload
+    }
+}
+
+/// Accepts connections of Home Assistant and relays the advertisements passed to [`Proxy::send`].
+pub struct Proxy {
+    tx: broadcast::Sender<Relayed>,
+    daemon: Option<(ServiceDaemon, String)>,
+}
+
+impl Proxy {
+    /// Listens on `address` and announces the proxy via mDNS, so that Home Assistant discovers it.
+    pub async fn start(address: &str, name: &str) -> io::Result<Proxy> {
+        let listener = TcpListener::bind(address).await?;
+        let port = listener.local_addr()?.port();
+        let info = Arc::new(DeviceInfo::new(name));
+        let (tx, _) = broadcast::channel(QUEUE_LENGTH);
+        let daemon = match announce(&info, port) {
+            Ok(daemon) => Some(daemon),
+            Err(err) => {
+                warn!(error = %err, "Could not announce the ESPHome proxy, it has to be added to Home Assistant manually");
+                None
+            }
+        };
+        info!(address, name = info.name, mac = %info.mac, "Serving the ESPHome native API");
+
+        let advertisements = tx.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(err) => {
+                        warn!(error = %err, "Error accepting ESPHome API connection");
+                        continue;
+                    }
+                };
+                let info = info.clone();
+                let advertisements = advertisements.clone();
+                tokio::spawn(async move {
+                    debug!(%peer, "ESPHome API client connected");
+                    if let Err(err) = serve(stream, &info, advertisements).await {
+                        debug!(%peer, error = %err, "ESPHome API connection failed");
+                    }
+                    debug!(%peer, "ESPHome API client disconnected");
+                });
+            }
+        });
+        Ok(Proxy { tx, daemon })
+    }
+
+    /// Relays an advertisement to all subscribed clients.
+    pub fn send(&self, advertisement: &Advertisement) {
+        // Nobody is subscribed if Home Assistant is not connected
+        let _ = self.tx.send(Relayed {
+            address: advertisement.address,
+            address_type: advertisement.address_type,
+            // ESPHome has no way to tell that the RSSI is unknown, report the weakest signal
+            rssi: advertisement.rssi.unwrap_or(-127),
+            service_data: advertisement.service_data.clone(),
+        });
+    }
+
+    /// Withdraws the mDNS announcement.
+    pub fn shutdown(self) {
+        if let Some((daemon, fullname)) = self.daemon {
+            if let Ok(receiver) = daemon.unregister(&fullname) {
+                let _ = receiver.recv_timeout(std::time::Duration::from_secs(1));
+            }
+            let _ = daemon.shutdown();
+        }
+    }
+}
+
+fn announce(info: &DeviceInfo, port: u16) -> Result<(ServiceDaemon, String), mdns_sd::Error> {
+    let daemon = ServiceDaemon::new()?;
+    let properties = vec![
+        ("mac", info.mac.to_string().replace(':', "").to_ascii_lowercase()),
+        ("version", ESPHOME_VERSION.to_string()),
+        ("friendly_name", info.friendly_name.clone()),
+        ("platform", "bthome-sniffer".to_string()),
+        ("network", "ethernet".to_string()),
+    ];
+    let host = format!("{}.local.", info.name);
+    let service = ServiceInfo::new(SERVICE_TYPE, &info.name, &host, "", port, properties.as_slice())?.enable_addr_auto();
+    let fullname = service.get_fullname().to_string();
+    daemon.register(service)?;
+    Ok((daemon, fullname))
+}
+
+async fn serve(stream: TcpStream, info: &DeviceInfo, advertisements: broadcast::Sender<Relayed>) -> Result<(), SourceError> {
+    let (reader, mut writer) = stream.into_split();
+    // Reading a message is not cancel safe, so it is done in a task of its own
+    let (requests_tx, mut requests) = mpsc::unbounded_channel();
+    let reader = tokio::spawn(async move {
+        let mut reader = BufReader::new(reader);
+        // Clients are not authenticated, messages beyond the maximum length close the connection
+        loop {
+            match read_message(&mut reader).await {
+                Ok(request) => {
+                    if requests_tx.send(request).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    debug!(error = %err, "Closing ESPHome API connection");
+                    break;
+                }
+            }
+        }
+    });
+    let result = async {
+        let mut subscription = None;
+        let mut raw = false;
+        loop {
+            tokio::select! {
+                request = requests.recv() => {
+                    let Some((message_type, payload)) = request else {
+                        return Ok(());
+                    };
+                    match message_type {
+                        HELLO_REQUEST => write_message(&mut writer, HELLO_RESPONSE, &info.hello()).await?,
+                        // There is no password, any password is accepted
+                        CONNECT_REQUEST => write_message(&mut writer, CONNECT_RESPONSE, &[]).await?,
+                        DISCONNECT_REQUEST => {
+                            write_message(&mut writer, DISCONNECT_RESPONSE, &[]).await?;
+                            return Ok(());
+                        }
+                        DISCONNECT_RESPONSE => return Ok(()),
+                        PING_REQUEST => write_message(&mut writer, PING_RESPONSE, &[]).await?,
+                        DEVICE_INFO_REQUEST => write_message(&mut writer, DEVICE_INFO_RESPONSE, &info.device_info()).await?,
+                        LIST_ENTITIES_REQUEST => write_message(&mut writer, LIST_ENTITIES_DONE_RESPONSE, &[]).await?,
+                        SUBSCRIBE_BLUETOOTH_LE_ADVERTISEMENTS_REQUEST => {
+                            raw = fields(&payload).any(|(field, value)| {
+                                matches!((field, value), (1, Value::Varint(flags)) if flags & SUBSCRIPTION_FLAG_RAW_ADVERTISEMENTS != 0)
+                            });
+                            subscription = Some(advertisements.subscribe());
+                        }
+                        UNSUBSCRIBE_BLUETOOTH_LE_ADVERTISEMENTS_REQUEST => subscription = None,
+                        // No connections to devices are possible, zero free of zero
+                        SUBSCRIBE_BLUETOOTH_CONNECTIONS_FREE_REQUEST => {
+                            write_message(&mut writer, BLUETOOTH_CONNECTIONS_FREE_RESPONSE, &[]).await?
+                        }
+                        _ => debug!(message_type, "Ignoring ESPHome API request"),
+                    }
+                }
+                // The precondition is checked before the future is created, so unwrapping is safe
+                batch = async { next_batch(subscription.as_mut().unwrap()).await }, if subscription.is_some() => {
+                    let Some(batch) = batch else {
+                        return Ok(());
+                    };
+                    if raw {
+                        write_message(&mut writer, BLUETOOTH_LE_RAW_ADVERTISEMENTS_RESPONSE, &raw_advertisements(&batch)).await?;
+                    } else {
+                        for relayed in &batch {
+                            write_message(&mut writer, BLUETOOTH_LE_ADVERTISEMENT_RESPONSE, &advertisement(relayed)).await?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    .await;
+    reader.abort();
+    let _ = writer.shutdown().await;
+    result
+}
+
+/// Waits for the next advertisements, returns `None` when the sniffer stops.
+async fn next_batch(subscription: &mut broadcast::Receiver<Relayed>) -> Option<Vec<Relayed>> {
+    let mut batch = Vec::new();
+    while batch.is_empty() {
+        match subscription.recv().await {
+            Ok(relayed) => batch.push(relayed),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                debug!(skipped, "ESPHome API client is too slow, dropped advertisements")
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+    while batch.len() < MAX_BATCH {
+        match subscription.try_recv() {
+            Ok(relayed) => batch.push(relayed),
+            Err(_) => break,
+        }
+    }
+    Some(batch)
+}
+
+fn mac(address: &Address) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes[2..].copy_from_slice(&address.0);
+    u64::from_be_bytes(bytes)
+}
+
+fn zigzag_encode(value: i16) -> u64 {
+    ((value as i64) << 1 ^ (value as i64) >> 63) as u64
+}
+
+/// Encodes a `BluetoothLERawAdvertisementsResponse`.
+fn raw_advertisements(batch: &[Relayed]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    for relayed in batch {
+        let mut advertisement = Vec::new();
+        put_varint_field(&mut advertisement, 1, mac(&relayed.address));
+        put_varint_field(&mut advertisement, 2, zigzag_encode(relayed.rssi));
+        if let Some(address_type) = relayed.address_type {
+            put_varint_field(&mut advertisement, 3, address_type.hci().into());
+        }
+        put_bytes(&mut advertisement, 4, &advertising_data(&relayed.service_data));
+        put_bytes(&mut payload, 1, &advertisement);
+    }
+    payload
+}
+
+/// Encodes a `BluetoothLEAdvertisementResponse`, for clients not asking for raw advertisements.
+fn advertisement(relayed: &Relayed) -> Vec<u8> {
+    let mut service_data = Vec::new();
+    put_string(&mut service_data, 1, "0000fcd2-0000-1000-8000-00805f9b34fb");
+    put_bytes(&mut service_data, 3, &relayed.service_data);
+    let mut payload = Vec::new();
+    put_varint_field(&mut payload, 1, mac(&relayed.address));
+    put_varint_field(&mut payload, 3, zigzag_encode(relayed.rssi));
+    put_bytes(&mut payload, 5, &service_data);
+    if let Some(address_type) = relayed.address_type {
+        put_varint_field(&mut payload, 7, address_type.hci().into());
+    }
+    payload
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::source::esphome::{parse_advertisement, parse_raw_advertisements};
+
+    #[test]
+    fn encode_advertisements() {
+        let relayed = Relayed {
+            address: Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]),
+            address_type: Some(AddressType::Random),
+            rssi: -60,
+            service_data: vec![0x40, 0x02, 0xC4, 0x09],
+        };
+        let expected = (relayed.address, relayed.address_type, -60, relayed.service_data.clone());
+        assert_eq!(
+            parse_raw_advertisements(&raw_advertisements(&[relayed.clone(), relayed.clone()])),
+            vec![expected.clone(), expected.clone()]
+        );
+        assert_eq!(parse_advertisement(&advertisement(&relayed)), Some(expected));
+        assert_eq!(zigzag_encode(1), 2);
+        assert_eq!(zigzag_encode(-127), 253);
+    }
+
+    #[tokio::test]
+    async fn close_on_long_messages() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (advertisements, _) = broadcast::channel(1);
+        let server = tokio::spawn(async move { serve(stream, &DeviceInfo::new("proxy"), advertisements).await });
+
+        let mut frame = vec![0x00];
+        crate::source::esphome::put_varint(&mut frame, u32::MAX as u64);
+        frame.push(HELLO_REQUEST as u8);
+        client.write_all(&frame).await.unwrap();
+        server.await.unwrap().unwrap();
+        let mut rest = Vec::new();
+        assert_eq!(tokio::io::AsyncReadExt::read_to_end(&mut client, &mut rest).await.unwrap(), 0);
+    }
+
+    #[test]
+    fn device_info() {
+        let info = DeviceInfo::new("Living room");
+        assert_eq!(info.name, "living-room");
+        assert_eq!(info.mac.0[0] & 0x03, 0x02);
+        assert_eq!(DeviceInfo::new("Living room").mac, info.mac);
+        let payload = info.device_info();
+        let flags = fields(&payload)
+            .find(|(field, _)| *field == 15)
+            .map(|(_, value)| value);
+        assert_eq!(flags, Some(Value::Varint(FEATURE_PASSIVE_SCAN | FEATURE_RAW_ADVERTISEMENTS)));
+    }
+}
@@ -0,0 +1,87 @@
+//! The devices whose advertisements are decoded and forwarded, given with `--device` and
+//! `--ignore` or in the `[filter]` section of the configuration file for long lists:
+//!
+//! ```toml
+//! [filter]
+//! devices = ["A4:C1:38:12:34:56", "A4:C1:38:65:43:21"]
+//! ignore = ["3C:E9:0E:AA:BB:CC"]
+//! ```
+
+use std::collections::HashSet;
+
+use serde::Deserialize;
+
+use crate::{address::Address, config::Args};
+
+/// The `[filter]` section of the configuration file.
+#[derive(Deserialize, Debug, Default)]
+pub struct FilterConfig {
+    #[serde(default)]
+    devices: Vec<String>,
+    #[serde(default)]
+    ignore: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Filter {
+    /// Only these devices are accepted, all if empty
+    pub devices: HashSet<Address>,
+    /// Never accepted, even if listed in `devices`
+    pub ignore: HashSet<Address>,
+}
+
+impl TryFrom<FilterConfig> for Filter {
+    type Error = String;
+
+    fn try_from(config: FilterConfig) -> Result<Self, Self::Error> {
+        let parse = |addresses: Vec<String>| {
+            addresses
+                .into_iter()
+                .map(|address| address.parse().map_err(|_| format!("Invalid address {:?} in the filter", address)))
+                .collect::<Result<HashSet<Address>, String>>()
+        };
+        Ok(Filter {
+            devices: parse(config.devices)?,
+            ignore: parse(config.ignore)?,
+        })
+    }
+}
+
+impl Filter {
+    /// Adds the devices given on the command line to those of the configuration file.
+    pub fn with_args(&self, args: &Args) -> Filter {
+        let mut filter = self.clone();
+        filter.devices.extend(&args.device);
+        filter.ignore.extend(&args.ignore);
+        filter
+    }
+
+    pub fn accepts(&self, address: &Address) -> bool {
+        !self.ignore.contains(address) && (self.devices.is_empty() || self.devices.contains(address))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn allow_and_deny_lists() {
+        let kitchen = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        let bedroom = Address([0xA4, 0xC1, 0x38, 0x65, 0x43, 0x21]);
+        let neighbour = Address([0x3C, 0xE9, 0x0E, 0xAA, 0xBB, 0xCC]);
+        assert!(Filter::default().accepts(&neighbour));
+
+        let config = Config::parse("[filter]\ndevices = [\"A4:C1:38:12:34:56\"]\n").expect("Config to parse");
+        let args = Args::parse_from(["bthome-sniffer", "--device", "A4:C1:38:65:43:21", "--ignore", "A4:C1:38:12:34:56"]);
+        let filter = config.filter.with_args(&args);
+        assert!(!filter.accepts(&kitchen));
+        assert!(filter.accepts(&bedroom));
+        assert!(!filter.accepts(&neighbour));
+
+        assert!(Config::parse("[filter]\nignore = [\"neighbour\"]\n").is_err());
+    }
+}
@@ -0,0 +1,235 @@
+//! Forwarding of raw advertisements from satellite instances to a central instance.
+//!
+//! Each advertisement is sent as one line of JSON over TCP, or as one datagram over UDP:
+//!
+//! ```json
+//! {"satellite":"kitchen-pi","source":"hci0","address":"A4:C1:38:12:34:56","address_type":"random","rssi":-60,"advertising":"legacy","timestamp":1700000000123,"data":"4002c409"}
+//! ```
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use bthome::hex;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{lookup_host, TcpStream, UdpSocket},
+};
+use tracing::warn;
+
+use crate::{
+    address::{Address, AddressType},
+    hci::AdvertisingKind,
+    source::Advertisement,
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Where to forward advertisements to, or where to listen for forwarded advertisements.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(String),
+    Udp(String),
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(address) = s.strip_prefix("tcp://") {
+            Ok(Endpoint::Tcp(address.to_string()))
+        } else if let Some(address) = s.strip_prefix("udp://") {
+            Ok(Endpoint::Udp(address.to_string()))
+        } else {
+            Err(format!("expected tcp://HOST:PORT or udp://HOST:PORT, got {:?}", s))
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(address) => write!(f, "tcp://{}", address),
+            Endpoint::Udp(address) => write!(f, "udp://{}", address),
+        }
+    }
+}
+
+/// An advertisement as sent over the wire.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ForwardedAdvertisement {
+    pub satellite: String,
+    pub source: String,
+    pub address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address_type: Option<AddressType>,
+    pub rssi: Option<i16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub advertising: Option<AdvertisingKind>,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    /// Hex encoded BTHome service data
+    pub data: String,
+}
+
+impl ForwardedAdvertisement {
+    pub fn new(satellite: &str, advertisement: &Advertisement) -> Self {
+        ForwardedAdvertisement {
+            satellite: satellite.to_string(),
+            source: advertisement.source.clone(),
+            address: advertisement.address.to_string(),
+            address_type: advertisement.address_type,
+            rssi: advertisement.rssi,
+            advertising: advertisement.advertising,
+            timestamp: advertisement
+                .received
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            data: hex::encode(&advertisement.service_data),
+        }
+    }
+
+    /// Converts back into an advertisement, the source is prefixed with the satellite name.
+    pub fn into_advertisement(self) -> Result<Advertisement, String> {
+        let address: Address = self
+            .address
+            .parse()
+            .map_err(|_| format!("invalid address {:?}", self.address))?;
+        let service_data = hex::decode(&self.data).ok_or_else(|| format!("invalid service data {:?}", self.data))?;
+        Ok(Advertisement {
+            source: format!("{}/{}", self.satellite, self.source),
+            address,
+            address_type: self.address_type,
+            rssi: self.rssi,
+            advertising: self.advertising,
+            received: UNIX_EPOCH + Duration::from_millis(self.timestamp),
+            service_data,
+        })
+    }
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+/// Sends advertisements to a central instance, reconnecting as necessary.
+///
+/// Advertisements received while the central instance is unreachable are dropped.
+pub struct Forwarder {
+    endpoint: Endpoint,
+    satellite: String,
+    connection: Option<Connection>,
+    last_attempt: Option<Instant>,
+}
+
+impl Forwarder {
+    pub fn new(endpoint: Endpoint, satellite: String) -> Self {
+        Forwarder {
+            endpoint,
+            satellite,
+            connection: None,
+            last_attempt: None,
+        }
+    }
+
+    /// Sends an advertisement, returns whether it was sent. Advertisements are dropped while the
+    /// central instance is unreachable.
+    pub async fn send(&mut self, advertisement: &Advertisement) -> bool {
+        let mut line = serde_json::to_string(&ForwardedAdvertisement::new(&self.satellite, advertisement))
+            .expect("Advertisement to be serializable");
+        line.push('\n');
+        if self.connection.is_none() {
+            if self.last_attempt.is_some_and(|t| t.elapsed() < RECONNECT_DELAY) {
+                return false;
+            }
+            self.last_attempt = Some(Instant::now());
+            match self.connect().await {
+                Ok(connection) => self.connection = Some(connection),
+                Err(err) => {
+                    warn!(endpoint = %self.endpoint, error = %err, "Could not connect to central instance");
+                    return false;
+                }
+            }
+        }
+        let result = match self.connection.as_mut() {
+            Some(Connection::Tcp(stream)) => stream.write_all(line.as_bytes()).await,
+            Some(Connection::Udp(socket)) => socket.send(line.as_bytes()).await.map(|_| ()),
+            None => return false,
+        };
+        if let Err(err) = result {
+            warn!(endpoint = %self.endpoint, error = %err, "Forwarding failed");
+            self.connection = None;
+            return false;
+        }
+        true
+    }
+
+    /// Closes the connection after everything sent so far was written.
+    pub async fn close(&mut self) {
+        if let Some(Connection::Tcp(mut stream)) = self.connection.take() {
+            if let Err(err) = stream.shutdown().await {
+                warn!(endpoint = %self.endpoint, error = %err, "Error closing connection");
+            }
+        }
+    }
+
+    async fn connect(&self) -> std::io::Result<Connection> {
+        match &self.endpoint {
+            Endpoint::Tcp(address) => Ok(Connection::Tcp(TcpStream::connect(address).await?)),
+            Endpoint::Udp(address) => {
+                let target = lookup_host(address)
+                    .await?
+                    .next()
+                    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "host not found"))?;
+                let local = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = UdpSocket::bind(local).await?;
+                socket.connect(target).await?;
+                Ok(Connection::Udp(socket))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let advertisement = Advertisement {
+            source: "hci0".to_string(),
+            address: Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]),
+            address_type: Some(AddressType::Random),
+            rssi: Some(-60),
+            advertising: Some(AdvertisingKind::Extended),
+            received: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            service_data: vec![0x40, 0x02, 0xC4, 0x09],
+        };
+        let line = serde_json::to_string(&ForwardedAdvertisement::new("kitchen", &advertisement)).unwrap();
+        assert_eq!(
+            line,
+            r#"{"satellite":"kitchen","source":"hci0","address":"A4:C1:38:12:34:56","address_type":"random","rssi":-60,"advertising":"extended","timestamp":1700000000123,"data":"4002c409"}"#
+        );
+        let forwarded: ForwardedAdvertisement = serde_json::from_str(&line).unwrap();
+        let received = forwarded.into_advertisement().expect("Advertisement to be valid");
+        assert_eq!(received.source, "kitchen/hci0");
+        assert_eq!(received.address, advertisement.address);
+        assert_eq!(received.address_type, advertisement.address_type);
+        assert_eq!(received.rssi, advertisement.rssi);
+        assert_eq!(received.advertising, advertisement.advertising);
+        assert_eq!(received.received, advertisement.received);
+        assert_eq!(received.service_data, advertisement.service_data);
+    }
+
+    #[test]
+    fn parse_endpoint() {
+        assert_eq!("tcp://central:7000".parse(), Ok(Endpoint::Tcp("central:7000".to_string())));
+        assert_eq!("udp://10.0.0.1:7000".parse(), Ok(Endpoint::Udp("10.0.0.1:7000".to_string())));
+        assert!("central:7000".parse::<Endpoint>().is_err());
+    }
+}
@@ -0,0 +1,137 @@
+//! Building HCI event packets for capture files and ESPHome proxies, they are parsed with
+//! [`bthome::hci`].
+
+use std::fmt;
+
+use bthome::{
+    hci::{Report, EVT_LE_ADVERTISING_REPORT, EVT_LE_EXTENDED_ADVERTISING_REPORT, EVT_LE_META_EVENT, HCI_EVENT_PKT},
+    BTHOME_UUID16,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::address::{Address, AddressType};
+
+const AD_TYPE_SERVICE_DATA_UUID16: u8 = 0x16;
+
+/// Whether an advertisement was sent with the legacy PDUs, which carry at most 31 bytes, or with
+/// the extended PDUs of Bluetooth 5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdvertisingKind {
+    Legacy,
+    Extended,
+}
+
+impl fmt::Display for AdvertisingKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdvertisingKind::Legacy => write!(f, "legacy"),
+            AdvertisingKind::Extended => write!(f, "extended"),
+        }
+    }
+}
+
+impl AdvertisingKind {
+    /// The PDUs `report` was sent with.
+    pub fn of(report: &Report) -> AdvertisingKind {
+        if report.extended {
+            AdvertisingKind::Extended
+        } else {
+            AdvertisingKind::Legacy
+        }
+    }
+}
+
+/// Builds an LE advertising report event carrying `service_data` as BTHome service data, for
+/// writing advertisements that were not received from a raw HCI socket to capture files.
+/// Extended advertisements are written as extended advertising reports, everything else as
+/// legacy ones.
+pub fn advertising_report(
+    address: Address,
+    address_type: Option<AddressType>,
+    rssi: Option<i16>,
+    advertising: Option<AdvertisingKind>,
+    service_data: &[u8],
+) -> Vec<u8> {
+    let ad_len = service_data.len() + 3;
+    let mut address = address.0;
+    address.reverse();
+    // 127 means the RSSI is not available
+    let rssi = rssi.map(|rssi| rssi.clamp(-127, 126) as i8).unwrap_or(127) as u8;
+    if advertising == Some(AdvertisingKind::Extended) {
+        let mut packet = vec![
+            HCI_EVENT_PKT,
+            EVT_LE_META_EVENT,
+            (ad_len + 27) as u8,
+            EVT_LE_EXTENDED_ADVERTISING_REPORT,
+            0x01, // number of reports
+            0x00, 0x00, // non-connectable, non-scannable, complete
+            address_type.map_or(0x00, AddressType::hci),
+        ];
+        packet.extend_from_slice(&address);
+        // LE 1M PHYs, no SID, no TX power
+        packet.extend_from_slice(&[0x01, 0x01, 0xFF, 0x7F, rssi]);
+        // No periodic advertising, not directed
+        packet.extend_from_slice(&[0x00; 9]);
+        packet.push(ad_len as u8 + 1);
+        packet.extend_from_slice(&advertising_data(service_data));
+        return packet;
+    }
+    let mut packet = vec![
+        HCI_EVENT_PKT,
+        EVT_LE_META_EVENT,
+        (ad_len + 13) as u8,
+        EVT_LE_ADVERTISING_REPORT,
+        0x01, // number of reports
+        0x03, // ADV_NONCONN_IND
+        // Public if the type is not known
+        address_type.map_or(0x00, AddressType::hci),
+    ];
+    packet.extend_from_slice(&address);
+    packet.push(ad_len as u8 + 1);
+    packet.extend_from_slice(&advertising_data(service_data));
+    packet.push(rssi);
+    packet
+}
+
+/// Builds advertising data consisting of only the BTHome service data.
+pub fn advertising_data(service_data: &[u8]) -> Vec<u8> {
+    let mut ad = vec![service_data.len() as u8 + 3, AD_TYPE_SERVICE_DATA_UUID16];
+    ad.extend_from_slice(&BTHOME_UUID16.to_le_bytes());
+    ad.extend_from_slice(service_data);
+    ad
+}
+
+#[cfg(test)]
+mod test {
+    use bthome::{extract_bthome_from_adv, hci::parse_event};
+
+    use super::*;
+
+    #[test]
+    fn build_advertising_report() {
+        let address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        let packet = advertising_report(address, Some(AddressType::Random), Some(-60), None, &[0x40, 0x02, 0xC4, 0x09]);
+        assert_eq!(packet[2] as usize, packet.len() - 3);
+        assert_eq!(
+            parse_event(&packet),
+            vec![Report {
+                address: address.0,
+                address_type: AddressType::Random.hci(),
+                rssi: -60,
+                data: vec![0x07, 0x16, 0xD2, 0xFC, 0x40, 0x02, 0xC4, 0x09],
+                extended: false,
+                scannable: false,
+                scan_response: false,
+                incomplete: false,
+            }]
+        );
+
+        let packet = advertising_report(address, None, None, Some(AdvertisingKind::Extended), &[0x40, 0x02, 0xC4, 0x09]);
+        assert_eq!(packet[2] as usize, packet.len() - 3);
+        let reports = parse_event(&packet);
+        assert_eq!(AdvertisingKind::of(&reports[0]), AdvertisingKind::Extended);
+        assert_eq!(reports[0].rssi, 127);
+        assert_eq!(extract_bthome_from_adv(&reports[0].data), Some(&[0x40, 0x02, 0xC4, 0x09][..]));
+    }
+}
@@ -0,0 +1,181 @@
+//! Health check and metrics in the Prometheus text format, so that orchestrators and dashboards
+//! notice a sniffer that is running but no longer receives or delivers anything.
+
+use std::{collections::HashMap, fmt::Write, time::Duration};
+
+use bthome::{DeviceState, ObjectValue};
+
+use crate::{
+    address::Address,
+    config::Config,
+    http::Response,
+    stats::{Statistics, Summary},
+};
+
+/// Name, description and value of a counter per device.
+type Counter = (&'static str, &'static str, fn(&Summary) -> u64);
+
+/// Answer to `/healthz`, unhealthy if nothing was received for `timeout`.
+pub fn check(silent: Duration, timeout: Duration) -> Response {
+    if silent < timeout {
+        Response::text(200, "OK\n")
+    } else {
+        Response::text(503, format!("No advertisements received for {:.0?}\n", silent))
+    }
+}
+
+/// Answer to `/metrics`, with the latest measurements of each device as gauges.
+pub fn metrics(
+    config: &Config,
+    statistics: &Statistics,
+    states: &HashMap<Address, DeviceState>,
+    advertisements: u64,
+    silent: Duration,
+) -> Response {
+    let mut body = String::new();
+    let _ = writeln!(body, "# HELP bthome_sniffer_advertisements_total Received advertisements including duplicates");
+    let _ = writeln!(body, "# TYPE bthome_sniffer_advertisements_total counter");
+    let _ = writeln!(body, "bthome_sniffer_advertisements_total {}", advertisements);
+    let _ = writeln!(body, "# HELP bthome_sniffer_last_advertisement_seconds Time since the last advertisement was received");
+    let _ = writeln!(body, "# TYPE bthome_sniffer_last_advertisement_seconds gauge");
+    let _ = writeln!(body, "bthome_sniffer_last_advertisement_seconds {:.3}", silent.as_secs_f64());
+
+    let summaries = statistics.summaries();
+    let counters: [Counter; 5] = [
+        ("packets", "Decoded packets", |summary| summary.received),
+        ("lost_packets", "Packets that were never received according to the packet id", |summary| summary.lost),
+        ("parse_errors", "Packets that could not be decoded", |summary| summary.errors),
+        ("decryption_failures", "Encrypted packets that could not be decrypted", |summary| {
+            summary.decryption_failures
+        }),
+        ("sink_failures", "Packets that could not be delivered to a sink", |summary| summary.sink_failures),
+    ];
+    for (name, help, value) in counters {
+        let _ = writeln!(body, "# HELP bthome_sniffer_{}_total {}", name, help);
+        let _ = writeln!(body, "# TYPE bthome_sniffer_{}_total counter", name);
+        for (address, summary) in &summaries {
+            let _ = writeln!(body, "bthome_sniffer_{}_total{{{}}} {}", name, device_labels(config, address), value(summary));
+        }
+    }
+
+    let _ = writeln!(body, "# HELP bthome_sniffer_measurement Latest value of a measurement or binary sensor");
+    let _ = writeln!(body, "# TYPE bthome_sniffer_measurement gauge");
+    let mut addresses: Vec<&Address> = states.keys().collect();
+    addresses.sort();
+    for address in addresses {
+        // Instances are numbered by name, objects like temperature 0x02 and 0x45 share the name
+        let mut instances: Vec<&str> = Vec::new();
+        for (object_id, _, value) in states[address].values() {
+            let value = match value {
+                ObjectValue::Float(value) => *value,
+                ObjectValue::Int(value) => *value as f64,
+                ObjectValue::UInt(value) => *value as f64,
+                ObjectValue::Bool(value) => f64::from(u8::from(*value)),
+                _ => continue,
+            };
+            let name = object_id.name();
+            if name == "packet_id" {
+                continue;
+            }
+            let instance = instances.iter().filter(|seen| **seen == name).count();
+            instances.push(name);
+            let mut labels = format!("{},object=\"{}\"", device_labels(config, address), name);
+            if instance > 0 {
+                let _ = write!(labels, ",instance=\"{}\"", instance + 1);
+            }
+            if let Some(unit) = object_id.unit() {
+                let _ = write!(labels, ",unit=\"{}\"", escape(unit));
+            }
+            let _ = writeln!(body, "bthome_sniffer_measurement{{{}}} {}", labels, value);
+        }
+    }
+    Response {
+        status: 200,
+        content_type: "text/plain; version=0.0.4; charset=utf-8",
+        body,
+    }
+}
+
+/// The address and, if configured, the name of a device.
+fn device_labels(config: &Config, address: &Address) -> String {
+    let mut labels = format!("address=\"{}\"", address);
+    if let Some(device) = config.name(address) {
+        let _ = write!(labels, ",name=\"{}\"", escape(device));
+    }
+    labels
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use bthome::parse_service_data;
+
+    use super::*;
+
+    #[test]
+    fn render_metrics() {
+        let config = Config::parse("[devices.\"A4:C1:38:12:34:56\"]\nname = \"Kitchen \\\"north\\\"\"").unwrap();
+        let address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        let mut statistics = Statistics::default();
+        statistics.record(address, Some(1), Instant::now());
+        statistics.record_error(address);
+        statistics.record_decryption_failure(address);
+        let mut states = HashMap::new();
+        // Packet id, two temperatures and an open window
+        let packet = parse_service_data(&[0x40, 0x00, 0x01, 0x02, 0xC4, 0x09, 0x02, 0x10, 0x27, 0x2D, 0x01]).unwrap();
+        states.entry(address).or_insert_with(DeviceState::new).update(&packet, Instant::now());
+
+        let response = metrics(&config, &statistics, &states, 3, Duration::from_millis(1500));
+        assert_eq!(response.status, 200);
+        let lines: Vec<&str> = response.body.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "bthome_sniffer_advertisements_total 3",
+                "bthome_sniffer_last_advertisement_seconds 1.500",
+                r#"bthome_sniffer_packets_total{address="A4:C1:38:12:34:56",name="Kitchen \"north\""} 1"#,
+                r#"bthome_sniffer_lost_packets_total{address="A4:C1:38:12:34:56",name="Kitchen \"north\""} 0"#,
+                r#"bthome_sniffer_parse_errors_total{address="A4:C1:38:12:34:56",name="Kitchen \"north\""} 1"#,
+                r#"bthome_sniffer_decryption_failures_total{address="A4:C1:38:12:34:56",name="Kitchen \"north\""} 1"#,
+                r#"bthome_sniffer_sink_failures_total{address="A4:C1:38:12:34:56",name="Kitchen \"north\""} 0"#,
+                r#"bthome_sniffer_measurement{address="A4:C1:38:12:34:56",name="Kitchen \"north\"",object="temperature",unit="°C"} 25"#,
+                r#"bthome_sniffer_measurement{address="A4:C1:38:12:34:56",name="Kitchen \"north\"",object="temperature",instance="2",unit="°C"} 100"#,
+                r#"bthome_sniffer_measurement{address="A4:C1:38:12:34:56",name="Kitchen \"north\"",object="window"} 1"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn number_instances_by_name() {
+        let config = Config::default();
+        let address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        let mut states = HashMap::new();
+        // A temperature with 0.01 and one with 0.1 °C resolution
+        let packet = parse_service_data(&[0x40, 0x02, 0xC4, 0x09, 0x45, 0xFA, 0x00]).unwrap();
+        states.entry(address).or_insert_with(DeviceState::new).update(&packet, Instant::now());
+
+        let response = metrics(&config, &Statistics::default(), &states, 1, Duration::ZERO);
+        let measurements = response.body.lines().filter(|line| line.starts_with("bthome_sniffer_measurement{"));
+        let lines: Vec<&str> = measurements.collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"bthome_sniffer_measurement{address="A4:C1:38:12:34:56",object="temperature",unit="°C"} 25"#,
+                r#"bthome_sniffer_measurement{address="A4:C1:38:12:34:56",object="temperature",instance="2",unit="°C"} 25"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn unhealthy_when_silent() {
+        let timeout = Duration::from_secs(300);
+        assert_eq!(check(Duration::from_secs(10), timeout).status, 200);
+        assert_eq!(check(Duration::from_secs(301), timeout).status, 503);
+    }
+}
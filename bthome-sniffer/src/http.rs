@@ -0,0 +1,159 @@
+//! Minimal HTTP server for health checks and metrics. Requests are written by hand as only
+//! `GET` of a handful of paths is needed.
+
+use std::{io, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+};
+use tracing::{debug, warn};
+
+/// Longest request head that is accepted.
+const MAX_REQUEST_LENGTH: u64 = 8192;
+/// Time a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A `GET` request, answered by the main loop.
+pub struct Request {
+    /// Path without the query string
+    pub path: String,
+    pub reply: oneshot::Sender<Response>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into(),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Response::text(404, "Not found\n")
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            503 => "Service Unavailable",
+            _ => "",
+        }
+    }
+}
+
+/// Listens on `address` and passes the requests to `requests`.
+pub async fn listen(address: &str, requests: mpsc::UnboundedSender<Request>) -> io::Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve(stream, requests.clone()));
+                }
+                Err(err) => {
+                    warn!(error = %err, "Error accepting HTTP connection");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn serve(mut stream: TcpStream, requests: mpsc::UnboundedSender<Request>) {
+    let (read, mut write) = stream.split();
+    let mut reader = BufReader::new(read.take(MAX_REQUEST_LENGTH));
+    let request_line = match tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut reader)).await {
+        Ok(Ok(request_line)) => request_line,
+        Ok(Err(err)) => {
+            debug!(error = %err, "Error reading HTTP request");
+            return;
+        }
+        Err(_) => return,
+    };
+    let (response, head) = match parse_request_line(&request_line) {
+        Some((method, path)) => {
+            let head = method == "HEAD";
+            let response = if method != "GET" && !head {
+                Response::text(405, "Method not allowed\n")
+            } else {
+                let (reply, response) = oneshot::channel();
+                let request = Request {
+                    path: path.to_string(),
+                    reply,
+                };
+                match requests.send(request) {
+                    Ok(()) => response
+                        .await
+                        .unwrap_or_else(|_| Response::text(503, "Shutting down\n")),
+                    Err(_) => Response::text(503, "Shutting down\n"),
+                }
+            };
+            (response, head)
+        }
+        None => (Response::text(400, "Bad request\n"), false),
+    };
+    let mut message = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len()
+    );
+    if !head {
+        message.push_str(&response.body);
+    }
+    if let Err(err) = write.write_all(message.as_bytes()).await {
+        debug!(error = %err, "Error answering HTTP request");
+    }
+}
+
+/// Reads the request head and returns its first line, the headers are not needed.
+async fn read_head<R: tokio::io::AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<String> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            return Ok(request_line);
+        }
+    }
+}
+
+/// Returns the method and the path without the query string.
+fn parse_request_line(line: &str) -> Option<(&str, &str)> {
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?;
+    let target = parts.next()?;
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    Some((method, target.split('?').next().unwrap_or(target)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_request_lines() {
+        assert_eq!(parse_request_line("GET /healthz HTTP/1.1\r\n"), Some(("GET", "/healthz")));
+        assert_eq!(parse_request_line("HEAD /metrics?x=1 HTTP/1.0\r\n"), Some(("HEAD", "/metrics")));
+        assert_eq!(parse_request_line("GET /healthz\r\n"), None);
+        assert_eq!(parse_request_line(""), None);
+    }
+}
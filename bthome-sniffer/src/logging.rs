@@ -0,0 +1,45 @@
+//! Operational logging via `tracing`, written to stderr or the journal so that it does not mix
+//! with the decoded data written to stdout.
+
+use clap::ValueEnum;
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::tui::LogLines;
+
+/// Log level used if `RUST_LOG` is not set.
+const DEFAULT_FILTER: &str = "info";
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human readable lines on stderr
+    #[default]
+    Text,
+    /// One JSON object per line on stderr
+    Json,
+    /// Structured entries in the systemd journal
+    #[cfg(target_os = "linux")]
+    Journald,
+}
+
+/// Installs the global logger, the levels are controlled by the `RUST_LOG` environment variable.
+/// Text and JSON go to `dashboard` instead of stderr if given.
+pub fn init(format: LogFormat, dashboard: Option<LogLines>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let registry = tracing_subscriber::registry().with(filter);
+    let ansi = dashboard.is_none();
+    let writer = match dashboard {
+        Some(lines) => BoxMakeWriter::new(move || lines.clone()),
+        None => BoxMakeWriter::new(std::io::stderr),
+    };
+    match format {
+        LogFormat::Text => registry
+            .with(tracing_subscriber::fmt::layer().with_ansi(ansi).with_writer(writer))
+            .try_init()?,
+        LogFormat::Json => registry
+            .with(tracing_subscriber::fmt::layer().json().with_writer(writer))
+            .try_init()?,
+        #[cfg(target_os = "linux")]
+        LogFormat::Journald => registry.with(tracing_journald::layer()?).try_init()?,
+    }
+    Ok(())
+}
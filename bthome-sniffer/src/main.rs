@@ -1,72 +1,633 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    process::ExitCode,
+    time::{Duration, Instant, SystemTime},
+};
 
-use bluer::{monitor::{Monitor, MonitorEvent, Pattern, RssiSamplingPeriod}, DeviceEvent, DeviceProperty, Uuid};
-use bthome::{parse_service_data, BTHOME_UUID, BTHOME_UUID16};
-use futures::StreamExt;
+use bthome::{parse_encrypted_service_data, parse_service_data, DeviceState, Object, ServiceData};
+use clap::Parser;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
 
-const SERVICE_DATA_UUID16: u8 = 0x16;
+mod address;
+mod capture;
+mod config;
+mod control;
+mod discovery;
+mod duration;
+mod filter;
+mod esphome_proxy;
+mod forward;
+mod hci;
+mod health;
+mod http;
+mod influx;
+mod logging;
+mod merge;
+mod mqtt;
+mod output;
+mod presence;
+mod report;
+mod rssi;
+mod source;
+mod stats;
+#[cfg(target_os = "linux")]
+mod systemd;
+mod tui;
+
+use address::Address;
+use config::{Args, Config, SharedConfig, SnifferCommand};
 
 #[tokio::main(flavor="current_thread")]
-async fn main() -> bluer::Result<()> {
+async fn main() -> Result<ExitCode, Box<dyn Error + Send + Sync>> {
+    let args = Args::parse();
+    let dashboard_log = args.tui.then(tui::LogLines::default);
+    logging::init(args.log_format(), dashboard_log.clone())?;
+    #[cfg(unix)]
+    if let Some(SnifferCommand::Control(command)) = &args.command {
+        let path = args
+            .control_socket
+            .as_ref()
+            .ok_or("The control socket of the running sniffer has to be given with --control-socket")?;
+        print!("{}", control::send(path, command).await?);
+        return Ok(ExitCode::SUCCESS);
+    }
+    let shared_config = SharedConfig::new(match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    });
+    let mut config = shared_config.get();
+    if let Some(SnifferCommand::Decode { pcap }) = &args.command {
+        return decode_capture(pcap, &config, args.output);
+    }
+    let mut filter = config.filter.with_args(&args);
 
-    let patterns = vec![
-        Pattern { data_type: SERVICE_DATA_UUID16, start_position: 0x00, content: BTHOME_UUID16.to_le_bytes().to_vec() }
-    ];
+    // The configuration is reloaded on SIGHUP, without interrupting the sources
+    let (reload_tx, mut reload_rx) = mpsc::unbounded_channel();
+    #[cfg(unix)]
+    if args.config.is_some() {
+        let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                if reload_tx.send(()).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    drop(reload_tx);
 
-    let session = bluer::Session::new().await?;
+    let (control_tx, mut control_rx) = mpsc::unbounded_channel();
+    #[cfg(unix)]
+    if let Some(path) = &args.control_socket {
+        control::listen(path, control_tx)
+            .map_err(|err| format!("Error creating the control socket {}: {}", path.display(), err))?;
+    }
+    #[cfg(not(unix))]
+    drop(control_tx);
+    let (http_tx, mut http_rx) = mpsc::unbounded_channel();
+    for address in args.http_listen.iter().chain(&args.prometheus_listen) {
+        http::listen(address, http_tx.clone())
+            .await
+            .map_err(|err| format!("Error listening for HTTP on {}: {}", address, err))?;
+    }
+    drop(http_tx);
+    let mut muted = HashSet::new();
+    // Keys added at runtime, they are kept when the configuration is reloaded
+    let mut keys: HashMap<Address, control::Key> = HashMap::new();
 
-    let bthome_uuid = Uuid::from_u128(BTHOME_UUID);
+    let mut capture = match &args.capture {
+        Some(path) => Some(bthome::capture::PcapWriter::new(BufWriter::new(File::create(path)?))?),
+        None => None,
+    };
 
-    let adapter = session.default_adapter().await?;
+    let mut recording = match &args.record {
+        Some(path) => Some(bthome::capture::CaptureWriter::new(BufWriter::new(File::create(path)?))?),
+        None => None,
+    };
 
-    adapter.set_powered(true).await?;
+    let mut forwarder = args.forward.clone().map(|endpoint| {
+        let satellite = args.satellite_name.clone().unwrap_or_else(|| hostname("satellite"));
+        forward::Forwarder::new(endpoint, satellite)
+    });
+    let mut publisher = args.mqtt_url.as_ref().map(|url| {
+        let client_id = format!("bthome-sniffer-{}", hostname("sniffer"));
+        let discovery = args.mqtt_discovery.then(|| args.mqtt_discovery_prefix.clone());
+        mqtt::Publisher::connect(url, &client_id, &args.mqtt_topic, args.mqtt_qos, discovery)
+    });
+    let influx = args.influx_url.as_ref().map(|url| {
+        let api = match (&args.influx_org, &args.influx_token) {
+            (Some(org), Some(token)) => influx::Api::V2 {
+                org: org.clone(),
+                bucket: args.influx_bucket.clone(),
+                token: token.clone(),
+            },
+            _ => influx::Api::V1 { database: args.influx_bucket.clone() },
+        };
+        influx::Writer::start(url, api, &args.influx_measurement, args.influx_flush_interval)
+    });
+    let proxy = match &args.esphome_proxy {
+        Some(address) => {
+            let name = args.esphome_proxy_name.clone().unwrap_or_else(|| hostname("bthome-sniffer"));
+            Some(
+                esphome_proxy::Proxy::start(address, &name)
+                    .await
+                    .map_err(|err| format!("Error listening for ESPHome API connections on {}: {}", address, err))?,
+            )
+        }
+        None => None,
+    };
 
-    let mm = adapter.monitor().await?;
-    let mut monitor_handle = mm
-        .register(Monitor {
-            monitor_type: bluer::monitor::Type::OrPatterns,
-            rssi_low_threshold: None,
-            rssi_high_threshold: None,
-            rssi_low_timeout: None,
-            rssi_high_timeout: None,
-            rssi_sampling_period: Some(RssiSamplingPeriod::All),
-            patterns: Some(patterns),
-            ..Default::default()
-        })
-        .await?;
+    // Devices repeat each packet several times and several receivers may pick up the same
+    // packet, so each packet is only reported once
+    let mut merger = (!args.keep_duplicates).then(|| merge::Merger::new(args.dedupe));
+    let mut rssi_tracker = rssi::RssiTracker::new(args.rssi_smoothing);
+    let mut presence = presence::Presence::default();
+    let mut presence_check = tokio::time::interval(presence::CHECK_INTERVAL);
+    let started = Instant::now();
+    let mut statistics = stats::Statistics::default();
+    let mut states: HashMap<Address, DeviceState> = HashMap::new();
+    let mut stats_report = args.stats_interval.map(|period| {
+        tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+    });
 
-    while let Some(mevt) = &monitor_handle.next().await {
-        let MonitorEvent::DeviceFound(devid) = mevt else {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let sources = source::start(&args, shared_config.clone(), &tx).await?;
+    if sources == 0 {
+        return Err("No Bluetooth adapter or other source of advertisements found".into());
+    }
+    drop(tx);
+    notify_systemd("READY=1");
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    #[cfg(target_os = "linux")]
+    let mut watchdog = systemd::watchdog_interval().map(tokio::time::interval);
+    #[cfg(not(target_os = "linux"))]
+    let mut watchdog: Option<tokio::time::Interval> = None;
+    let mut deadline = args.duration.map(|duration| Box::pin(tokio::time::sleep(duration)));
+    let mut dashboard = dashboard_log.map(tui::Dashboard::start);
+    let mut redraw = tokio::time::interval(tui::REDRAW_INTERVAL);
+    let mut decoded = 0;
+    let mut advertisements = 0;
+    let mut last_advertisement = None;
+
+    loop {
+        let advertisement = tokio::select! {
+            advertisement = rx.recv() => match advertisement {
+                Some(advertisement) => advertisement,
+                None => break,
+            },
+            result = &mut shutdown => {
+                if let Err(err) = result {
+                    error!(error = %err, "Error waiting for shutdown signals");
+                }
+                info!("Shutting down");
+                break;
+            }
+            _ = async { deadline.as_mut().unwrap().await }, if deadline.is_some() => break,
+            _ = async { dashboard.as_mut().unwrap().quit_requested().await }, if dashboard.is_some() => break,
+            _ = redraw.tick(), if dashboard.is_some() => {
+                if let Err(err) = dashboard.as_mut().unwrap().draw(&config, &states) {
+                    error!(error = %err, "Error drawing the dashboard");
+                }
+                continue;
+            }
+            _ = presence_check.tick() => {
+                for (address, silent) in presence.expire(Instant::now()) {
+                    info!(device = %config.label(&address), ?silent, "Device is offline");
+                    let name = config.name(&address);
+                    let time = (SystemTime::now(), started.elapsed());
+                    let sinks = (publisher.as_mut(), influx.as_ref());
+                    if !publish_availability(&address, name, false, time, args.output, sinks) {
+                        statistics.record_sink_failure(address);
+                    }
+                }
+                continue;
+            }
+            // The precondition is checked before the future is created, so unwrapping is safe
+            _ = async { stats_report.as_mut().unwrap().tick().await }, if stats_report.is_some() => {
+                print_statistics(&config, &statistics, args.output);
+                continue;
+            }
+            Some(()) = reload_rx.recv() => {
+                if let Some(path) = &args.config {
+                    match Config::load(path) {
+                        Ok(reloaded) => {
+                            shared_config.set(reloaded);
+                            config = shared_config.get();
+                            filter = config.filter.with_args(&args);
+                            info!(path = %path.display(), "Reloaded configuration");
+                        }
+                        Err(err) => error!(path = %path.display(), error = %err, "Error reloading configuration, keeping the current one"),
+                    }
+                }
+                continue;
+            }
+            Some(request) = control_rx.recv() => {
+                let response = match request.command {
+                    control::Command::Devices => {
+                        Ok(list_devices(&config, &presence, &statistics, &rssi_tracker, &states, &muted, &keys))
+                    }
+                    control::Command::AddKey { address, key } => {
+                        keys.insert(address, key);
+                        Ok(format!("Added the key of {}\n", config.label(&address)))
+                    }
+                    control::Command::Mute { address } => {
+                        muted.insert(address);
+                        Ok(format!("Muted {}\n", config.label(&address)))
+                    }
+                    control::Command::Unmute { address } => {
+                        if muted.remove(&address) {
+                            Ok(format!("Unmuted {}\n", config.label(&address)))
+                        } else {
+                            Err(format!("{} is not muted", config.label(&address)))
+                        }
+                    }
+                };
+                // The client may have given up waiting
+                let _ = request.reply.send(response);
+                continue;
+            }
+            Some(request) = http_rx.recv() => {
+                let silent = last_advertisement.unwrap_or(started).elapsed();
+                let response = match request.path.as_str() {
+                    "/healthz" => health::check(silent, args.health_timeout),
+                    "/metrics" => health::metrics(&config, &statistics, &states, advertisements, silent),
+                    _ => http::Response::not_found(),
+                };
+                let _ = request.reply.send(response);
+                continue;
+            }
+            // Pinged from the main loop, so that systemd restarts the sniffer if it hangs
+            _ = async { watchdog.as_mut().unwrap().tick().await }, if watchdog.is_some() => {
+                notify_systemd("WATCHDOG=1");
+                continue;
+            }
+        };
+        advertisements += 1;
+        last_advertisement = Some(Instant::now());
+        let since_start = started.elapsed();
+        if !filter.accepts(&advertisement.address) {
             continue;
+        }
+        if let Some(capture) = &mut capture {
+            let packet = hci::advertising_report(
+                advertisement.address,
+                advertisement.address_type,
+                advertisement.rssi,
+                advertisement.advertising,
+                &advertisement.service_data,
+            );
+            if let Err(err) = capture.write(advertisement.received, &packet) {
+                error!(error = %err, "Error writing capture file");
+            }
+        }
+        if let Some(recording) = &mut recording {
+            let captured = bthome::capture::CapturedAdvertisement {
+                time: advertisement.received,
+                address: advertisement.address.0,
+                rssi: advertisement.rssi.and_then(|rssi| i8::try_from(rssi).ok()),
+                service_data: advertisement.service_data.clone(),
+            };
+            if let Err(err) = recording.write(&captured) {
+                error!(error = %err, "Error writing recording");
+            }
+        }
+        if let Some(proxy) = &proxy {
+            proxy.send(&advertisement);
+        }
+        if let Some(forwarder) = &mut forwarder {
+            if !forwarder.send(&advertisement).await {
+                statistics.record_sink_failure(advertisement.address);
+            }
+            continue;
+        }
+        let offline_timeout = config.offline_timeout(&advertisement.address).unwrap_or(
+            if presence::is_trigger_based(&advertisement.service_data) {
+                args.trigger_offline_timeout
+            } else {
+                args.offline_timeout
+            },
+        );
+        if let Some(transition) = presence.seen(advertisement.address, offline_timeout, Instant::now()) {
+            if transition == presence::Transition::Online {
+                info!(device = %config.label(&advertisement.address), "Device is online again");
+            }
+            let name = config.name(&advertisement.address);
+            let time = (advertisement.received, since_start);
+            let sinks = (publisher.as_mut(), influx.as_ref());
+            if !publish_availability(&advertisement.address, name, true, time, args.output, sinks) {
+                statistics.record_sink_failure(advertisement.address);
+            }
+        }
+        // Every copy of a packet is a measurement of the signal strength, even if it is not reported
+        let average_rssi = advertisement
+            .rssi
+            .map(|rssi| rssi_tracker.update(advertisement.address, rssi));
+        if muted.contains(&advertisement.address) {
+            continue;
+        }
+        let mut label = config.label(&advertisement.address);
+        if let Some(merger) = &mut merger {
+            let now = Instant::now();
+            if !merger.accept(&advertisement, now) {
+                continue;
+            }
+            if sources > 1 {
+                if let Some((receiver, rssi)) = merger.closest_receiver(&advertisement.address, now) {
+                    label = format!("{} (closest to {} at {} dBm)", label, receiver, rssi);
+                }
+            }
+        }
+        let mut details = Vec::new();
+        if let (Some(rssi), Some(average)) = (advertisement.rssi, average_rssi) {
+            details.push(format!("RSSI {} dBm, average {:.1} dBm", rssi, average));
+        }
+        if let Some(address_type) = advertisement.address_type {
+            details.push(format!("{} address", address_type));
+        }
+        if let Some(advertising) = advertisement.advertising {
+            details.push(format!("{} advertising", advertising));
+        }
+        details.push(format!("{:.3}s after start", since_start.as_secs_f64()));
+        let duplicates = merger.as_ref().map_or(0, |merger| merger.duplicates(&advertisement.address));
+        if duplicates > 0 {
+            details.push(format!("{} duplicates suppressed so far", duplicates));
+        }
+        let details = if details.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", details.join(", "))
         };
-        let dev = adapter.device(devid.device)?;
-        let name = dev.name().await?;
-        println!("Discovered potential BTHome device {:?} {:?}", devid.device, name);
-        if let Ok(Some(service_data)) = dev.service_data().await {
-            if let Some(bthome_data) = service_data.get(&bthome_uuid) {
-                match parse_service_data(bthome_data.as_slice()) {
-                    Ok(bthome_data) => println!("BTHome data is {:?}", bthome_data),
-                    Err(err) => println!("Error parsing BTHome data {:?}", err),
+        if args.prints_packets() {
+            println!(
+                "[{}] Received raw data from bthome device {} {:0x?}{}",
+                advertisement.source, label, advertisement.service_data, details
+            );
+        }
+        // Keys added over the control socket take precedence over the configuration file
+        let key = keys.get(&advertisement.address).or_else(|| config.key(&advertisement.address));
+        let result = decode(&advertisement, key);
+        if let Some(dashboard) = &mut dashboard {
+            dashboard.record(&advertisement, &result);
+        }
+        if args.output.is_json() {
+            let packet = output::Packet::new(&advertisement, config.name(&advertisement.address), since_start, &result);
+            println!("{}", packet.render(args.output));
+        }
+        match result {
+            Ok(bthome_data) => {
+                statistics.record(advertisement.address, bthome_data.packet_id(), Instant::now());
+                states.entry(advertisement.address).or_default().update(&bthome_data, Instant::now());
+                let device_name = config.name(&advertisement.address);
+                if publisher
+                    .as_mut()
+                    .is_some_and(|publisher| !publisher.publish(&advertisement, device_name, &bthome_data))
+                {
+                    statistics.record_sink_failure(advertisement.address);
+                }
+                if influx
+                    .as_ref()
+                    .is_some_and(|influx| !influx.write(&advertisement, device_name, &bthome_data))
+                {
+                    statistics.record_sink_failure(advertisement.address);
+                }
+                if args.prints_packets() {
+                    println!("[{}] BTHome data from {} is {:?}", advertisement.source, label, bthome_data);
+                }
+                decoded += 1;
+                if args.count.is_some_and(|count| decoded >= count) {
+                    break;
+                }
+            }
+            Err(err) => {
+                if matches!(err, bthome::Error::DecryptionFailed) {
+                    warn!(device = %label, "Decryption failed, the MIC does not match, the key of the device is probably wrong");
+                }
+                if matches!(err, bthome::Error::Encrypted | bthome::Error::DecryptionFailed) {
+                    statistics.record_decryption_failure(advertisement.address);
+                } else {
+                    statistics.record_error(advertisement.address);
+                }
+                if args.prints_packets() {
+                    println!("[{}] Error parsing BTHome data from {} {:?}", advertisement.source, label, err)
                 }
             }
         }
+    }
 
-        tokio::spawn(async move {
-            let mut events = dev.events().await.unwrap();
-            while let Some(ev) = events.next().await {
-                let DeviceEvent::PropertyChanged(dp) = ev;
-                if let DeviceProperty::ServiceData(data) = dp {
-                    if let Some(raw_data) = data.get(&bthome_uuid) {
-                        println!("Received raw data from bthome device {:0x?}", raw_data);
-                        match parse_service_data(raw_data.as_slice()) {
-                            Ok(bthome_data) => println!("BTHome data is {:?}", bthome_data),
-                            Err(err) => println!("Error parsing BTHome data {:?}", err),
-                        }
-                    }
+    notify_systemd("STOPPING=1");
+    // Restores the terminal for the summary
+    drop(dashboard);
+    // Flush all outputs, the sources are stopped and the advertisement monitors unregistered when
+    // the runtime shuts down
+    drop(rx);
+    if let Some(forwarder) = &mut forwarder {
+        forwarder.close().await;
+    }
+    if let Some(publisher) = publisher {
+        publisher.close().await;
+    }
+    if let Some(influx) = influx {
+        influx.close().await;
+    }
+    drop(capture);
+    if let Some(mut recording) = recording {
+        if let Err(err) = recording.flush() {
+            error!(error = %err, "Error writing recording");
+        }
+    }
+    if let Some(proxy) = proxy {
+        proxy.shutdown();
+    }
+    #[cfg(unix)]
+    if let Some(path) = &args.control_socket {
+        let _ = std::fs::remove_file(path);
+    }
 
-                }
+    let duplicates = merger.as_ref().map_or(0, |merger| merger.total_duplicates());
+    if args.one_shot() {
+        let report = report::Report::new(&config, &statistics, &args.expect, args.count, duplicates, started.elapsed());
+        println!("{}", serde_json::to_string(&report)?);
+        return Ok(report.exit_code());
+    }
+
+    let summaries = statistics.summaries();
+    let summary = format!(
+        "Session summary after {:.1?}: {} devices, {} packets decoded, {} errors, {} duplicates suppressed",
+        started.elapsed(),
+        summaries.len(),
+        summaries.iter().map(|(_, summary)| summary.received).sum::<u64>(),
+        summaries.iter().map(|(_, summary)| summary.errors).sum::<u64>(),
+        duplicates,
+    );
+    print_human(&summary, args.output);
+    print_statistics(&config, &statistics, args.output);
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Parses the service data, decrypting it if a key of the device is known.
+fn decode(advertisement: &source::Advertisement, key: Option<&control::Key>) -> Result<ServiceData, bthome::Error> {
+    let Some(key) = key else {
+        return parse_service_data(&advertisement.service_data);
+    };
+    match parse_encrypted_service_data(&advertisement.service_data, key, &advertisement.address.0) {
+        Err(bthome::Error::NotEncrypted) => parse_service_data(&advertisement.service_data),
+        result => result.map(|(service_data, _counter)| service_data),
+    }
+}
+
+/// Decodes the advertisements of a btsnoop, pcap or pcapng capture, with the keys of the configuration.
+fn decode_capture(path: &Path, config: &Config, output: output::Output) -> Result<ExitCode, Box<dyn Error + Send + Sync>> {
+    let error = |err: &dyn std::fmt::Display| format!("Error reading {}: {}", path.display(), err);
+    let data = std::fs::read(path).map_err(|err| error(&err))?;
+    let name = path.file_name().map_or_else(|| "capture".to_string(), |name| name.to_string_lossy().into_owned());
+    let advertisements = capture::advertisements(&data, &name).map_err(|err| error(&err))?;
+    let first = advertisements.first().map(|advertisement| advertisement.received);
+    let mut failed = 0;
+    for advertisement in &advertisements {
+        let result = decode(advertisement, config.key(&advertisement.address));
+        let since_start = first
+            .and_then(|first| advertisement.received.duration_since(first).ok())
+            .unwrap_or_default();
+        if output.is_json() {
+            let packet = output::Packet::new(advertisement, config.name(&advertisement.address), since_start, &result);
+            println!("{}", packet.render(output));
+        } else {
+            let rssi = advertisement.rssi.map(|rssi| format!(" at {} dBm", rssi)).unwrap_or_default();
+            let decoded = match &result {
+                Ok(service_data) => service_data.objects.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "),
+                Err(err) => format!("error: {}", err),
+            };
+            let label = config.label(&advertisement.address);
+            println!("+{:.3}s {}{}: {}", since_start.as_secs_f64(), label, rssi, decoded);
+        }
+        if result.is_err() {
+            failed += 1;
+        }
+    }
+    print_human(
+        &format!("{} BTHome advertisements in {}, {} failed to decode", advertisements.len(), name, failed),
+        output,
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Writes a device going offline or coming back to the JSON output and the sinks. Returns false if
+/// a sink couldn't queue it.
+fn publish_availability(
+    address: &Address,
+    name: Option<&str>,
+    online: bool,
+    (time, since_start): (SystemTime, Duration),
+    output: output::Output,
+    (publisher, influx): (Option<&mut mqtt::Publisher>, Option<&influx::Writer>),
+) -> bool {
+    if output.is_json() {
+        println!("{}", output::Availability::new(address, name, online, time, since_start).render(output));
+    }
+    let mut queued = true;
+    if let Some(publisher) = publisher {
+        queued &= publisher.publish_availability(address, online);
+    }
+    if let Some(influx) = influx {
+        queued &= influx.write_availability(address, name, online, time);
+    }
+    queued
+}
+
+fn print_statistics(config: &Config, statistics: &stats::Statistics, output: output::Output) {
+    for (address, summary) in statistics.summaries() {
+        print_human(&format!("Statistics for {}: {}", config.label(&address), summary), output);
+    }
+}
+
+/// Prints text meant for humans, on stderr if stdout is machine readable.
+fn print_human(text: &str, output: output::Output) {
+    if output.is_json() {
+        eprintln!("{}", text);
+    } else {
+        println!("{}", text);
+    }
+}
+
+/// Answer to the `devices` command, one line per device.
+fn list_devices(
+    config: &Config,
+    presence: &presence::Presence,
+    statistics: &stats::Statistics,
+    rssi_tracker: &rssi::RssiTracker,
+    states: &HashMap<Address, DeviceState>,
+    muted: &HashSet<Address>,
+    keys: &HashMap<Address, control::Key>,
+) -> String {
+    let devices = presence.devices();
+    if devices.is_empty() {
+        return "No devices received yet\n".to_string();
+    }
+    let mut lines = String::new();
+    for (address, last_seen, online) in devices {
+        let mut details = vec![format!(
+            "{}, last seen {:.0?} ago",
+            if online { "online" } else { "offline" },
+            last_seen.elapsed()
+        )];
+        if let Some(rssi) = rssi_tracker.average(&address) {
+            details.push(format!("average RSSI {:.1} dBm", rssi));
+        }
+        if let Some(summary) = statistics.summary(&address) {
+            details.push(summary.to_string());
+        }
+        if muted.contains(&address) {
+            details.push("muted".to_string());
+        }
+        if keys.contains_key(&address) {
+            details.push("key added".to_string());
+        }
+        lines.push_str(&format!("{}: {}\n", config.label(&address), details.join(", ")));
+        if let Some(state) = states.get(&address) {
+            for (object_id, index, value) in state.values() {
+                let mut object = Object::new(object_id, value.clone());
+                object.instance = index as u8;
+                lines.push_str(&format!("  {}\n", object));
             }
-        });
+        }
+    }
+    lines
+}
+
+/// The hostname of the machine, or `fallback` if it is not known.
+fn hostname(fallback: &str) -> String {
+    std::fs::read_to_string("/etc/hostname")
+        .map(|name| name.trim().to_string())
+        .unwrap_or_else(|_| fallback.to_string())
+}
+
+/// Resolves on SIGINT or SIGTERM.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
     }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
 
-    Ok(())
+fn notify_systemd(state: &str) {
+    #[cfg(target_os = "linux")]
+    if let Err(err) = systemd::notify(state) {
+        warn!(error = %err, "Error notifying systemd");
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = state;
 }
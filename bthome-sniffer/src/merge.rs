@@ -0,0 +1,185 @@
+//! Merging of the advertisement streams of several receivers, e.g. adapters, ESPHome proxies or
+//! satellites in different rooms.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use bthome::{parse_service_data, Deduplicator};
+
+use crate::{address::Address, source::Advertisement};
+
+/// Copies of the same packet received within this window are considered duplicates, unless
+/// `--dedupe` sets another one.
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(10);
+/// Receivers that did not see a device for this long are not considered for presence.
+const RECEIVER_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct DeviceSightings {
+    /// Hash of the last packet without a packet id, those are deduplicated by their content
+    last_content: Option<(u64, Instant)>,
+    receivers: HashMap<String, (i16, Instant)>,
+    duplicates: u64,
+}
+
+/// Deduplicates packets received by multiple receivers and tracks which receiver is closest to
+/// each device.
+pub struct Merger {
+    devices: HashMap<Address, DeviceSightings>,
+    deduplicator: Deduplicator<Address>,
+    duplicates: u64,
+}
+
+impl Default for Merger {
+    fn default() -> Self {
+        Merger::new(DUPLICATE_WINDOW)
+    }
+}
+
+impl Merger {
+    /// Considers copies of a packet received within `window` as duplicates.
+    pub fn new(window: Duration) -> Self {
+        Merger {
+            devices: HashMap::new(),
+            deduplicator: Deduplicator::new(window),
+            duplicates: 0,
+        }
+    }
+
+    /// Records the advertisement and returns whether it is the first copy of its packet.
+    ///
+    /// Packets are identified by their packet id, or by their content if they don't carry one.
+    pub fn accept(&mut self, advertisement: &Advertisement, now: Instant) -> bool {
+        let sightings = self.devices.entry(advertisement.address).or_default();
+        if let Some(rssi) = advertisement.rssi {
+            sightings.receivers.insert(advertisement.source.clone(), (rssi, now));
+        }
+        let packet_id = parse_service_data(&advertisement.service_data)
+            .ok()
+            .and_then(|data| data.packet_id());
+        let duplicate = match packet_id {
+            Some(packet_id) => !self.deduplicator.is_new_packet_id(advertisement.address, packet_id, now),
+            None => {
+                let mut hasher = DefaultHasher::new();
+                advertisement.service_data.hash(&mut hasher);
+                let content = hasher.finish();
+                let window = self.deduplicator.ttl();
+                let duplicate = matches!(
+                    sightings.last_content,
+                    Some((last, seen)) if last == content && now.duration_since(seen) < window
+                );
+                if !duplicate {
+                    sightings.last_content = Some((content, now));
+                }
+                duplicate
+            }
+        };
+        if duplicate {
+            sightings.duplicates += 1;
+            self.duplicates += 1;
+        }
+        !duplicate
+    }
+
+    /// Number of duplicates suppressed for `address`.
+    pub fn duplicates(&self, address: &Address) -> u64 {
+        self.devices.get(address).map_or(0, |sightings| sightings.duplicates)
+    }
+
+    /// Number of duplicates suppressed for all devices.
+    pub fn total_duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// The receiver that recently received the device with the strongest signal.
+    pub fn closest_receiver(&self, address: &Address, now: Instant) -> Option<(&str, i16)> {
+        self.devices
+            .get(address)?
+            .receivers
+            .iter()
+            .filter(|(_, (_, seen))| now.duration_since(*seen) < RECEIVER_WINDOW)
+            .max_by_key(|(_, (rssi, _))| *rssi)
+            .map(|(receiver, (rssi, _))| (receiver.as_str(), *rssi))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    fn advertisement(source: &str, rssi: i16, service_data: &[u8]) -> Advertisement {
+        Advertisement {
+            source: source.to_string(),
+            address: Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]),
+            address_type: None,
+            rssi: Some(rssi),
+            advertising: None,
+            received: SystemTime::now(),
+            service_data: service_data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn deduplicate_by_packet_id() {
+        let mut merger = Merger::default();
+        let now = Instant::now();
+        assert!(merger.accept(&advertisement("kitchen/hci0", -80, &[0x40, 0x00, 0x01, 0x01, 0x60]), now));
+        assert!(!merger.accept(&advertisement("office/hci0", -50, &[0x40, 0x00, 0x01, 0x01, 0x60]), now));
+        assert!(merger.accept(&advertisement("office/hci0", -50, &[0x40, 0x00, 0x02, 0x01, 0x5F]), now));
+        assert!(merger.accept(&advertisement("office/hci0", -50, &[0x40, 0x00, 0x02, 0x01, 0x5F]), now + DUPLICATE_WINDOW));
+        assert_eq!(
+            merger.closest_receiver(&Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]), now),
+            Some(("office/hci0", -50))
+        );
+    }
+
+    #[test]
+    fn deduplicate_by_content() {
+        let mut merger = Merger::default();
+        let now = Instant::now();
+        assert!(merger.accept(&advertisement("kitchen/hci0", -80, &[0x40, 0x01, 0x60]), now));
+        assert!(!merger.accept(&advertisement("office/hci0", -50, &[0x40, 0x01, 0x60]), now));
+        assert!(merger.accept(&advertisement("office/hci0", -50, &[0x40, 0x01, 0x5F]), now));
+    }
+
+    #[test]
+    fn custom_window() {
+        let mut merger = Merger::new(Duration::from_secs(1));
+        let now = Instant::now();
+        assert!(merger.accept(&advertisement("hci0", -60, &[0x40, 0x00, 0x03, 0x01, 0x60]), now));
+        assert!(merger.accept(&advertisement("hci0", -60, &[0x40, 0x00, 0x03, 0x01, 0x60]), now + Duration::from_secs(1)));
+        assert!(merger.accept(&advertisement("hci0", -60, &[0x40, 0x01, 0x60]), now));
+        assert!(merger.accept(&advertisement("hci0", -60, &[0x40, 0x01, 0x60]), now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn count_duplicates() {
+        let mut merger = Merger::default();
+        let now = Instant::now();
+        let address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        for _ in 0..3 {
+            merger.accept(&advertisement("hci0", -60, &[0x40, 0x00, 0x07, 0x01, 0x60]), now);
+        }
+        merger.accept(&advertisement("hci1", -70, &[0x40, 0x00, 0x07, 0x01, 0x60]), now);
+        assert_eq!(merger.duplicates(&address), 3);
+        assert_eq!(merger.duplicates(&Address::default()), 0);
+        assert_eq!(merger.total_duplicates(), 3);
+    }
+
+    #[test]
+    fn forget_stale_receivers() {
+        let mut merger = Merger::default();
+        let now = Instant::now();
+        merger.accept(&advertisement("office/hci0", -50, &[0x40, 0x01, 0x60]), now);
+        merger.accept(&advertisement("kitchen/hci0", -80, &[0x40, 0x01, 0x5F]), now + RECEIVER_WINDOW);
+        assert_eq!(
+            merger.closest_receiver(&Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]), now + RECEIVER_WINDOW),
+            Some(("kitchen/hci0", -80))
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! Publishing of decoded measurements to an MQTT broker, one message per object on
+//! `<prefix>/<address>/<name>`, e.g. `bthome/a4c138123456/temperature` with
+//! `{"name":"temperature","id":2,"unit":"°C","value":21.5,"rssi":-60,"address_type":"public","received":1700000000123}`,
+//! the object with the signal strength, address type and time of the advertisement. Repeated
+//! objects are published on numbered topics like `bthome/a4c138123456/temperature_2`. Whether a
+//! device is `online` or `offline` is retained on `<prefix>/<address>/availability`.
+
+use std::{
+    collections::HashSet,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+
+use bthome::{Object, ServiceData};
+use rumqttc::{AsyncClient, ConnectionError, Event, MqttOptions, Outgoing, Packet, QoS};
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::{
+    address::{Address, AddressType},
+    discovery,
+    source::Advertisement,
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// The broker to publish to, `mqtt://[USER[:PASSWORD]@]HOST[:PORT]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttUrl {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl FromStr for MqttUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s
+            .strip_prefix("mqtt://")
+            .ok_or_else(|| format!("expected mqtt://[USER[:PASSWORD]@]HOST[:PORT], got {:?}", s))?;
+        let rest = rest.trim_end_matches('/');
+        let (credentials, address) = match rest.rsplit_once('@') {
+            Some((credentials, address)) => (Some(credentials), address),
+            None => (None, rest),
+        };
+        let (username, password) = match credentials.map(|credentials| credentials.split_once(':')) {
+            Some(Some((username, password))) => (Some(username.to_string()), Some(password.to_string())),
+            Some(None) => (credentials.map(str::to_string), None),
+            None => (None, None),
+        };
+        // IPv6 addresses are written in brackets, their colons don't separate the port
+        let (host, port) = match address.rsplit_once(':').filter(|(_, port)| !port.ends_with(']')) {
+            Some((host, port)) => (host, port.parse().map_err(|_| format!("invalid port {:?}", port))?),
+            None => (address, 1883),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(format!("no host in {:?}", s));
+        }
+        Ok(MqttUrl {
+            host: host.to_string(),
+            port,
+            username,
+            password,
+        })
+    }
+}
+
+impl fmt::Display for MqttUrl {
+    /// Without the password, for logging.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.username {
+            Some(username) => write!(f, "mqtt://{}@{}:{}", username, self.host, self.port),
+            None => write!(f, "mqtt://{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// Parses the QoS level 0, 1 or 2.
+pub fn parse_qos(s: &str) -> Result<QoS, String> {
+    match s {
+        "0" => Ok(QoS::AtMostOnce),
+        "1" => Ok(QoS::AtLeastOnce),
+        "2" => Ok(QoS::ExactlyOnce),
+        _ => Err(format!("invalid QoS {:?}, expected 0, 1 or 2", s)),
+    }
+}
+
+/// The last topic level of the availability of a device.
+pub const AVAILABILITY: &str = "availability";
+
+/// The topic of an object of a device, the address is written without separators.
+pub fn topic(prefix: &str, address: &Address, name: &str) -> String {
+    format!("{}/{}/{}", prefix.trim_end_matches('/'), discovery::node_id(address), name)
+}
+
+pub struct Publisher {
+    client: AsyncClient,
+    prefix: String,
+    qos: QoS,
+    /// The discovery prefix of Home Assistant, if entities are announced
+    discovery: Option<String>,
+    /// Measurements of devices whose entities were announced since connecting
+    announced: HashSet<(Address, String)>,
+    /// Set on every connection, as the broker may have lost the retained announcements
+    connected: Arc<AtomicBool>,
+    task: JoinHandle<()>,
+}
+
+impl Publisher {
+    /// Connects to the broker, the connection is kept and re-established in the background.
+    pub fn connect(url: &MqttUrl, client_id: &str, prefix: &str, qos: QoS, discovery: Option<String>) -> Publisher {
+        let mut options = MqttOptions::new(client_id, &url.host, url.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(username) = &url.username {
+            options.set_credentials(username, url.password.as_deref().unwrap_or_default());
+        }
+        let (client, mut eventloop) = AsyncClient::new(options, 100);
+        let broker = url.to_string();
+        let connected = Arc::new(AtomicBool::new(false));
+        let connected_flag = connected.clone();
+        let task = tokio::spawn(async move {
+            let mut connected = false;
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        info!(broker, "Connected to MQTT broker");
+                        connected = true;
+                        connected_flag.store(true, Ordering::Relaxed);
+                    }
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(ConnectionError::RequestsDone) => return,
+                    Ok(_) => {}
+                    Err(err) => {
+                        if connected {
+                            warn!(broker, error = %err, "Lost connection to MQTT broker, reconnecting");
+                        } else {
+                            warn!(broker, error = %err, "Error connecting to MQTT broker, retrying");
+                        }
+                        connected = false;
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                    }
+                }
+            }
+        });
+        Publisher {
+            client,
+            prefix: prefix.to_string(),
+            qos,
+            discovery,
+            announced: HashSet::new(),
+            connected,
+            task,
+        }
+    }
+
+    /// Queues a message for every object, preceded by the discovery configuration of objects
+    /// not announced yet. Returns false if the queue is full because the broker can't be reached.
+    pub fn publish(
+        &mut self,
+        advertisement: &Advertisement,
+        device_name: Option<&str>,
+        service_data: &ServiceData,
+    ) -> bool {
+        let address = &advertisement.address;
+        if self.connected.swap(false, Ordering::Relaxed) {
+            self.announced.clear();
+        }
+        let mut queued = true;
+        let keys = discovery::object_keys(&service_data.objects);
+        for (object, key) in service_data.objects.iter().zip(keys) {
+            let topic = topic(&self.prefix, address, &key);
+            if let Some(discovery_prefix) = &self.discovery {
+                if !self.announced.contains(&(*address, key.clone())) {
+                    let availability = self::topic(&self.prefix, address, AVAILABILITY);
+                    let entity = discovery::entity_config(
+                        discovery_prefix, &topic, &availability, address, device_name, object, &key,
+                    );
+                    if let Some((config_topic, config)) = entity {
+                        if self.client.try_publish(config_topic, QoS::AtLeastOnce, true, config.to_string()).is_err() {
+                            queued = false;
+                            continue;
+                        }
+                    }
+                    self.announced.insert((*address, key));
+                }
+            }
+            queued &= self.client.try_publish(topic, self.qos, false, payload(object, advertisement)).is_ok();
+        }
+        queued
+    }
+
+    /// Queues the retained availability of a device. Returns false if the queue is full.
+    pub fn publish_availability(&mut self, address: &Address, online: bool) -> bool {
+        let payload = if online { "online" } else { "offline" };
+        self.client
+            .try_publish(topic(&self.prefix, address, AVAILABILITY), QoS::AtLeastOnce, true, payload)
+            .is_ok()
+    }
+
+    /// Disconnects after the queued messages were sent.
+    pub async fn close(self) {
+        if self.client.disconnect().await.is_ok() {
+            let _ = self.task.await;
+        }
+    }
+}
+
+/// An object with the advertisement it was received in.
+#[derive(Serialize)]
+struct Message<'a> {
+    #[serde(flatten)]
+    object: &'a Object,
+    rssi: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address_type: Option<AddressType>,
+    /// Milliseconds since the Unix epoch
+    received: u64,
+}
+
+/// The JSON message of an object.
+pub fn payload(object: &Object, advertisement: &Advertisement) -> Vec<u8> {
+    let message = Message {
+        object,
+        rssi: advertisement.rssi,
+        address_type: advertisement.address_type,
+        received: advertisement.received.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64,
+    };
+    serde_json::to_vec(&message).expect("Object to be serializable")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_urls() {
+        assert_eq!(
+            "mqtt://broker".parse(),
+            Ok(MqttUrl { host: "broker".to_string(), port: 1883, username: None, password: None })
+        );
+        assert_eq!(
+            "mqtt://sniffer:s3cr:et@10.0.0.2:1884/".parse(),
+            Ok(MqttUrl {
+                host: "10.0.0.2".to_string(),
+                port: 1884,
+                username: Some("sniffer".to_string()),
+                password: Some("s3cr:et".to_string()),
+            })
+        );
+        assert_eq!("mqtt://[::1]:1885".parse::<MqttUrl>().map(|url| (url.host, url.port)), Ok(("::1".to_string(), 1885)));
+        assert!("tcp://broker:1883".parse::<MqttUrl>().is_err());
+        assert!("mqtt://broker:port".parse::<MqttUrl>().is_err());
+        assert_eq!("mqtt://user:pw@broker".parse::<MqttUrl>().unwrap().to_string(), "mqtt://user@broker:1883");
+    }
+
+    #[test]
+    fn topics() {
+        let address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        assert_eq!(topic("bthome/", &address, "temperature"), "bthome/a4c138123456/temperature");
+        assert_eq!(parse_qos("1"), Ok(QoS::AtLeastOnce));
+        assert!(parse_qos("3").is_err());
+    }
+
+    #[test]
+    fn object_payload() {
+        let advertisement = Advertisement {
+            source: "hci0".to_string(),
+            address: Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]),
+            address_type: Some(AddressType::Public),
+            rssi: Some(-60),
+            advertising: None,
+            received: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            service_data: vec![0x40, 0x02, 0xCA, 0x08],
+        };
+        let object = &bthome::parse_service_data(&advertisement.service_data).unwrap().objects[0];
+        assert_eq!(
+            String::from_utf8(payload(object, &advertisement)).unwrap(),
+            r#"{"name":"temperature","id":2,"unit":"°C","value":22.5,"rssi":-60,"address_type":"public","received":1700000000123}"#
+        );
+    }
+}
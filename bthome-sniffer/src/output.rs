@@ -0,0 +1,213 @@
+//! The decoded packets written to stdout, for reading or as JSON for pipelines like `jq`,
+//! `vector` or `telegraf`.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bthome::{hex, Error, ServiceData};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    address::{Address, AddressType},
+    hci::AdvertisingKind,
+    source::Advertisement,
+};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Output {
+    /// Human readable lines with the raw and the decoded data
+    #[default]
+    Pretty,
+    /// One indented JSON object per packet
+    Json,
+    /// One JSON object per line and packet
+    Ndjson,
+}
+
+impl Output {
+    /// Whether summaries and statistics go to stderr, to keep stdout machine readable.
+    pub fn is_json(self) -> bool {
+        self != Output::Pretty
+    }
+}
+
+/// A received packet as written with `--output json` or `ndjson`.
+#[derive(Serialize, Debug)]
+pub struct Packet<'a> {
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    /// Milliseconds since the sniffer started, unaffected by changes of the system clock
+    pub monotonic_ms: u64,
+    pub address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address_type: Option<AddressType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'a str>,
+    pub source: &'a str,
+    pub rssi: Option<i16>,
+    /// Legacy or extended advertising, if the source tells
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub advertising: Option<AdvertisingKind>,
+    /// Hex encoded service data
+    pub payload: String,
+    #[serde(flatten)]
+    pub result: PacketResult<'a>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum PacketResult<'a> {
+    Decoded {
+        version: u8,
+        encrypted: bool,
+        trigger_based: bool,
+        objects: &'a [bthome::Object],
+    },
+    Failed {
+        error: String,
+    },
+}
+
+impl<'a> Packet<'a> {
+    /// `since_start` is the time the advertisement was received after the sniffer started.
+    pub fn new(
+        advertisement: &'a Advertisement,
+        name: Option<&'a str>,
+        since_start: Duration,
+        result: &'a Result<ServiceData, Error>,
+    ) -> Self {
+        Packet {
+            timestamp: advertisement
+                .received
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or_default(),
+            monotonic_ms: since_start.as_millis() as u64,
+            address: advertisement.address.to_string(),
+            address_type: advertisement.address_type,
+            name,
+            source: &advertisement.source,
+            rssi: advertisement.rssi,
+            advertising: advertisement.advertising,
+            payload: hex::encode(&advertisement.service_data),
+            result: match result {
+                Ok(service_data) => PacketResult::Decoded {
+                    version: service_data.version,
+                    encrypted: service_data.encrypted,
+                    trigger_based: service_data.trigger_based,
+                    objects: &service_data.objects,
+                },
+                Err(err) => PacketResult::Failed { error: err.to_string() },
+            },
+        }
+    }
+
+    pub fn render(&self, output: Output) -> String {
+        render(self, output)
+    }
+}
+
+/// A device going offline or coming back, written between the packets with `--output json` or
+/// `ndjson`.
+#[derive(Serialize, Debug)]
+pub struct Availability<'a> {
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    /// Milliseconds since the sniffer started, unaffected by changes of the system clock
+    pub monotonic_ms: u64,
+    pub address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'a str>,
+    /// `online` or `offline`
+    pub availability: &'static str,
+}
+
+impl<'a> Availability<'a> {
+    /// `time` is when the device was received again or found offline.
+    pub fn new(address: &Address, name: Option<&'a str>, online: bool, time: SystemTime, since_start: Duration) -> Self {
+        Availability {
+            timestamp: time
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or_default(),
+            monotonic_ms: since_start.as_millis() as u64,
+            address: address.to_string(),
+            name,
+            availability: if online { "online" } else { "offline" },
+        }
+    }
+
+    pub fn render(&self, output: Output) -> String {
+        render(self, output)
+    }
+}
+
+fn render(record: &impl Serialize, output: Output) -> String {
+    let rendered = match output {
+        Output::Json => serde_json::to_string_pretty(record),
+        Output::Pretty | Output::Ndjson => serde_json::to_string(record),
+    };
+    rendered.expect("Record to be serializable")
+}
+
+#[cfg(test)]
+mod test {
+    use bthome::parse_service_data;
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    #[test]
+    fn packet_json() {
+        let advertisement = Advertisement {
+            source: "hci0".to_string(),
+            address: Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]),
+            address_type: Some(AddressType::Public),
+            rssi: Some(-60),
+            advertising: Some(AdvertisingKind::Legacy),
+            received: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            service_data: vec![0x40, 0x01, 0x61],
+        };
+        let result = parse_service_data(&advertisement.service_data);
+        let line = Packet::new(&advertisement, Some("Kitchen"), Duration::from_millis(12_345), &result).render(Output::Ndjson);
+        assert!(!line.contains('\n'));
+        let packet: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            packet,
+            json!({
+                "timestamp": 1_700_000_000_123u64,
+                "monotonic_ms": 12_345,
+                "address": "A4:C1:38:12:34:56",
+                "address_type": "public",
+                "name": "Kitchen",
+                "source": "hci0",
+                "rssi": -60,
+                "advertising": "legacy",
+                "payload": "400161",
+                "version": 2,
+                "encrypted": false,
+                "trigger_based": false,
+                "objects": [{"name": "battery", "id": 1, "unit": "%", "value": 97}],
+            })
+        );
+
+        let result = parse_service_data(&[0x41, 0x01]);
+        let packet: Value = serde_json::from_str(&Packet::new(&advertisement, None, Duration::ZERO, &result).render(Output::Json)).unwrap();
+        assert_eq!(packet["error"], "the data is encrypted");
+        assert!(packet.get("name").is_none());
+        assert_eq!(packet["monotonic_ms"], 0);
+    }
+
+    #[test]
+    fn availability_json() {
+        let address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let line = Availability::new(&address, Some("Kitchen"), false, time, Duration::from_secs(90)).render(Output::Ndjson);
+        let record: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(record["timestamp"], 1_700_000_000_123u64);
+        assert_eq!(record["address"], "A4:C1:38:12:34:56");
+        assert_eq!(record["name"], "Kitchen");
+        assert_eq!(record["availability"], "offline");
+        assert_eq!(record["monotonic_ms"], 90_000);
+    }
+}
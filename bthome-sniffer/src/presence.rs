@@ -0,0 +1,113 @@
+//! Detection of devices going offline, i.e. not being received for a while, and coming back.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use bthome::DeviceInfo;
+
+use crate::address::Address;
+
+/// How often devices are checked for having gone offline.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+struct DeviceState {
+    last_seen: Instant,
+    timeout: Duration,
+    online: bool,
+}
+
+/// Tracks when each device was last seen.
+#[derive(Default)]
+pub struct Presence {
+    devices: HashMap<Address, DeviceState>,
+}
+
+/// A change of the availability of a device.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Transition {
+    /// The device was seen for the first time
+    Discovered,
+    /// The device was seen again after having been offline
+    Online,
+}
+
+impl Presence {
+    /// Records that the device was seen, it is considered offline if it is not seen again within
+    /// `timeout`.
+    pub fn seen(&mut self, address: Address, timeout: Duration, now: Instant) -> Option<Transition> {
+        let state = DeviceState {
+            last_seen: now,
+            timeout,
+            online: true,
+        };
+        match self.devices.insert(address, state) {
+            None => Some(Transition::Discovered),
+            Some(previous) if !previous.online => Some(Transition::Online),
+            Some(_) => None,
+        }
+    }
+
+    /// Marks devices that have not been seen within their timeout as offline and returns them
+    /// together with the time since they were last seen.
+    pub fn expire(&mut self, now: Instant) -> Vec<(Address, Duration)> {
+        let mut offline = Vec::new();
+        for (address, state) in self.devices.iter_mut() {
+            let silent = now.duration_since(state.last_seen);
+            if state.online && silent >= state.timeout {
+                state.online = false;
+                offline.push((*address, silent));
+            }
+        }
+        offline.sort();
+        offline
+    }
+
+    /// All devices seen so far with the time they were last seen and whether they are online,
+    /// ordered by address.
+    pub fn devices(&self) -> Vec<(Address, Instant, bool)> {
+        let mut devices: Vec<_> = self
+            .devices
+            .iter()
+            .map(|(address, state)| (*address, state.last_seen, state.online))
+            .collect();
+        devices.sort_by_key(|(address, _, _)| *address);
+        devices
+    }
+}
+
+/// Whether the device info byte of the service data marks the device as trigger based, i.e. it
+/// only sends advertisements when something happens and can be silent for a long time.
+pub fn is_trigger_based(service_data: &[u8]) -> bool {
+    service_data.first().is_some_and(|&device_info| DeviceInfo::from(device_info).trigger_based)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn offline_and_online() {
+        let address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        let timeout = Duration::from_secs(60);
+        let mut presence = Presence::default();
+        let now = Instant::now();
+        assert_eq!(presence.seen(address, timeout, now), Some(Transition::Discovered));
+        assert_eq!(presence.seen(address, timeout, now + Duration::from_secs(30)), None);
+        assert_eq!(presence.expire(now + Duration::from_secs(60)), vec![]);
+        assert_eq!(
+            presence.expire(now + Duration::from_secs(90)),
+            vec![(address, Duration::from_secs(60))]
+        );
+        assert_eq!(presence.expire(now + Duration::from_secs(120)), vec![]);
+        assert_eq!(presence.seen(address, timeout, now + Duration::from_secs(150)), Some(Transition::Online));
+    }
+
+    #[test]
+    fn detect_trigger_based() {
+        assert!(is_trigger_based(&[0x44, 0x3A, 0x01]));
+        assert!(!is_trigger_based(&[0x40, 0x3A, 0x01]));
+        assert!(!is_trigger_based(&[]));
+    }
+}
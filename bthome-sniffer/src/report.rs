@@ -0,0 +1,124 @@
+//! Machine readable summary printed when the sniffer exits after `--duration` or `--count`.
+
+use std::{process::ExitCode, time::Duration};
+
+use serde::Serialize;
+
+use crate::{address::Address, config::Config, stats::Statistics};
+
+/// Exit status if one of the expected devices was never received.
+const EXIT_MISSING_DEVICE: u8 = 2;
+/// Exit status if fewer packets than `--count` were decoded before the sniffer stopped.
+const EXIT_COUNT_NOT_REACHED: u8 = 3;
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub elapsed_secs: f64,
+    pub packets: u64,
+    pub errors: u64,
+    pub duplicates: u64,
+    pub devices: Vec<DeviceReport>,
+    /// Devices given with `--expect` that were never received
+    pub missing: Vec<String>,
+    /// Whether `--count` packets were decoded, always true without `--count`
+    pub count_reached: bool,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DeviceReport {
+    pub address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub packets: u64,
+    pub lost: u64,
+    pub errors: u64,
+    pub decryption_failures: u64,
+    pub sink_failures: u64,
+    pub reception_ratio: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<f64>,
+}
+
+impl Report {
+    pub fn new(
+        config: &Config,
+        statistics: &Statistics,
+        expected: &[Address],
+        count: Option<u64>,
+        duplicates: u64,
+        elapsed: Duration,
+    ) -> Report {
+        let summaries = statistics.summaries();
+        let packets = summaries.iter().map(|(_, summary)| summary.received).sum();
+        let missing = expected
+            .iter()
+            .filter(|address| summaries.iter().all(|(received, _)| received != *address))
+            .map(Address::to_string)
+            .collect();
+        let devices = summaries
+            .iter()
+            .map(|(address, summary)| DeviceReport {
+                address: address.to_string(),
+                name: config.name(address).map(str::to_string),
+                packets: summary.received,
+                lost: summary.lost,
+                errors: summary.errors,
+                decryption_failures: summary.decryption_failures,
+                sink_failures: summary.sink_failures,
+                reception_ratio: summary.reception_ratio,
+                interval_secs: summary.interval.map(|interval| interval.as_secs_f64()),
+            })
+            .collect();
+        Report {
+            elapsed_secs: elapsed.as_secs_f64(),
+            packets,
+            errors: summaries.iter().map(|(_, summary)| summary.errors).sum(),
+            duplicates,
+            devices,
+            missing,
+            count_reached: count.is_none_or(|count| packets >= count),
+        }
+    }
+
+    pub fn exit_code(&self) -> ExitCode {
+        if !self.missing.is_empty() {
+            ExitCode::from(EXIT_MISSING_DEVICE)
+        } else if !self.count_reached {
+            ExitCode::from(EXIT_COUNT_NOT_REACHED)
+        } else {
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+
+    const SEEN: Address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+    const UNSEEN: Address = Address([0xA4, 0xC1, 0x38, 0x65, 0x43, 0x21]);
+
+    #[test]
+    fn report_missing_devices() {
+        let config = Config::parse("[devices.\"A4:C1:38:12:34:56\"]\nname = \"Kitchen\"").expect("Config to parse");
+        let mut statistics = Statistics::default();
+        statistics.record(SEEN, Some(1), Instant::now());
+        statistics.record_error(SEEN);
+
+        let report = Report::new(&config, &statistics, &[SEEN, UNSEEN], Some(1), 0, Duration::from_secs(30));
+        assert_eq!(report.packets, 1);
+        assert_eq!(report.errors, 1);
+        assert_eq!(report.missing, vec!["A4:C1:38:65:43:21".to_string()]);
+        assert_eq!(report.devices[0].name.as_deref(), Some("Kitchen"));
+        assert!(report.count_reached);
+        assert_eq!(report.exit_code(), ExitCode::from(EXIT_MISSING_DEVICE));
+
+        let report = Report::new(&config, &statistics, &[SEEN], Some(2), 0, Duration::from_secs(30));
+        assert_eq!(report.exit_code(), ExitCode::from(EXIT_COUNT_NOT_REACHED));
+
+        let report = Report::new(&config, &statistics, &[SEEN], None, 0, Duration::from_secs(30));
+        assert_eq!(report.exit_code(), ExitCode::SUCCESS);
+    }
+}
@@ -0,0 +1,69 @@
+//! Smoothing of the signal strength of each device, which fluctuates a lot from packet to packet.
+
+use std::collections::HashMap;
+
+use crate::address::Address;
+
+/// Exponentially weighted moving average of the RSSI of each device.
+pub struct RssiTracker {
+    /// Weight of the newest measurement, between 0 (never changes) and 1 (no smoothing)
+    factor: f32,
+    devices: HashMap<Address, f32>,
+}
+
+impl RssiTracker {
+    pub fn new(factor: f32) -> Self {
+        RssiTracker {
+            factor,
+            devices: HashMap::new(),
+        }
+    }
+
+    /// Adds a measurement for `address` and returns the new average.
+    pub fn update(&mut self, address: Address, rssi: i16) -> f32 {
+        let rssi = rssi as f32;
+        let average = self
+            .devices
+            .entry(address)
+            .and_modify(|average| *average += self.factor * (rssi - *average))
+            .or_insert(rssi);
+        *average
+    }
+
+    pub fn average(&self, address: &Address) -> Option<f32> {
+        self.devices.get(address).copied()
+    }
+}
+
+/// Parses the smoothing factor given on the command line.
+pub fn parse_factor(s: &str) -> Result<f32, String> {
+    let factor: f32 = s.parse().map_err(|_| format!("{:?} is not a number", s))?;
+    if !(factor > 0.0 && factor <= 1.0) {
+        return Err("the smoothing factor has to be greater than 0 and at most 1".to_string());
+    }
+    Ok(factor)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn smooth_rssi() {
+        let address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        let mut tracker = RssiTracker::new(0.25);
+        assert_eq!(tracker.update(address, -60), -60.0);
+        assert_eq!(tracker.update(address, -80), -65.0);
+        assert_eq!(tracker.update(address, -65), -65.0);
+        assert_eq!(tracker.update(Address::default(), -90), -90.0);
+    }
+
+    #[test]
+    fn parse_smoothing_factor() {
+        assert_eq!(parse_factor("0.5"), Ok(0.5));
+        assert_eq!(parse_factor("1"), Ok(1.0));
+        assert!(parse_factor("0").is_err());
+        assert!(parse_factor("1.5").is_err());
+        assert!(parse_factor("strong").is_err());
+    }
+}
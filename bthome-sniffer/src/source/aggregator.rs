@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::mpsc::UnboundedSender,
+};
+use tracing::warn;
+
+use super::{Advertisement, Source, SourceError};
+use crate::forward::{Endpoint, ForwardedAdvertisement};
+
+/// The longest line accepted from a satellite, forwarded advertisements are far shorter.
+const MAX_LINE_LENGTH: u64 = 4096;
+/// How long to wait before accepting again after an error, e.g. when out of file descriptors.
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+/// A socket that was already bound, e.g. passed by systemd socket activation.
+enum BoundSocket {
+    Tcp(std::net::TcpListener),
+    Udp(std::net::UdpSocket),
+}
+
+/// Receives the advertisements forwarded by satellite instances.
+pub struct AggregatorSource {
+    endpoint: Endpoint,
+    socket: Option<BoundSocket>,
+}
+
+impl AggregatorSource {
+    pub fn new(endpoint: Endpoint) -> Self {
+        AggregatorSource { endpoint, socket: None }
+    }
+
+    /// Receives on a socket passed by systemd socket activation, which may be a TCP or UDP socket.
+    #[cfg(target_os = "linux")]
+    pub fn from_fd(fd: std::os::fd::OwnedFd) -> std::io::Result<Self> {
+        use std::{io, os::fd::AsRawFd};
+
+        let mut socket_type: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: socket_type and len are valid for writes of the given length
+        let result = unsafe {
+            libc::getsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_TYPE,
+                &mut socket_type as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (endpoint, socket) = match socket_type {
+            libc::SOCK_STREAM => {
+                let listener = std::net::TcpListener::from(fd);
+                listener.set_nonblocking(true)?;
+                (Endpoint::Tcp(listener.local_addr()?.to_string()), BoundSocket::Tcp(listener))
+            }
+            libc::SOCK_DGRAM => {
+                let socket = std::net::UdpSocket::from(fd);
+                socket.set_nonblocking(true)?;
+                (Endpoint::Udp(socket.local_addr()?.to_string()), BoundSocket::Udp(socket))
+            }
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported socket type")),
+        };
+        Ok(AggregatorSource { endpoint, socket: Some(socket) })
+    }
+}
+
+impl Source for AggregatorSource {
+    fn name(&self) -> String {
+        self.endpoint.to_string()
+    }
+
+    async fn run(self, tx: UnboundedSender<Advertisement>) -> Result<(), SourceError> {
+        let name = self.name();
+        match (self.socket, &self.endpoint) {
+            (Some(BoundSocket::Tcp(listener)), _) => accept(TcpListener::from_std(listener)?, &name, &tx).await,
+            (Some(BoundSocket::Udp(socket)), _) => receive_datagrams(UdpSocket::from_std(socket)?, &name, &tx).await,
+            (None, Endpoint::Tcp(address)) => accept(TcpListener::bind(address).await?, &name, &tx).await,
+            (None, Endpoint::Udp(address)) => receive_datagrams(UdpSocket::bind(address).await?, &name, &tx).await,
+        }
+    }
+}
+
+async fn accept(listener: TcpListener, name: &str, tx: &UnboundedSender<Advertisement>) -> Result<(), SourceError> {
+    while !tx.is_closed() {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                warn!(source = %name, error = %err, "Failed to accept satellite connection");
+                tokio::time::sleep(ACCEPT_RETRY).await;
+                continue;
+            }
+        };
+        let tx = tx.clone();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            if let Err(err) = receive_stream(stream, &tx).await {
+                warn!(source = %name, %peer, error = %err, "Connection from satellite failed");
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn receive_datagrams(socket: UdpSocket, name: &str, tx: &UnboundedSender<Advertisement>) -> Result<(), SourceError> {
+    let mut buffer = vec![0u8; 65536];
+    while !tx.is_closed() {
+        let (len, peer) = socket.recv_from(&mut buffer).await?;
+        match decode(&buffer[..len]) {
+            Ok(advertisement) => {
+                let _ = tx.send(advertisement);
+            }
+            Err(err) => warn!(source = %name, %peer, error = %err, "Invalid advertisement from satellite"),
+        }
+    }
+    Ok(())
+}
+
+async fn receive_stream(stream: TcpStream, tx: &UnboundedSender<Advertisement>) -> Result<(), SourceError> {
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if (&mut reader).take(MAX_LINE_LENGTH + 1).read_until(b'\n', &mut line).await? == 0 {
+            return Ok(());
+        }
+        if line.last() != Some(&b'\n') && line.len() as u64 > MAX_LINE_LENGTH {
+            return Err(format!("line longer than {} bytes", MAX_LINE_LENGTH).into());
+        }
+        if tx.send(decode(line.trim_ascii_end())?).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+fn decode(data: &[u8]) -> Result<Advertisement, SourceError> {
+    let forwarded: ForwardedAdvertisement = serde_json::from_slice(data)?;
+    Ok(forwarded.into_advertisement()?)
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::{io::AsyncWriteExt, sync::mpsc};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reject_long_lines() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let receiver = tokio::spawn(async move { receive_stream(stream, &tx).await.map_err(|err| err.to_string()) });
+        let _ = client.write_all(&vec![b'{'; MAX_LINE_LENGTH as usize * 2]).await;
+        assert_eq!(receiver.await.unwrap().unwrap_err(), "line longer than 4096 bytes");
+    }
+}
@@ -0,0 +1,264 @@
+use std::{str::FromStr, time::Duration};
+
+use bluer::{monitor::RssiSamplingPeriod, Session};
+use bthome_listener::{Listener, MonitorSettings};
+use clap::ValueEnum;
+use futures::StreamExt;
+use serde::Deserialize;
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{Advertisement, Source, SourceError};
+use crate::address::AddressType;
+use crate::config::{Args, SharedConfig};
+
+/// How advertisements are received from BlueZ.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScanMode {
+    /// Use the advertisement monitor if BlueZ supports it, otherwise fall back to discovery
+    #[default]
+    Auto,
+    /// Use the advertisement monitor API, which scans passively, requires BlueZ experimental features
+    Monitor,
+    /// Use regular device discovery, which scans actively, and watch the service data property.
+    /// Works with every BlueZ, but devices receive scan requests.
+    #[value(alias = "active")]
+    Discovery,
+}
+
+/// Which advertisements of a matched device the advertisement monitor reports.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum SamplingPeriod {
+    /// Every advertisement
+    All,
+    /// Only the first advertisement after the device was found
+    First,
+    /// One advertisement per period, which BlueZ supports in steps of 100ms up to 25.4s
+    Every(Duration),
+}
+
+impl FromStr for SamplingPeriod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(SamplingPeriod::All),
+            "first" => Ok(SamplingPeriod::First),
+            _ => {
+                let period = crate::duration::parse(s)?;
+                if period.is_zero() || period > Duration::from_millis(25_400) || period.as_millis() % 100 != 0 {
+                    return Err(format!("invalid sampling period {:?}, expected a multiple of 100ms up to 25.4s", s));
+                }
+                Ok(SamplingPeriod::Every(period))
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for SamplingPeriod {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<SamplingPeriod> for RssiSamplingPeriod {
+    fn from(value: SamplingPeriod) -> Self {
+        match value {
+            SamplingPeriod::All => RssiSamplingPeriod::All,
+            SamplingPeriod::First => RssiSamplingPeriod::First,
+            SamplingPeriod::Every(period) => RssiSamplingPeriod::Period(period),
+        }
+    }
+}
+
+/// Settings of the advertisement monitor, from the `[monitor]` section of the configuration file.
+///
+/// High thresholds report only nearby devices and save the battery of the host, the default is to
+/// report every advertisement of every device in range.
+///
+/// ```toml
+/// [monitor]
+/// rssi_low_threshold = -90
+/// rssi_high_threshold = -70
+/// rssi_low_timeout = "30s"
+/// rssi_high_timeout = "5s"
+/// rssi_sampling_period = "1s"
+/// ```
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct MonitorConfig {
+    pub rssi_low_threshold: Option<i16>,
+    pub rssi_high_threshold: Option<i16>,
+    #[serde(default, deserialize_with = "crate::duration::deserialize_option")]
+    pub rssi_low_timeout: Option<Duration>,
+    #[serde(default, deserialize_with = "crate::duration::deserialize_option")]
+    pub rssi_high_timeout: Option<Duration>,
+    pub rssi_sampling_period: Option<SamplingPeriod>,
+}
+
+impl MonitorConfig {
+    /// Applies the options given on the command line on top of the configuration file.
+    fn with_args(&self, args: &Args) -> Result<MonitorConfig, String> {
+        let config = MonitorConfig {
+            rssi_low_threshold: args.rssi_low_threshold.or(self.rssi_low_threshold),
+            rssi_high_threshold: args.rssi_high_threshold.or(self.rssi_high_threshold),
+            rssi_low_timeout: args.rssi_low_timeout.or(self.rssi_low_timeout),
+            rssi_high_timeout: args.rssi_high_timeout.or(self.rssi_high_timeout),
+            rssi_sampling_period: args.rssi_sampling_period.or(self.rssi_sampling_period),
+        };
+        // BlueZ rejects monitors with only one of the thresholds
+        if config.rssi_low_threshold.is_some() != config.rssi_high_threshold.is_some() {
+            return Err("the low and high RSSI thresholds have to be configured together".to_string());
+        }
+        if let (Some(low), Some(high)) = (config.rssi_low_threshold, config.rssi_high_threshold) {
+            if low > high {
+                return Err("the low RSSI threshold has to be below the high threshold".to_string());
+            }
+        }
+        for timeout in [config.rssi_low_timeout, config.rssi_high_timeout].into_iter().flatten() {
+            if timeout < Duration::from_secs(1) || timeout > Duration::from_secs(300) {
+                return Err("the RSSI timeouts have to be between 1s and 300s".to_string());
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl From<ScanMode> for bthome_listener::ScanMode {
+    fn from(value: ScanMode) -> Self {
+        match value {
+            ScanMode::Auto => bthome_listener::ScanMode::Auto,
+            ScanMode::Monitor => bthome_listener::ScanMode::Monitor,
+            ScanMode::Discovery => bthome_listener::ScanMode::Discovery,
+        }
+    }
+}
+
+impl From<MonitorConfig> for MonitorSettings {
+    fn from(value: MonitorConfig) -> Self {
+        MonitorSettings {
+            rssi_low_threshold: value.rssi_low_threshold,
+            rssi_high_threshold: value.rssi_high_threshold,
+            rssi_low_timeout: value.rssi_low_timeout,
+            rssi_high_timeout: value.rssi_high_timeout,
+            rssi_sampling_period: value.rssi_sampling_period.map(RssiSamplingPeriod::from),
+        }
+    }
+}
+
+/// Scans for BTHome devices on a BlueZ adapter.
+pub struct BluezSource {
+    listener: Listener,
+}
+
+/// Creates a source for each adapter selected on the command line.
+pub async fn sources(args: &Args, config: SharedConfig) -> Result<Vec<BluezSource>, SourceError> {
+    let monitor = config.get().monitor.with_args(args)?;
+    let session = Session::new().await?;
+
+    let adapters = if args.all_adapters {
+        let mut adapters = Vec::new();
+        for name in session.adapter_names().await? {
+            adapters.push(session.adapter(&name)?);
+        }
+        adapters
+    } else if !args.adapter.is_empty() {
+        args.adapter
+            .iter()
+            .map(|name| session.adapter(name))
+            .collect::<bluer::Result<_>>()?
+    } else {
+        vec![session.default_adapter().await?]
+    };
+
+    Ok(adapters
+        .into_iter()
+        .map(|adapter| BluezSource {
+            listener: Listener::new(adapter).scan_mode(args.scan_mode.into()).monitor(monitor.clone().into()),
+        })
+        .collect())
+}
+
+impl Source for BluezSource {
+    fn name(&self) -> String {
+        self.listener.adapter().name().to_string()
+    }
+
+    async fn run(self, tx: UnboundedSender<Advertisement>) -> Result<(), SourceError> {
+        // The listener restarts scanning when the adapter is unplugged or bluetoothd restarted,
+        // so the stream only ends when the receiver is dropped.
+        let mut advertisements = self.listener.advertisements();
+        while let Some(advertisement) = advertisements.next().await {
+            let advertisement = Advertisement {
+                source: advertisement.adapter,
+                address: advertisement.mac.into(),
+                address_type: advertisement.address_type.and_then(AddressType::from_bluer),
+                rssi: advertisement.rssi,
+                advertising: None,
+                received: advertisement.received,
+                service_data: advertisement.service_data,
+            };
+            if tx.send(advertisement).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn parse_sampling_period() {
+        assert_eq!("all".parse(), Ok(SamplingPeriod::All));
+        assert_eq!("first".parse(), Ok(SamplingPeriod::First));
+        assert_eq!("1s".parse(), Ok(SamplingPeriod::Every(Duration::from_secs(1))));
+        assert!("150ms".parse::<SamplingPeriod>().is_err());
+        assert!("30s".parse::<SamplingPeriod>().is_err());
+    }
+
+    #[test]
+    fn parse_scan_mode() {
+        let scan_mode = |mode| Args::parse_from(["bthome-sniffer", "--scan-mode", mode]).scan_mode;
+        assert_eq!(scan_mode("active"), ScanMode::Discovery);
+        assert_eq!(scan_mode("discovery"), ScanMode::Discovery);
+        assert!(Args::try_parse_from(["bthome-sniffer", "--scan-mode", "passive"]).is_err());
+    }
+
+    #[test]
+    fn merge_monitor_settings() {
+        let config = Config::parse(
+            r#"
+            [monitor]
+            rssi_low_threshold = -90
+            rssi_high_threshold = -70
+            rssi_sampling_period = "first"
+            "#,
+        )
+        .expect("Config to parse");
+        let args = Args::parse_from(["bthome-sniffer", "--rssi-low-timeout", "30s", "--rssi-sampling-period", "all"]);
+        assert_eq!(
+            config.monitor.with_args(&args),
+            Ok(MonitorConfig {
+                rssi_low_threshold: Some(-90),
+                rssi_high_threshold: Some(-70),
+                rssi_low_timeout: Some(Duration::from_secs(30)),
+                rssi_high_timeout: None,
+                rssi_sampling_period: Some(SamplingPeriod::All),
+            })
+        );
+    }
+
+    #[test]
+    fn reject_incomplete_thresholds() {
+        let config = Config::parse("[monitor]\nrssi_low_threshold = -90").expect("Config to parse");
+        let args = Args::parse_from(["bthome-sniffer"]);
+        assert!(config.monitor.with_args(&args).is_err());
+    }
+}
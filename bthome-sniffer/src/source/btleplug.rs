@@ -0,0 +1,119 @@
+use std::{collections::HashSet, time::SystemTime};
+
+use btleplug::{
+    api::{bleuuid::uuid_from_u16, BDAddr, Central, CentralEvent, Manager as _, Peripheral as _, ScanFilter},
+    platform::{Adapter, Manager, PeripheralId},
+};
+use bthome::BTHOME_UUID16;
+use futures::StreamExt;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::info;
+
+use super::{Advertisement, Source, SourceError};
+use crate::{
+    address::{Address, AddressType},
+    config::{Args, SharedConfig},
+};
+
+/// Scans for BTHome devices using btleplug, which works on Linux, macOS, Windows and Android.
+pub struct BtleplugSource {
+    adapter: Adapter,
+    name: String,
+    config: SharedConfig,
+}
+
+/// Creates a source for each adapter selected on the command line.
+///
+/// As btleplug has no stable adapter names, `--adapter` matches against the adapter description.
+pub async fn sources(args: &Args, config: SharedConfig) -> Result<Vec<BtleplugSource>, SourceError> {
+    let manager = Manager::new().await.map_err(manager_error)?;
+    let mut sources = Vec::new();
+    for adapter in manager.adapters().await? {
+        let name = adapter.adapter_info().await?;
+        let selected = args.all_adapters
+            || args.adapter.iter().any(|wanted| name.contains(wanted.as_str()))
+            || (args.adapter.is_empty() && sources.is_empty());
+        if selected {
+            sources.push(BtleplugSource {
+                adapter,
+                name,
+                config: config.clone(),
+            });
+        }
+    }
+    Ok(sources)
+}
+
+impl Source for BtleplugSource {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn run(self, tx: UnboundedSender<Advertisement>) -> Result<(), SourceError> {
+        let bthome_uuid = uuid_from_u16(BTHOME_UUID16);
+        let mut events = self.adapter.events().await?;
+        self.adapter.start_scan(ScanFilter::default()).await?;
+
+        let mut known = HashSet::new();
+        while let Some(event) = events.next().await {
+            let CentralEvent::ServiceDataAdvertisement { id, service_data } = event else {
+                continue;
+            };
+            let Some(bthome_data) = service_data.get(&bthome_uuid) else {
+                continue;
+            };
+            let peripheral = self.adapter.peripheral(&id).await?;
+            let address = device_address(peripheral.address(), &id);
+            let properties = peripheral.properties().await?;
+            if known.insert(address) {
+                let name = properties.as_ref().and_then(|p| p.local_name.clone());
+                info!(source = %self.name, device = %self.config.get().label(&address), ?name, "Discovered BTHome device");
+            }
+            let advertisement = Advertisement {
+                source: self.name.clone(),
+                address,
+                address_type: properties.as_ref().and_then(|p| p.address_type).map(AddressType::from),
+                rssi: properties.and_then(|p| p.rssi),
+                advertising: None,
+                received: SystemTime::now(),
+                service_data: bthome_data.clone(),
+            };
+            if tx.send(advertisement).is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// On Android btleplug talks to the Bluetooth stack through the JVM of the app, which has to be
+/// handed over with `btleplug::platform::init` before any manager is created.
+#[cfg(target_os = "android")]
+fn manager_error(err: btleplug::Error) -> SourceError {
+    format!("{} (btleplug::platform::init has to be called with the JNI environment first)", err).into()
+}
+
+#[cfg(not(target_os = "android"))]
+fn manager_error(err: btleplug::Error) -> SourceError {
+    err.into()
+}
+
+/// CoreBluetooth on macOS does not expose device addresses, only a random per host UUID.
+/// In that case a stable pseudo address is derived from the last six bytes of that UUID,
+/// so that the device can still be named in the configuration.
+fn device_address(address: BDAddr, id: &PeripheralId) -> Address {
+    if address != BDAddr::default() {
+        return Address(address.into_inner());
+    }
+    let digits: Vec<u8> = id
+        .to_string()
+        .chars()
+        .filter_map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect();
+    let mut bytes = [0u8; 6];
+    for (byte, pair) in bytes.iter_mut().zip(digits[digits.len().saturating_sub(12)..].chunks(2)) {
+        *byte = pair.iter().fold(0, |acc, d| acc << 4 | d);
+    }
+    Address(bytes)
+}
@@ -0,0 +1,366 @@
+//! Receives advertisements relayed by ESPHome Bluetooth proxies via the ESPHome native API.
+//!
+//! Only the plaintext protocol is supported, the API of the proxy must not be configured with an
+//! encryption key. Messages are encoded by hand as only a handful of them is needed, the encoding
+//! is shared with the server side in [`crate::esphome_proxy`].
+
+use std::time::{Duration, SystemTime};
+
+use bthome::{extract_bthome_from_adv, BTHOME_UUID, BTHOME_UUID16};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc::UnboundedSender,
+};
+use tracing::warn;
+
+use super::{Advertisement, Source, SourceError};
+use crate::address::{Address, AddressType};
+
+const DEFAULT_PORT: u16 = 6053;
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+/// Longer messages are rejected before allocating them, the BLE messages are far smaller
+pub const MAX_MESSAGE_LENGTH: u64 = 64 * 1024;
+
+pub const HELLO_REQUEST: u32 = 1;
+pub const CONNECT_REQUEST: u32 = 3;
+pub const CONNECT_RESPONSE: u32 = 4;
+pub const DISCONNECT_REQUEST: u32 = 5;
+pub const DISCONNECT_RESPONSE: u32 = 6;
+pub const PING_REQUEST: u32 = 7;
+pub const PING_RESPONSE: u32 = 8;
+pub const SUBSCRIBE_BLUETOOTH_LE_ADVERTISEMENTS_REQUEST: u32 = 66;
+pub const BLUETOOTH_LE_ADVERTISEMENT_RESPONSE: u32 = 67;
+pub const BLUETOOTH_LE_RAW_ADVERTISEMENTS_RESPONSE: u32 = 93;
+
+pub const SUBSCRIPTION_FLAG_RAW_ADVERTISEMENTS: u64 = 1;
+
+/// Connects to an ESPHome Bluetooth proxy and receives the advertisements it relays.
+pub struct EsphomeSource {
+    host: String,
+    password: Option<String>,
+}
+
+impl EsphomeSource {
+    pub fn new(host: &str, password: Option<String>) -> Self {
+        let host = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:{}", host, DEFAULT_PORT)
+        };
+        EsphomeSource { host, password }
+    }
+
+    async fn receive(&self, tx: &UnboundedSender<Advertisement>) -> Result<(), SourceError> {
+        let stream = TcpStream::connect(&self.host).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        let mut hello = Vec::new();
+        put_string(&mut hello, 1, concat!("bthome-sniffer ", env!("CARGO_PKG_VERSION")));
+        put_varint_field(&mut hello, 2, 1);
+        put_varint_field(&mut hello, 3, 9);
+        write_message(&mut writer, HELLO_REQUEST, &hello).await?;
+
+        let mut connect = Vec::new();
+        if let Some(password) = &self.password {
+            put_string(&mut connect, 1, password);
+        }
+        write_message(&mut writer, CONNECT_REQUEST, &connect).await?;
+
+        let mut subscribe = Vec::new();
+        put_varint_field(&mut subscribe, 1, SUBSCRIPTION_FLAG_RAW_ADVERTISEMENTS);
+        write_message(&mut writer, SUBSCRIBE_BLUETOOTH_LE_ADVERTISEMENTS_REQUEST, &subscribe).await?;
+
+        loop {
+            let (message_type, payload) = read_message(&mut reader).await?;
+            let advertisements = match message_type {
+                CONNECT_RESPONSE => {
+                    if fields(&payload).any(|(field, value)| field == 1 && value == Value::Varint(1)) {
+                        return Err("invalid API password".into());
+                    }
+                    continue;
+                }
+                PING_REQUEST => {
+                    write_message(&mut writer, PING_RESPONSE, &[]).await?;
+                    continue;
+                }
+                DISCONNECT_REQUEST => {
+                    write_message(&mut writer, DISCONNECT_RESPONSE, &[]).await?;
+                    return Err("proxy closed the connection".into());
+                }
+                BLUETOOTH_LE_RAW_ADVERTISEMENTS_RESPONSE => parse_raw_advertisements(&payload),
+                BLUETOOTH_LE_ADVERTISEMENT_RESPONSE => parse_advertisement(&payload).into_iter().collect(),
+                _ => continue,
+            };
+            for (address, address_type, rssi, service_data) in advertisements {
+                let advertisement = Advertisement {
+                    source: self.host.clone(),
+                    address,
+                    address_type,
+                    rssi: Some(rssi),
+                    advertising: None,
+                    received: SystemTime::now(),
+                    service_data,
+                };
+                if tx.send(advertisement).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl Source for EsphomeSource {
+    fn name(&self) -> String {
+        self.host.clone()
+    }
+
+    async fn run(self, tx: UnboundedSender<Advertisement>) -> Result<(), SourceError> {
+        // Proxies reboot or drop off the network, so keep reconnecting until the receiver is gone
+        while !tx.is_closed() {
+            if let Err(err) = self.receive(&tx).await {
+                warn!(source = %self.host, error = %err, delay = ?RECONNECT_DELAY, "Connection to ESPHome proxy failed, reconnecting");
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+        Ok(())
+    }
+}
+
+pub async fn write_message(writer: &mut (impl AsyncWriteExt + Unpin), message_type: u32, payload: &[u8]) -> std::io::Result<()> {
+    let mut frame = vec![0x00];
+    put_varint(&mut frame, payload.len() as u64);
+    put_varint(&mut frame, message_type as u64);
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame).await
+}
+
+pub async fn read_message(reader: &mut (impl AsyncReadExt + Unpin)) -> Result<(u32, Vec<u8>), SourceError> {
+    if reader.read_u8().await? != 0x00 {
+        return Err("unexpected frame, is the API encrypted?".into());
+    }
+    let length = read_varint(reader).await?;
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(format!("message of {} bytes is longer than the maximum of {}", length, MAX_MESSAGE_LENGTH).into());
+    }
+    let message_type = read_varint(reader).await?;
+    let mut payload = vec![0u8; length as usize];
+    reader.read_exact(&mut payload).await?;
+    Ok((message_type as u32, payload))
+}
+
+async fn read_varint(reader: &mut (impl AsyncReadExt + Unpin)) -> std::io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = reader.read_u8().await?;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok(value)
+}
+
+pub fn put_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+pub fn put_varint_field(buffer: &mut Vec<u8>, field: u32, value: u64) {
+    put_varint(buffer, (field << 3) as u64);
+    put_varint(buffer, value);
+}
+
+pub fn put_string(buffer: &mut Vec<u8>, field: u32, value: &str) {
+    put_bytes(buffer, field, value.as_bytes());
+}
+
+pub fn put_bytes(buffer: &mut Vec<u8>, field: u32, value: &[u8]) {
+    put_varint(buffer, (field << 3 | 2) as u64);
+    put_varint(buffer, value.len() as u64);
+    buffer.extend_from_slice(value);
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// Iterates over the fields of a protobuf message, stopping at the first malformed field.
+pub fn fields(mut data: &[u8]) -> impl Iterator<Item = (u32, Value<'_>)> {
+    std::iter::from_fn(move || {
+        let key = take_varint(&mut data)?;
+        let value = match key & 0x7 {
+            0 => Value::Varint(take_varint(&mut data)?),
+            2 => {
+                let length = take_varint(&mut data)? as usize;
+                if data.len() < length {
+                    return None;
+                }
+                let (value, rest) = data.split_at(length);
+                data = rest;
+                Value::Bytes(value)
+            }
+            5 => {
+                data = data.get(4..)?;
+                Value::Varint(0)
+            }
+            1 => {
+                data = data.get(8..)?;
+                Value::Varint(0)
+            }
+            _ => return None,
+        };
+        Some(((key >> 3) as u32, value))
+    })
+}
+
+fn take_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (i, byte) in data.iter().enumerate().take(10) {
+        value |= ((byte & 0x7F) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *data = &data[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn zigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+fn address(value: u64) -> Address {
+    let mut address = [0u8; 6];
+    address.copy_from_slice(&value.to_be_bytes()[2..]);
+    Address(address)
+}
+
+/// Parses a `BluetoothLERawAdvertisementsResponse`, returning the BTHome advertisements.
+pub fn parse_raw_advertisements(payload: &[u8]) -> Vec<(Address, Option<AddressType>, i16, Vec<u8>)> {
+    let mut result = Vec::new();
+    for (field, value) in fields(payload) {
+        let (1, Value::Bytes(advertisement)) = (field, value) else {
+            continue;
+        };
+        let (mut mac, mut address_type, mut rssi, mut data) = (0, None, 0, &[][..]);
+        for (field, value) in fields(advertisement) {
+            match (field, value) {
+                (1, Value::Varint(value)) => mac = value,
+                (2, Value::Varint(value)) => rssi = zigzag(value),
+                (3, Value::Varint(value)) => address_type = u8::try_from(value).ok().and_then(AddressType::from_hci),
+                (4, Value::Bytes(value)) => data = value,
+                _ => {}
+            }
+        }
+        if let Some(service_data) = extract_bthome_from_adv(data) {
+            result.push((address(mac), address_type, rssi as i16, service_data.to_vec()));
+        }
+    }
+    result
+}
+
+/// Parses a `BluetoothLEAdvertisementResponse` as sent by proxies not supporting raw advertisements.
+pub fn parse_advertisement(payload: &[u8]) -> Option<(Address, Option<AddressType>, i16, Vec<u8>)> {
+    let (mut mac, mut address_type, mut rssi, mut bthome_data) = (0, None, 0, None);
+    for (field, value) in fields(payload) {
+        match (field, value) {
+            (1, Value::Varint(value)) => mac = value,
+            (3, Value::Varint(value)) => rssi = zigzag(value),
+            (5, Value::Bytes(service_data)) => {
+                let (mut uuid, mut data) = ("", &[][..]);
+                for (field, value) in fields(service_data) {
+                    match (field, value) {
+                        (1, Value::Bytes(value)) => uuid = std::str::from_utf8(value).unwrap_or_default(),
+                        (3, Value::Bytes(value)) => data = value,
+                        _ => {}
+                    }
+                }
+                if is_bthome_uuid(uuid) {
+                    bthome_data = Some(data.to_vec());
+                }
+            }
+            (7, Value::Varint(value)) => address_type = u8::try_from(value).ok().and_then(AddressType::from_hci),
+            _ => {}
+        }
+    }
+    Some((address(mac), address_type, rssi as i16, bthome_data?))
+}
+
+fn is_bthome_uuid(uuid: &str) -> bool {
+    match uuid.strip_prefix("0x") {
+        Some(short) => u16::from_str_radix(short, 16) == Ok(BTHOME_UUID16),
+        None => u128::from_str_radix(&uuid.replace('-', ""), 16) == Ok(BTHOME_UUID),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_raw_advertisement_response() {
+        let mut advertisement = Vec::new();
+        put_varint_field(&mut advertisement, 1, 0xA4C1_3812_3456);
+        put_varint_field(&mut advertisement, 2, 119); // zigzag encoded -60
+        put_varint_field(&mut advertisement, 3, 1); // random address
+        put_varint(&mut advertisement, 4 << 3 | 2);
+        let ad = [0x02, 0x01, 0x06, 0x07, 0x16, 0xD2, 0xFC, 0x40, 0x02, 0xC4, 0x09];
+        put_varint(&mut advertisement, ad.len() as u64);
+        advertisement.extend_from_slice(&ad);
+        let mut payload = Vec::new();
+        put_varint(&mut payload, 1 << 3 | 2);
+        put_varint(&mut payload, advertisement.len() as u64);
+        payload.extend_from_slice(&advertisement);
+
+        assert_eq!(
+            parse_raw_advertisements(&payload),
+            vec![(
+                Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]),
+                Some(AddressType::Random),
+                -60,
+                vec![0x40, 0x02, 0xC4, 0x09]
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_long_messages() {
+        let mut frame = vec![0x00];
+        put_varint(&mut frame, 1 << 40);
+        put_varint(&mut frame, PING_REQUEST as u64);
+        assert!(read_message(&mut &frame[..]).await.is_err());
+
+        let mut frame = Vec::new();
+        write_message(&mut frame, PING_REQUEST, &[0x01, 0x02]).await.unwrap();
+        assert_eq!(read_message(&mut &frame[..]).await.unwrap(), (PING_REQUEST, vec![0x01, 0x02]));
+    }
+
+    #[test]
+    fn parse_advertisement_response() {
+        let mut service_data = Vec::new();
+        put_string(&mut service_data, 1, "0000fcd2-0000-1000-8000-00805f9b34fb");
+        put_varint(&mut service_data, 3 << 3 | 2);
+        put_varint(&mut service_data, 2);
+        service_data.extend_from_slice(&[0x40, 0x00]);
+        let mut payload = Vec::new();
+        put_varint_field(&mut payload, 1, 0xA4C1_3812_3456);
+        put_varint_field(&mut payload, 3, 149); // zigzag encoded -75
+        put_varint(&mut payload, 5 << 3 | 2);
+        put_varint(&mut payload, service_data.len() as u64);
+        payload.extend_from_slice(&service_data);
+
+        assert_eq!(
+            parse_advertisement(&payload),
+            Some((Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]), None, -75, vec![0x40, 0x00]))
+        );
+        assert!(is_bthome_uuid("0xFCD2"));
+        assert!(!is_bthome_uuid("0x181A"));
+    }
+}
@@ -0,0 +1,231 @@
+//! Receives advertising reports directly from a raw HCI socket.
+//!
+//! This bypasses BlueZ's device cache and deduplication, so every single advertisement is seen
+//! as soon as the controller reports it. Opening the socket requires `CAP_NET_RAW`. It works
+//! without bluetoothd as well, then the adapter is brought up by the sniffer, which requires
+//! `CAP_NET_ADMIN`.
+//!
+//! Controllers supporting Bluetooth 5 scan for extended advertisements as well, which carry more
+//! than the 31 bytes of legacy advertisements.
+
+use std::{
+    io,
+    mem::size_of,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    time::{Instant, SystemTime},
+};
+
+use bthome::hci::{parse_event, Assembler, EVT_LE_META_EVENT, HCI_EVENT_PKT};
+use tokio::{io::unix::AsyncFd, sync::mpsc::UnboundedSender};
+use tracing::warn;
+
+use super::{bluez::ScanMode, Advertisement, Backoff, Source, SourceError};
+use crate::{
+    address::{Address, AddressType},
+    config::Args,
+    hci::AdvertisingKind,
+};
+
+const BTPROTO_HCI: libc::c_int = 1;
+const SOL_HCI: libc::c_int = 0;
+const HCI_FILTER: libc::c_int = 2;
+const HCI_CHANNEL_RAW: u16 = 0;
+/// `_IOW('H', 201, int)`
+const HCIDEVUP: u32 = 0x400448C9;
+
+const HCI_COMMAND_PKT: u8 = 0x01;
+const OCF_LE_SET_SCAN_PARAMETERS: u16 = 0x200B;
+const OCF_LE_SET_SCAN_ENABLE: u16 = 0x200C;
+const OCF_LE_SET_EXTENDED_SCAN_PARAMETERS: u16 = 0x2041;
+const OCF_LE_SET_EXTENDED_SCAN_ENABLE: u16 = 0x2042;
+
+#[repr(C)]
+struct SockaddrHci {
+    hci_family: libc::sa_family_t,
+    hci_dev: u16,
+    hci_channel: u16,
+}
+
+#[repr(C)]
+struct HciFilter {
+    type_mask: u32,
+    event_mask: [u32; 2],
+    opcode: u16,
+}
+
+pub struct HciSource {
+    device: u16,
+    /// Send scan requests, so that devices answer with their scan response
+    active: bool,
+}
+
+/// Creates a source for the adapter of the backend, e.g. `--backend hci1-raw`, or for each adapter
+/// selected on the command line, e.g. `--adapter hci1`.
+pub fn sources(args: &Args, device: Option<u16>) -> Result<Vec<HciSource>, SourceError> {
+    let active = args.scan_mode == ScanMode::Discovery;
+    if let Some(device) = device {
+        if !args.adapter.is_empty() {
+            return Err("--adapter can't be combined with the adapter of --backend hciN-raw".into());
+        }
+        return Ok(vec![HciSource { device, active }]);
+    }
+    if args.adapter.is_empty() {
+        return Ok(vec![HciSource { device: 0, active }]);
+    }
+    args.adapter
+        .iter()
+        .map(|name| {
+            let device = name
+                .trim_start_matches("hci")
+                .parse()
+                .map_err(|_| format!("Invalid HCI device name {:?}", name))?;
+            Ok(HciSource { device, active })
+        })
+        .collect()
+}
+
+impl Source for HciSource {
+    fn name(&self) -> String {
+        format!("hci{}", self.device)
+    }
+
+    async fn run(self, tx: UnboundedSender<Advertisement>) -> Result<(), SourceError> {
+        // Reading fails when the adapter is unplugged or reset, so reopen the socket until it is back
+        let mut backoff = Backoff::new();
+        while !tx.is_closed() {
+            let started = Instant::now();
+            if let Err(err) = self.receive(&tx).await {
+                let delay = backoff.next(started);
+                warn!(source = %self.name(), error = %err, ?delay, "Receiving advertisements failed, retrying");
+                tokio::time::sleep(delay).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HciSource {
+    async fn receive(&self, tx: &UnboundedSender<Advertisement>) -> Result<(), SourceError> {
+        let socket = AsyncFd::new(open_socket(self.device)?)?;
+        device_up(socket.get_ref(), self.device)?;
+        // Scanning without duplicate filtering, BlueZ may already be scanning in which case the
+        // controller rejects the commands, but reports are delivered to us anyway. Controllers
+        // without extended scanning reject the extended commands, those with it the legacy
+        // commands once extended scanning is enabled, so that always one set takes effect.
+        let scan_type = u8::from(self.active);
+        let fd = socket.get_ref();
+        send_command(fd, OCF_LE_SET_EXTENDED_SCAN_PARAMETERS, &[0x00, 0x00, 0x01, scan_type, 0x10, 0x00, 0x10, 0x00])?;
+        send_command(fd, OCF_LE_SET_EXTENDED_SCAN_ENABLE, &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00])?;
+        send_command(fd, OCF_LE_SET_SCAN_PARAMETERS, &[scan_type, 0x10, 0x00, 0x10, 0x00, 0x00, 0x00])?;
+        send_command(fd, OCF_LE_SET_SCAN_ENABLE, &[0x01, 0x00])?;
+
+        let mut buffer = [0u8; 1024];
+        let mut assembler = Assembler::default();
+        loop {
+            let mut guard = socket.readable().await?;
+            let len = match guard.try_io(|fd| read(fd.get_ref(), &mut buffer)) {
+                Ok(result) => result?,
+                Err(_would_block) => continue,
+            };
+            for report in parse_event(&buffer[..len]) {
+                let Some(service_data) = assembler.add(&report) else {
+                    continue;
+                };
+                let advertisement = Advertisement {
+                    source: self.name(),
+                    address: Address(report.address),
+                    address_type: AddressType::from_hci(report.address_type),
+                    rssi: Some(report.rssi.into()),
+                    advertising: Some(AdvertisingKind::of(&report)),
+                    received: SystemTime::now(),
+                    service_data,
+                };
+                if tx.send(advertisement).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn open_socket(device: u16) -> io::Result<OwnedFd> {
+    // SAFETY: plain socket syscalls, the returned descriptor is checked and immediately owned.
+    unsafe {
+        let fd = libc::socket(
+            libc::AF_BLUETOOTH,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+            BTPROTO_HCI,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = OwnedFd::from_raw_fd(fd);
+
+        let filter = HciFilter {
+            type_mask: 1 << HCI_EVENT_PKT,
+            event_mask: [0, 1 << (EVT_LE_META_EVENT - 32)],
+            opcode: 0,
+        };
+        if libc::setsockopt(
+            fd.as_raw_fd(),
+            SOL_HCI,
+            HCI_FILTER,
+            &filter as *const HciFilter as *const libc::c_void,
+            size_of::<HciFilter>() as libc::socklen_t,
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        let addr = SockaddrHci {
+            hci_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+            hci_dev: device,
+            hci_channel: HCI_CHANNEL_RAW,
+        };
+        if libc::bind(
+            fd.as_raw_fd(),
+            &addr as *const SockaddrHci as *const libc::sockaddr,
+            size_of::<SockaddrHci>() as libc::socklen_t,
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(fd)
+    }
+}
+
+/// Brings the adapter up, which bluetoothd does if it is running. Without `CAP_NET_ADMIN` this
+/// fails even for adapters that are up, so then the adapter is assumed to be up.
+fn device_up(fd: &OwnedFd, device: u16) -> io::Result<()> {
+    // SAFETY: HCIDEVUP takes the device id as its argument and doesn't access memory.
+    if unsafe { libc::ioctl(fd.as_raw_fd(), HCIDEVUP as _, device as libc::c_int) } < 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::EALREADY) | Some(libc::EPERM) => Ok(()),
+            _ => Err(err),
+        };
+    }
+    Ok(())
+}
+
+fn read(fd: &OwnedFd, buffer: &mut [u8]) -> io::Result<usize> {
+    // SAFETY: the buffer is valid for writes of its full length.
+    let len = unsafe { libc::read(fd.as_raw_fd(), buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(len as usize)
+}
+
+fn send_command(fd: &OwnedFd, opcode: u16, parameters: &[u8]) -> io::Result<()> {
+    let mut packet = vec![HCI_COMMAND_PKT];
+    packet.extend_from_slice(&opcode.to_le_bytes());
+    packet.push(parameters.len() as u8);
+    packet.extend_from_slice(parameters);
+    // SAFETY: the packet is valid for reads of its full length.
+    let len = unsafe { libc::write(fd.as_raw_fd(), packet.as_ptr() as *const libc::c_void, packet.len()) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,225 @@
+use std::{error::Error, ffi::OsStr, future::Future, time::SystemTime};
+
+pub use bthome_listener::Backoff;
+use clap::{
+    builder::{EnumValueParser, PossibleValue, TypedValueParser},
+    Arg, Command, ValueEnum,
+};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::error;
+
+use crate::{
+    address::{Address, AddressType},
+    config::{Args, SharedConfig},
+    hci::AdvertisingKind,
+};
+
+pub mod aggregator;
+#[cfg(target_os = "linux")]
+pub mod bluez;
+#[cfg(feature = "btleplug")]
+pub mod btleplug;
+pub mod esphome;
+#[cfg(target_os = "linux")]
+pub mod hci_socket;
+pub mod replay;
+pub mod stdin;
+
+#[cfg(not(any(target_os = "linux", feature = "btleplug")))]
+compile_error!("On platforms other than Linux, including Android, the `btleplug` feature has to be enabled");
+
+pub type SourceError = Box<dyn Error + Send + Sync>;
+
+/// Raw BTHome service data received by one of the sources.
+#[derive(Debug)]
+pub struct Advertisement {
+    /// Name of the source, e.g. the adapter, that received the advertisement
+    pub source: String,
+    pub address: Address,
+    /// Whether the address is public or random, if the source tells
+    pub address_type: Option<AddressType>,
+    /// Signal strength in dBm, if known
+    pub rssi: Option<i16>,
+    /// Whether it was sent with legacy or extended advertising, if the source tells
+    pub advertising: Option<AdvertisingKind>,
+    pub received: SystemTime,
+    pub service_data: Vec<u8>,
+}
+
+/// Something that receives BTHome advertisements, e.g. a Bluetooth adapter.
+pub trait Source {
+    /// Name used to tag the advertisements received by this source.
+    fn name(&self) -> String;
+
+    /// Receive advertisements and forward them to `tx` until the source stops or the receiver is dropped.
+    fn run(self, tx: UnboundedSender<Advertisement>) -> impl Future<Output = Result<(), SourceError>> + Send;
+}
+
+/// The BLE stack used to receive advertisements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// BlueZ via D-Bus (Linux only)
+    #[cfg(target_os = "linux")]
+    Bluez,
+    /// Raw HCI socket of the given adapter number, or of the adapters selected with `--adapter`
+    #[cfg(target_os = "linux")]
+    Hci(Option<u16>),
+    /// btleplug, supports Linux, macOS, Windows and Android
+    #[cfg(feature = "btleplug")]
+    Btleplug,
+    /// Do not use a local adapter, e.g. when only receiving from satellites or ESPHome proxies
+    None,
+}
+
+impl ValueEnum for Backend {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            #[cfg(target_os = "linux")]
+            Backend::Bluez,
+            #[cfg(target_os = "linux")]
+            Backend::Hci(None),
+            #[cfg(feature = "btleplug")]
+            Backend::Btleplug,
+            Backend::None,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            #[cfg(target_os = "linux")]
+            Backend::Bluez => PossibleValue::new("bluez").help("BlueZ via D-Bus (Linux only)"),
+            #[cfg(target_os = "linux")]
+            Backend::Hci(None) => PossibleValue::new("hci").alias("hci-raw").help(
+                "Raw HCI socket, receives every advertisement bypassing BlueZ, also without bluetoothd, \
+                 hciN-raw selects adapter N (Linux only, needs CAP_NET_RAW and CAP_NET_ADMIN to bring the adapter up)",
+            ),
+            #[cfg(target_os = "linux")]
+            Backend::Hci(Some(_)) => return None,
+            #[cfg(feature = "btleplug")]
+            Backend::Btleplug => PossibleValue::new("btleplug").help("btleplug, supports Linux, macOS, Windows and Android"),
+            Backend::None => PossibleValue::new("none")
+                .help("Do not use a local adapter, e.g. when only receiving from satellites or ESPHome proxies"),
+        })
+    }
+}
+
+/// Parses `--backend`, which besides the names of the backends accepts `hci0-raw` for the raw HCI
+/// socket of a single adapter.
+#[derive(Clone)]
+pub struct BackendParser;
+
+impl TypedValueParser for BackendParser {
+    type Value = Backend;
+
+    fn parse_ref(&self, cmd: &Command, arg: Option<&Arg>, value: &OsStr) -> Result<Backend, clap::Error> {
+        #[cfg(target_os = "linux")]
+        if let Some(device) = value
+            .to_str()
+            .and_then(|value| value.strip_prefix("hci")?.strip_suffix("-raw")?.parse().ok())
+        {
+            return Ok(Backend::Hci(Some(device)));
+        }
+        EnumValueParser::<Backend>::new().parse_ref(cmd, arg, value)
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        Some(Box::new(Backend::value_variants().iter().filter_map(Backend::to_possible_value)))
+    }
+}
+
+impl Default for Backend {
+    #[cfg(target_os = "linux")]
+    fn default() -> Self {
+        Backend::Bluez
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn default() -> Self {
+        Backend::Btleplug
+    }
+}
+
+/// Run `source` in the background, forwarding its advertisements to `tx`.
+pub fn spawn<S: Source + Send + 'static>(source: S, tx: UnboundedSender<Advertisement>) {
+    tokio::spawn(async move {
+        let name = source.name();
+        if let Err(err) = source.run(tx).await {
+            error!(source = %name, error = %err, "Receiving advertisements stopped");
+        }
+    });
+}
+
+/// Starts all sources selected on the command line and returns how many were started.
+pub async fn start(
+    args: &Args,
+    config: SharedConfig,
+    tx: &UnboundedSender<Advertisement>,
+) -> Result<usize, SourceError> {
+    if let Some(path) = &args.replay {
+        spawn(replay::ReplaySource { path: path.clone() }, tx.clone());
+        return Ok(1);
+    }
+    if args.stdin {
+        spawn(stdin::StdinSource, tx.clone());
+        return Ok(1);
+    }
+
+    let mut sources = 0;
+    for endpoint in &args.listen {
+        spawn(aggregator::AggregatorSource::new(endpoint.clone()), tx.clone());
+        sources += 1;
+    }
+    // Sockets passed by systemd socket activation are used to receive from satellites as well
+    #[cfg(target_os = "linux")]
+    for fd in crate::systemd::listen_fds() {
+        spawn(aggregator::AggregatorSource::from_fd(fd)?, tx.clone());
+        sources += 1;
+    }
+    for host in &args.esphome {
+        spawn(esphome::EsphomeSource::new(host, args.esphome_password.clone()), tx.clone());
+        sources += 1;
+    }
+    match args.backend {
+        #[cfg(target_os = "linux")]
+        Backend::Bluez => {
+            for source in bluez::sources(args, config).await? {
+                spawn(source, tx.clone());
+                sources += 1;
+            }
+        }
+        #[cfg(target_os = "linux")]
+        Backend::Hci(device) => {
+            for source in hci_socket::sources(args, device)? {
+                spawn(source, tx.clone());
+                sources += 1;
+            }
+        }
+        #[cfg(feature = "btleplug")]
+        Backend::Btleplug => {
+            for source in btleplug::sources(args, config).await? {
+                spawn(source, tx.clone());
+                sources += 1;
+            }
+        }
+        Backend::None => {}
+    }
+    Ok(sources)
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn raw_hci_backend() {
+        let backend = |value| Args::try_parse_from(["bthome-sniffer", "--backend", value]).map(|args| args.backend);
+        assert_eq!(backend("hci").unwrap(), Backend::Hci(None));
+        assert_eq!(backend("hci-raw").unwrap(), Backend::Hci(None));
+        assert_eq!(backend("hci1-raw").unwrap(), Backend::Hci(Some(1)));
+        assert_eq!(backend("none").unwrap(), Backend::None);
+        assert!(backend("hciX-raw").is_err());
+    }
+}
@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+
+use bthome::capture::{CaptureReader, MAGIC};
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{Advertisement, Source, SourceError};
+use crate::{address::Address, capture};
+
+/// Replays the BTHome advertisements contained in a btsnoop, pcap or pcapng capture file, or in a capture
+/// of raw service data written with `--record`.
+pub struct ReplaySource {
+    pub path: PathBuf,
+}
+
+impl Source for ReplaySource {
+    fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "replay".to_string())
+    }
+
+    async fn run(self, tx: UnboundedSender<Advertisement>) -> Result<(), SourceError> {
+        let name = self.name();
+        let data = tokio::fs::read(&self.path).await?;
+        if data.starts_with(MAGIC) {
+            for captured in CaptureReader::new(&data[..])? {
+                let captured = captured?;
+                let advertisement = Advertisement {
+                    source: name.clone(),
+                    address: Address(captured.address),
+                    address_type: None,
+                    rssi: captured.rssi.map(i16::from),
+                    advertising: None,
+                    received: captured.time,
+                    service_data: captured.service_data,
+                };
+                if tx.send(advertisement).is_err() {
+                    return Ok(());
+                }
+            }
+            return Ok(());
+        }
+        for advertisement in capture::advertisements(&data, &name)? {
+            if tx.send(advertisement).is_err() {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use std::time::SystemTime;
+
+use bthome::hex;
+use tokio::{
+    io::{stdin, AsyncBufReadExt, BufReader},
+    sync::mpsc::UnboundedSender,
+};
+use tracing::warn;
+
+use super::{Advertisement, Source, SourceError};
+use crate::address::Address;
+
+/// Reads hex encoded service data from stdin, one payload per line, optionally prefixed by the
+/// MAC address of the device:
+///
+/// ```text
+/// A4:C1:38:12:34:56 40 02 c4 09 03 bf 13
+/// 4002c40903bf13
+/// ```
+///
+/// Empty lines and lines starting with `#` are ignored.
+pub struct StdinSource;
+
+impl Source for StdinSource {
+    fn name(&self) -> String {
+        "stdin".to_string()
+    }
+
+    async fn run(self, tx: UnboundedSender<Advertisement>) -> Result<(), SourceError> {
+        let mut lines = BufReader::new(stdin()).lines();
+        while let Some(line) = lines.next_line().await? {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((address, service_data)) = parse_line(line) else {
+                warn!(source = "stdin", line, "Ignoring invalid line");
+                continue;
+            };
+            let advertisement = Advertisement {
+                source: self.name(),
+                address,
+                address_type: None,
+                rssi: None,
+                advertising: None,
+                received: SystemTime::now(),
+                service_data,
+            };
+            if tx.send(advertisement).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(Address, Vec<u8>)> {
+    let (address, payload) = match line.split_once(char::is_whitespace) {
+        Some((first, rest)) => match first.parse::<Address>() {
+            Ok(address) => (address, rest),
+            Err(_) => (Address::default(), line),
+        },
+        None => (Address::default(), line),
+    };
+    Some((address, hex::decode(payload)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_lines() {
+        let address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        assert_eq!(
+            parse_line("A4:C1:38:12:34:56 40 02 c4 09"),
+            Some((address, vec![0x40, 0x02, 0xC4, 0x09]))
+        );
+        assert_eq!(parse_line("4002c409"), Some((Address::default(), vec![0x40, 0x02, 0xC4, 0x09])));
+        assert_eq!(parse_line("40 02 c4 09"), Some((Address::default(), vec![0x40, 0x02, 0xC4, 0x09])));
+        assert_eq!(parse_line("A4:C1:38:12:34:56 zz"), None);
+    }
+}
@@ -0,0 +1,235 @@
+//! Reception statistics per device, estimated from gaps in the packet ids and the time between
+//! packets.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::address::Address;
+
+/// Number of recent packets the current reception ratio is calculated from.
+const RECENT_PACKETS: usize = 64;
+/// Weight of the newest measurement in the moving average of the advertising interval.
+const INTERVAL_SMOOTHING: f64 = 0.1;
+/// Packet id gaps larger than this are considered a restart of the device rather than loss.
+const MAX_GAP: u8 = 128;
+
+#[derive(Default)]
+struct DeviceStats {
+    received: u64,
+    lost: u64,
+    errors: u64,
+    decryption_failures: u64,
+    sink_failures: u64,
+    last_packet: Option<(Option<u8>, Instant)>,
+    /// Moving average of the time between packets in seconds
+    interval: Option<f64>,
+    /// Whether each of the recent packets was received or lost
+    recent: VecDeque<bool>,
+}
+
+impl DeviceStats {
+    fn record(&mut self, packet_id: Option<u8>, now: Instant) {
+        // Packets between the last and this one that were never received
+        let mut missing = 0;
+        if let Some((last_id, last_seen)) = self.last_packet {
+            if let (Some(last_id), Some(packet_id)) = (last_id, packet_id) {
+                let gap = packet_id.wrapping_sub(last_id);
+                if gap == 0 {
+                    // Another copy of the same packet
+                    return;
+                }
+                if gap < MAX_GAP {
+                    missing = gap - 1;
+                }
+            }
+            let interval = now.duration_since(last_seen).as_secs_f64() / (missing as f64 + 1.0);
+            self.interval = Some(match self.interval {
+                Some(average) => average + INTERVAL_SMOOTHING * (interval - average),
+                None => interval,
+            });
+        }
+        self.received += 1;
+        self.lost += missing as u64;
+        for _ in 0..missing {
+            self.push_recent(false);
+        }
+        self.push_recent(true);
+        self.last_packet = Some((packet_id, now));
+    }
+
+    fn push_recent(&mut self, received: bool) {
+        if self.recent.len() == RECENT_PACKETS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(received);
+    }
+}
+
+/// Summary of the reception of one device.
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    pub received: u64,
+    /// Packets that were never received, only known for devices sending a packet id
+    pub lost: u64,
+    /// Packets that could not be decoded
+    pub errors: u64,
+    /// Encrypted packets that could not be decrypted
+    pub decryption_failures: u64,
+    /// Packets that could not be delivered to a sink, e.g. forwarded to a central instance
+    pub sink_failures: u64,
+    /// Share of the packets that were received, overall and of the recent packets
+    pub reception_ratio: f64,
+    pub recent_reception_ratio: f64,
+    /// Estimated time between two packets
+    pub interval: Option<Duration>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} packets, {} lost, {} errors, {:.1}% received ({:.1}% of the last {})",
+            self.received,
+            self.lost,
+            self.errors,
+            self.reception_ratio * 100.0,
+            self.recent_reception_ratio * 100.0,
+            RECENT_PACKETS
+        )?;
+        if self.decryption_failures > 0 {
+            write!(f, ", {} not decrypted", self.decryption_failures)?;
+        }
+        if self.sink_failures > 0 {
+            write!(f, ", {} not delivered", self.sink_failures)?;
+        }
+        if let Some(interval) = self.interval {
+            write!(f, ", every {:.1}s", interval.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct Statistics {
+    devices: HashMap<Address, DeviceStats>,
+}
+
+impl Statistics {
+    /// Records a received packet. Copies of the same packet are only counted once, as long as
+    /// the device sends a packet id.
+    pub fn record(&mut self, address: Address, packet_id: Option<u8>, now: Instant) {
+        self.devices.entry(address).or_default().record(packet_id, now);
+    }
+
+    /// Records a packet that could not be decoded.
+    pub fn record_error(&mut self, address: Address) {
+        self.devices.entry(address).or_default().errors += 1;
+    }
+
+    /// Records an encrypted packet that could not be decrypted.
+    pub fn record_decryption_failure(&mut self, address: Address) {
+        self.devices.entry(address).or_default().decryption_failures += 1;
+    }
+
+    /// Records a packet that could not be delivered to a sink.
+    pub fn record_sink_failure(&mut self, address: Address) {
+        self.devices.entry(address).or_default().sink_failures += 1;
+    }
+
+    pub fn summary(&self, address: &Address) -> Option<Summary> {
+        let stats = self.devices.get(address)?;
+        let recent_received = stats.recent.iter().filter(|received| **received).count();
+        Some(Summary {
+            received: stats.received,
+            lost: stats.lost,
+            errors: stats.errors,
+            decryption_failures: stats.decryption_failures,
+            sink_failures: stats.sink_failures,
+            reception_ratio: ratio(stats.received, stats.received + stats.lost),
+            recent_reception_ratio: ratio(recent_received as u64, stats.recent.len() as u64),
+            interval: stats.interval.map(Duration::from_secs_f64),
+        })
+    }
+
+    /// Summaries of all devices, ordered by address.
+    pub fn summaries(&self) -> Vec<(Address, Summary)> {
+        let mut addresses: Vec<_> = self.devices.keys().copied().collect();
+        addresses.sort();
+        addresses
+            .into_iter()
+            .filter_map(|address| Some((address, self.summary(&address)?)))
+            .collect()
+    }
+}
+
+fn ratio(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ADDRESS: Address = Address([0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+
+    #[test]
+    fn count_lost_packets() {
+        let mut stats = Statistics::default();
+        let now = Instant::now();
+        stats.record(ADDRESS, Some(254), now);
+        stats.record(ADDRESS, Some(254), now + Duration::from_secs(1));
+        stats.record(ADDRESS, Some(255), now + Duration::from_secs(10));
+        // Packets 0 and 1 are lost
+        stats.record(ADDRESS, Some(2), now + Duration::from_secs(40));
+        let summary = stats.summary(&ADDRESS).expect("Device to be known");
+        assert_eq!(summary.received, 3);
+        assert_eq!(summary.lost, 2);
+        assert_eq!(summary.reception_ratio, 0.6);
+        assert_eq!(summary.recent_reception_ratio, 0.6);
+        assert_eq!(summary.interval, Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn ignore_restarts() {
+        let mut stats = Statistics::default();
+        let now = Instant::now();
+        stats.record(ADDRESS, Some(200), now);
+        stats.record(ADDRESS, Some(100), now + Duration::from_secs(5));
+        let summary = stats.summary(&ADDRESS).expect("Device to be known");
+        assert_eq!(summary.lost, 0);
+        assert_eq!(summary.interval, Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn devices_without_packet_id() {
+        let mut stats = Statistics::default();
+        let now = Instant::now();
+        stats.record(ADDRESS, None, now);
+        stats.record(ADDRESS, None, now + Duration::from_secs(60));
+        stats.record_error(ADDRESS);
+        stats.record_decryption_failure(ADDRESS);
+        assert_eq!(
+            stats.summaries(),
+            vec![(
+                ADDRESS,
+                Summary {
+                    received: 2,
+                    lost: 0,
+                    errors: 1,
+                    decryption_failures: 1,
+                    sink_failures: 0,
+                    reception_ratio: 1.0,
+                    recent_reception_ratio: 1.0,
+                    interval: Some(Duration::from_secs(60)),
+                }
+            )]
+        );
+    }
+}
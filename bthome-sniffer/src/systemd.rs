@@ -0,0 +1,90 @@
+//! Integration with systemd: readiness and watchdog notifications and socket activation.
+//!
+//! The protocols are simple enough to be implemented directly, see sd_notify(3) and
+//! sd_listen_fds(3). Outside of systemd all functions do nothing.
+
+use std::{
+    env, io,
+    os::{
+        fd::{FromRawFd, OwnedFd},
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    time::Duration,
+};
+
+/// First file descriptor passed by socket activation.
+const LISTEN_FDS_START: i32 = 3;
+
+/// Sends a notification like `READY=1` to systemd, if the service was started by systemd.
+pub fn notify(state: &str) -> io::Result<()> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let path = path.to_string_lossy();
+    let address = match path.strip_prefix('@') {
+        Some(abstract_name) => SocketAddr::from_abstract_name(abstract_name.as_bytes())?,
+        None => SocketAddr::from_pathname(path.as_ref())?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &address)?;
+    Ok(())
+}
+
+/// How often the watchdog has to be notified, if it is enabled for this service.
+///
+/// This is half the configured `WatchdogSec=`, as recommended by sd_watchdog_enabled(3).
+pub fn watchdog_interval() -> Option<Duration> {
+    if !is_for_this_process("WATCHDOG_PID") {
+        return None;
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Takes the sockets passed by socket activation, they can only be taken once.
+pub fn listen_fds() -> Vec<OwnedFd> {
+    let pid = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse().ok());
+    let count: i32 = match env::var("LISTEN_FDS").ok().and_then(|count| count.parse().ok()) {
+        Some(count) if pid == Some(std::process::id()) => count,
+        _ => return Vec::new(),
+    };
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        // SAFETY: systemd passes ownership of these descriptors to this process
+        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+        .collect()
+}
+
+/// Whether the variables of the process with the PID in `variable` are meant for us, they may
+/// have been inherited from a parent otherwise. A missing PID is accepted.
+fn is_for_this_process(variable: &str) -> bool {
+    match env::var(variable) {
+        Ok(pid) => pid.parse() == Ok(std::process::id()),
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn notify_and_watchdog() {
+        let path = env::temp_dir().join(format!("bthome-sniffer-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).expect("Socket to bind");
+        env::set_var("NOTIFY_SOCKET", &path);
+        env::set_var("WATCHDOG_USEC", "30000000");
+        notify("READY=1").expect("Notification to be sent");
+        let mut buffer = [0u8; 64];
+        let len = receiver.recv(&mut buffer).expect("Notification to be received");
+        assert_eq!(&buffer[..len], b"READY=1");
+        assert_eq!(watchdog_interval(), Some(Duration::from_secs(15)));
+        env::set_var("WATCHDOG_PID", "1");
+        assert_eq!(watchdog_interval(), None);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,239 @@
+//! A live table of the received devices with `--tui`, for walking around with a sensor instead of
+//! scrolling through the printed packets.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bthome::{DeviceState, Object, ObjectId, ServiceData};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Cell, Paragraph, Row, Table},
+    DefaultTerminal,
+};
+use tokio::sync::mpsc;
+
+use crate::{address::Address, config::Config, source::Advertisement};
+
+/// How often the table is drawn, the ages in it change even without new packets.
+pub const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+/// RSSI samples per device in the sparkline.
+const RSSI_HISTORY: usize = 20;
+/// The weakest and strongest RSSI told apart in the sparkline, in dBm.
+const RSSI_SCALE: (i16, i16) = (-100, -30);
+const LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const LOG_LINES: usize = 6;
+
+/// The log messages shown below the table, the terminal belongs to the dashboard while it runs.
+#[derive(Clone, Default)]
+pub struct LogLines(Arc<Mutex<Log>>);
+
+#[derive(Default)]
+struct Log {
+    lines: VecDeque<String>,
+    /// The line being written, messages may come in several writes
+    partial: String,
+}
+
+impl Write for LogLines {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut log = self.0.lock().unwrap();
+        log.partial.push_str(&String::from_utf8_lossy(buf));
+        while let Some(end) = log.partial.find('\n') {
+            let line: String = log.partial.drain(..=end).collect();
+            if log.lines.len() == LOG_LINES {
+                log.lines.pop_front();
+            }
+            log.lines.push_back(line.trim_end().to_string());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Device {
+    rssi: VecDeque<i16>,
+    packet_id: Option<u8>,
+    last_seen: Option<Instant>,
+    packets: u64,
+    errors: u64,
+}
+
+pub struct Dashboard {
+    terminal: DefaultTerminal,
+    devices: BTreeMap<Address, Device>,
+    log: LogLines,
+    quit: mpsc::UnboundedReceiver<()>,
+}
+
+impl Dashboard {
+    /// Switches the terminal to the dashboard, it is restored when the dashboard is dropped.
+    pub fn start(log: LogLines) -> Dashboard {
+        let terminal = ratatui::init();
+        // Raw mode turns Ctrl-C into a key press, so the keys are watched for quitting
+        let (quit_tx, quit) = mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            while let Ok(event) = event::read() {
+                if let Event::Key(key) = event {
+                    let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                    if key.kind == KeyEventKind::Press
+                        && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || ctrl_c)
+                        && quit_tx.send(()).is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+        });
+        Dashboard {
+            terminal,
+            devices: BTreeMap::new(),
+            log,
+            quit,
+        }
+    }
+
+    /// Resolves when q, Esc or Ctrl-C is pressed.
+    pub async fn quit_requested(&mut self) {
+        if self.quit.recv().await.is_none() {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    pub fn record(&mut self, advertisement: &Advertisement, result: &Result<ServiceData, bthome::Error>) {
+        let device = self.devices.entry(advertisement.address).or_default();
+        if let Some(rssi) = advertisement.rssi {
+            if device.rssi.len() == RSSI_HISTORY {
+                device.rssi.pop_front();
+            }
+            device.rssi.push_back(rssi);
+        }
+        device.last_seen = Some(Instant::now());
+        match result {
+            Ok(service_data) => {
+                device.packets += 1;
+                device.packet_id = service_data.packet_id().or(device.packet_id);
+            }
+            Err(_) => device.errors += 1,
+        }
+    }
+
+    pub fn draw(&mut self, config: &Config, states: &HashMap<Address, DeviceState>) -> io::Result<()> {
+        let rows = self.devices.iter().map(|(address, device)| {
+            let rssi: Vec<_> = device.rssi.iter().copied().collect();
+            let values = states.get(address).map(values).unwrap_or_default();
+            Row::new(vec![
+                Cell::from(config.name(address).unwrap_or_default().to_string()),
+                Cell::from(address.to_string()),
+                Cell::from(rssi.last().map(|rssi| format!("{} dBm", rssi)).unwrap_or_default()),
+                Cell::from(sparkline(&rssi)),
+                Cell::from(device.packet_id.map(|id| id.to_string()).unwrap_or_default()),
+                Cell::from(device.packets.to_string()),
+                Cell::from(device.errors.to_string()),
+                Cell::from(device.last_seen.map(|seen| format!("{:.0?}", seen.elapsed())).unwrap_or_default()),
+                Cell::from(values),
+            ])
+        });
+        let header = ["Name", "MAC", "RSSI", "", "Packet", "Packets", "Errors", "Last seen", "Values"];
+        let widths = [
+            Constraint::Max(16),
+            Constraint::Length(17),
+            Constraint::Length(8),
+            Constraint::Length(RSSI_HISTORY as u16),
+            Constraint::Length(6),
+            Constraint::Length(7),
+            Constraint::Length(6),
+            Constraint::Length(9),
+            Constraint::Fill(1),
+        ];
+        let table = Table::new(rows, widths)
+            .header(Row::new(header).style(Style::new().add_modifier(Modifier::BOLD)))
+            .block(Block::bordered().title(format!(" {} BTHome devices, q to quit ", self.devices.len())));
+        let log: Vec<Line> = self.log.0.lock().unwrap().lines.iter().map(|line| Line::raw(line.clone())).collect();
+        let log = Paragraph::new(log).block(Block::bordered().title(" Log "));
+
+        self.terminal.draw(|frame| {
+            let [devices, messages] =
+                Layout::vertical([Constraint::Min(3), Constraint::Length(LOG_LINES as u16 + 2)]).areas(frame.area());
+            frame.render_widget(table, devices);
+            frame.render_widget(log, messages);
+        })?;
+        Ok(())
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+/// The RSSI samples as bars, from the weakest to the strongest signal of [`RSSI_SCALE`].
+fn sparkline(rssi: &[i16]) -> String {
+    let (weakest, strongest) = RSSI_SCALE;
+    rssi.iter()
+        .map(|rssi| {
+            let level = (rssi.clamp(&weakest, &strongest) - weakest) as usize * (LEVELS.len() - 1);
+            LEVELS[level / (strongest - weakest) as usize]
+        })
+        .collect()
+}
+
+/// The latest measurements like `temperature: 21.46 °C, humidity: 50.5 %`, without the packet id
+/// shown in its own column.
+fn values(state: &DeviceState) -> String {
+    state
+        .values()
+        .into_iter()
+        .filter(|(object_id, ..)| *object_id != ObjectId::PacketId)
+        .map(|(object_id, index, value)| {
+            let mut object = Object::new(object_id, value.clone());
+            object.instance = index as u8;
+            object.to_string()
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use bthome::parse_service_data;
+
+    use super::*;
+
+    #[test]
+    fn rssi_sparkline() {
+        assert_eq!(sparkline(&[-110, -100, -65, -30, -20]), "▁▁▄██");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn latest_values() {
+        let mut state = DeviceState::new();
+        let packet = parse_service_data(&[0x40, 0x00, 0x07, 0x02, 0x62, 0x08, 0x02, 0x2B, 0x09]).unwrap();
+        state.update(&packet, Instant::now());
+        assert_eq!(values(&state), "temperature: 21.46 °C, temperature_2: 23.47 °C");
+    }
+
+    #[test]
+    fn log_lines() {
+        let mut log = LogLines::default();
+        for line in 0..10 {
+            writeln!(log, "line {}", line).unwrap();
+        }
+        write!(log, "partial").unwrap();
+        let lines = &log.0.lock().unwrap().lines;
+        assert_eq!(lines.len(), LOG_LINES);
+        assert_eq!(lines.back().unwrap(), "line 9");
+    }
+}
@@ -0,0 +1,18 @@
+//! JavaScript bindings of the parser for WebAssembly, so that Web Bluetooth dashboards decode the
+//! service data like the receivers in Rust do. Build them with
+//! `wasm-pack build bthome-wasm --target web`.
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+/// Parses unencrypted service data, the value for the BTHome UUID in the `serviceData` of a
+/// Web Bluetooth advertisement, into an object like
+/// `{encrypted: false, trigger_based: false, version: 2, objects: [{name: "temperature", id: 2,
+/// unit: "°C", value: 21.5}]}`. Throws an `Error` saying why if the data can't be parsed.
+#[wasm_bindgen(js_name = parseServiceData)]
+pub fn parse_service_data(data: &[u8]) -> Result<JsValue, JsError> {
+    let service_data = bthome::parse_service_data(data).map_err(|err| JsError::new(&err.to_string()))?;
+    // Plain objects instead of maps, and null for objects without a unit
+    let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+    Ok(service_data.serialize(&serializer)?)
+}
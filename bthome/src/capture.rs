@@ -0,0 +1,678 @@
+//! Captures of raw BTHome advertisements, to decode them again later, e.g. after a fix of the
+//! parser, and to use real advertisements as test data.
+//!
+//! Capture files start with [`MAGIC`] followed by a record per advertisement: the time in
+//! microseconds since the Unix epoch as u64 little endian, the six bytes of the MAC address as
+//! written, the RSSI as i8 (127 if unknown, like in HCI), the length of the service data as u8 and
+//! the service data.
+//!
+//! The BTHome advertisements can also be extracted from captures of HCI traffic with
+//! [`hci_advertisements`], e.g. Android HCI snoop logs or captures taken with Wireshark or btmon.
+//! btsnoop, pcap and pcapng files with H1, H4 or Linux monitor packets are supported.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::hci::{parse_event, Assembler, Report, HCI_EVENT_PKT};
+
+pub const MAGIC: &[u8; 8] = b"BTHOMEC1";
+/// The extension of capture files, `capture.bthome`
+pub const EXTENSION: &str = "bthome";
+
+const UNKNOWN_RSSI: i8 = 127;
+const HEADER_LEN: usize = 16;
+
+/// An advertisement as received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedAdvertisement {
+    pub time: SystemTime,
+    pub address: [u8; 6],
+    /// Signal strength in dBm, if known
+    pub rssi: Option<i8>,
+    pub service_data: Vec<u8>,
+}
+
+/// Writes advertisements to a capture, the magic is written right away.
+#[derive(Debug)]
+pub struct CaptureWriter<W> {
+    out: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(MAGIC)?;
+        Ok(CaptureWriter { out })
+    }
+
+    /// Fails with `InvalidInput` for service data longer than 255 bytes, which doesn't fit into an
+    /// advertisement.
+    pub fn write(&mut self, advertisement: &CapturedAdvertisement) -> io::Result<()> {
+        let length = u8::try_from(advertisement.service_data.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "service data longer than 255 bytes"))?;
+        let micros = advertisement.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        let mut header = [0u8; HEADER_LEN];
+        header[..8].copy_from_slice(&micros.to_le_bytes());
+        header[8..14].copy_from_slice(&advertisement.address);
+        header[14] = advertisement.rssi.unwrap_or(UNKNOWN_RSSI) as u8;
+        header[15] = length;
+        self.out.write_all(&header)?;
+        self.out.write_all(&advertisement.service_data)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Reads the advertisements of a capture. A record cut short, e.g. because the receiver lost
+/// power while writing it, ends the capture.
+#[derive(Debug)]
+pub struct CaptureReader<R> {
+    input: R,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Fails with `InvalidData` if the input doesn't start with [`MAGIC`].
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        if read_full(&mut input, &mut magic)? < magic.len() || magic != *MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a BTHome capture"));
+        }
+        Ok(CaptureReader { input })
+    }
+
+    fn read_advertisement(&mut self) -> io::Result<Option<CapturedAdvertisement>> {
+        let mut header = [0u8; HEADER_LEN];
+        if read_full(&mut self.input, &mut header)? < HEADER_LEN {
+            return Ok(None);
+        }
+        let mut service_data = vec![0u8; header[15] as usize];
+        if read_full(&mut self.input, &mut service_data)? < service_data.len() {
+            return Ok(None);
+        }
+        let micros = u64::from_le_bytes(header[..8].try_into().unwrap());
+        Ok(Some(CapturedAdvertisement {
+            time: UNIX_EPOCH + Duration::from_micros(micros),
+            address: header[8..14].try_into().unwrap(),
+            rssi: Some(header[14] as i8).filter(|rssi| *rssi != UNKNOWN_RSSI),
+            service_data,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<CapturedAdvertisement>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_advertisement().transpose()
+    }
+}
+
+/// Reads until `buf` is full or the input ends, returns how many bytes were read.
+fn read_full(input: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match input.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read)
+}
+
+const BTSNOOP_MAGIC: &[u8; 8] = b"btsnoop\0";
+const BTSNOOP_H1: u32 = 1001;
+const BTSNOOP_H4: u32 = 1002;
+const BTSNOOP_MONITOR: u32 = 2001;
+/// Microseconds between 0000-01-01 and 1970-01-01, the btsnoop timestamp epoch.
+const BTSNOOP_EPOCH_OFFSET: u64 = 0x00DC_DDB3_0F2F_8000;
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+const LINKTYPE_BLUETOOTH_HCI_H4: u32 = 187;
+const LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR: u32 = 201;
+const LINKTYPE_BLUETOOTH_LINUX_MONITOR: u32 = 254;
+const LINKTYPES_BLUETOOTH: [u32; 3] =
+    [LINKTYPE_BLUETOOTH_HCI_H4, LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR, LINKTYPE_BLUETOOTH_LINUX_MONITOR];
+
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_OPTION_END: u16 = 0;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+
+const MONITOR_EVENT_PKT: u16 = 0x0003;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HciCaptureError {
+    UnknownFormat,
+    UnsupportedLinkType(u32),
+    Truncated,
+    /// A timestamp too far in the future to be represented as `SystemTime`
+    InvalidTimestamp,
+}
+
+impl fmt::Display for HciCaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HciCaptureError::UnknownFormat => write!(f, "not a btsnoop, pcap or pcapng file"),
+            HciCaptureError::UnsupportedLinkType(t) => {
+                write!(f, "unsupported link type {}, only Bluetooth HCI H1, H4 and Linux monitor are", t)
+            }
+            HciCaptureError::Truncated => write!(f, "capture file is truncated"),
+            HciCaptureError::InvalidTimestamp => write!(f, "timestamp out of range"),
+        }
+    }
+}
+
+impl std::error::Error for HciCaptureError {}
+
+/// An HCI packet including its H4 packet type indicator.
+#[derive(Debug, PartialEq, Eq)]
+pub struct HciPacket {
+    pub time: SystemTime,
+    pub packet: Vec<u8>,
+}
+
+/// A BTHome advertisement found in a capture of HCI traffic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HciAdvertisement {
+    pub time: SystemTime,
+    /// The report completing the service data, with the address, its type and the RSSI
+    pub report: Report,
+    pub service_data: Vec<u8>,
+}
+
+impl From<HciAdvertisement> for CapturedAdvertisement {
+    fn from(value: HciAdvertisement) -> Self {
+        CapturedAdvertisement {
+            time: value.time,
+            address: value.report.address,
+            rssi: Some(value.report.rssi).filter(|rssi| *rssi != UNKNOWN_RSSI),
+            service_data: value.service_data,
+        }
+    }
+}
+
+/// Reads all HCI packets of a btsnoop, pcap or pcapng capture, the format is detected
+/// automatically. Packets other than events, e.g. commands and ACL data, are left out.
+pub fn read_hci(data: &[u8]) -> Result<Vec<HciPacket>, HciCaptureError> {
+    if data.starts_with(BTSNOOP_MAGIC) {
+        read_btsnoop(data)
+    } else if data.starts_with(&PCAPNG_SECTION_HEADER.to_le_bytes()) {
+        read_pcapng(data)
+    } else {
+        read_pcap(data)
+    }
+}
+
+/// The BTHome advertisements of a btsnoop, pcap or pcapng capture, with extended advertisements
+/// and scan responses assembled like when receiving them live.
+pub fn hci_advertisements(data: &[u8]) -> Result<Vec<HciAdvertisement>, HciCaptureError> {
+    let mut assembler = Assembler::default();
+    let mut advertisements = Vec::new();
+    for captured in read_hci(data)? {
+        for report in parse_event(&captured.packet) {
+            if let Some(service_data) = assembler.add(&report) {
+                advertisements.push(HciAdvertisement {
+                    time: captured.time,
+                    report,
+                    service_data,
+                });
+            }
+        }
+    }
+    Ok(advertisements)
+}
+
+fn read_btsnoop(data: &[u8]) -> Result<Vec<HciPacket>, HciCaptureError> {
+    let mut fields = Fields { data, big_endian: true };
+    fields.take(8)?;
+    let _version = fields.u32()?;
+    let datalink = fields.u32()?;
+    if ![BTSNOOP_H1, BTSNOOP_H4, BTSNOOP_MONITOR].contains(&datalink) {
+        return Err(HciCaptureError::UnsupportedLinkType(datalink));
+    }
+
+    let mut packets = Vec::new();
+    while !fields.data.is_empty() {
+        let _original_length = fields.u32()?;
+        let included_length = fields.u32()?;
+        let flags = fields.u32()?;
+        let _drops = fields.u32()?;
+        let timestamp = fields.u64()?;
+        let data = fields.take(included_length as usize)?;
+        let packet = match datalink {
+            BTSNOOP_H4 => data.to_vec(),
+            // Bit 1 of the flags marks commands and events, bit 0 the direction
+            BTSNOOP_H1 if flags & 0b11 == 0b11 => h4_event(data),
+            BTSNOOP_MONITOR if flags & 0xFFFF == MONITOR_EVENT_PKT as u32 => h4_event(data),
+            _ => continue,
+        };
+        let micros = timestamp.saturating_sub(BTSNOOP_EPOCH_OFFSET);
+        packets.push(HciPacket {
+            time: UNIX_EPOCH + Duration::from_micros(micros),
+            packet,
+        });
+    }
+    Ok(packets)
+}
+
+fn read_pcap(data: &[u8]) -> Result<Vec<HciPacket>, HciCaptureError> {
+    let magic = data.get(..4).ok_or(HciCaptureError::UnknownFormat)?;
+    let (big_endian, nanos) = match (
+        u32::from_le_bytes(magic.try_into().unwrap()),
+        u32::from_be_bytes(magic.try_into().unwrap()),
+    ) {
+        (PCAP_MAGIC_MICROS, _) => (false, false),
+        (PCAP_MAGIC_NANOS, _) => (false, true),
+        (_, PCAP_MAGIC_MICROS) => (true, false),
+        (_, PCAP_MAGIC_NANOS) => (true, true),
+        _ => return Err(HciCaptureError::UnknownFormat),
+    };
+    let mut fields = Fields { data, big_endian };
+    fields.take(20)?;
+    let linktype = fields.u32()?;
+
+    let mut packets = Vec::new();
+    while !fields.data.is_empty() {
+        let seconds = fields.u32()?;
+        let fraction = fields.u32()?;
+        let included_length = fields.u32()?;
+        let _original_length = fields.u32()?;
+        let data = fields.take(included_length as usize)?;
+        let Some(packet) = h4_packet(linktype, data)? else {
+            continue;
+        };
+        let fraction = if nanos {
+            Duration::from_nanos(fraction as u64)
+        } else {
+            Duration::from_micros(fraction as u64)
+        };
+        packets.push(HciPacket {
+            time: UNIX_EPOCH + Duration::from_secs(seconds as u64) + fraction,
+            packet,
+        });
+    }
+    Ok(packets)
+}
+
+/// Reads the enhanced packet blocks of a pcapng capture, other blocks than section headers and
+/// interface descriptions are skipped. So are the packets of interfaces other than Bluetooth, as
+/// long as there is one Bluetooth interface.
+fn read_pcapng(data: &[u8]) -> Result<Vec<HciPacket>, HciCaptureError> {
+    let mut fields = Fields { data, big_endian: false };
+    // The link type and timestamp resolution, as units per second, of each interface
+    let mut interfaces: Vec<(u32, u64)> = Vec::new();
+    let mut bluetooth = false;
+    let mut unsupported = None;
+    let mut packets = Vec::new();
+    while !fields.data.is_empty() {
+        let block_type = fields.u32()?;
+        if block_type == PCAPNG_SECTION_HEADER {
+            // The byte order is only known from the magic after the length
+            let magic: [u8; 4] = fields.data.get(4..8).ok_or(HciCaptureError::Truncated)?.try_into().unwrap();
+            fields.big_endian = match (u32::from_be_bytes(magic), u32::from_le_bytes(magic)) {
+                (PCAPNG_BYTE_ORDER_MAGIC, _) => true,
+                (_, PCAPNG_BYTE_ORDER_MAGIC) => false,
+                _ => return Err(HciCaptureError::UnknownFormat),
+            };
+            interfaces.clear();
+        }
+        let length = fields.u32()? as usize;
+        let body_length = length.checked_sub(12).ok_or(HciCaptureError::Truncated)?;
+        let mut body = Fields {
+            data: fields.take(body_length)?,
+            big_endian: fields.big_endian,
+        };
+        fields.take(4)?;
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION => {
+                let linktype = body.u16()? as u32;
+                body.take(6)?;
+                let mut resolution = 1_000_000;
+                while body.data.len() >= 4 {
+                    let code = body.u16()?;
+                    let length = body.u16()? as usize;
+                    let value = body.take(length)?;
+                    body.take((4 - length % 4) % 4)?;
+                    match (code, value) {
+                        (PCAPNG_OPTION_END, _) => break,
+                        // Negative powers of 10, or of 2 if the highest bit is set
+                        (PCAPNG_OPTION_TSRESOL, [exponent]) if exponent & 0x80 == 0 => {
+                            resolution = 10u64.pow(u32::from(*exponent).min(19))
+                        }
+                        (PCAPNG_OPTION_TSRESOL, [exponent]) => resolution = 1 << u32::from(exponent & 0x7F).min(63),
+                        _ => {}
+                    }
+                }
+                if LINKTYPES_BLUETOOTH.contains(&linktype) {
+                    bluetooth = true;
+                } else {
+                    unsupported.get_or_insert(linktype);
+                }
+                interfaces.push((linktype, resolution));
+            }
+            PCAPNG_ENHANCED_PACKET => {
+                let interface = body.u32()? as usize;
+                let timestamp = (body.u32()? as u64) << 32 | body.u32()? as u64;
+                let included_length = body.u32()?;
+                let _original_length = body.u32()?;
+                let data = body.take(included_length as usize)?;
+                let (linktype, resolution) = *interfaces.get(interface).ok_or(HciCaptureError::UnknownFormat)?;
+                if !LINKTYPES_BLUETOOTH.contains(&linktype) {
+                    continue;
+                }
+                let Some(packet) = h4_packet(linktype, data)? else {
+                    continue;
+                };
+                let fraction = (timestamp % resolution) as u128 * 1_000_000_000 / resolution as u128;
+                let time = UNIX_EPOCH
+                    .checked_add(Duration::new(timestamp / resolution, fraction as u32))
+                    .ok_or(HciCaptureError::InvalidTimestamp)?;
+                packets.push(HciPacket { time, packet });
+            }
+            _ => {}
+        }
+    }
+    match unsupported {
+        Some(linktype) if !bluetooth => Err(HciCaptureError::UnsupportedLinkType(linktype)),
+        _ => Ok(packets),
+    }
+}
+
+/// The H4 event in a packet of a pcap or pcapng capture, `None` for other packets.
+fn h4_packet(linktype: u32, data: &[u8]) -> Result<Option<Vec<u8>>, HciCaptureError> {
+    Ok(match linktype {
+        LINKTYPE_BLUETOOTH_HCI_H4 => Some(data.to_vec()),
+        LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR => Some(data.get(4..).ok_or(HciCaptureError::Truncated)?.to_vec()),
+        LINKTYPE_BLUETOOTH_LINUX_MONITOR => match data {
+            [_, _, opcode_hi, opcode_lo, rest @ ..]
+                if u16::from_be_bytes([*opcode_hi, *opcode_lo]) == MONITOR_EVENT_PKT =>
+            {
+                Some(h4_event(rest))
+            }
+            _ => None,
+        },
+        _ => return Err(HciCaptureError::UnsupportedLinkType(linktype)),
+    })
+}
+
+fn h4_event(data: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(data.len() + 1);
+    packet.push(HCI_EVENT_PKT);
+    packet.extend_from_slice(data);
+    packet
+}
+
+/// Writes HCI packets to a pcap file which can be opened with Wireshark or read with
+/// [`hci_advertisements`].
+#[derive(Debug)]
+pub struct PcapWriter<W> {
+    out: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(&PCAP_MAGIC_MICROS.to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&4u16.to_le_bytes())?;
+        out.write_all(&0i32.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(&u32::from(u16::MAX).to_le_bytes())?;
+        out.write_all(&LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR.to_le_bytes())?;
+        Ok(PcapWriter { out })
+    }
+
+    /// Appends a received H4 packet, i.e. including the packet type indicator.
+    pub fn write(&mut self, time: SystemTime, packet: &[u8]) -> io::Result<()> {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let length = packet.len() as u32 + 4;
+        self.out.write_all(&(since_epoch.as_secs() as u32).to_le_bytes())?;
+        self.out.write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+        self.out.write_all(&length.to_le_bytes())?;
+        self.out.write_all(&length.to_le_bytes())?;
+        // Direction of the packet, 1 means received by the host
+        self.out.write_all(&1u32.to_be_bytes())?;
+        self.out.write_all(packet)?;
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// The fields of a btsnoop, pcap or pcapng file, in the byte order of the file.
+struct Fields<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], HciCaptureError> {
+        if self.data.len() < len {
+            return Err(HciCaptureError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> Result<u16, HciCaptureError> {
+        let bytes = self.take(2)?.try_into().unwrap();
+        Ok(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&mut self) -> Result<u32, HciCaptureError> {
+        let bytes = self.take(4)?.try_into().unwrap();
+        Ok(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn u64(&mut self) -> Result<u64, HciCaptureError> {
+        let bytes = self.take(8)?.try_into().unwrap();
+        Ok(if self.big_endian { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_and_read() {
+        let advertisements = vec![
+            CapturedAdvertisement {
+                time: UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456),
+                address: [0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56],
+                rssi: Some(-60),
+                service_data: vec![0x40, 0x02, 0xC4, 0x09],
+            },
+            CapturedAdvertisement {
+                time: UNIX_EPOCH + Duration::from_secs(1_700_000_001),
+                address: [0xA4, 0xC1, 0x38, 0x65, 0x43, 0x21],
+                rssi: None,
+                service_data: vec![],
+            },
+        ];
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        for advertisement in &advertisements {
+            writer.write(advertisement).unwrap();
+        }
+        let data = writer.into_inner();
+        assert_eq!(data.len(), 8 + 16 + 4 + 16);
+
+        let read: Vec<_> = CaptureReader::new(&data[..]).unwrap().collect::<io::Result<_>>().unwrap();
+        assert_eq!(read, advertisements);
+        // The second record cut short
+        assert_eq!(CaptureReader::new(&data[..data.len() - 3]).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn reject_other_files() {
+        let err = CaptureReader::new(&b"btsnoop\0\0\0\0\x01"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(CaptureReader::new(&b"BTHO"[..]).is_err());
+
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        let advertisement = CapturedAdvertisement {
+            time: UNIX_EPOCH,
+            address: [0; 6],
+            rssi: None,
+            service_data: vec![0; 256],
+        };
+        assert_eq!(writer.write(&advertisement).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    const EVENT: [u8; 7] = [0x04, 0x3E, 0x04, 0x02, 0x01, 0x00, 0x00];
+    const REPORT: [u8; 22] = [
+        0x04, 0x3E, 0x13, 0x02, 0x01, // LE advertising report, one report
+        0x03, 0x01, 0x56, 0x34, 0x12, 0x38, 0xC1, 0xA4, // ADV_NONCONN_IND, random address
+        0x07, 0x06, 0x16, 0xD2, 0xFC, 0x40, 0x01, 0x61, // BTHome service data
+        0xC4, // RSSI -60
+    ];
+
+    #[test]
+    fn read_btsnoop_h4() {
+        let mut file = b"btsnoop\0".to_vec();
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(&BTSNOOP_H4.to_be_bytes());
+        for flags in [3u32, 2] {
+            file.extend_from_slice(&(EVENT.len() as u32).to_be_bytes());
+            file.extend_from_slice(&(EVENT.len() as u32).to_be_bytes());
+            file.extend_from_slice(&flags.to_be_bytes());
+            file.extend_from_slice(&0u32.to_be_bytes());
+            file.extend_from_slice(&(BTSNOOP_EPOCH_OFFSET + 1_500_000).to_be_bytes());
+            file.extend_from_slice(&EVENT);
+        }
+        let packets = read_hci(&file).expect("Capture to be readable");
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].packet, EVENT);
+        assert_eq!(packets[0].time, UNIX_EPOCH + Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn read_pcap_with_phdr() {
+        let mut file = Vec::new();
+        file.extend_from_slice(&PCAP_MAGIC_MICROS.to_le_bytes());
+        file.extend_from_slice(&[0x02, 0x00, 0x04, 0x00]);
+        file.extend_from_slice(&[0u8; 12]);
+        file.extend_from_slice(&LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR.to_le_bytes());
+        file.extend_from_slice(&10u32.to_le_bytes());
+        file.extend_from_slice(&250u32.to_le_bytes());
+        file.extend_from_slice(&(EVENT.len() as u32 + 4).to_le_bytes());
+        file.extend_from_slice(&(EVENT.len() as u32 + 4).to_le_bytes());
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(&EVENT);
+        let packets = read_hci(&file).expect("Capture to be readable");
+        assert_eq!(
+            packets,
+            vec![HciPacket {
+                time: UNIX_EPOCH + Duration::from_secs(10) + Duration::from_micros(250),
+                packet: EVENT.to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn read_pcapng() {
+        let block = |block_type: u32, body: &[u8]| {
+            let length = (12 + body.len()) as u32;
+            [&block_type.to_be_bytes()[..], &length.to_be_bytes(), body, &length.to_be_bytes()].concat()
+        };
+        // Big endian, with an interface of another link type and one with millisecond timestamps
+        let section = [&PCAPNG_BYTE_ORDER_MAGIC.to_be_bytes()[..], &[0, 1, 0, 0], &[0xFF; 8]].concat();
+        let ethernet = [0, 1, 0, 0, 0, 0, 0xFF, 0xFF];
+        let hci = [&[0, 187, 0, 0, 0, 0, 0xFF, 0xFF][..], &[0, 9, 0, 1, 3, 0, 0, 0], &[0; 4]].concat();
+        let mut packet = [&1u32.to_be_bytes()[..], &0u32.to_be_bytes(), &1_500u32.to_be_bytes()].concat();
+        packet.extend_from_slice(&(REPORT.len() as u32).to_be_bytes());
+        packet.extend_from_slice(&(REPORT.len() as u32).to_be_bytes());
+        packet.extend_from_slice(&REPORT);
+        packet.extend_from_slice(&[0; 2]);
+        let file = [
+            block(PCAPNG_SECTION_HEADER, &section),
+            block(PCAPNG_INTERFACE_DESCRIPTION, &ethernet),
+            block(PCAPNG_INTERFACE_DESCRIPTION, &hci),
+            // A name resolution block
+            block(4, &[0; 4]),
+            block(PCAPNG_ENHANCED_PACKET, &packet),
+        ]
+        .concat();
+        let packets = read_hci(&file).expect("Capture to be readable");
+        assert_eq!(packets, vec![HciPacket { time: UNIX_EPOCH + Duration::from_millis(1_500), packet: REPORT.to_vec() }]);
+
+        // Packets of the other interface are skipped
+        let mut other = packet.clone();
+        other[..4].copy_from_slice(&0u32.to_be_bytes());
+        let file = [&file[..file.len() - packet.len() - 12], &block(PCAPNG_ENHANCED_PACKET, &other)].concat();
+        assert_eq!(read_hci(&file), Ok(Vec::new()));
+
+        // Unless there is no Bluetooth interface
+        let file = [
+            block(PCAPNG_SECTION_HEADER, &section),
+            block(PCAPNG_INTERFACE_DESCRIPTION, &ethernet),
+            block(PCAPNG_ENHANCED_PACKET, &other),
+        ]
+        .concat();
+        assert_eq!(read_hci(&file), Err(HciCaptureError::UnsupportedLinkType(1)));
+
+        // Timestamps in seconds beyond what SystemTime can hold
+        let seconds = [&[0, 187, 0, 0, 0, 0, 0xFF, 0xFF][..], &[0, 9, 0, 1, 0, 0, 0, 0], &[0; 4]].concat();
+        packet[..4].copy_from_slice(&0u32.to_be_bytes());
+        packet[4..12].copy_from_slice(&[0xFF; 8]);
+        let file = [
+            block(PCAPNG_SECTION_HEADER, &section),
+            block(PCAPNG_INTERFACE_DESCRIPTION, &seconds),
+            block(PCAPNG_ENHANCED_PACKET, &packet),
+        ]
+        .concat();
+        assert_eq!(read_hci(&file), Err(HciCaptureError::InvalidTimestamp));
+    }
+
+    #[test]
+    fn write_and_read_pcap() {
+        let time = UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+        let mut writer = PcapWriter::new(Vec::new()).expect("Header to be written");
+        writer.write(time, &EVENT).expect("Packet to be written");
+        let packets = read_hci(&writer.into_inner()).expect("Capture to be readable");
+        assert_eq!(packets, vec![HciPacket { time, packet: EVENT.to_vec() }]);
+    }
+
+    #[test]
+    fn extract_advertisements() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut writer = PcapWriter::new(Vec::new()).expect("Header to be written");
+        writer.write(time, &EVENT).expect("Packet to be written");
+        writer.write(time, &REPORT).expect("Packet to be written");
+
+        let advertisements = hci_advertisements(&writer.into_inner()).expect("Capture to be readable");
+        assert_eq!(advertisements.len(), 1);
+        assert_eq!(advertisements[0].report.address_type, 0x01);
+        assert_eq!(
+            CapturedAdvertisement::from(advertisements[0].clone()),
+            CapturedAdvertisement {
+                time,
+                address: [0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56],
+                rssi: Some(-60),
+                service_data: vec![0x40, 0x01, 0x61],
+            }
+        );
+    }
+
+    #[test]
+    fn reject_unknown_hci_captures() {
+        assert_eq!(read_hci(b"hello world"), Err(HciCaptureError::UnknownFormat));
+        assert_eq!(read_hci(b"btsnoop\0\0\0"), Err(HciCaptureError::Truncated));
+        assert_eq!(read_hci(&[0x0A, 0x0D, 0x0D, 0x0A, 0, 0, 0, 12, 0, 0, 0, 0]), Err(HciCaptureError::UnknownFormat));
+    }
+}
@@ -0,0 +1,93 @@
+//! Suppressing the copies of a packet, which devices repeat several times to make sure it is
+//! received.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use crate::ServiceData;
+
+/// Remembers the packet ids received from each device for `ttl`, so that every packet is only
+/// reported once. The packet id wraps after 255 packets, so `ttl` should be shorter than the
+/// time a device takes to send that many.
+///
+/// The devices are identified by `K`, usually their MAC address.
+#[derive(Debug, Clone)]
+pub struct Deduplicator<K> {
+    ttl: Duration,
+    seen: HashMap<(K, u8), Instant>,
+    last_purge: Option<Instant>,
+}
+
+impl<K: Hash + Eq> Deduplicator<K> {
+    pub fn new(ttl: Duration) -> Self {
+        Deduplicator {
+            ttl,
+            seen: HashMap::new(),
+            last_purge: None,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Records the packet and returns whether it is the first copy received within `ttl`.
+    /// Packets without a packet id can't be told apart and are always new.
+    pub fn is_new(&mut self, device: K, service_data: &ServiceData, now: Instant) -> bool {
+        match service_data.packet_id() {
+            Some(packet_id) => self.is_new_packet_id(device, packet_id, now),
+            None => true,
+        }
+    }
+
+    /// Like [`Deduplicator::is_new`] for a packet id that was already looked up.
+    pub fn is_new_packet_id(&mut self, device: K, packet_id: u8, now: Instant) -> bool {
+        self.purge(now);
+        match self.seen.entry((device, packet_id)) {
+            Entry::Occupied(entry) if now.saturating_duration_since(*entry.get()) < self.ttl => false,
+            Entry::Occupied(mut entry) => {
+                entry.insert(now);
+                true
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(now);
+                true
+            }
+        }
+    }
+
+    /// Forgets the expired packet ids once per `ttl`, to not grow with every device ever seen.
+    fn purge(&mut self, now: Instant) {
+        if self.last_purge.is_some_and(|last| now.saturating_duration_since(last) < self.ttl) {
+            return;
+        }
+        let ttl = self.ttl;
+        self.seen.retain(|_, seen| now.saturating_duration_since(*seen) < ttl);
+        self.last_purge = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse_service_data;
+
+    #[test]
+    fn suppress_copies() {
+        let mut deduplicator = Deduplicator::new(Duration::from_secs(10));
+        let now = Instant::now();
+        let packet = parse_service_data(&[0x40, 0x00, 0x01, 0x01, 0x60]).unwrap();
+        assert!(deduplicator.is_new("kitchen", &packet, now));
+        assert!(!deduplicator.is_new("kitchen", &packet, now + Duration::from_secs(1)));
+        assert!(deduplicator.is_new("office", &packet, now));
+        assert!(deduplicator.is_new_packet_id("kitchen", 2, now));
+        assert!(deduplicator.is_new("kitchen", &packet, now + Duration::from_secs(10)));
+
+        let without_id = parse_service_data(&[0x40, 0x01, 0x60]).unwrap();
+        assert!(deduplicator.is_new("kitchen", &without_id, now));
+        assert!(deduplicator.is_new("kitchen", &without_id, now));
+    }
+}
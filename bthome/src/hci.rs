@@ -0,0 +1,237 @@
+//! Parsing of the HCI event packets with LE advertising reports, as received from a Bluetooth
+//! controller or found in captures of HCI traffic.
+
+use std::collections::HashMap;
+
+use crate::extract_bthome_from_adv;
+
+/// The H4 packet type indicator of events.
+pub const HCI_EVENT_PKT: u8 = 0x04;
+pub const EVT_LE_META_EVENT: u8 = 0x3E;
+pub const EVT_LE_ADVERTISING_REPORT: u8 = 0x02;
+pub const EVT_LE_EXTENDED_ADVERTISING_REPORT: u8 = 0x0D;
+
+const ADV_IND: u8 = 0x00;
+const ADV_SCAN_IND: u8 = 0x02;
+const SCAN_RSP: u8 = 0x04;
+
+const EXT_SCANNABLE: u16 = 1 << 1;
+const EXT_SCAN_RESPONSE: u16 = 1 << 3;
+const EXT_LEGACY: u16 = 1 << 4;
+const EXT_DATA_STATUS: u16 = 0b11 << 5;
+const EXT_INCOMPLETE: u16 = 0b01 << 5;
+
+/// Devices whose advertisement is kept until its scan response, or whose fragments until the
+/// last one, more are forgotten.
+const MAX_PENDING: usize = 1024;
+
+/// A single advertising report as delivered by the controller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// The MAC address as written, most significant byte first
+    pub address: [u8; 6],
+    /// The address type as in HCI, 0 and 2 are public, 1 and 3 random addresses
+    pub address_type: u8,
+    /// Signal strength in dBm, 127 if the controller doesn't know it
+    pub rssi: i8,
+    pub data: Vec<u8>,
+    /// Sent with the extended PDUs of Bluetooth 5, extended reports of legacy advertisements are
+    /// legacy as well
+    pub extended: bool,
+    /// The advertisement asks for scan requests, which are answered with a scan response
+    pub scannable: bool,
+    /// The data is the scan response to the last advertisement of the device
+    pub scan_response: bool,
+    /// More fragments of the data of an extended advertisement follow in the next reports
+    pub incomplete: bool,
+}
+
+/// Extracts the advertising reports from an HCI event packet including its packet type
+/// indicator, other packets yield no reports.
+pub fn parse_event(packet: &[u8]) -> Vec<Report> {
+    match packet {
+        [HCI_EVENT_PKT, EVT_LE_META_EVENT, _len, EVT_LE_ADVERTISING_REPORT, _num, reports @ ..] => {
+            parse_reports(reports, false)
+        }
+        [HCI_EVENT_PKT, EVT_LE_META_EVENT, _len, EVT_LE_EXTENDED_ADVERTISING_REPORT, _num, reports @ ..] => {
+            parse_reports(reports, true)
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn parse_reports(mut data: &[u8], extended: bool) -> Vec<Report> {
+    // Legacy reports: event type, address type, address, data length, data, RSSI.
+    // Extended reports: event type (2 bytes), address type, address, primary PHY, secondary PHY,
+    // SID, TX power, RSSI, periodic advertising interval (2 bytes), direct address type,
+    // direct address, data length, data.
+    let (address_offset, data_len_offset) = if extended { (3, 23) } else { (2, 8) };
+    let mut reports = Vec::new();
+    while data.len() > data_len_offset {
+        let mut address = [0u8; 6];
+        address.copy_from_slice(&data[address_offset..address_offset + 6]);
+        address.reverse();
+        let data_len = data[data_len_offset] as usize;
+        let data_end = data_len_offset + 1 + data_len;
+        let (rssi, report_len) = if extended {
+            (data[13], data_end)
+        } else {
+            match data.get(data_end) {
+                Some(rssi) => (*rssi, data_end + 1),
+                None => break,
+            }
+        };
+        let Some(ad) = data.get(data_len_offset + 1..data_end) else {
+            break;
+        };
+        let (extended_pdu, scannable, scan_response, incomplete) = if extended {
+            let event_type = u16::from_le_bytes([data[0], data[1]]);
+            (
+                event_type & EXT_LEGACY == 0,
+                event_type & EXT_SCANNABLE != 0,
+                event_type & EXT_SCAN_RESPONSE != 0,
+                event_type & EXT_DATA_STATUS == EXT_INCOMPLETE,
+            )
+        } else {
+            (false, matches!(data[0], ADV_IND | ADV_SCAN_IND), data[0] == SCAN_RSP, false)
+        };
+        reports.push(Report {
+            address,
+            address_type: data[address_offset - 1],
+            rssi: rssi as i8,
+            data: ad.to_vec(),
+            extended: extended_pdu,
+            scannable,
+            scan_response,
+            incomplete,
+        });
+        data = &data[report_len..];
+    }
+    reports
+}
+
+/// Puts together the advertising data of a device that arrives in several reports: the fragments
+/// of an extended advertisement, and an advertisement and its scan response, which are merged so
+/// that the BTHome service data may be in either.
+#[derive(Debug, Default)]
+pub struct Assembler {
+    fragments: HashMap<[u8; 6], Vec<u8>>,
+    /// The last scannable advertisement of each device, and whether it had BTHome service data
+    scannable: HashMap<[u8; 6], (Vec<u8>, bool)>,
+}
+
+impl Assembler {
+    /// The BTHome service data completed by `report`. Service data in an advertisement is
+    /// returned right away, as scan responses only follow when scanning actively, and not again
+    /// with the scan response.
+    pub fn add(&mut self, report: &Report) -> Option<Vec<u8>> {
+        let mut data = self.fragments.remove(&report.address).unwrap_or_default();
+        data.extend_from_slice(&report.data);
+        if report.incomplete {
+            if self.fragments.len() >= MAX_PENDING {
+                self.fragments.clear();
+            }
+            self.fragments.insert(report.address, data);
+            return None;
+        }
+        if report.scan_response {
+            let (mut merged, reported) = self.scannable.remove(&report.address)?;
+            if reported {
+                return None;
+            }
+            merged.extend_from_slice(&data);
+            return extract_bthome_from_adv(&merged).map(<[u8]>::to_vec);
+        }
+        let service_data = extract_bthome_from_adv(&data).map(<[u8]>::to_vec);
+        if report.scannable {
+            if self.scannable.len() >= MAX_PENDING {
+                self.scannable.clear();
+            }
+            self.scannable.insert(report.address, (data, service_data.is_some()));
+        }
+        service_data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_legacy_report() {
+        let packet = [
+            0x04, 0x3E, 0x1A, 0x02, 0x01, // LE advertising report, one report
+            0x00, 0x00, 0x56, 0x34, 0x12, 0x38, 0xC1, 0xA4, // ADV_IND, public address
+            0x0D, // data length
+            0x02, 0x01, 0x06, // flags
+            0x09, 0x16, 0xD2, 0xFC, 0x40, 0x02, 0xC4, 0x09, 0x03, 0xBF, // BTHome service data
+            0xC4, // RSSI -60
+        ];
+        let reports = parse_event(&packet);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].address, [0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        assert_eq!(reports[0].rssi, -60);
+        assert!(!reports[0].extended);
+        assert_eq!(
+            extract_bthome_from_adv(&reports[0].data),
+            Some(&[0x40, 0x02, 0xC4, 0x09, 0x03, 0xBF][..])
+        );
+    }
+
+    #[test]
+    fn parse_extended_report() {
+        let packet = [
+            0x04, 0x3E, 0x25, 0x0D, 0x01, // LE extended advertising report, one report
+            0x13, 0x00, 0x01, 0x56, 0x34, 0x12, 0x38, 0xC1, 0xA4, // legacy ADV_IND, random address
+            0x01, 0x00, 0xFF, 0x7F, 0xB5, // PHYs, SID, TX power, RSSI -75
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // periodic interval, direct address
+            0x05, 0x04, 0x16, 0xD2, 0xFC, 0x44, // BTHome service data
+        ];
+        let reports = parse_event(&packet);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].address, [0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+        assert_eq!(reports[0].address_type, 0x01);
+        assert_eq!(reports[0].rssi, -75);
+        assert_eq!(extract_bthome_from_adv(&reports[0].data), Some(&[0x44][..]));
+        assert!(!reports[0].extended);
+        assert!(reports[0].scannable);
+    }
+
+    #[test]
+    fn assemble_reports() {
+        let report = |data: &[u8], scannable, scan_response, incomplete| Report {
+            address: [0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56],
+            address_type: 0x00,
+            rssi: -60,
+            data: data.to_vec(),
+            extended: false,
+            scannable,
+            scan_response,
+            incomplete,
+        };
+        let flags = [0x02, 0x01, 0x06];
+        let service_data = [0x06, 0x16, 0xD2, 0xFC, 0x40, 0x01, 0x61];
+        let mut assembler = Assembler::default();
+
+        // Service data in the scan response
+        assert_eq!(assembler.add(&report(&flags, true, false, false)), None);
+        assert_eq!(assembler.add(&report(&service_data, false, true, false)), Some(vec![0x40, 0x01, 0x61]));
+
+        // Service data in the advertisement is not reported again with the scan response
+        assert!(assembler.add(&report(&service_data, true, false, false)).is_some());
+        assert_eq!(assembler.add(&report(&[0x03, 0x09, b'H', b'i'], false, true, false)), None);
+        // Nor is a scan response without advertisement
+        assert_eq!(assembler.add(&report(&service_data, false, true, false)), None);
+
+        // Fragments of an extended advertisement
+        assert_eq!(assembler.add(&report(&service_data[..4], false, false, true)), None);
+        assert_eq!(assembler.add(&report(&service_data[4..], false, false, false)), Some(vec![0x40, 0x01, 0x61]));
+
+        // Fragments of devices that never send the last one are forgotten
+        for i in 0..=MAX_PENDING {
+            let fragment = Report { address: [0, 0, 0, 0, (i >> 8) as u8, i as u8], ..report(&flags, false, false, true) };
+            assembler.add(&fragment);
+        }
+        assert_eq!(assembler.fragments.len(), 1);
+    }
+}
@@ -0,0 +1,40 @@
+//! Hex encoding of payloads, as written in logs, recordings and test files.
+
+/// Lower case hex digits without separators.
+pub fn encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes hex digits, ignoring whitespace, `,`, `:` and `-` separators and `0x` prefixes.
+/// Text without any digits is not a payload.
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = text
+        .replace("0x", "")
+        .replace("0X", "")
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, ',' | ':' | '-'))
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_formats() {
+        let expected = Some(vec![0x40, 0x02, 0xC4, 0x09]);
+        assert_eq!(decode("4002c409"), expected);
+        assert_eq!(decode("40 02 C4 09"), expected);
+        assert_eq!(decode("0x40, 0x02, 0xC4, 0x09"), expected);
+        assert_eq!(decode("40:02:c4:09"), expected);
+        assert_eq!(decode("4002c40"), None);
+        assert_eq!(decode("4002g409"), None);
+        assert_eq!(decode(" "), None);
+        assert_eq!(encode(&[0x40, 0x02, 0xC4, 0x09]), "4002c409");
+    }
+}
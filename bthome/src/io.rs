@@ -0,0 +1,33 @@
+//! Reading service data with `std::io`.
+
+use std::io::{self, Read};
+
+use crate::{parse_service_data, Error, ServiceData};
+
+/// Converts a parsing error into an I/O error of kind `InvalidData`, with the parsing error as
+/// its source.
+pub fn to_io_error(err: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Reads `reader` to its end and parses the content as service data, e.g. a payload saved to a
+/// file.
+pub fn read_service_data(reader: &mut impl Read) -> io::Result<ServiceData> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    parse_service_data(&data).map_err(to_io_error)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_from_reader() {
+        let service_data = read_service_data(&mut &[0x40, 0x02, 0xC4, 0x09][..]).unwrap();
+        assert_eq!(service_data.objects.len(), 1);
+        let err = read_service_data(&mut &[0x41, 0x02][..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.get_ref().and_then(|err| err.downcast_ref::<Error>()), Some(&Error::Encrypted));
+    }
+}
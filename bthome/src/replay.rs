@@ -0,0 +1,193 @@
+//! Protection against replayed encrypted packets, whose counter has to increase with every packet
+//! a device sends.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    fs,
+    hash::Hash,
+    io,
+    path::PathBuf,
+    str::FromStr,
+};
+
+/// Keeps the last counter of each device across restarts of the receiver.
+pub trait CounterStore<K> {
+    /// The counters saved before, read once when the guard is created.
+    fn load(&mut self) -> io::Result<Vec<(K, u32)>>;
+
+    /// Saves the counter of a packet accepted from `device`.
+    fn save(&mut self, device: &K, counter: u32) -> io::Result<()>;
+}
+
+/// Keeps the counters in memory only, they are lost when the receiver restarts.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InMemory;
+
+impl<K> CounterStore<K> for InMemory {
+    fn load(&mut self) -> io::Result<Vec<(K, u32)>> {
+        Ok(Vec::new())
+    }
+
+    fn save(&mut self, _device: &K, _counter: u32) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps the counters in a text file with a line `DEVICE COUNTER` per device. The file is replaced
+/// on every save, so that it is never left half written.
+#[derive(Debug, Clone)]
+pub struct FileStore<K> {
+    path: PathBuf,
+    counters: BTreeMap<K, u32>,
+}
+
+impl<K> FileStore<K> {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileStore {
+            path: path.into(),
+            counters: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Clone + Ord + Display + FromStr> CounterStore<K> for FileStore<K> {
+    fn load(&mut self) -> io::Result<Vec<(K, u32)>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("invalid counter line {:?}", line));
+            let (device, counter) = line.trim().rsplit_once(' ').ok_or_else(invalid)?;
+            let device = device.trim().parse().map_err(|_| invalid())?;
+            let counter = counter.parse().map_err(|_| invalid())?;
+            self.counters.insert(device, counter);
+        }
+        Ok(self.counters.iter().map(|(device, counter)| (device.clone(), *counter)).collect())
+    }
+
+    fn save(&mut self, device: &K, counter: u32) -> io::Result<()> {
+        self.counters.insert(device.clone(), counter);
+        let content: String = self
+            .counters
+            .iter()
+            .map(|(device, counter)| format!("{} {}\n", device, counter))
+            .collect();
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, content)?;
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+/// Rejects encrypted packets whose counter is not higher than the one of the last packet accepted
+/// from the same device, so that recorded packets can't be replayed. The counter is the one
+/// returned by [`crate::parse_encrypted_service_data`].
+///
+/// Devices send every packet several times with the same counter, so the guard also drops those
+/// copies. The devices are identified by `K`, usually their MAC address, and the counters are
+/// kept in `S`.
+#[derive(Debug, Clone)]
+pub struct ReplayGuard<K, S = InMemory> {
+    counters: HashMap<K, u32>,
+    store: S,
+}
+
+impl<K: Hash + Eq> ReplayGuard<K> {
+    /// A guard forgetting the counters when the receiver restarts.
+    pub fn new() -> Self {
+        ReplayGuard {
+            counters: HashMap::new(),
+            store: InMemory,
+        }
+    }
+}
+
+impl<K: Hash + Eq> Default for ReplayGuard<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone, S: CounterStore<K>> ReplayGuard<K, S> {
+    /// A guard continuing with the counters saved in `store`.
+    pub fn with_store(mut store: S) -> io::Result<Self> {
+        let counters = store.load()?.into_iter().collect();
+        Ok(ReplayGuard { counters, store })
+    }
+
+    /// Returns whether a packet with `counter` is newer than the last one accepted from `device`,
+    /// and if so saves the counter. The counter is kept in memory even if saving it fails.
+    pub fn accept(&mut self, device: K, counter: u32) -> io::Result<bool> {
+        if self.counters.get(&device).is_some_and(|last| counter <= *last) {
+            return Ok(false);
+        }
+        self.counters.insert(device.clone(), counter);
+        self.store.save(&device, counter)?;
+        Ok(true)
+    }
+
+    /// The counter of the last packet accepted from `device`.
+    pub fn last_counter(&self, device: &K) -> Option<u32> {
+        self.counters.get(device).copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reject_stale_counters() {
+        let mut guard = ReplayGuard::new();
+        assert!(guard.accept("kitchen", 5).unwrap());
+        assert!(!guard.accept("kitchen", 5).unwrap());
+        assert!(!guard.accept("kitchen", 4).unwrap());
+        assert!(guard.accept("office", 1).unwrap());
+        assert!(guard.accept("kitchen", 6).unwrap());
+        assert_eq!(guard.last_counter(&"kitchen"), Some(6));
+        assert_eq!(guard.last_counter(&"garage"), None);
+    }
+
+    struct ReadOnly;
+
+    impl CounterStore<&'static str> for ReadOnly {
+        fn load(&mut self) -> io::Result<Vec<(&'static str, u32)>> {
+            Ok(vec![("kitchen", 5)])
+        }
+
+        fn save(&mut self, _device: &&'static str, _counter: u32) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only"))
+        }
+    }
+
+    #[test]
+    fn keep_counters_if_saving_fails() {
+        let mut guard = ReplayGuard::with_store(ReadOnly).unwrap();
+        assert!(!guard.accept("kitchen", 5).unwrap());
+        assert_eq!(guard.accept("kitchen", 6).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(guard.last_counter(&"kitchen"), Some(6));
+        assert!(!guard.accept("kitchen", 6).unwrap());
+    }
+
+    #[test]
+    fn persist_counters() {
+        let path = std::env::temp_dir().join(format!("bthome-replay-{}.txt", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut guard = ReplayGuard::with_store(FileStore::<String>::new(&path)).unwrap();
+        assert!(guard.accept("A4:C1:38:12:34:56".to_string(), 1000).unwrap());
+        assert!(guard.accept("A4:C1:38:65:43:21".to_string(), 7).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "A4:C1:38:12:34:56 1000\nA4:C1:38:65:43:21 7\n");
+
+        let mut restarted = ReplayGuard::with_store(FileStore::<String>::new(&path)).unwrap();
+        assert!(!restarted.accept("A4:C1:38:12:34:56".to_string(), 1000).unwrap());
+        assert!(restarted.accept("A4:C1:38:12:34:56".to_string(), 1001).unwrap());
+
+        fs::write(&path, "A4:C1:38:12:34:56 many\n").unwrap();
+        let err = ReplayGuard::with_store(FileStore::<String>::new(&path)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
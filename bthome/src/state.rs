@@ -0,0 +1,89 @@
+//! The current state of a device, folded from the packets it sends.
+
+use std::{collections::HashMap, time::Instant};
+
+use crate::{ObjectId, ObjectValue, ServiceData};
+
+/// The latest value of every object a device sent, e.g. to show a table of the current
+/// measurements. Objects missing from a packet keep their previous value.
+///
+/// Devices with several sensors of the same kind send an object id several times in a packet,
+/// those instances are kept in the order they were sent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceState {
+    values: HashMap<ObjectId, Vec<ObjectValue>>,
+    last_seen: Option<Instant>,
+}
+
+impl DeviceState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes the values of a packet received at `now`.
+    pub fn update(&mut self, service_data: &ServiceData, now: Instant) {
+        let mut received: HashMap<ObjectId, Vec<ObjectValue>> = HashMap::new();
+        for object in &service_data.objects {
+            received.entry(object.object_id).or_default().push(object.value.clone());
+        }
+        self.values.extend(received);
+        self.last_seen = Some(now);
+    }
+
+    /// The value of the first instance of `object_id`.
+    pub fn get(&self, object_id: ObjectId) -> Option<&ObjectValue> {
+        self.get_all(object_id).first()
+    }
+
+    /// The values of all instances of `object_id`.
+    pub fn get_all(&self, object_id: ObjectId) -> &[ObjectValue] {
+        self.values.get(&object_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// All values sorted by object id, with the index of the instance.
+    pub fn values(&self) -> Vec<(ObjectId, usize, &ObjectValue)> {
+        let mut values: Vec<_> = self
+            .values
+            .iter()
+            .flat_map(|(object_id, values)| values.iter().enumerate().map(|(index, value)| (*object_id, index, value)))
+            .collect();
+        values.sort_by_key(|(object_id, index, _)| (*object_id as u8, *index));
+        values
+    }
+
+    /// When the last packet was received, `None` before the first one.
+    pub fn last_seen(&self) -> Option<Instant> {
+        self.last_seen
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::parse_service_data;
+
+    #[test]
+    fn fold_packets() {
+        let mut state = DeviceState::new();
+        let now = Instant::now();
+        assert_eq!(state.last_seen(), None);
+        // Battery and two temperatures
+        state.update(&parse_service_data(&[0x40, 0x01, 0x61, 0x02, 0xC4, 0x09, 0x02, 0x10, 0x27]).unwrap(), now);
+        assert_eq!(state.get(ObjectId::Battery), Some(&ObjectValue::Int(97)));
+        assert_eq!(state.get_all(ObjectId::Temperature4), &[ObjectValue::Float(25.0), ObjectValue::Float(100.0)]);
+
+        // Only the first temperature
+        let later = now + Duration::from_secs(10);
+        state.update(&parse_service_data(&[0x40, 0x02, 0xCA, 0x08]).unwrap(), later);
+        assert_eq!(state.get(ObjectId::Battery), Some(&ObjectValue::Int(97)));
+        assert_eq!(state.get_all(ObjectId::Temperature4), &[ObjectValue::Float(22.5)]);
+        assert_eq!(state.get(ObjectId::HumidityU16), None);
+        assert_eq!(state.last_seen(), Some(later));
+        assert_eq!(
+            state.values(),
+            vec![(ObjectId::Battery, 0, &ObjectValue::Int(97)), (ObjectId::Temperature4, 0, &ObjectValue::Float(22.5))]
+        );
+    }
+}
@@ -0,0 +1,26 @@
+//! The example capture of the repository decodes with the current parser, the same files can be
+//! recorded with `bthome-sniffer --record` and replayed with `--replay`.
+
+use bthome::{
+    capture::{CaptureReader, CapturedAdvertisement},
+    parse_service_data, ObjectId, ObjectValue,
+};
+
+#[test]
+fn example_capture() {
+    let data = include_bytes!("example.bthome");
+    let advertisements: Vec<CapturedAdvertisement> =
+        CaptureReader::new(&data[..]).unwrap().collect::<std::io::Result<_>>().unwrap();
+    assert_eq!(advertisements.len(), 3);
+    assert_eq!(advertisements[0].address, [0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56]);
+    assert_eq!(advertisements[0].rssi, Some(-60));
+    assert_eq!(advertisements[2].rssi, None);
+
+    let decoded: Vec<_> = advertisements
+        .iter()
+        .map(|advertisement| parse_service_data(&advertisement.service_data).unwrap())
+        .collect();
+    assert_eq!(decoded[0].objects[1].object_id, ObjectId::Temperature4);
+    assert_eq!(decoded[1].objects[1].value, ObjectValue::Int(97));
+    assert!(decoded[2].trigger_based);
+}
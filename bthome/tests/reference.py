@@ -0,0 +1,41 @@
+"""Decodes hex encoded BTHome payloads read from stdin with bthome-ble, the implementation used
+by Home Assistant, and prints the values of each payload as a line of JSON."""
+
+import json
+import sys
+
+from bthome_ble import BTHomeBluetoothDeviceData
+from home_assistant_bluetooth import BluetoothServiceInfo
+
+BTHOME_UUID = "0000fcd2-0000-1000-8000-00805f9b34fb"
+# Added by bthome-ble itself, not part of the payload
+IGNORED_KEYS = {"signal_strength", "rssi"}
+
+
+def decode(payload):
+    info = BluetoothServiceInfo(
+        name="reference",
+        address="A4:C1:38:12:34:56",
+        rssi=-60,
+        manufacturer_data={},
+        service_data={BTHOME_UUID: payload},
+        service_uuids=[BTHOME_UUID],
+        source="local",
+    )
+    update = BTHomeBluetoothDeviceData().update(info)
+    values = []
+    for key, value in update.entity_values.items():
+        native = value.native_value
+        if key.key in IGNORED_KEYS or isinstance(native, bool) or not isinstance(native, (int, float)):
+            continue
+        values.append(float(native))
+    for key, value in update.binary_entity_values.items():
+        values.append(1.0 if value.native_value else 0.0)
+    return sorted(values)
+
+
+for line in sys.stdin:
+    try:
+        print(json.dumps({"values": decode(bytes.fromhex(line.strip()))}), flush=True)
+    except Exception as err:  # noqa: BLE001, every failure is reported as a divergence
+        print(json.dumps({"error": repr(err)}), flush=True)
@@ -0,0 +1,180 @@
+//! Differential test against bthome-ble, the Python implementation used by Home Assistant.
+//!
+//! The payloads of `corpus.txt` and randomly generated ones are decoded by both implementations,
+//! which have to agree on the values, catching wrong factors, signs or sizes. Objects are matched
+//! by value only, as the two implementations name them differently. It needs `python3` with the
+//! `bthome-ble` package, so it is ignored by default:
+//!
+//! ```sh
+//! pip install bthome-ble
+//! cargo test -p bthome --test reference -- --ignored
+//! ```
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{Command, Stdio},
+};
+
+use bthome::{encode_service_data, parse_service_data, Object, ObjectId, ObjectValue, ServiceData};
+
+/// Objects bthome-ble does not report as numeric sensors: the timestamp and the device information.
+const NOT_COMPARED: &[u8] = &[0x50, 0xF0, 0xF1, 0xF2];
+const GENERATED_PAYLOADS: usize = 500;
+
+/// The values of the comparable objects, sorted like the output of `reference.py`.
+fn values(payload: &[u8]) -> Result<Vec<f64>, String> {
+    let service_data = parse_service_data(payload).map_err(|err| format!("{:?}", err))?;
+    let mut values: Vec<f64> = service_data
+        .objects
+        .into_iter()
+        .filter_map(|object| match object.value {
+            _ if NOT_COMPARED.contains(&(object.object_id as u8)) => None,
+            ObjectValue::Float(value) => Some(value),
+            ObjectValue::Int(value) => Some(value as f64),
+            ObjectValue::UInt(value) => Some(value as f64),
+            ObjectValue::Bool(value) => Some(if value { 1.0 } else { 0.0 }),
+            _ => None,
+        })
+        .collect();
+    values.sort_by(f64::total_cmp);
+    Ok(values)
+}
+
+/// Numeric and binary objects with the size of their value in bytes, found by encoding a zero.
+fn generatable_objects() -> Vec<(u8, usize, bool)> {
+    (0..=255u8)
+        .filter(|id| !NOT_COMPARED.contains(id))
+        .filter_map(|id| {
+            [ObjectValue::Int(0), ObjectValue::Bool(false)]
+                .into_iter()
+                .enumerate()
+                .find_map(|(i, value)| {
+                    let service_data = ServiceData {
+                        encrypted: false,
+                        trigger_based: false,
+                        version: 2,
+                        objects: vec![Object::new(ObjectId::try_from(id).ok()?, value)],
+                    };
+                    let encoded = encode_service_data(&service_data).ok()?;
+                    Some((id, encoded.len() - 2, i == 1))
+                })
+        })
+        .collect()
+}
+
+/// Payloads with one to four random objects ordered by id, from a fixed seed so that failures
+/// can be reproduced.
+fn generate(count: usize) -> Vec<Vec<u8>> {
+    let objects = generatable_objects();
+    let mut state = 0x2545_F491_4F6C_DD1Du64;
+    let mut random = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    (0..count)
+        .map(|_| {
+            let mut chosen: Vec<_> = (0..1 + random() % 4)
+                .map(|_| objects[random() as usize % objects.len()])
+                .collect();
+            chosen.sort_by_key(|(id, ..)| *id);
+            let mut payload = vec![0x40];
+            for (id, size, binary) in chosen {
+                payload.push(id);
+                if binary {
+                    payload.push((random() % 2) as u8);
+                } else {
+                    payload.extend((0..size).map(|_| random() as u8));
+                }
+            }
+            payload
+        })
+        .collect()
+}
+
+fn corpus() -> Vec<Vec<u8>> {
+    include_str!("corpus.txt")
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().replace(' ', ""))
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            (0..line.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&line[i..i + 2], 16).expect("Corpus to be hex encoded"))
+                .collect()
+        })
+        .collect()
+}
+
+/// Whether two decoded values are the same, allowing for bthome-ble computing in double and
+/// rounding to two decimals.
+fn same(ours: f64, theirs: f64) -> bool {
+    (ours - theirs).abs() <= 0.01 + theirs.abs() * 1e-5
+}
+
+#[test]
+#[ignore = "needs python3 with bthome-ble"]
+fn compare_with_bthome_ble() {
+    let payloads: Vec<Vec<u8>> = corpus().into_iter().chain(generate(GENERATED_PAYLOADS)).collect();
+    let script = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/reference.py");
+    let mut python = Command::new("python3")
+        .arg(script)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("python3 to start");
+    let mut stdin = python.stdin.take().unwrap();
+    let input: String = payloads
+        .iter()
+        .map(|payload| payload.iter().map(|b| format!("{:02x}", b)).collect::<String>() + "\n")
+        .collect();
+    // Written from a thread, as python answers while the input is still being written
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let lines: Vec<String> = BufReader::new(python.stdout.take().unwrap())
+        .lines()
+        .collect::<Result<_, _>>()
+        .expect("Output of reference.py to be readable");
+    writer.join().unwrap().expect("Payloads to be written");
+    assert!(python.wait().unwrap().success(), "reference.py failed, is bthome-ble installed?");
+    assert_eq!(lines.len(), payloads.len(), "reference.py has to answer every payload");
+
+    let mut divergences = Vec::new();
+    for (payload, line) in payloads.iter().zip(&lines) {
+        let reference: serde_json::Value = serde_json::from_str(line).expect("Output to be JSON");
+        let theirs: Option<Vec<f64>> = reference["values"]
+            .as_array()
+            .map(|values| values.iter().filter_map(|value| value.as_f64()).collect());
+        let agree = match (values(payload), &theirs) {
+            (Ok(ours), Some(theirs)) => {
+                ours.len() == theirs.len() && ours.iter().zip(theirs).all(|(ours, theirs)| same(*ours, *theirs))
+            }
+            // bthome-ble logs invalid payloads and reports no values instead of failing
+            (Err(_), None) => true,
+            (Err(_), Some(theirs)) => theirs.is_empty(),
+            _ => false,
+        };
+        if !agree {
+            divergences.push(format!(
+                "{}: ours {:?}, bthome-ble {}",
+                payload.iter().map(|b| format!("{:02x}", b)).collect::<String>(),
+                values(payload),
+                line
+            ));
+        }
+    }
+    assert!(
+        divergences.is_empty(),
+        "{} of {} payloads decode differently:\n{}",
+        divergences.len(),
+        payloads.len(),
+        divergences.join("\n")
+    );
+}
+
+#[test]
+fn generated_payloads_parse() {
+    for payload in corpus().into_iter().chain(generate(50)) {
+        assert!(values(&payload).is_ok(), "{:02x?}", payload);
+    }
+}
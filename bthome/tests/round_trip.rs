@@ -0,0 +1,99 @@
+//! Round trips between the parser and the encoder for every object id: the bytes of a parsed
+//! object encode to the same bytes again, which locks down sizes, signs and factors of the wire
+//! format. Objects are read with [`ServiceDataIter`], so that the bytes after the first object
+//! don't matter. Values that parse but are beyond the limits of the measurement, like a battery
+//! above 100 %, are rejected by the encoder instead.
+
+use bthome::{Error, Object, ObjectId, ObjectValue, ServiceData, ServiceDataIter};
+use proptest::{prelude::*, sample::select};
+
+/// The ids of all objects the crate knows.
+fn object_ids() -> Vec<ObjectId> {
+    (0..=u8::MAX).filter_map(|id| ObjectId::try_from(id).ok()).collect()
+}
+
+/// Parses the first object of `value` after the id, returns it and its bytes, or `None` if the
+/// bytes are not a valid value, like 2 for a binary sensor.
+fn first_object(object_id: ObjectId, value: &[u8]) -> Option<(Object, Vec<u8>)> {
+    let mut data = vec![0x40, object_id as u8];
+    data.extend_from_slice(value);
+    let mut objects = ServiceDataIter::new(&data).ok()?;
+    let object = Object::from(objects.next()?.ok()?);
+    Some((object, data[..objects.offset()].to_vec()))
+}
+
+fn try_encode(object: &Object) -> Result<Vec<u8>, Error> {
+    let service_data = ServiceData {
+        encrypted: false,
+        trigger_based: false,
+        version: 2,
+        objects: vec![object.clone()],
+    };
+    service_data.to_bytes()
+}
+
+fn encode(object: &Object) -> Vec<u8> {
+    try_encode(object).unwrap_or_else(|err| panic!("{:?} to encode: {}", object, err))
+}
+
+fn is_valid(object: &Object) -> bool {
+    let number = match object.value {
+        ObjectValue::Float(value) => value,
+        ObjectValue::Int(value) => value as f64,
+        ObjectValue::UInt(value) => value as f64,
+        _ => return true,
+    };
+    object.object_id.valid_range().is_none_or(|range| range.contains(&number))
+}
+
+fn assert_round_trip(object: &Object, bytes: &[u8]) {
+    if !is_valid(object) {
+        assert!(matches!(try_encode(object), Err(Error::ValueOutOfRange { .. })), "{:?}", object);
+        return;
+    }
+    let encoded = encode(object);
+    assert_eq!(encoded, bytes, "{:?}", object);
+    let (parsed, _) = first_object(object.object_id, &encoded[2..]).unwrap();
+    assert_eq!(&parsed, object);
+}
+
+#[test]
+fn edge_values() {
+    for object_id in object_ids() {
+        let mut round_trips = 0;
+        // The minimum and maximum of every width, signed and unsigned, padded to eight bytes
+        for width in 1..=8 {
+            let mut values = vec![[0x00; 8], [0xFF; 8]];
+            for (last, rest) in [(0x80, 0x00), (0x7F, 0xFF)] {
+                let mut value = [0x00; 8];
+                value[..width].fill(rest);
+                value[width - 1] = last;
+                values.push(value);
+            }
+            for value in values {
+                if let Some((object, bytes)) = first_object(object_id, &value) {
+                    assert_round_trip(&object, &bytes);
+                    round_trips += 1;
+                }
+            }
+        }
+        assert!(round_trips > 0, "no value of {:?} parsed", object_id);
+    }
+}
+
+/// Bytes biased towards the edges of the value ranges.
+fn value_byte() -> impl Strategy<Value = u8> {
+    prop_oneof![Just(0x00), Just(0x01), Just(0x7F), Just(0x80), Just(0xFF), any::<u8>()]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig { cases: 2048, max_global_rejects: 8192, ..ProptestConfig::default() })]
+
+    #[test]
+    fn random_values(object_id in select(object_ids()), value in prop::collection::vec(value_byte(), 8..24)) {
+        let first = first_object(object_id, &value);
+        prop_assume!(first.is_some());
+        let (object, bytes) = first.unwrap();
+        assert_round_trip(&object, &bytes);
+    }
+}
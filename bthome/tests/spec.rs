@@ -0,0 +1,85 @@
+//! The examples of the BTHome specification in `spec_examples.txt`, decoded exactly as listed
+//! there, so that deviations from the specification are caught.
+
+use bthome::{parse_encrypted_service_data, parse_service_data, Object, ServiceData};
+
+const EXAMPLES: &str = include_str!("spec_examples.txt");
+
+#[derive(Debug, Default)]
+struct Example {
+    title: String,
+    payload: Vec<u8>,
+    key: Option<[u8; 16]>,
+    mac: Option<[u8; 6]>,
+    counter: Option<u32>,
+    trigger_based: bool,
+    objects: Vec<String>,
+}
+
+fn hex(text: &str) -> Vec<u8> {
+    let digits: String = text.split_whitespace().collect();
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap_or_else(|_| panic!("invalid hex {:?}", text)))
+        .collect()
+}
+
+fn examples() -> Vec<Example> {
+    let mut examples = Vec::new();
+    // The comment before the first blank line describes the file
+    for block in EXAMPLES.split("\n\n").skip(1) {
+        let mut example = Example::default();
+        for line in block.lines() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "#" => example.title = value.to_string(),
+                "payload" => example.payload = hex(value),
+                "key" => example.key = Some(hex(value).try_into().expect("key of 16 bytes")),
+                "mac" => example.mac = Some(hex(value).try_into().expect("MAC of 6 bytes")),
+                "counter" => example.counter = Some(u32::from_str_radix(value, 16).expect("hex counter")),
+                "trigger_based" => example.trigger_based = true,
+                "-" => example.objects.push(value.to_string()),
+                _ => panic!("unexpected line {:?}", line),
+            }
+        }
+        examples.push(example);
+    }
+    examples
+}
+
+/// An object like in the examples, e.g. `Temperature4#1 Float(19.99)`.
+fn describe(object: &Object) -> String {
+    match object.instance {
+        0 => format!("{:?} {:?}", object.object_id, object.value),
+        instance => format!("{:?}#{} {:?}", object.object_id, instance, object.value),
+    }
+}
+
+fn decode(example: &Example) -> ServiceData {
+    match (example.key, example.mac) {
+        (Some(key), Some(mac)) => {
+            let (service_data, counter) = parse_encrypted_service_data(&example.payload, &key, &mac)
+                .unwrap_or_else(|err| panic!("{}: {}", example.title, err));
+            assert_eq!(Some(counter), example.counter, "{}", example.title);
+            service_data
+        }
+        _ => parse_service_data(&example.payload).unwrap_or_else(|err| panic!("{}: {}", example.title, err)),
+    }
+}
+
+#[test]
+fn spec_examples() {
+    let examples = examples();
+    assert!(examples.len() >= 10);
+    for example in examples {
+        let service_data = decode(&example);
+        assert_eq!(service_data.version, 2, "{}", example.title);
+        assert_eq!(service_data.encrypted, example.key.is_some(), "{}", example.title);
+        assert_eq!(service_data.trigger_based, example.trigger_based, "{}", example.title);
+        let objects: Vec<String> = service_data.objects.iter().map(describe).collect();
+        assert_eq!(objects, example.objects, "{}", example.title);
+        if example.key.is_none() {
+            assert_eq!(service_data.to_bytes().unwrap(), example.payload, "{}", example.title);
+        }
+    }
+}
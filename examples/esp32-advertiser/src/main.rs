@@ -0,0 +1,52 @@
+//! A BTHome advertiser for the ESP32 on ESP-IDF: advertises a temperature every 30 seconds with
+//! NimBLE through esp32-nimble. The service data is encoded into a buffer on the stack, without
+//! allocating.
+
+use bthome_core::{encode_service_data_into, ObjectId, ObjectRef, ObjectValueRef};
+use esp32_nimble::enums::ConnMode;
+use esp32_nimble::{BLEAdvertisementData, BLEDevice, BleUuid};
+use esp_idf_svc::hal::delay::FreeRtos;
+use log::{info, warn};
+
+const NAME: &str = "BTHome ESP32";
+const BTHOME_UUID: u16 = 0xFCD2;
+const UPDATE_INTERVAL_MS: u32 = 30_000;
+
+fn main() {
+    esp_idf_svc::sys::link_patches();
+    esp_idf_svc::log::EspLogger::initialize_default();
+
+    let advertising = BLEDevice::take().get_advertising();
+    let mut packet_id: u8 = 0;
+    let mut started = false;
+    loop {
+        let temperature = read_temperature();
+        let objects = [
+            ObjectRef::new(ObjectId::PacketId, ObjectValueRef::UInt(packet_id.into())),
+            ObjectRef::new(ObjectId::Temperature4, ObjectValueRef::Float(f64::from(temperature))),
+        ];
+        let mut payload = [0u8; 24];
+        let len = encode_service_data_into(false, &objects, &mut payload).expect("Temperature to be in range");
+        info!("Advertising {} °C", temperature);
+
+        // The data is replaced while advertising, NimBLE picks it up with the next event
+        let mut data = BLEAdvertisementData::new();
+        data.name(NAME).service_data(BleUuid::from_uuid16(BTHOME_UUID), &payload[..len]);
+        {
+            let mut advertising = advertising.lock();
+            if let Err(err) = advertising.advertisement_type(ConnMode::Non).set_data(&mut data) {
+                warn!("Setting the advertising data failed: {:?}", err);
+            } else if !started {
+                started = advertising.start().inspect_err(|err| warn!("Advertising failed: {:?}", err)).is_ok();
+            }
+        }
+
+        packet_id = packet_id.wrapping_add(1);
+        FreeRtos::delay_ms(UPDATE_INTERVAL_MS);
+    }
+}
+
+/// The ESP32 has no usable sensor of its own, replace this with the driver of the attached one.
+fn read_temperature() -> f32 {
+    21.5
+}
@@ -0,0 +1,111 @@
+//! A BTHome beacon for the ESP32-C3: advertises the chip temperature and the battery level, read
+//! through a 1:2 voltage divider on GPIO3, every 30 seconds. The advertising is set up with plain
+//! HCI commands to the controller, no host stack is needed for a beacon.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use bthome_core::{encode_service_data, Object, ObjectId, ObjectValue, ServiceData};
+use embedded_io::{Read, Write};
+use esp_backtrace as _;
+use esp_hal::analog::adc::{Adc, AdcConfig, Attenuation};
+use esp_hal::clock::CpuClock;
+use esp_hal::delay::Delay;
+use esp_hal::interrupt::software::SoftwareInterruptControl;
+use esp_hal::timer::timg::TimerGroup;
+use esp_hal::tsens::{self, TemperatureSensor};
+use esp_println::println;
+use esp_radio::ble::controller::BleConnector;
+
+esp_bootloader_esp_idf::esp_app_desc!();
+
+const NAME: &[u8] = b"BTHome C3";
+const UPDATE_INTERVAL_MS: u32 = 30_000;
+
+const HCI_COMMAND: u8 = 0x01;
+const HCI_RESET: u16 = 0x0C03;
+const LE_SET_ADVERTISING_PARAMETERS: u16 = 0x2006;
+const LE_SET_ADVERTISING_DATA: u16 = 0x2008;
+const LE_SET_ADVERTISING_ENABLE: u16 = 0x200A;
+/// Non connectable undirected advertising every second on all three channels.
+const ADVERTISING_PARAMETERS: [u8; 15] = [0x40, 0x06, 0x40, 0x06, 0x03, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0x07, 0x00];
+
+#[esp_hal::main]
+fn main() -> ! {
+    let peripherals = esp_hal::init(esp_hal::Config::default().with_cpu_clock(CpuClock::max()));
+    esp_alloc::heap_allocator!(size: 72 * 1024);
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    let software_interrupts = SoftwareInterruptControl::new(peripherals.SW_INTERRUPT);
+    esp_rtos::start(timg0.timer0, software_interrupts.software_interrupt0);
+    let radio = esp_radio::init().expect("Radio to initialize");
+    let mut hci = BleConnector::new(&radio, peripherals.BT, Default::default()).expect("BLE controller to start");
+
+    let sensor = TemperatureSensor::new(peripherals.TSENS, tsens::Config::default()).expect("Temperature sensor");
+    let mut adc_config = AdcConfig::new();
+    let mut battery_pin = adc_config.enable_pin(peripherals.GPIO3, Attenuation::_11dB);
+    let mut adc = Adc::new(peripherals.ADC1, adc_config);
+    let delay = Delay::new();
+
+    command(&mut hci, HCI_RESET, &[]);
+    command(&mut hci, LE_SET_ADVERTISING_PARAMETERS, &ADVERTISING_PARAMETERS);
+    let mut advertising = false;
+    loop {
+        let temperature = sensor.get_temperature().to_celsius();
+        let raw = nb::block!(adc.read_oneshot(&mut battery_pin)).unwrap_or(0);
+        let battery = battery_level(raw);
+        println!("Advertising {} °C, battery {} %", temperature, battery);
+
+        // The data is replaced while advertising, the controller picks it up with the next event
+        let data = advertising_data(temperature, battery);
+        let mut parameters = [0u8; 32];
+        parameters[0] = data.len() as u8;
+        parameters[1..=data.len()].copy_from_slice(&data);
+        command(&mut hci, LE_SET_ADVERTISING_DATA, &parameters);
+        if !advertising {
+            command(&mut hci, LE_SET_ADVERTISING_ENABLE, &[0x01]);
+            advertising = true;
+        }
+        delay.delay_millis(UPDATE_INTERVAL_MS);
+    }
+}
+
+/// Sends an HCI command and waits for the controller to answer it.
+fn command(hci: &mut BleConnector, opcode: u16, parameters: &[u8]) {
+    let [low, high] = opcode.to_le_bytes();
+    let mut packet = alloc::vec![HCI_COMMAND, low, high, parameters.len() as u8];
+    packet.extend_from_slice(parameters);
+    hci.write_all(&packet).expect("HCI command to be sent");
+    let mut event = [0u8; 64];
+    while hci.read(&mut event).unwrap_or(0) == 0 {}
+}
+
+/// Battery level in percent, linear between 3.0 V and 4.2 V as for a LiPo cell. The ADC reads
+/// up to about 2.5 V with 11 dB attenuation, behind the divider that is 5 V.
+fn battery_level(raw: u16) -> u8 {
+    let millivolts = raw as u32 * 5000 / 4095;
+    ((millivolts.clamp(3000, 4200) - 3000) / 12) as u8
+}
+
+/// The flags, the BTHome service data and the name, as advertised.
+fn advertising_data(temperature: f32, battery: u8) -> Vec<u8> {
+    let service_data = ServiceData {
+        encrypted: false,
+        trigger_based: false,
+        version: 2,
+        objects: alloc::vec![
+            Object::new(ObjectId::Battery, ObjectValue::Int(battery.into())),
+            Object::new(ObjectId::Temperature4, ObjectValue::Float(f64::from(temperature))),
+        ],
+    };
+    let payload = encode_service_data(&service_data).expect("Temperature and battery to be in range");
+    let mut data = alloc::vec![0x02, 0x01, 0x06, payload.len() as u8 + 3, 0x16, 0xD2, 0xFC];
+    data.extend_from_slice(&payload);
+    data.extend_from_slice(&[NAME.len() as u8 + 1, 0x09]);
+    data.extend_from_slice(NAME);
+    data
+}
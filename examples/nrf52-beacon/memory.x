@@ -0,0 +1,6 @@
+/* nRF52840 with the S140 7.x SoftDevice, which occupies the start of flash and RAM */
+MEMORY
+{
+  FLASH : ORIGIN = 0x00027000, LENGTH = 868K
+  RAM : ORIGIN = 0x20020000, LENGTH = 128K
+}
@@ -0,0 +1,130 @@
+//! A BTHome beacon for the nRF52840: advertises the die temperature and the battery level, read
+//! from the supply voltage, every 30 seconds using the S140 SoftDevice.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::mem;
+
+use bthome_core::{encode_service_data, Object, ObjectId, ObjectValue, ServiceData};
+use defmt::{info, unwrap, warn};
+use embassy_executor::Spawner;
+use embassy_nrf::interrupt::{self, InterruptExt, Priority};
+use embassy_nrf::saadc::{self, ChannelConfig, Saadc, VddInput};
+use embassy_nrf::{bind_interrupts, peripherals};
+use embassy_time::{Duration, Timer};
+use embedded_alloc::LlffHeap as Heap;
+use nrf_softdevice::ble::peripheral::{self, NonconnectableAdvertisement};
+use nrf_softdevice::{raw, temperature_celsius, Softdevice};
+use {defmt_rtt as _, panic_probe as _};
+
+const NAME: &[u8] = b"BTHome nRF52";
+/// How long a reading is advertised, in units of 10 ms.
+const ADVERTISING_TIMEOUT: u16 = 3_000;
+/// Advertising interval in units of 0.625 ms, i.e. one second.
+const ADVERTISING_INTERVAL: u32 = 1_600;
+const HEAP_SIZE: usize = 1024;
+
+#[global_allocator]
+static HEAP: Heap = Heap::empty();
+
+bind_interrupts!(struct Irqs {
+    SAADC => saadc::InterruptHandler;
+});
+
+#[embassy_executor::task]
+async fn softdevice_task(sd: &'static Softdevice) -> ! {
+    sd.run().await
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    {
+        static mut HEAP_MEMORY: [mem::MaybeUninit<u8>; HEAP_SIZE] = [mem::MaybeUninit::uninit(); HEAP_SIZE];
+        unsafe { HEAP.init(&raw mut HEAP_MEMORY as usize, HEAP_SIZE) }
+    }
+
+    // Priorities 0, 1 and 4 are reserved by the SoftDevice
+    let mut config = embassy_nrf::config::Config::default();
+    config.gpiote_interrupt_priority = Priority::P2;
+    config.time_interrupt_priority = Priority::P2;
+    let p = embassy_nrf::init(config);
+    interrupt::SAADC.set_priority(Priority::P3);
+
+    let sd = Softdevice::enable(&softdevice_config());
+    unwrap!(spawner.spawn(softdevice_task(sd)));
+
+    let channel = ChannelConfig::single_ended(VddInput);
+    let mut saadc = Saadc::new(p.SAADC, Irqs, saadc::Config::default(), [channel]);
+    saadc.calibrate().await;
+
+    loop {
+        let temperature = unwrap!(temperature_celsius(sd)).to_num::<f32>();
+        let battery = battery_level(&mut saadc).await;
+        info!("Advertising {} °C, battery {} %", temperature, battery);
+        let adv_data = advertising_data(temperature, battery);
+        let advertisement = NonconnectableAdvertisement::NonscannableUndirected { adv_data: &adv_data };
+        let config = peripheral::Config {
+            interval: ADVERTISING_INTERVAL,
+            timeout: Some(ADVERTISING_TIMEOUT),
+            ..Default::default()
+        };
+        if let Err(err) = peripheral::advertise(sd, advertisement, &config).await {
+            // Stopping at the timeout is reported as an error, anything else is worth a look
+            if !matches!(err, peripheral::AdvertiseError::Timeout) {
+                warn!("Advertising failed: {}", err);
+                Timer::after(Duration::from_secs(30)).await;
+            }
+        }
+    }
+}
+
+/// Battery level in percent, linear between 2.0 V and 3.0 V as for a CR2032 coin cell.
+async fn battery_level(saadc: &mut Saadc<'_, 1>) -> u8 {
+    let mut sample = [0i16; 1];
+    saadc.sample(&mut sample).await;
+    // With the default gain of 1/6 and the internal 0.6 V reference, 4096 is 3.6 V
+    let millivolts = sample[0].max(0) as u32 * 3600 / 4096;
+    (millivolts.clamp(2000, 3000) - 2000).div_ceil(10) as u8
+}
+
+/// The flags, the BTHome service data and the name, as advertised.
+fn advertising_data(temperature: f32, battery: u8) -> Vec<u8> {
+    let service_data = ServiceData {
+        encrypted: false,
+        trigger_based: false,
+        version: 2,
+        objects: alloc::vec![
+            Object::new(ObjectId::Battery, ObjectValue::Int(battery.into())),
+            Object::new(ObjectId::Temperature4, ObjectValue::Float(f64::from(temperature))),
+        ],
+    };
+    let payload = unwrap!(encode_service_data(&service_data));
+    let mut data = alloc::vec![0x02, 0x01, 0x06, payload.len() as u8 + 3, 0x16, 0xD2, 0xFC];
+    data.extend_from_slice(&payload);
+    data.extend_from_slice(&[NAME.len() as u8 + 1, 0x09]);
+    data.extend_from_slice(NAME);
+    data
+}
+
+fn softdevice_config() -> nrf_softdevice::Config {
+    nrf_softdevice::Config {
+        clock: Some(raw::nrf_clock_lf_cfg_t {
+            source: raw::NRF_CLOCK_LF_SRC_RC as u8,
+            rc_ctiv: 16,
+            rc_temp_ctiv: 2,
+            accuracy: raw::NRF_CLOCK_LF_ACCURACY_500_PPM as u8,
+        }),
+        gap_role_count: Some(raw::ble_gap_cfg_role_count_t {
+            adv_set_count: 1,
+            periph_role_count: 0,
+            central_role_count: 0,
+            central_sec_count: 0,
+            _bitfield_1: raw::ble_gap_cfg_role_count_t::new_bitfield_1(0),
+        }),
+        ..Default::default()
+    }
+}